@@ -0,0 +1,30 @@
+use inkwell::context::Context;
+use std::error::Error;
+use zydis2llvmir::{compiler::Compiler, loader::LoadedImage};
+
+/// Lifts a function of an ELF file by its symbol name. Usage:
+///
+/// `cargo run --example lift_elf_symbol -- <path to ELF file> <symbol name>`
+///
+/// Calls through PLT stubs become calls to external declarations named after the imported symbol
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or("Path to the ELF file is required")?;
+    let symbol = args.next().ok_or("Symbol name is required")?;
+
+    let bytes = std::fs::read(path)?;
+    let image = LoadedImage::load(&bytes)?;
+
+    let context = Context::create();
+    let compiler = Compiler::new_for_image(&context, image)?;
+    let (cfg, report) = compiler.lift_image_symbol(&symbol, true)?;
+
+    println!(
+        "Lifted {} basic blocks of {symbol}, {} instructions weren't supported",
+        cfg.blocks.len(),
+        report.unsupported().count()
+    );
+    compiler.lifter.module.print_to_file("lifted.ll")?;
+
+    Ok(())
+}
@@ -0,0 +1,34 @@
+use inkwell::context::Context;
+use std::error::Error;
+use zydis2llvmir::{compiler::Compiler, loader::LoadedImage};
+
+/// Lifts a function of a PE file. Usage:
+///
+/// `cargo run --example lift_pe_function -- <path to .exe/.dll> [virtual address in hex]`
+///
+/// Entry point of the image is lifted when no address is given
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or("Path to the PE file is required")?;
+
+    let bytes = std::fs::read(path)?;
+    let image = LoadedImage::load(&bytes)?;
+
+    let start_address = match args.next() {
+        Some(address) => u64::from_str_radix(address.trim_start_matches("0x"), 16)?,
+        None => image.entry_point,
+    };
+
+    let context = Context::create();
+    let compiler = Compiler::new_for_image(&context, image)?;
+    let (cfg, report) = compiler.lift_image_function(start_address, true)?;
+
+    println!(
+        "Lifted {} basic blocks starting at {start_address:#x}, {} instructions weren't supported",
+        cfg.blocks.len(),
+        report.unsupported().count()
+    );
+    compiler.lifter.module.print_to_file("lifted.ll")?;
+
+    Ok(())
+}
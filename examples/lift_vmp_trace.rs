@@ -31,17 +31,10 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     let now = Instant::now();
     let mut instructions = Vec::with_capacity(17_833);
-    let mut last_is_rep = false;
+    // Traces repeat REP instructions once per iteration, lift_function skips the copies
     for instruction_info in decoder.decode_all::<AllOperands>(raw_bytes, 0) {
         let (_ip, _raw_bytes, instruction) = instruction_info?;
-
-        let curr_ins_attributes = instruction
-            .attributes
-            .contains(zydis::InstructionAttributes::HAS_REP);
-        if !last_is_rep {
-            instructions.push(instruction);
-        }
-        last_is_rep = curr_ins_attributes;
+        instructions.push(instruction);
     }
 
     let mode = zydis::MachineMode::LONG_64;
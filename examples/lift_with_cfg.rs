@@ -0,0 +1,32 @@
+use inkwell::context::Context;
+use std::error::Error;
+use zydis2llvmir::compiler::Compiler;
+
+/// This example lifts a function with a branch. Instead of passing a list of already decoded
+/// instructions, the raw bytes are given and the control flow graph is recovered by the compiler
+/// itself, so each basic block of the function ends up in its own LLVM basic block
+fn main() -> Result<(), Box<dyn Error>> {
+    let context = Context::create();
+
+    let mode = zydis::MachineMode::LONG_64;
+
+    const BASE_ADDRESS: u64 = 0x140001000;
+    let compiler = Compiler::new_with_x86_lifter(&context, mode, None)?;
+    let (cfg, _report) =
+        compiler.lift_function_at(&MAX_OF_TWO_64, BASE_ADDRESS, BASE_ADDRESS, true)?;
+
+    println!("Recovered {} basic blocks", cfg.blocks.len());
+    compiler.lifter.module.print_to_stderr();
+
+    Ok(())
+}
+
+/// ```assembly
+/// mov eax, ecx
+/// cmp ecx, edx
+/// jge skip
+/// mov eax, edx
+/// skip:
+/// ret
+/// ```
+const MAX_OF_TWO_64: [u8; 9] = [0x89, 0xC8, 0x39, 0xD1, 0x7D, 0x02, 0x89, 0xD0, 0xC3];
@@ -0,0 +1,38 @@
+use inkwell::context::Context;
+use std::error::Error;
+use zydis2llvmir::compiler::{contexts::StartContextX86, Compiler};
+
+/// Same function as in `lift_with_cfg.rs`, but the arguments are known before lifting. They are
+/// passed as a start context, so after optimization the whole function folds into a constant
+fn main() -> Result<(), Box<dyn Error>> {
+    let context = Context::create();
+
+    let mode = zydis::MachineMode::LONG_64;
+
+    const BASE_ADDRESS: u64 = 0x140001000;
+    let compiler = Compiler::new_with_x86_lifter(&context, mode, None)?;
+
+    let start_context: StartContextX86<u64> = StartContextX86 {
+        rcx: 7,
+        rdx: 42,
+        rsp: 0x14FF00,
+        rip: BASE_ADDRESS,
+        ..Default::default()
+    };
+    compiler.set_start_context(start_context)?;
+
+    compiler.lift_function_at(&MAX_OF_TWO_64, BASE_ADDRESS, BASE_ADDRESS, true)?;
+    compiler.lifter.module.print_to_stderr();
+
+    Ok(())
+}
+
+/// ```assembly
+/// mov eax, ecx
+/// cmp ecx, edx
+/// jge skip
+/// mov eax, edx
+/// skip:
+/// ret
+/// ```
+const MAX_OF_TWO_64: [u8; 9] = [0x89, 0xC8, 0x39, 0xD1, 0x7D, 0x02, 0x89, 0xD0, 0xC3];
@@ -39,11 +39,11 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 
     //let lifter = LifterX86::new(&context, mode);
-    let compiler = Compiler::new_with_x86_lifter(&context, mode)?;
+    let compiler = Compiler::new_with_x86_lifter(&context, mode, None)?;
 
     //let lifter = LifterX86::new(&context, mode)?;
     //lifter.lift_function(&all_instructions)?;
-    compiler.lift_function(&all_instructions)?;
+    compiler.lift_function(&all_instructions, false)?;
 
     // // Append ret void at the end of the basic block
     //lifter.builder.build_return(None)?;
@@ -0,0 +1,432 @@
+//! Recursive-descent recovery of the control flow graph of a function from raw bytes
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::loader::LoadedImage;
+
+use zydis::{
+    ffi::DecodedOperandKind, AllOperands, Decoder, FullInstruction, InstructionCategory,
+    MachineMode, Mnemonic, StackWidth,
+};
+
+use super::error::Error;
+use super::jump_table::{self, JumpTable};
+use super::Result;
+
+/// How control leaves a recovered block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockExit {
+    /// Block ends right before another block starts (or before bytes which couldn't be decoded)
+    FallThrough(u64),
+    /// Direct unconditional jump
+    Jump(u64),
+    /// Direct conditional jump
+    ConditionalJump {
+        target: u64,
+        fallthrough: u64,
+    },
+    /// Indirect jump through a recovered jump table
+    JumpTable(JumpTable),
+    /// Jump with a target unknown at decoding time
+    IndirectJump,
+    Return,
+    /// Instructions after which execution never continues (`HLT`, `UD2`, `INT3`)
+    Trap,
+}
+
+impl BlockExit {
+    pub fn successors(&self) -> Vec<u64> {
+        match self {
+            Self::FallThrough(address) | Self::Jump(address) => vec![*address],
+            Self::ConditionalJump {
+                target,
+                fallthrough,
+            } => vec![*target, *fallthrough],
+            Self::JumpTable(table) => {
+                let targets: BTreeSet<_> = table.targets.iter().copied().collect();
+                targets.into_iter().collect()
+            }
+            Self::IndirectJump | Self::Return | Self::Trap => vec![],
+        }
+    }
+}
+
+/// Sequence of instructions with a single entry and single exit
+#[derive(Debug, Clone)]
+pub struct RecoveredBlock {
+    pub start_address: u64,
+    /// Instructions with their addresses
+    pub instructions: Vec<(u64, FullInstruction)>,
+    pub exit: BlockExit,
+}
+
+/// Blocks reachable from some start address
+#[derive(Debug, Clone)]
+pub struct ControlFlowGraph {
+    pub entry: u64,
+    pub blocks: BTreeMap<u64, RecoveredBlock>,
+    /// Targets of direct calls. They are discovered but not followed
+    pub call_targets: BTreeSet<u64>,
+}
+
+impl ControlFlowGraph {
+    /// Decodes `code` (mapped at `base_address`) starting from `start_address`, following direct
+    /// jumps and fall-throughs
+    pub fn recover(
+        mode: MachineMode,
+        code: &[u8],
+        base_address: u64,
+        start_address: u64,
+    ) -> Result<Self> {
+        Self::recover_with_image(mode, code, base_address, start_address, None)
+    }
+
+    /// Same as [Self::recover], but also follows indirect jumps through jump tables found in
+    /// `image`
+    pub fn recover_with_image(
+        mode: MachineMode,
+        code: &[u8],
+        base_address: u64,
+        start_address: u64,
+        image: Option<&LoadedImage>,
+    ) -> Result<Self> {
+        if offset_in(code, base_address, start_address).is_none() {
+            return Err(Error::AddressOutOfRange(start_address));
+        }
+
+        let decoder = Decoder::new(mode, stack_width_for_mode(mode))?;
+
+        let mut instructions: BTreeMap<u64, FullInstruction> = BTreeMap::new();
+        let mut leaders = BTreeSet::from([start_address]);
+        let mut call_targets = BTreeSet::new();
+        let mut jump_tables = BTreeMap::new();
+        let mut worklist = vec![start_address];
+
+        while let Some(mut address) = worklist.pop() {
+            while !instructions.contains_key(&address) {
+                let Some(offset) = offset_in(code, base_address, address) else {
+                    break;
+                };
+                let Some(instruction) = decoder.decode_first::<AllOperands>(&code[offset..])?
+                else {
+                    break;
+                };
+
+                let next_address = address + u64::from(instruction.length);
+                let direct_target = direct_branch_target(&instruction, address)?;
+                let category = instruction.meta.category;
+                let mnemonic = instruction.mnemonic;
+                instructions.insert(address, instruction);
+
+                match category {
+                    InstructionCategory::COND_BR => {
+                        if let Some(target) = direct_target {
+                            leaders.insert(target);
+                            worklist.push(target);
+                        }
+                        leaders.insert(next_address);
+                        worklist.push(next_address);
+                        break;
+                    }
+                    InstructionCategory::UNCOND_BR => {
+                        if let Some(target) = direct_target {
+                            leaders.insert(target);
+                            worklist.push(target);
+                        } else if let Some(table) = image.and_then(|image| {
+                            let window = search_window(&instructions, address);
+                            jump_table::recover(mode, image, &window)
+                        }) {
+                            leaders.extend(&table.targets);
+                            worklist.extend(&table.targets);
+                            jump_tables.insert(address, table);
+                        }
+                        break;
+                    }
+                    InstructionCategory::RET => break,
+                    InstructionCategory::CALL => {
+                        if let Some(target) = direct_target {
+                            call_targets.insert(target);
+                        }
+                    }
+                    _ if is_trap(mnemonic) => break,
+                    _ => {}
+                }
+
+                address = next_address;
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for &leader in &leaders {
+            if !instructions.contains_key(&leader) {
+                continue;
+            }
+
+            let mut block_instructions = vec![];
+            let mut address = leader;
+            let exit = loop {
+                let Some(instruction) = instructions.get(&address) else {
+                    break BlockExit::FallThrough(address);
+                };
+                let next_address = address + u64::from(instruction.length);
+                block_instructions.push((address, instruction.clone()));
+
+                if let Some(table) = jump_tables.get(&address) {
+                    break BlockExit::JumpTable(table.clone());
+                }
+                if let Some(exit) = terminator_exit(instruction, address, next_address)? {
+                    break exit;
+                }
+                if leaders.contains(&next_address) {
+                    break BlockExit::FallThrough(next_address);
+                }
+
+                address = next_address;
+            };
+
+            let block = RecoveredBlock {
+                start_address: leader,
+                instructions: block_instructions,
+                exit,
+            };
+            blocks.insert(leader, block);
+        }
+
+        let cfg = Self {
+            entry: start_address,
+            blocks,
+            call_targets,
+        };
+        Ok(cfg)
+    }
+
+    /// Block addresses in reverse postorder. Blocks come after every block they are reachable
+    /// from, except for blocks of the same loop
+    pub fn reverse_postorder(&self) -> Vec<u64> {
+        let successors = |address: u64| {
+            self.blocks
+                .get(&address)
+                .map(|block| block.exit.successors())
+                .unwrap_or_default()
+                .into_iter()
+        };
+
+        let mut visited = BTreeSet::from([self.entry]);
+        let mut postorder = Vec::with_capacity(self.blocks.len());
+        let mut stack = vec![(self.entry, successors(self.entry))];
+        while let Some((address, successors_left)) = stack.last_mut() {
+            let next = successors_left.find(|successor| {
+                self.blocks.contains_key(successor) && visited.insert(*successor)
+            });
+            match next {
+                Some(successor) => stack.push((successor, successors(successor))),
+                None => {
+                    postorder.push(*address);
+                    stack.pop();
+                }
+            }
+        }
+
+        postorder.reverse();
+        postorder
+    }
+}
+
+/// Stack width the decoder has to be created with for `mode`
+pub fn stack_width_for_mode(mode: MachineMode) -> StackWidth {
+    match mode {
+        MachineMode::LONG_64 => StackWidth::_64,
+        MachineMode::LONG_COMPAT_32 | MachineMode::LEGACY_32 => StackWidth::_32,
+        MachineMode::LONG_COMPAT_16 | MachineMode::LEGACY_16 | MachineMode::REAL_16 => {
+            StackWidth::_16
+        }
+    }
+}
+
+/// Instructions consecutive in memory which end with the one at `address`
+fn search_window(
+    instructions: &BTreeMap<u64, FullInstruction>,
+    address: u64,
+) -> Vec<(u64, FullInstruction)> {
+    let mut window: Vec<(u64, FullInstruction)> = vec![];
+    for (&previous_address, instruction) in instructions.range(..=address).rev() {
+        let is_consecutive = match window.last() {
+            Some((next_address, _)) => {
+                previous_address + u64::from(instruction.length) == *next_address
+            }
+            None => true,
+        };
+        if !is_consecutive
+            || window.len() > jump_table::SEARCH_WINDOW
+            || (!window.is_empty() && jump_table::ends_search_window(instruction))
+        {
+            break;
+        }
+
+        window.push((previous_address, instruction.clone()));
+    }
+
+    window.reverse();
+    window
+}
+
+fn offset_in(code: &[u8], base_address: u64, address: u64) -> Option<usize> {
+    let offset = usize::try_from(address.checked_sub(base_address)?).ok()?;
+    (offset < code.len()).then_some(offset)
+}
+
+fn is_trap(mnemonic: Mnemonic) -> bool {
+    [Mnemonic::HLT, Mnemonic::UD2, Mnemonic::INT3].contains(&mnemonic)
+}
+
+/// Target of a branch or call with a relative immediate operand
+fn direct_branch_target(instruction: &FullInstruction, address: u64) -> Result<Option<u64>> {
+    let Some(operand) = instruction.operands().first() else {
+        return Ok(None);
+    };
+
+    if let DecodedOperandKind::Imm(imm) = &operand.kind {
+        if imm.is_relative {
+            let target = instruction.calc_absolute_address(address, operand)?;
+            return Ok(Some(target));
+        }
+    }
+
+    Ok(None)
+}
+
+fn terminator_exit(
+    instruction: &FullInstruction,
+    address: u64,
+    next_address: u64,
+) -> Result<Option<BlockExit>> {
+    let direct_target = direct_branch_target(instruction, address)?;
+
+    let exit = match instruction.meta.category {
+        InstructionCategory::COND_BR => match direct_target {
+            Some(target) => BlockExit::ConditionalJump {
+                target,
+                fallthrough: next_address,
+            },
+            None => BlockExit::FallThrough(next_address),
+        },
+        InstructionCategory::UNCOND_BR => match direct_target {
+            Some(target) => BlockExit::Jump(target),
+            None => BlockExit::IndirectJump,
+        },
+        InstructionCategory::RET => BlockExit::Return,
+        _ if is_trap(instruction.mnemonic) => BlockExit::Trap,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(exit))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use zydis::MachineMode;
+
+    use super::{BlockExit, ControlFlowGraph};
+    use crate::loader::{ImageFormat, LoadedImage, Section};
+
+    #[test]
+    fn recover_diamond() {
+        // 0x1000: test ecx, ecx
+        // 0x1002: jz 0x1009
+        // 0x1004: mov eax, 1
+        // 0x1009: ret
+        const CODE: [u8; 10] = [0x85, 0xC9, 0x74, 0x05, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3];
+
+        let cfg = ControlFlowGraph::recover(MachineMode::LONG_64, &CODE, 0x1000, 0x1000).unwrap();
+
+        assert_eq!(cfg.blocks.len(), 3);
+        assert_eq!(
+            cfg.blocks[&0x1000].exit,
+            BlockExit::ConditionalJump {
+                target: 0x1009,
+                fallthrough: 0x1004
+            }
+        );
+        assert_eq!(cfg.blocks[&0x1004].exit, BlockExit::FallThrough(0x1009));
+        assert_eq!(cfg.blocks[&0x1009].exit, BlockExit::Return);
+    }
+
+    #[test]
+    fn recover_relative_jump_table() {
+        // 0x1000: cmp edi, 2
+        // 0x1003: ja 0x1018
+        // 0x1005: lea rdx, [rip + 0xFF4] ; 0x2000
+        // 0x100C: movsxd rax, dword ptr [rdx + rdi*4]
+        // 0x1010: add rax, rdx
+        // 0x1013: jmp rax
+        // 0x1015: ret (x4)
+        const CODE: [u8; 25] = [
+            0x83, 0xFF, 0x02, 0x77, 0x13, 0x48, 0x8D, 0x15, 0xF4, 0x0F, 0x00, 0x00, 0x48, 0x63,
+            0x04, 0xBA, 0x48, 0x01, 0xD0, 0xFF, 0xE0, 0xC3, 0xC3, 0xC3, 0xC3,
+        ];
+        // Offsets of 0x1015, 0x1016 and 0x1015 from the table
+        const TABLE: [u8; 12] = [
+            0x15, 0xF0, 0xFF, 0xFF, 0x16, 0xF0, 0xFF, 0xFF, 0x15, 0xF0, 0xFF, 0xFF,
+        ];
+
+        let section = |name: &str, virtual_address, data: &[u8], executable| Section {
+            name: name.to_string(),
+            virtual_address,
+            data: data.to_vec(),
+            executable,
+            writable: false,
+        };
+        let image = LoadedImage {
+            format: ImageFormat::Elf,
+            mode: MachineMode::LONG_64,
+            image_base: 0,
+            entry_point: 0x1000,
+            sections: vec![
+                section(".text", 0x1000, &CODE, true),
+                section(".rodata", 0x2000, &TABLE, false),
+            ],
+            imports: vec![],
+            import_stubs: BTreeMap::new(),
+            relocations: vec![],
+            symbols: BTreeMap::new(),
+        };
+
+        let cfg = ControlFlowGraph::recover_with_image(
+            MachineMode::LONG_64,
+            &CODE,
+            0x1000,
+            0x1000,
+            Some(&image),
+        )
+        .unwrap();
+
+        let BlockExit::JumpTable(table) = &cfg.blocks[&0x1005].exit else {
+            panic!("Jump table wasn't recovered");
+        };
+        assert_eq!(table.load_address, 0x100C);
+        assert_eq!(table.table_address, 0x2000);
+        assert_eq!(table.base, Some(0x2000));
+        assert_eq!(table.targets, vec![0x1015, 0x1016, 0x1015]);
+        assert_eq!(cfg.blocks[&0x1005].exit.successors(), vec![0x1015, 0x1016]);
+        assert_eq!(cfg.blocks[&0x1015].exit, BlockExit::Return);
+    }
+
+    #[test]
+    fn reverse_postorder_puts_loops_before_their_exits() {
+        // 0x1000: push rbx
+        // 0x1001: jmp 0x1005
+        // 0x1003: pop rbx
+        // 0x1004: ret
+        // 0x1005: dec ecx
+        // 0x1007: jnz 0x1005
+        // 0x1009: jmp 0x1003
+        const CODE: [u8; 11] = [
+            0x53, 0xEB, 0x02, 0x5B, 0xC3, 0xFF, 0xC9, 0x75, 0xFC, 0xEB, 0xF8,
+        ];
+
+        let cfg = ControlFlowGraph::recover(MachineMode::LONG_64, &CODE, 0x1000, 0x1000).unwrap();
+
+        assert_eq!(cfg.reverse_postorder(), [0x1000, 0x1005, 0x1009, 0x1003]);
+    }
+}
@@ -4,14 +4,19 @@ use std::collections::HashMap;
 
 use inkwell::{
     context::Context,
-    values::{BasicValue, BasicValueEnum, IntValue},
+    llvm_sys::core::LLVMConstBitCast,
+    types::AsTypeRef,
+    values::{AsValueRef, BasicValue, BasicValueEnum, FloatValue, IntValue},
 };
 use zydis::{MachineMode, Register};
 
+use super::cpu_flag_type;
+use crate::lifter::largest_enclosing_register;
 use crate::miscellaneous::ExtendedRegisterEnum;
 
-/// Trait for defining your CPU context for simulation
-pub(super) trait CpuContext {
+/// Trait for defining your CPU context for simulation. Created variables replace the parameters of
+/// the lifted function, so LLVM can fold them as constants
+pub trait CpuContext {
     fn create_variables(
         self,
         context: &Context,
@@ -20,14 +25,14 @@ pub(super) trait CpuContext {
 }
 
 /// Marker trait for x86 cpu contexts
-pub(super) trait SupportedIntTypesX86 {}
+pub trait SupportedIntTypesX86 {}
 
 impl SupportedIntTypesX86 for u32 {}
 impl SupportedIntTypesX86 for u64 {}
 
 /// Context for x86 CPUs. Accepts both 64 and 32 bit variables
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-pub(super) struct StartContextX86<I: SupportedIntTypesX86> {
+pub struct StartContextX86<I: SupportedIntTypesX86> {
     // General purpose registers
     pub rax: I,
     pub rbx: I,
@@ -49,6 +54,17 @@ pub(super) struct StartContextX86<I: SupportedIntTypesX86> {
     // Instruction pointer (may remove this in future)
     pub rip: I,
 
+    // Segment registers, real mode addresses are computed from them
+    pub cs: u16,
+    pub ds: u16,
+    pub es: u16,
+    pub ss: u16,
+    pub fs: u16,
+    pub gs: u16,
+    // Base addresses of FS and GS, e.g. the TEB
+    pub fs_base: I,
+    pub gs_base: I,
+
     // Flags
     pub cf: u8,
     pub pf: u8,
@@ -67,6 +83,22 @@ pub(super) struct StartContextX86<I: SupportedIntTypesX86> {
     pub vif: u8,
     pub vip: u8,
     pub id: u8,
+
+    // Lower 128 bits of the vector registers
+    pub xmm: [u128; 32],
+    // Bits 128..255 of the vector registers (upper halves of YMM registers)
+    pub ymm_high: [u128; 32],
+    // Bits 256..511 of the vector registers as two 128 bit words, lower first
+    pub zmm_high: [[u128; 2]; 32],
+    // AVX-512 opmask registers
+    pub k: [u64; 8],
+
+    // x87 stack registers ST(0)..ST(7), the 80 bit values are in the low bits
+    pub st: [u128; 8],
+    // x87 control, status and tag words
+    pub fpu_control: u16,
+    pub fpu_status: u16,
+    pub fpu_tag: u16,
 }
 
 impl<I: SupportedIntTypesX86> CpuContext for StartContextX86<I>
@@ -78,9 +110,12 @@ where
         context: &Context,
         mode: MachineMode,
     ) -> HashMap<ExtendedRegisterEnum, BasicValueEnum<'_>> {
-        //let int_type = get_int_type(context, &Register::AX.largest_enclosing(mode), &mode);
-        let int_type =
-            context.custom_width_int_type(Register::AX.largest_enclosing(mode).width(mode).into());
+        //let int_type = get_int_type(context, &largest_enclosing_register(Register::AX, mode), &mode);
+        let int_type = context.custom_width_int_type(
+            largest_enclosing_register(Register::AX, mode)
+                .width(mode)
+                .into(),
+        );
         let mut regs_hashmap: HashMap<ExtendedRegisterEnum, IntValue> = HashMap::new();
 
         let rax = int_type.const_int(self.rax.into(), false);
@@ -101,41 +136,66 @@ where
         let r15 = int_type.const_int(self.r15.into(), false);
         let rip = int_type.const_int(self.rip.into(), false);
 
-        let cf = context.i8_type().const_int(self.cf.into(), false);
-        let pf = context.i8_type().const_int(self.pf.into(), false);
-        let af = context.i8_type().const_int(self.af.into(), false);
-        let zf = context.i8_type().const_int(self.zf.into(), false);
-        let sf = context.i8_type().const_int(self.sf.into(), false);
-        let tf = context.i8_type().const_int(self.tf.into(), false);
-        let r#if = context.i8_type().const_int(self.r#if.into(), false);
-        let df = context.i8_type().const_int(self.df.into(), false);
-        let of = context.i8_type().const_int(self.of.into(), false);
-        let iopl = context.i8_type().const_int(self.iopl.into(), false);
-        let nt = context.i8_type().const_int(self.nt.into(), false);
-        let rf = context.i8_type().const_int(self.rf.into(), false);
-        let vm = context.i8_type().const_int(self.vm.into(), false);
-        let ac = context.i8_type().const_int(self.ac.into(), false);
-        let vif = context.i8_type().const_int(self.vif.into(), false);
-        let vip = context.i8_type().const_int(self.vip.into(), false);
-        let id = context.i8_type().const_int(self.id.into(), false);
-
-        regs_hashmap.insert(Register::AX.largest_enclosing(mode).into(), rax);
-        regs_hashmap.insert(Register::BX.largest_enclosing(mode).into(), rbx);
-        regs_hashmap.insert(Register::CX.largest_enclosing(mode).into(), rcx);
-        regs_hashmap.insert(Register::DX.largest_enclosing(mode).into(), rdx);
-        regs_hashmap.insert(Register::SI.largest_enclosing(mode).into(), rsi);
-        regs_hashmap.insert(Register::DI.largest_enclosing(mode).into(), rdi);
-        regs_hashmap.insert(Register::BP.largest_enclosing(mode).into(), rbp);
-        regs_hashmap.insert(Register::SP.largest_enclosing(mode).into(), rsp);
-        regs_hashmap.insert(Register::R8B.largest_enclosing(mode).into(), r8);
-        regs_hashmap.insert(Register::R9B.largest_enclosing(mode).into(), r9);
-        regs_hashmap.insert(Register::R10B.largest_enclosing(mode).into(), r10);
-        regs_hashmap.insert(Register::R11B.largest_enclosing(mode).into(), r11);
-        regs_hashmap.insert(Register::R12B.largest_enclosing(mode).into(), r12);
-        regs_hashmap.insert(Register::R13B.largest_enclosing(mode).into(), r13);
-        regs_hashmap.insert(Register::R14B.largest_enclosing(mode).into(), r14);
-        regs_hashmap.insert(Register::R15B.largest_enclosing(mode).into(), r15);
-        regs_hashmap.insert(Register::RIP.largest_enclosing(mode).into(), rip);
+        let i16_type = context.i16_type();
+        let segments = [
+            (Register::CS, self.cs),
+            (Register::DS, self.ds),
+            (Register::ES, self.es),
+            (Register::SS, self.ss),
+            (Register::FS, self.fs),
+            (Register::GS, self.gs),
+        ];
+        for (reg, value) in segments {
+            regs_hashmap.insert(reg.into(), i16_type.const_int(value.into(), false));
+        }
+        regs_hashmap.insert(
+            ExtendedRegisterEnum::FSBASE,
+            int_type.const_int(self.fs_base.into(), false),
+        );
+        regs_hashmap.insert(
+            ExtendedRegisterEnum::GSBASE,
+            int_type.const_int(self.gs_base.into(), false),
+        );
+
+        // Flags are stored as i1 by the lifter, IOPL as i2
+        let bool_type = context.bool_type();
+
+        let cf = bool_type.const_int(self.cf.into(), false);
+        let pf = bool_type.const_int(self.pf.into(), false);
+        let af = bool_type.const_int(self.af.into(), false);
+        let zf = bool_type.const_int(self.zf.into(), false);
+        let sf = bool_type.const_int(self.sf.into(), false);
+        let tf = bool_type.const_int(self.tf.into(), false);
+        let r#if = bool_type.const_int(self.r#if.into(), false);
+        let df = bool_type.const_int(self.df.into(), false);
+        let of = bool_type.const_int(self.of.into(), false);
+        let iopl =
+            cpu_flag_type(context, ExtendedRegisterEnum::IOPL).const_int(self.iopl.into(), false);
+        let nt = bool_type.const_int(self.nt.into(), false);
+        let rf = bool_type.const_int(self.rf.into(), false);
+        let vm = bool_type.const_int(self.vm.into(), false);
+        let ac = bool_type.const_int(self.ac.into(), false);
+        let vif = bool_type.const_int(self.vif.into(), false);
+        let vip = bool_type.const_int(self.vip.into(), false);
+        let id = bool_type.const_int(self.id.into(), false);
+
+        regs_hashmap.insert(largest_enclosing_register(Register::AX, mode).into(), rax);
+        regs_hashmap.insert(largest_enclosing_register(Register::BX, mode).into(), rbx);
+        regs_hashmap.insert(largest_enclosing_register(Register::CX, mode).into(), rcx);
+        regs_hashmap.insert(largest_enclosing_register(Register::DX, mode).into(), rdx);
+        regs_hashmap.insert(largest_enclosing_register(Register::SI, mode).into(), rsi);
+        regs_hashmap.insert(largest_enclosing_register(Register::DI, mode).into(), rdi);
+        regs_hashmap.insert(largest_enclosing_register(Register::BP, mode).into(), rbp);
+        regs_hashmap.insert(largest_enclosing_register(Register::SP, mode).into(), rsp);
+        regs_hashmap.insert(largest_enclosing_register(Register::R8B, mode).into(), r8);
+        regs_hashmap.insert(largest_enclosing_register(Register::R9B, mode).into(), r9);
+        regs_hashmap.insert(largest_enclosing_register(Register::R10B, mode).into(), r10);
+        regs_hashmap.insert(largest_enclosing_register(Register::R11B, mode).into(), r11);
+        regs_hashmap.insert(largest_enclosing_register(Register::R12B, mode).into(), r12);
+        regs_hashmap.insert(largest_enclosing_register(Register::R13B, mode).into(), r13);
+        regs_hashmap.insert(largest_enclosing_register(Register::R14B, mode).into(), r14);
+        regs_hashmap.insert(largest_enclosing_register(Register::R15B, mode).into(), r15);
+        regs_hashmap.insert(largest_enclosing_register(Register::RIP, mode).into(), rip);
 
         regs_hashmap.insert(ExtendedRegisterEnum::CF, cf);
         regs_hashmap.insert(ExtendedRegisterEnum::PF, pf);
@@ -155,23 +215,81 @@ where
         regs_hashmap.insert(ExtendedRegisterEnum::VIP, vip);
         regs_hashmap.insert(ExtendedRegisterEnum::ID, id);
 
+        let i128_type = context.i128_type();
+        let vector_regs = crate::compiler::VECTOR_REGS
+            .into_iter()
+            .zip(self.xmm)
+            .chain(
+                crate::compiler::VECTOR_HIGH_REGS
+                    .into_iter()
+                    .zip(self.ymm_high),
+            );
+        for (reg, value) in vector_regs {
+            let words = [value as u64, (value >> 64) as u64];
+            regs_hashmap.insert(reg.into(), i128_type.const_int_arbitrary_precision(&words));
+        }
+
+        let i256_type = context.custom_width_int_type(256);
+        for (reg, [low, high]) in crate::compiler::ZMM_HIGH_REGS
+            .into_iter()
+            .zip(self.zmm_high)
+        {
+            let words = [
+                low as u64,
+                (low >> 64) as u64,
+                high as u64,
+                (high >> 64) as u64,
+            ];
+            regs_hashmap.insert(reg.into(), i256_type.const_int_arbitrary_precision(&words));
+        }
+
+        let i64_type = context.i64_type();
+        for (reg, value) in crate::compiler::MASK_REGS.into_iter().zip(self.k) {
+            regs_hashmap.insert(reg.into(), i64_type.const_int(value, false));
+        }
+
+        let x87_words = [self.fpu_control, self.fpu_status, self.fpu_tag];
+        for (word, value) in crate::compiler::X87_WORDS.into_iter().zip(x87_words) {
+            regs_hashmap.insert(word, i16_type.const_int(value.into(), false));
+        }
+
         let mut resulting_hashmap: HashMap<ExtendedRegisterEnum, BasicValueEnum<'_>> =
             HashMap::new();
         for (k, v) in regs_hashmap {
             resulting_hashmap.insert(k, v.as_basic_value_enum());
         }
+        for (reg, value) in crate::compiler::X87_REGS.into_iter().zip(self.st) {
+            resulting_hashmap.insert(reg.into(), x87_constant(context, value).into());
+        }
 
         resulting_hashmap
     }
 }
 
+/// x86_fp80 constant with the bits of `value`
+fn x87_constant(context: &Context, value: u128) -> FloatValue<'_> {
+    let words = [value as u64, (value >> 64) as u64 & 0xFFFF];
+    let bits = context
+        .custom_width_int_type(80)
+        .const_int_arbitrary_precision(&words);
+    // SAFETY: i80 and x86_fp80 have the same size, so the cast is a valid constant expression
+    unsafe {
+        FloatValue::new(LLVMConstBitCast(
+            bits.as_value_ref(),
+            context.x86_f80_type().as_type_ref(),
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use inkwell::context::Context;
-    use zydis::MachineMode;
+    use zydis::{Decoder, FullInstruction, MachineMode};
 
     use super::{CpuContext, StartContextX86};
+    use crate::compiler::{error::Error, Compiler};
+    use crate::miscellaneous::ExtendedRegisterEnum;
 
     #[test]
     fn create_sample_context() {
@@ -186,7 +304,55 @@ mod tests {
         assert_eq!(1110, x86_ctx.rbx + x86_ctx.rcx);
 
         let vars = x86_ctx.create_variables(&context, MachineMode::LONG_64);
+        assert_eq!(
+            vars[&ExtendedRegisterEnum::CF].get_type(),
+            context.bool_type().into()
+        );
 
         dbg!(vars);
     }
+
+    #[test]
+    fn iopl_keeps_both_bits() {
+        let context = Context::create();
+        let x86_ctx: StartContextX86<u64> = StartContextX86 {
+            iopl: 3,
+            ..Default::default()
+        };
+
+        let vars = x86_ctx.create_variables(&context, MachineMode::LONG_64);
+        let iopl = vars[&ExtendedRegisterEnum::IOPL].into_int_value();
+        assert_eq!(iopl.get_type().get_bit_width(), 2);
+        assert_eq!(iopl.get_zero_extended_constant(), Some(3));
+    }
+
+    #[test]
+    fn x87_registers_keep_their_bits() {
+        let context = Context::create();
+        let mut x86_ctx: StartContextX86<u64> = StartContextX86 {
+            fpu_control: 0x37F,
+            ..Default::default()
+        };
+        // 1.5, the integer bit of the significand is explicit
+        x86_ctx.st[2] = 0x3FFF_C000_0000_0000_0000;
+
+        let vars = x86_ctx.create_variables(&context, MachineMode::LONG_64);
+        let st2 = vars[&ExtendedRegisterEnum::ST2].into_float_value();
+        assert_eq!(st2.get_type(), context.x86_f80_type());
+        assert_eq!(st2.get_constant(), Some((1.5, false)));
+        let control = vars[&ExtendedRegisterEnum::X87CONTROL].into_int_value();
+        assert_eq!(control.get_zero_extended_constant(), Some(0x37F));
+    }
+
+    #[test]
+    fn start_context_is_rejected_after_lifting() {
+        let context = Context::create();
+        let compiler = Compiler::new_with_x86_lifter(&context, MachineMode::LONG_64, None).unwrap();
+        let nop: FullInstruction = Decoder::new64().decode_first(&[0x90]).unwrap().unwrap();
+
+        compiler.lift_function(&vec![nop], false).unwrap();
+
+        let result = compiler.set_start_context(StartContextX86::<u64>::default());
+        assert!(matches!(result, Err(Error::StartContextAfterLifting)));
+    }
 }
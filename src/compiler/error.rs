@@ -1,6 +1,8 @@
 use inkwell::{builder::BuilderError, support::LLVMString};
 use thiserror::Error;
 
+use super::report::LiftReport;
+
 pub(crate) type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -15,4 +17,43 @@ pub enum Error {
 
     #[error("An error occured while running optimizations: {0}")]
     OptimizationsError(LLVMString),
+
+    #[error(transparent)]
+    Zydis(#[from] zydis::Status),
+
+    #[error("Address {0:#x} is outside of the provided code")]
+    AddressOutOfRange(u64),
+
+    /// Lifting stopped at an instruction which isn't just unsupported. The report ends with it
+    #[error(
+        "Lifting failed: {}",
+        .0.failure()
+            .and_then(|record| record.error.as_ref())
+            .map_or_else(String::new, ToString::to_string)
+    )]
+    LiftingFailed(Box<LiftReport>),
+
+    #[error("Start context has to be set before lifting")]
+    StartContextAfterLifting,
+
+    #[error("Image is already loaded")]
+    ImageAlreadyLoaded,
+
+    #[error("No image is loaded")]
+    NoImageLoaded,
+
+    #[error("Image has no symbol named {0}")]
+    UnknownSymbol(String),
+
+    #[error("Only functions lifted with the state ABI can be executed")]
+    NotAStateFunction,
+
+    #[error("Unable to create execution engine: {0}")]
+    UnableToCreateExecutionEngine(String),
+
+    #[error("Lifted module uses {0}, which can't be provided when executing it")]
+    UnresolvedFunction(String),
+
+    #[error("State field {0:?} doesn't fit into the start context")]
+    StateFieldMismatch(crate::miscellaneous::ExtendedRegisterEnum),
 }
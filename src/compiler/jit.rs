@@ -0,0 +1,626 @@
+//! Execution of lifted functions with LLVM's JIT, for checking the lifted semantics.
+//!
+//! Only functions lifted with the state ABI ([Compiler::new_state_function]) can be executed. With
+//! [CallbackMemory] the memory callbacks read and write a [MemoryImage], other memory models keep
+//! memory to themselves. Calls to [CALL_HOOK] or [JUMP_HOOK] are recorded without changing the
+//! state:
+//!
+//! ```ignore
+//! let compiler = Compiler::new_state_function(&context, mode, address, Box::new(CallbackMemory))?;
+//! compiler.lift_function(&instructions, false)?;
+//!
+//! let jit = compiler.create_jit()?;
+//! let execution = jit.run(start_context, MemoryImage::default())?;
+//! assert_eq!(execution.context.rax, 3);
+//! ```
+use super::contexts::{StartContextX86, SupportedIntTypesX86};
+use super::error::Error;
+use super::{Compiler, Result};
+use crate::lifter::memory::CallbackMemory;
+use crate::lifter::state::{state_layout, state_parameter, CALL_HOOK, JUMP_HOOK};
+use crate::loader::LoadedImage;
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use inkwell::execution_engine::ExecutionEngine;
+use inkwell::targets::{InitializationConfig, Target};
+use inkwell::types::BasicTypeEnum;
+use inkwell::OptimizationLevel;
+use zydis::{MachineMode, Register, RegisterClass};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Sparse guest memory. Bytes which were never written read as zero
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryImage {
+    pages: BTreeMap<u64, Box<[u8; PAGE_SIZE as usize]>>,
+}
+
+impl MemoryImage {
+    /// Memory with the sections of `image` mapped at their virtual addresses
+    pub fn from_image(image: &LoadedImage) -> Self {
+        let mut memory = Self::default();
+        for section in &image.sections {
+            memory.write(section.virtual_address, &section.data);
+        }
+        memory
+    }
+
+    pub fn write(&mut self, address: u64, bytes: &[u8]) {
+        for (offset, byte) in bytes.iter().enumerate() {
+            let address = address.wrapping_add(offset as u64);
+            let page = self
+                .pages
+                .entry(address / PAGE_SIZE)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE as usize]));
+            page[(address % PAGE_SIZE) as usize] = *byte;
+        }
+    }
+
+    pub fn read(&self, address: u64, size: usize) -> Vec<u8> {
+        (0..size as u64)
+            .map(|offset| {
+                let address = address.wrapping_add(offset);
+                self.pages
+                    .get(&(address / PAGE_SIZE))
+                    .map_or(0, |page| page[(address % PAGE_SIZE) as usize])
+            })
+            .collect()
+    }
+
+    pub fn read_u64(&self, address: u64) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.read(address, 8));
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn write_u64(&mut self, address: u64, value: u64) {
+        self.write(address, &value.to_le_bytes());
+    }
+
+    /// Addresses of the pages which were written to, in ascending order
+    pub fn pages(&self) -> impl Iterator<Item = u64> + '_ {
+        self.pages.keys().map(|page| page * PAGE_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Call,
+    Jump,
+}
+
+/// Call of [CALL_HOOK] or [JUMP_HOOK] made by the executed function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookCall {
+    pub hook: Hook,
+    pub target: u64,
+}
+
+/// State left by the executed function
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution<I: SupportedIntTypesX86> {
+    pub context: StartContextX86<I>,
+    pub memory: MemoryImage,
+    pub hook_calls: Vec<HookCall>,
+}
+
+/// Memory and hook calls of the function running on this thread
+struct Running {
+    memory: MemoryImage,
+    hook_calls: Vec<HookCall>,
+}
+
+thread_local! {
+    static RUNNING: RefCell<Option<Running>> = const { RefCell::new(None) };
+}
+
+/// Field of the state struct
+#[derive(Debug, Clone, Copy)]
+struct StateField {
+    register: ExtendedRegisterEnum,
+    offset: usize,
+    size: usize,
+    bits: u32,
+}
+
+/// JIT compiled state ABI function
+pub struct Jit<'ctx> {
+    // Owns the compiled code
+    _execution_engine: ExecutionEngine<'ctx>,
+    function: unsafe extern "C" fn(*mut u8),
+    fields: Vec<StateField>,
+    state_size: usize,
+}
+
+impl<'ctx> Compiler<'ctx> {
+    /// Compiles the lifted module. The module can't be compiled twice, so this must be called
+    /// once and after lifting
+    pub fn create_jit(&self) -> Result<Jit<'ctx>> {
+        if state_parameter(self.func_value).is_none() {
+            return Err(Error::NotAStateFunction);
+        }
+
+        Target::initialize_native(&InitializationConfig::default())
+            .map_err(Error::UnableToCreateExecutionEngine)?;
+        let execution_engine = self
+            .lifter
+            .module
+            .create_jit_execution_engine(OptimizationLevel::None)
+            .map_err(|error| Error::UnableToCreateExecutionEngine(error.to_string()))?;
+
+        for function in self.lifter.module.get_functions() {
+            if function.count_basic_blocks() > 0 {
+                continue;
+            }
+            let name = function.get_name().to_string_lossy();
+            if name.starts_with("llvm.") {
+                continue;
+            }
+
+            let address = external_function(&name)
+                .ok_or_else(|| Error::UnresolvedFunction(name.to_string()))?;
+            execution_engine.add_global_mapping(&function, address);
+        }
+
+        let name = self.func_value.get_name().to_string_lossy();
+        let address = execution_engine
+            .get_function_address(&name)
+            .map_err(|_| Error::UnresolvedFunction(name.to_string()))?;
+        // SAFETY: state ABI functions have the type `void (ptr)`
+        let function =
+            unsafe { std::mem::transmute::<usize, unsafe extern "C" fn(*mut u8)>(address) };
+
+        let state_type = self.lifter.state_type();
+        let target_data = execution_engine.get_target_data();
+        let fields = state_layout(self.lifter.context, self.lifter.mode)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (register, ty))| StateField {
+                register,
+                offset: target_data
+                    .offset_of_element(&state_type, index as u32)
+                    .expect("Field index is in range") as usize,
+                size: target_data.get_store_size(&ty) as usize,
+                bits: match ty {
+                    BasicTypeEnum::IntType(ty) => ty.get_bit_width(),
+                    // x86_fp80 is the only float in the state
+                    _ => 80,
+                },
+            })
+            .collect();
+        let state_size = target_data.get_abi_size(&state_type) as usize;
+
+        let jit = Jit {
+            _execution_engine: execution_engine,
+            function,
+            fields,
+            state_size,
+        };
+        Ok(jit)
+    }
+}
+
+impl Jit<'_> {
+    /// Runs the function with the registers of `start` and `memory`
+    pub fn run<I>(&self, start: StartContextX86<I>, memory: MemoryImage) -> Result<Execution<I>>
+    where
+        I: SupportedIntTypesX86 + TryFrom<u64> + Copy,
+        u64: From<I>,
+    {
+        // u128 elements keep the state aligned like LLVM expects
+        let mut state = vec![0u128; self.state_size.div_ceil(16)];
+        let bytes = state_bytes(&mut state);
+        let mut context = start;
+        for field in &self.fields {
+            let value = read_context_field(&mut context, field.register)?
+                .truncate(field.bits)
+                .to_le_bytes();
+            let size = field.size.min(value.len());
+            bytes[field.offset..field.offset + size].copy_from_slice(&value[..size]);
+        }
+
+        let running = Running {
+            memory,
+            hook_calls: Vec::new(),
+        };
+        RUNNING.with(|current| *current.borrow_mut() = Some(running));
+        // SAFETY: the state buffer has the layout of the state struct
+        unsafe { (self.function)(state.as_mut_ptr().cast()) };
+        let running = RUNNING
+            .with(|current| current.borrow_mut().take())
+            .expect("Execution state is set while running");
+
+        let bytes = state_bytes(&mut state);
+        for field in &self.fields {
+            let mut value = [0; 32];
+            let size = field.size.min(value.len());
+            value[..size].copy_from_slice(&bytes[field.offset..field.offset + size]);
+            let value = StateValue::from_le_bytes(value).truncate(field.bits);
+            write_context_field(&mut context, field.register, value)?;
+        }
+
+        Ok(Execution {
+            context,
+            memory: running.memory,
+            hook_calls: running.hook_calls,
+        })
+    }
+}
+
+fn state_bytes(state: &mut [u128]) -> &mut [u8] {
+    // SAFETY: any bytes are valid u128 values
+    unsafe { std::slice::from_raw_parts_mut(state.as_mut_ptr().cast(), state.len() * 16) }
+}
+
+/// Value of a state field, up to 256 bits
+#[derive(Debug, Clone, Copy, Default)]
+struct StateValue([u128; 2]);
+
+impl StateValue {
+    fn to_le_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        bytes[..16].copy_from_slice(&self.0[0].to_le_bytes());
+        bytes[16..].copy_from_slice(&self.0[1].to_le_bytes());
+        bytes
+    }
+
+    fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let word = |range: std::ops::Range<usize>| {
+            u128::from_le_bytes(bytes[range].try_into().expect("Range is 16 bytes long"))
+        };
+        Self([word(0..16), word(16..32)])
+    }
+
+    fn low_u64(self) -> u64 {
+        self.0[0] as u64
+    }
+
+    /// Keeps the lowest `bits` bits, as LLVM only defines those for narrow integers
+    fn truncate(self, bits: u32) -> Self {
+        let mask = |bits: u32| match bits {
+            0 => 0,
+            1..=127 => (1 << bits) - 1,
+            _ => u128::MAX,
+        };
+        Self([
+            self.0[0] & mask(bits),
+            self.0[1] & mask(bits.saturating_sub(128)),
+        ])
+    }
+}
+
+impl From<u64> for StateValue {
+    fn from(value: u64) -> Self {
+        Self([value.into(), 0])
+    }
+}
+
+impl From<u128> for StateValue {
+    fn from(value: u128) -> Self {
+        Self([value, 0])
+    }
+}
+
+/// Where a state field is kept in a [StartContextX86]
+enum ContextField<'a, I> {
+    Gpr(&'a mut I),
+    Segment(&'a mut u16),
+    Flag(&'a mut u8),
+    Vector(&'a mut u128),
+    ZmmHigh(&'a mut [u128; 2]),
+    Mask(&'a mut u64),
+    X87(&'a mut u128),
+    X87Word(&'a mut u16),
+    /// RFLAGS isn't filled from the separate flags
+    Ignored,
+}
+
+/// Field of `context` for the state field of `register`, see `state_layout`
+fn context_field<I: SupportedIntTypesX86>(
+    context: &mut StartContextX86<I>,
+    register: ExtendedRegisterEnum,
+) -> Option<ContextField<'_, I>> {
+    use ContextField::*;
+
+    let c = context;
+    let field = match register {
+        ExtendedRegisterEnum::CF => Flag(&mut c.cf),
+        ExtendedRegisterEnum::PF => Flag(&mut c.pf),
+        ExtendedRegisterEnum::AF => Flag(&mut c.af),
+        ExtendedRegisterEnum::ZF => Flag(&mut c.zf),
+        ExtendedRegisterEnum::SF => Flag(&mut c.sf),
+        ExtendedRegisterEnum::TF => Flag(&mut c.tf),
+        ExtendedRegisterEnum::IF => Flag(&mut c.r#if),
+        ExtendedRegisterEnum::DF => Flag(&mut c.df),
+        ExtendedRegisterEnum::OF => Flag(&mut c.of),
+        ExtendedRegisterEnum::IOPL => Flag(&mut c.iopl),
+        ExtendedRegisterEnum::NT => Flag(&mut c.nt),
+        ExtendedRegisterEnum::RF => Flag(&mut c.rf),
+        ExtendedRegisterEnum::VM => Flag(&mut c.vm),
+        ExtendedRegisterEnum::AC => Flag(&mut c.ac),
+        ExtendedRegisterEnum::VIF => Flag(&mut c.vif),
+        ExtendedRegisterEnum::VIP => Flag(&mut c.vip),
+        ExtendedRegisterEnum::ID => Flag(&mut c.id),
+        ExtendedRegisterEnum::RFLAGS => Ignored,
+        ExtendedRegisterEnum::FSBASE => Gpr(&mut c.fs_base),
+        ExtendedRegisterEnum::GSBASE => Gpr(&mut c.gs_base),
+        ExtendedRegisterEnum::X87CONTROL => X87Word(&mut c.fpu_control),
+        ExtendedRegisterEnum::X87STATUS => X87Word(&mut c.fpu_status),
+        ExtendedRegisterEnum::X87TAG => X87Word(&mut c.fpu_tag),
+        _ => {
+            let register = Register::from(register);
+            let id = register.id() as usize;
+            match register.class() {
+                RegisterClass::GPR8
+                | RegisterClass::GPR16
+                | RegisterClass::GPR32
+                | RegisterClass::GPR64
+                | RegisterClass::IP => match register.largest_enclosing(MachineMode::LONG_64) {
+                    Register::RAX => Gpr(&mut c.rax),
+                    Register::RBX => Gpr(&mut c.rbx),
+                    Register::RCX => Gpr(&mut c.rcx),
+                    Register::RDX => Gpr(&mut c.rdx),
+                    Register::RSI => Gpr(&mut c.rsi),
+                    Register::RDI => Gpr(&mut c.rdi),
+                    Register::RSP => Gpr(&mut c.rsp),
+                    Register::RBP => Gpr(&mut c.rbp),
+                    Register::R8 => Gpr(&mut c.r8),
+                    Register::R9 => Gpr(&mut c.r9),
+                    Register::R10 => Gpr(&mut c.r10),
+                    Register::R11 => Gpr(&mut c.r11),
+                    Register::R12 => Gpr(&mut c.r12),
+                    Register::R13 => Gpr(&mut c.r13),
+                    Register::R14 => Gpr(&mut c.r14),
+                    Register::R15 => Gpr(&mut c.r15),
+                    Register::RIP => Gpr(&mut c.rip),
+                    _ => return None,
+                },
+                RegisterClass::SEGMENT => match register {
+                    Register::CS => Segment(&mut c.cs),
+                    Register::DS => Segment(&mut c.ds),
+                    Register::ES => Segment(&mut c.es),
+                    Register::SS => Segment(&mut c.ss),
+                    Register::FS => Segment(&mut c.fs),
+                    Register::GS => Segment(&mut c.gs),
+                    _ => return None,
+                },
+                RegisterClass::XMM => Vector(c.xmm.get_mut(id)?),
+                RegisterClass::YMM => Vector(c.ymm_high.get_mut(id)?),
+                RegisterClass::ZMM => ZmmHigh(c.zmm_high.get_mut(id)?),
+                RegisterClass::MASK => Mask(c.k.get_mut(id)?),
+                RegisterClass::X87 => X87(c.st.get_mut(id)?),
+                _ => return None,
+            }
+        }
+    };
+    Some(field)
+}
+
+/// Value of the state field of `register`
+fn read_context_field<I>(
+    context: &mut StartContextX86<I>,
+    register: ExtendedRegisterEnum,
+) -> Result<StateValue>
+where
+    I: SupportedIntTypesX86 + Copy,
+    u64: From<I>,
+{
+    let field = context_field(context, register).ok_or(Error::StateFieldMismatch(register))?;
+    let value = match field {
+        ContextField::Gpr(value) => u64::from(*value).into(),
+        ContextField::Segment(value) => StateValue([(*value).into(), 0]),
+        ContextField::Flag(value) => StateValue([(*value).into(), 0]),
+        ContextField::Vector(value) => (*value).into(),
+        ContextField::ZmmHigh(value) => StateValue(*value),
+        ContextField::Mask(value) => (*value).into(),
+        ContextField::X87(value) => (*value).into(),
+        ContextField::X87Word(value) => StateValue([(*value).into(), 0]),
+        ContextField::Ignored => StateValue::default(),
+    };
+    Ok(value)
+}
+
+/// Inverse of [read_context_field]. `value` must already be truncated to the field's width
+fn write_context_field<I>(
+    context: &mut StartContextX86<I>,
+    register: ExtendedRegisterEnum,
+    value: StateValue,
+) -> Result<()>
+where
+    I: SupportedIntTypesX86 + TryFrom<u64>,
+{
+    let field = context_field(context, register).ok_or(Error::StateFieldMismatch(register))?;
+    match field {
+        ContextField::Gpr(field) => {
+            *field = I::try_from(value.low_u64())
+                .ok()
+                .ok_or(Error::StateFieldMismatch(register))?;
+        }
+        ContextField::Segment(field) => *field = value.low_u64() as u16,
+        ContextField::Flag(field) => *field = value.low_u64() as u8,
+        ContextField::Vector(field) => *field = value.0[0],
+        ContextField::ZmmHigh(field) => *field = value.0,
+        ContextField::Mask(field) => *field = value.low_u64(),
+        ContextField::X87(field) => *field = value.0[0],
+        ContextField::X87Word(field) => *field = value.low_u64() as u16,
+        ContextField::Ignored => {}
+    }
+    Ok(())
+}
+
+/// Host function for an external declaration of the lifted module
+fn external_function(name: &str) -> Option<usize> {
+    if let Some(width) = name.strip_prefix(CallbackMemory::READ_PREFIX) {
+        let address = match width {
+            "8" => read_memory_8 as *const () as usize,
+            "16" => read_memory_16 as *const () as usize,
+            "32" => read_memory_32 as *const () as usize,
+            "64" => read_memory_64 as *const () as usize,
+            "128" => read_memory_128 as *const () as usize,
+            _ => return None,
+        };
+        return Some(address);
+    }
+    if let Some(width) = name.strip_prefix(CallbackMemory::WRITE_PREFIX) {
+        let address = match width {
+            "8" => write_memory_8 as *const () as usize,
+            "16" => write_memory_16 as *const () as usize,
+            "32" => write_memory_32 as *const () as usize,
+            "64" => write_memory_64 as *const () as usize,
+            "128" => write_memory_128 as *const () as usize,
+            _ => return None,
+        };
+        return Some(address);
+    }
+
+    match name {
+        CALL_HOOK => Some(call_hook as *const () as usize),
+        JUMP_HOOK => Some(jump_hook as *const () as usize),
+        _ => None,
+    }
+}
+
+// The callbacks must not unwind into the lifted code, so they do nothing when no function is
+// running instead of panicking
+
+fn read_memory(address: u64, size: usize) -> u128 {
+    RUNNING.with(|running| {
+        let Some(running) = running
+            .borrow()
+            .as_ref()
+            .map(|running| running.memory.read(address, size))
+        else {
+            return 0;
+        };
+        let mut bytes = [0; 16];
+        bytes[..size].copy_from_slice(&running);
+        u128::from_le_bytes(bytes)
+    })
+}
+
+fn write_memory(address: u64, value: u128, size: usize) {
+    RUNNING.with(|running| {
+        if let Some(running) = running.borrow_mut().as_mut() {
+            running.memory.write(address, &value.to_le_bytes()[..size]);
+        }
+    });
+}
+
+fn record_hook_call(hook: Hook, target: u64) {
+    RUNNING.with(|running| {
+        if let Some(running) = running.borrow_mut().as_mut() {
+            running.hook_calls.push(HookCall { hook, target });
+        }
+    });
+}
+
+extern "C" fn read_memory_8(address: u64) -> u8 {
+    read_memory(address, 1) as u8
+}
+
+extern "C" fn read_memory_16(address: u64) -> u16 {
+    read_memory(address, 2) as u16
+}
+
+extern "C" fn read_memory_32(address: u64) -> u32 {
+    read_memory(address, 4) as u32
+}
+
+extern "C" fn read_memory_64(address: u64) -> u64 {
+    read_memory(address, 8) as u64
+}
+
+extern "C" fn read_memory_128(address: u64) -> u128 {
+    read_memory(address, 16)
+}
+
+extern "C" fn write_memory_8(address: u64, value: u8) {
+    write_memory(address, value.into(), 1);
+}
+
+extern "C" fn write_memory_16(address: u64, value: u16) {
+    write_memory(address, value.into(), 2);
+}
+
+extern "C" fn write_memory_32(address: u64, value: u32) {
+    write_memory(address, value.into(), 4);
+}
+
+extern "C" fn write_memory_64(address: u64, value: u64) {
+    write_memory(address, value.into(), 8);
+}
+
+extern "C" fn write_memory_128(address: u64, value: u128) {
+    write_memory(address, value, 16);
+}
+
+extern "C" fn call_hook(target: u64, _state: *mut u8) {
+    record_hook_call(Hook::Call, target);
+}
+
+extern "C" fn jump_hook(target: u64, _state: *mut u8) {
+    record_hook_call(Hook::Jump, target);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        read_context_field, write_context_field, MachineMode, MemoryImage, StartContextX86,
+    };
+    use crate::lifter::state::state_layout;
+
+    use inkwell::context::Context;
+
+    #[test]
+    fn memory_image_crosses_pages() {
+        let mut memory = MemoryImage::default();
+        memory.write_u64(0x1ffc, 0x1122_3344_5566_7788);
+
+        assert_eq!(memory.read_u64(0x1ffc), 0x1122_3344_5566_7788);
+        assert_eq!(memory.read(0x2004, 4), [0; 4]);
+        assert_eq!(memory.pages().collect::<Vec<_>>(), [0x1000, 0x2000]);
+    }
+
+    #[test]
+    fn state_fields_round_trip() {
+        let context = Context::create();
+        let mut start: StartContextX86<u64> = StartContextX86 {
+            rax: 1,
+            r15: 2,
+            rip: 0x1000,
+            zf: 1,
+            iopl: 3,
+            gs_base: 0x7ff0_0000,
+            ds: 0x1234,
+            ..Default::default()
+        };
+        start.ymm_high[1] = 6;
+        start.zmm_high[31] = [3, 4];
+        start.k[7] = 5;
+        start.st[3] = 0x3FFF_C000_0000_0000_0000;
+        start.fpu_tag = 0xFFFF;
+
+        let mut restored = StartContextX86::default();
+        for (register, _) in state_layout(&context, MachineMode::LONG_64) {
+            let value = read_context_field(&mut start, register).unwrap();
+            write_context_field(&mut restored, register, value).unwrap();
+        }
+        assert_eq!(restored, start);
+    }
+
+    #[test]
+    fn every_32_bit_state_field_has_a_register() {
+        let context = Context::create();
+        let mut start: StartContextX86<u32> = StartContextX86::default();
+
+        for (register, _) in state_layout(&context, MachineMode::LEGACY_32) {
+            assert!(
+                read_context_field(&mut start, register).is_ok(),
+                "{register:?}"
+            );
+        }
+    }
+}
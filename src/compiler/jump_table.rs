@@ -0,0 +1,291 @@
+//! Recovery of switch jump tables from the instructions leading to an indirect jump.
+//!
+//! Recognised forms, where the index is bounded by a `cmp index, N` followed by `ja`/`jae`:
+//!
+//! ```text
+//! jmp [table + index*8]                   ; absolute entries
+//!
+//! mov reg, [table + index*8]              ; absolute entries through a register
+//! jmp reg
+//!
+//! lea base, [rip + table]                 ; GCC/Clang, signed offsets from the table
+//! movsxd reg, dword [base + index*4]
+//! add reg, base
+//! jmp reg
+//!
+//! lea base, [rip + __ImageBase]           ; MSVC, offsets from the image base
+//! mov reg32, dword [base + index*4 + table_rva]
+//! add reg, base
+//! jmp reg
+//! ```
+use crate::loader::LoadedImage;
+
+use zydis::{
+    ffi::{DecodedOperandKind, MemoryInfo},
+    FullInstruction, InstructionCategory, MachineMode, Mnemonic, OperandAction, Register,
+};
+
+/// Instructions before the jump which are searched for the table access and the bound check
+pub(super) const SEARCH_WINDOW: usize = 24;
+
+/// Tables larger than this are assumed to be misrecognised
+const MAX_ENTRIES: u64 = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    /// Address of the indirect jump
+    pub jump_address: u64,
+    /// Address of the instruction reading the entry. The lifted switch is on the value its index
+    /// register has right before it
+    pub load_address: u64,
+    pub index: Register,
+    /// Address of the first entry
+    pub table_address: u64,
+    /// Entry size in bytes
+    pub entry_size: u8,
+    /// Entries are offsets from this address instead of absolute targets
+    pub base: Option<u64>,
+    /// Jump targets by index
+    pub targets: Vec<u64>,
+}
+
+/// Entry read by the recognised table access
+struct TableAccess<'a> {
+    load_address: u64,
+    memory: &'a MemoryInfo,
+    entry_size: u8,
+    sign_extended: bool,
+    base: Option<u64>,
+    /// Instructions before the load
+    preceding: &'a [(u64, FullInstruction)],
+}
+
+/// Recovers the table used by the indirect jump which ends `instructions`. They have to be
+/// consecutive in memory, see [SEARCH_WINDOW]
+pub fn recover(
+    mode: MachineMode,
+    image: &LoadedImage,
+    instructions: &[(u64, FullInstruction)],
+) -> Option<JumpTable> {
+    let ((jump_address, jump), preceding) = instructions.split_last()?;
+    let target = jump.operands().first()?;
+
+    let access = match &target.kind {
+        DecodedOperandKind::Mem(memory) => TableAccess {
+            load_address: *jump_address,
+            memory,
+            entry_size: (target.size / 8) as u8,
+            sign_extended: false,
+            base: None,
+            preceding,
+        },
+        DecodedOperandKind::Reg(register) => register_table_access(mode, *register, preceding)?,
+        _ => return None,
+    };
+
+    let memory = access.memory;
+    if memory.index == Register::NONE {
+        return None;
+    }
+    let table_base = match memory.base {
+        Register::NONE => 0,
+        base => constant_value(mode, base, access.preceding)?,
+    };
+    let table_address = table_base.wrapping_add(memory.disp.displacement as u64);
+
+    let count = index_bound(mode, memory.index, access.preceding)?;
+    if count == 0 || count > MAX_ENTRIES {
+        return None;
+    }
+
+    let stride = u64::from(memory.scale.max(1));
+    let targets = (0..count)
+        .map(|index| {
+            let entry_address = table_address.wrapping_add(index * stride);
+            let entry = read_entry(
+                image,
+                entry_address,
+                access.entry_size,
+                access.sign_extended,
+            )?;
+            let target = access.base.unwrap_or(0).wrapping_add(entry);
+            let section = image.section_containing(target)?;
+            section.executable.then_some(target)
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let table = JumpTable {
+        jump_address: *jump_address,
+        load_address: access.load_address,
+        index: memory.index,
+        table_address,
+        entry_size: access.entry_size,
+        base: access.base,
+        targets,
+    };
+    Some(table)
+}
+
+/// Table access of `jmp reg`, either the load of an absolute entry or the addition of a base to
+/// a loaded offset
+fn register_table_access(
+    mode: MachineMode,
+    register: Register,
+    preceding: &[(u64, FullInstruction)],
+) -> Option<TableAccess<'_>> {
+    let definition = last_write(mode, register, preceding)?;
+    let (_, instruction) = &preceding[definition];
+    let operands = instruction.operands();
+
+    match instruction.mnemonic {
+        Mnemonic::MOV | Mnemonic::MOVSXD => load_access(preceding, definition, None),
+        Mnemonic::ADD => {
+            let DecodedOperandKind::Reg(base_register) = operands[1].kind else {
+                return None;
+            };
+            let base = constant_value(mode, base_register, &preceding[..definition])?;
+
+            let offset_definition = last_write(mode, register, &preceding[..definition])?;
+            load_access(preceding, offset_definition, Some(base))
+        }
+        _ => None,
+    }
+}
+
+fn load_access(
+    preceding: &[(u64, FullInstruction)],
+    index: usize,
+    base: Option<u64>,
+) -> Option<TableAccess<'_>> {
+    let (load_address, instruction) = &preceding[index];
+    if !matches!(instruction.mnemonic, Mnemonic::MOV | Mnemonic::MOVSXD) {
+        return None;
+    }
+
+    let source = &instruction.operands()[1];
+    let DecodedOperandKind::Mem(memory) = &source.kind else {
+        return None;
+    };
+
+    let access = TableAccess {
+        load_address: *load_address,
+        memory,
+        entry_size: (source.size / 8) as u8,
+        sign_extended: instruction.mnemonic == Mnemonic::MOVSXD,
+        base,
+        preceding: &preceding[..index],
+    };
+    Some(access)
+}
+
+/// Number of entries allowed by the last bound check of `index`. Copies of the index made after
+/// the check are followed back to the checked register
+fn index_bound(
+    mode: MachineMode,
+    index: Register,
+    preceding: &[(u64, FullInstruction)],
+) -> Option<u64> {
+    let mut index = index.largest_enclosing(mode);
+    let mut branch_mnemonic = None;
+
+    for (_, instruction) in preceding.iter().rev() {
+        let operands = instruction.operands();
+
+        match instruction.mnemonic {
+            Mnemonic::JNBE | Mnemonic::JNB => branch_mnemonic = Some(instruction.mnemonic),
+            Mnemonic::CMP => {
+                let (DecodedOperandKind::Reg(compared), DecodedOperandKind::Imm(bound)) =
+                    (&operands[0].kind, &operands[1].kind)
+                else {
+                    continue;
+                };
+                if compared.largest_enclosing(mode) != index {
+                    continue;
+                }
+
+                return match branch_mnemonic? {
+                    Mnemonic::JNBE => bound.value.checked_add(1),
+                    _ => Some(bound.value),
+                };
+            }
+            _ if writes_register(mode, instruction, index) => {
+                let source = operands.get(1).map(|operand| &operand.kind);
+                let copied_from = match (instruction.mnemonic, source) {
+                    (
+                        Mnemonic::MOV | Mnemonic::MOVSXD | Mnemonic::MOVZX,
+                        Some(DecodedOperandKind::Reg(source)),
+                    ) => *source,
+                    _ => return None,
+                };
+                index = copied_from.largest_enclosing(mode);
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Value of `register` set by a `lea register, [rip + offset]` or a `mov register, imm`
+fn constant_value(
+    mode: MachineMode,
+    register: Register,
+    preceding: &[(u64, FullInstruction)],
+) -> Option<u64> {
+    let definition = last_write(mode, register, preceding)?;
+    let (address, instruction) = &preceding[definition];
+    let source = instruction.operands().get(1)?;
+
+    match (&instruction.mnemonic, &source.kind) {
+        (Mnemonic::LEA, DecodedOperandKind::Mem(memory))
+            if memory.base == Register::RIP && memory.index == Register::NONE =>
+        {
+            instruction.calc_absolute_address(*address, source).ok()
+        }
+        (Mnemonic::MOV, DecodedOperandKind::Imm(immediate)) => Some(immediate.value),
+        _ => None,
+    }
+}
+
+/// Position of the last instruction in `preceding` writing to `register`
+fn last_write(
+    mode: MachineMode,
+    register: Register,
+    preceding: &[(u64, FullInstruction)],
+) -> Option<usize> {
+    let register = register.largest_enclosing(mode);
+    preceding
+        .iter()
+        .rposition(|(_, instruction)| writes_register(mode, instruction, register))
+}
+
+fn writes_register(mode: MachineMode, instruction: &FullInstruction, register: Register) -> bool {
+    instruction.operands().iter().any(|operand| {
+        matches!(operand.kind, DecodedOperandKind::Reg(written) if written.largest_enclosing(mode) == register)
+            && operand.action.intersects(OperandAction::MASK_WRITE)
+    })
+}
+
+fn read_entry(image: &LoadedImage, address: u64, size: u8, sign_extended: bool) -> Option<u64> {
+    let bytes = image.read_only_bytes(address, size.into())?;
+
+    let mut buffer = [0u8; 8];
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_le_bytes(buffer);
+
+    let unused_bits = 64 - u32::from(size) * 8;
+    if sign_extended && unused_bits > 0 {
+        Some((((value << unused_bits) as i64) >> unused_bits) as u64)
+    } else {
+        Some(value)
+    }
+}
+
+/// Whether the instructions before `instruction` can't be on its path, so the search window
+/// stops there
+pub(super) fn ends_search_window(instruction: &FullInstruction) -> bool {
+    matches!(
+        instruction.meta.category,
+        InstructionCategory::UNCOND_BR | InstructionCategory::RET
+    )
+}
@@ -1,20 +1,31 @@
+use crate::lifter::memory::{FlatStackMemory, MemoryModel};
 use crate::lifter::semantics::Lifter;
-use crate::lifter::LifterX86;
+use crate::lifter::state::{lifted_function_name, CallPolicy};
+use crate::lifter::{largest_enclosing_register, LifterX86};
+use crate::loader::LoadedImage;
 use crate::miscellaneous::ExtendedRegisterEnum;
 
-use error::Error;
+use cfg::{BlockExit, ControlFlowGraph};
+use contexts::CpuContext;
 use inkwell::module::Module;
 use inkwell::passes::PassBuilderOptions;
 use inkwell::targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine};
-use inkwell::types::{BasicMetadataTypeEnum, BasicTypeEnum};
+use inkwell::types::{BasicMetadataTypeEnum, BasicTypeEnum, IntType};
 use inkwell::values::FunctionValue;
-use inkwell::OptimizationLevel;
 use inkwell::{context::Context, values::IntValue};
+use inkwell::{AddressSpace, OptimizationLevel};
+use report::{InstructionOutcome, LiftReport, UnsupportedPolicy};
+use std::cell::Cell;
 use zydis::{FullInstruction, InstructionAttributes, MachineMode, Register};
 
+pub mod cfg;
 pub mod contexts;
+pub mod jit;
+pub mod jump_table;
+pub mod report;
 
 pub(super) mod error;
+pub use error::Error;
 pub(crate) use error::Result;
 
 pub struct Compiler<'ctx> {
@@ -22,6 +33,8 @@ pub struct Compiler<'ctx> {
     mode: MachineMode,
     pub lifter: LifterX86<'ctx>,
     func_value: FunctionValue<'ctx>,
+    unsupported_policy: Cell<UnsupportedPolicy>,
+    lifting_started: Cell<bool>,
 }
 
 pub(crate) const CPU_FLAGS: [ExtendedRegisterEnum; 18] = [
@@ -46,6 +59,14 @@ pub(crate) const CPU_FLAGS: [ExtendedRegisterEnum; 18] = [
     ExtendedRegisterEnum::RFLAGS,
 ];
 
+/// Type of `flag`. IOPL is the only flag wider than one bit
+pub(crate) fn cpu_flag_type(context: &Context, flag: ExtendedRegisterEnum) -> IntType<'_> {
+    match flag {
+        ExtendedRegisterEnum::IOPL => context.custom_width_int_type(2),
+        _ => context.bool_type(),
+    }
+}
+
 pub(crate) const ALL_REGS_IN_MIN_SIZE: [Register; 17] = [
     Register::AX,
     Register::BX,
@@ -66,72 +87,530 @@ pub(crate) const ALL_REGS_IN_MIN_SIZE: [Register; 17] = [
     Register::IP,
 ];
 
+/// Vector registers passed to the lifted function after the flags. These are their lower 128 bits
+pub(crate) const VECTOR_REGS: [Register; 32] = [
+    Register::XMM0,
+    Register::XMM1,
+    Register::XMM2,
+    Register::XMM3,
+    Register::XMM4,
+    Register::XMM5,
+    Register::XMM6,
+    Register::XMM7,
+    Register::XMM8,
+    Register::XMM9,
+    Register::XMM10,
+    Register::XMM11,
+    Register::XMM12,
+    Register::XMM13,
+    Register::XMM14,
+    Register::XMM15,
+    Register::XMM16,
+    Register::XMM17,
+    Register::XMM18,
+    Register::XMM19,
+    Register::XMM20,
+    Register::XMM21,
+    Register::XMM22,
+    Register::XMM23,
+    Register::XMM24,
+    Register::XMM25,
+    Register::XMM26,
+    Register::XMM27,
+    Register::XMM28,
+    Register::XMM29,
+    Register::XMM30,
+    Register::XMM31,
+];
+
+/// Bits 128..255 of the vector registers, passed after [VECTOR_REGS]
+pub(crate) const VECTOR_HIGH_REGS: [Register; 32] = [
+    Register::YMM0,
+    Register::YMM1,
+    Register::YMM2,
+    Register::YMM3,
+    Register::YMM4,
+    Register::YMM5,
+    Register::YMM6,
+    Register::YMM7,
+    Register::YMM8,
+    Register::YMM9,
+    Register::YMM10,
+    Register::YMM11,
+    Register::YMM12,
+    Register::YMM13,
+    Register::YMM14,
+    Register::YMM15,
+    Register::YMM16,
+    Register::YMM17,
+    Register::YMM18,
+    Register::YMM19,
+    Register::YMM20,
+    Register::YMM21,
+    Register::YMM22,
+    Register::YMM23,
+    Register::YMM24,
+    Register::YMM25,
+    Register::YMM26,
+    Register::YMM27,
+    Register::YMM28,
+    Register::YMM29,
+    Register::YMM30,
+    Register::YMM31,
+];
+
+/// Bits 256..511 of the vector registers, passed after [VECTOR_HIGH_REGS]
+pub(crate) const ZMM_HIGH_REGS: [Register; 32] = [
+    Register::ZMM0,
+    Register::ZMM1,
+    Register::ZMM2,
+    Register::ZMM3,
+    Register::ZMM4,
+    Register::ZMM5,
+    Register::ZMM6,
+    Register::ZMM7,
+    Register::ZMM8,
+    Register::ZMM9,
+    Register::ZMM10,
+    Register::ZMM11,
+    Register::ZMM12,
+    Register::ZMM13,
+    Register::ZMM14,
+    Register::ZMM15,
+    Register::ZMM16,
+    Register::ZMM17,
+    Register::ZMM18,
+    Register::ZMM19,
+    Register::ZMM20,
+    Register::ZMM21,
+    Register::ZMM22,
+    Register::ZMM23,
+    Register::ZMM24,
+    Register::ZMM25,
+    Register::ZMM26,
+    Register::ZMM27,
+    Register::ZMM28,
+    Register::ZMM29,
+    Register::ZMM30,
+    Register::ZMM31,
+];
+
+/// AVX-512 opmask registers, passed after [ZMM_HIGH_REGS]
+pub(crate) const MASK_REGS: [Register; 8] = [
+    Register::K0,
+    Register::K1,
+    Register::K2,
+    Register::K3,
+    Register::K4,
+    Register::K5,
+    Register::K6,
+    Register::K7,
+];
+
+/// Base addresses of the FS and GS segments, passed after [MASK_REGS]. Segment overridden
+/// accesses are relative to them outside of real mode
+pub(crate) const SEGMENT_BASES: [ExtendedRegisterEnum; 2] =
+    [ExtendedRegisterEnum::FSBASE, ExtendedRegisterEnum::GSBASE];
+
+/// Segment selectors as i16, passed after [SEGMENT_BASES]. Real mode addresses are computed from
+/// them
+pub(crate) const SEGMENT_REGS: [Register; 6] = [
+    Register::CS,
+    Register::DS,
+    Register::ES,
+    Register::SS,
+    Register::FS,
+    Register::GS,
+];
+
+/// x87 stack registers `ST(0)..ST(7)` as x86_fp80, passed after [SEGMENT_REGS]
+pub(crate) const X87_REGS: [Register; 8] = [
+    Register::ST0,
+    Register::ST1,
+    Register::ST2,
+    Register::ST3,
+    Register::ST4,
+    Register::ST5,
+    Register::ST6,
+    Register::ST7,
+];
+
+/// x87 control, status and tag words as i16, passed last
+pub(crate) const X87_WORDS: [ExtendedRegisterEnum; 3] = [
+    ExtendedRegisterEnum::X87CONTROL,
+    ExtendedRegisterEnum::X87STATUS,
+    ExtendedRegisterEnum::X87TAG,
+];
+
 impl<'ctx> Compiler<'ctx> {
+    /// Creates compiler which lifts memory accesses into [FlatStackMemory]
     pub fn new_with_x86_lifter(
         context: &'ctx Context,
         mode: MachineMode,
         runtime_address: Option<u64>,
+    ) -> Result<Self> {
+        let memory = Box::new(FlatStackMemory::default());
+        Self::new_with_x86_lifter_and_memory(context, mode, runtime_address, memory)
+    }
+
+    pub fn new_with_x86_lifter_and_memory(
+        context: &'ctx Context,
+        mode: MachineMode,
+        runtime_address: Option<u64>,
+        memory: Box<dyn MemoryModel<'ctx> + 'ctx>,
     ) -> Result<Self> {
         let module = context.create_module("protected");
         let func_value = create_func(&mode, context, &module);
-        let lifter = LifterX86::new(context, mode, func_value, module, runtime_address).unwrap();
+        let lifter = LifterX86::new(context, mode, func_value, module, runtime_address, memory)?;
+
+        let compiler = Self {
+            context,
+            mode,
+            lifter,
+            func_value,
+            unsupported_policy: Cell::default(),
+            lifting_started: Cell::default(),
+        };
+        Ok(compiler)
+    }
+
+    /// Creates compiler which lifts the function at `address` with the state ABI, so it can be
+    /// called from functions lifted with [CallPolicy::Call]. The function is named after its
+    /// address, see [lifted_function_name]
+    pub fn new_state_function(
+        context: &'ctx Context,
+        mode: MachineMode,
+        address: u64,
+        memory: Box<dyn MemoryModel<'ctx> + 'ctx>,
+    ) -> Result<Self> {
+        let name = lifted_function_name(address);
+        let module = context.create_module(&name);
+
+        let ptr_ty = context.ptr_type(AddressSpace::default());
+        let fn_type = context.void_type().fn_type(&[ptr_ty.into()], false);
+        let func_value = module.add_function(&name, fn_type, None);
+        func_value.get_nth_param(0).unwrap().set_name("state");
+
+        let lifter = LifterX86::new(context, mode, func_value, module, Some(address), memory)?;
 
         let compiler = Self {
             context,
             mode,
             lifter,
             func_value,
+            unsupported_policy: Cell::default(),
+            lifting_started: Cell::default(),
         };
         Ok(compiler)
     }
 
+    /// Creates compiler for lifting functions of `image`
+    pub fn new_for_image(context: &'ctx Context, image: LoadedImage) -> Result<Self> {
+        let compiler = Self::new_with_x86_lifter(context, image.mode, None)?;
+        compiler.load_image(image)?;
+        Ok(compiler)
+    }
+
+    /// Makes `image` available to the lifter. Reads from its read-only sections at constant
+    /// addresses are replaced with the actual bytes
+    pub fn load_image(&self, image: LoadedImage) -> Result<()> {
+        self.lifter
+            .image
+            .set(image)
+            .map_err(|_| Error::ImageAlreadyLoaded)
+    }
+
+    pub fn image(&self) -> Option<&LoadedImage> {
+        self.lifter.image.get()
+    }
+
+    /// Lifts function of the loaded image starting at virtual address `start_address`
+    pub fn lift_image_function(
+        &self,
+        start_address: u64,
+        optimize_results: bool,
+    ) -> Result<(ControlFlowGraph, LiftReport)> {
+        let image = self.image().ok_or(Error::NoImageLoaded)?;
+        let section = image
+            .section_containing(start_address)
+            .ok_or(Error::AddressOutOfRange(start_address))?;
+
+        self.lift_function_at(
+            &section.data,
+            section.virtual_address,
+            start_address,
+            optimize_results,
+        )
+    }
+
+    /// Lifts function of the loaded image by its symbol name
+    pub fn lift_image_symbol(
+        &self,
+        name: &str,
+        optimize_results: bool,
+    ) -> Result<(ControlFlowGraph, LiftReport)> {
+        let image = self.image().ok_or(Error::NoImageLoaded)?;
+        let address = *image
+            .symbols
+            .get(name)
+            .ok_or_else(|| Error::UnknownSymbol(name.to_string()))?;
+
+        self.lift_image_function(address, optimize_results)
+    }
+
+    /// Folds concrete register and flag values into the lifted function instead of reading them
+    /// from its parameters. Fails once an instruction was lifted
+    pub fn set_start_context<C: CpuContext>(&self, start_context: C) -> Result<()> {
+        if self.lifting_started.get() {
+            return Err(Error::StartContextAfterLifting);
+        }
+
+        let values = start_context.create_variables(self.context, self.mode);
+        self.lifter.set_register_values(values)?;
+        Ok(())
+    }
+
+    /// Decides what happens to instructions the lifter doesn't support. They are skipped by default
+    pub fn set_unsupported_policy(&self, policy: UnsupportedPolicy) {
+        self.unsupported_policy.set(policy);
+    }
+
+    /// Decides how CALL instructions are lifted. The callee is inlined by default
+    pub fn set_call_policy(&self, policy: CallPolicy) {
+        self.lifter.call_policy.set(policy);
+    }
+
+    /// Lifts a trace of executed instructions. Traces repeat a REP instruction once per iteration,
+    /// but it's lifted as a whole loop, so copies of it right after it are skipped
     pub fn lift_function(
         &self,
         instructions: &Vec<FullInstruction>,
         optimize_results: bool,
-    ) -> Result<()> {
-        #[cfg(debug_assertions)]
-        let mut problems_hs = std::collections::HashSet::new();
+    ) -> Result<LiftReport> {
+        let mut report = LiftReport::default();
+        let mut previous = None;
+        for instruction in instructions {
+            if previous == Some(instruction) && has_rep_prefix(instruction) {
+                continue;
+            }
+            previous = Some(instruction);
 
-        #[cfg(debug_assertions)]
-        let mut missed_instructions_count = 0;
-        #[cfg(debug_assertions)]
-        let mut lifted_instructions_count = 0;
+            match self.lift_instruction(instruction, &mut report)? {
+                InstructionOutcome::Lifted | InstructionOutcome::Skipped => {}
+                InstructionOutcome::Terminated
+                | InstructionOutcome::Stopped
+                | InstructionOutcome::Failed => break,
+            }
 
-        for instruction in instructions {
-            match self.lifter.lift_instr(instruction) {
-                Ok(_) => {
-                    #[cfg(debug_assertions)]
-                    {
-                        lifted_instructions_count += 1;
+            // Genuine returns end the trace
+            if self.current_block_is_terminated() {
+                break;
+            }
+        }
+
+        if !self.current_block_is_terminated() {
+            self.build_return_from_state()?;
+        }
+
+        // Branch targets which were never reached by the lifted instructions just return the state
+        // they were entered with
+        for address in self.lifter.unterminated_blocks() {
+            self.lifter.enter_block(address)?;
+            self.build_return_from_state()?;
+        }
+
+        if optimize_results {
+            self.optimize()?;
+        }
+
+        Ok(report)
+    }
+
+    /// Decodes `code` mapped at `base_address`, recovers the control flow graph reachable from
+    /// `start_address` and lifts every recovered block into its own LLVM basic block. Blocks are
+    /// lifted in reverse postorder, so RETs see every path to them when they are classified
+    pub fn lift_function_at(
+        &self,
+        code: &[u8],
+        base_address: u64,
+        start_address: u64,
+        optimize_results: bool,
+    ) -> Result<(ControlFlowGraph, LiftReport)> {
+        let cfg = ControlFlowGraph::recover_with_image(
+            self.mode,
+            code,
+            base_address,
+            start_address,
+            self.lifter.image.get(),
+        )?;
+        for block in cfg.blocks.values() {
+            if let BlockExit::JumpTable(table) = &block.exit {
+                self.lifter.add_jump_table(table.clone());
+            }
+        }
+
+        self.lifter.build_guest_branch(cfg.entry)?;
+
+        let mut report = LiftReport::default();
+        for address in cfg.reverse_postorder() {
+            let block = &cfg.blocks[&address];
+            self.lifter.enter_block(block.start_address)?;
+
+            let mut stopped = false;
+            for (address, instruction) in &block.instructions {
+                self.lifter.set_runtime_address(*address);
+                match self.lift_instruction(instruction, &mut report)? {
+                    InstructionOutcome::Lifted | InstructionOutcome::Skipped => {}
+                    InstructionOutcome::Terminated => break,
+                    InstructionOutcome::Stopped | InstructionOutcome::Failed => {
+                        stopped = true;
+                        break;
                     }
                 }
-                Err(e) => {
-                    #[cfg(debug_assertions)]
-                    {
-                        match e {
-                            crate::lifter::Error::UnsupportedInstr(_) => {
-                                problems_hs
-                                    .insert((instruction.mnemonic, instruction.meta.category));
-                                missed_instructions_count += 1;
-                            }
-                            _ => panic!("{e}"),
-                        }
+            }
+
+            if stopped {
+                self.build_return_from_state()?;
+                break;
+            }
+
+            // Jcc already terminated the block and moved on to the fall-through one. Other
+            // instructions may split the block, but then the builder is in a block of no address
+            let current_block = self.lifter.builder.get_insert_block().unwrap();
+            let moved_to_other_block = self
+                .lifter
+                .guest_address_of(current_block)
+                .is_some_and(|address| address != block.start_address);
+            if !moved_to_other_block && current_block.get_terminator().is_none() {
+                match &block.exit {
+                    BlockExit::FallThrough(address) | BlockExit::Jump(address) => {
+                        self.lifter.build_guest_branch(*address)?
+                    }
+                    // Only reached when the conditional branch itself wasn't lifted
+                    BlockExit::ConditionalJump { fallthrough, .. } => {
+                        self.lifter.build_guest_branch(*fallthrough)?
+                    }
+                    BlockExit::JumpTable(_) | BlockExit::IndirectJump => {
+                        self.lifter.build_jump_dispatch()?
                     }
-                } //last_ins_attr = instruction.attributes;
+                    BlockExit::Return | BlockExit::Trap => self.build_return_from_state()?,
+                }
             }
         }
 
-        #[cfg(debug_assertions)]
-        {
-            dbg!(problems_hs.len());
-            dbg!(problems_hs.len());
-            dbg!(problems_hs);
-            dbg!(missed_instructions_count);
-            dbg!(lifted_instructions_count);
+        for address in self.lifter.unterminated_blocks() {
+            self.lifter.enter_block(address)?;
+            self.build_return_from_state()?;
+        }
+
+        if optimize_results {
+            self.optimize()?;
+        }
+
+        Ok((cfg, report))
+    }
+
+    /// Lifts single instruction, applying the unsupported instruction policy when needed
+    fn lift_instruction(
+        &self,
+        instruction: &FullInstruction,
+        report: &mut LiftReport,
+    ) -> Result<InstructionOutcome> {
+        let address = self.lifter.runtime_address();
+        self.lifting_started.set(true);
+
+        let error = match self.lifter.lift_instr(instruction) {
+            Ok(()) => {
+                report.record(
+                    address,
+                    instruction.mnemonic,
+                    InstructionOutcome::Lifted,
+                    None,
+                );
+                return Ok(InstructionOutcome::Lifted);
+            }
+            Err(e @ crate::lifter::Error::UnsupportedInstr(_)) => e,
+            Err(e) => {
+                report.record(
+                    address,
+                    instruction.mnemonic,
+                    InstructionOutcome::Failed,
+                    Some(e),
+                );
+                return Err(Error::LiftingFailed(Box::new(std::mem::take(report))));
+            }
+        };
+
+        let outcome = match self.unsupported_policy.get() {
+            UnsupportedPolicy::Skip => InstructionOutcome::Skipped,
+            UnsupportedPolicy::Stop => InstructionOutcome::Stopped,
+            UnsupportedPolicy::Unreachable => {
+                self.lifter.builder.build_unreachable()?;
+                InstructionOutcome::Terminated
+            }
+            UnsupportedPolicy::Trap => {
+                self.lifter.build_trap()?;
+                InstructionOutcome::Terminated
+            }
+        };
+        report.record(address, instruction.mnemonic, outcome, Some(error));
+        Ok(outcome)
+    }
+
+    fn optimize(&self) -> Result<()> {
+        let pass_options = PassBuilderOptions::create();
+        //pass_options.set_verify_each(true);
+        //pass_options.set_debug_logging(true);
+        pass_options.set_loop_interleaving(true);
+        pass_options.set_loop_vectorization(true);
+        pass_options.set_loop_slp_vectorization(true);
+        pass_options.set_loop_unrolling(true);
+        pass_options.set_forget_all_scev_in_loop_unroll(true);
+        pass_options.set_licm_mssa_opt_cap(1);
+        pass_options.set_licm_mssa_no_acc_for_promotion_cap(10);
+        pass_options.set_call_graph_profile(true);
+        pass_options.set_merge_functions(true);
+
+        let initialization_config = &InitializationConfig::default();
+        Target::initialize_all(initialization_config);
+
+        let triple = TargetMachine::get_default_triple();
+        let target = Target::from_triple(&triple).unwrap();
+        let machine = target
+            .create_target_machine(
+                &triple,
+                //TODO : Add cpu features as optionals
+                "generic", //TargetMachine::get_host_cpu_name().to_string().as_str(),
+                "",        //TargetMachine::get_host_cpu_features().to_string().as_str(),
+                OptimizationLevel::Aggressive,
+                RelocMode::Default,
+                CodeModel::Default,
+            )
+            .ok_or(Error::UnableToCreateTargetMachine)?;
+        self.lifter
+            .module
+            .run_passes("default<O2>", &machine, pass_options)
+            .map_err(Error::OptimizationsError)?;
+        Ok(())
+    }
+
+    fn current_block_is_terminated(&self) -> bool {
+        self.lifter
+            .builder
+            .get_insert_block()
+            .is_some_and(|block| block.get_terminator().is_some())
+    }
+
+    /// Returns RAX (converted to the function return type) from the current block. Functions
+    /// with the state ABI write the whole state back instead
+    fn build_return_from_state(&self) -> Result<()> {
+        if let Some(state) = self.lifter.state_param {
+            self.lifter.store_state(state)?;
+            self.lifter.builder.build_return(None)?;
+            return Ok(());
         }
 
-        let rax = zydis::Register::AX.largest_enclosing(self.mode);
+        let rax = largest_enclosing_register(zydis::Register::AX, self.mode);
 
         if let Ok(rax_val) = self.lifter.load_register_value(&rax) {
             let rax_as_int: IntValue<'ctx> = rax_val.try_into()?;
@@ -153,43 +632,6 @@ impl<'ctx> Compiler<'ctx> {
             }
         }
 
-        if optimize_results {
-            let pass_options = PassBuilderOptions::create();
-            //pass_options.set_verify_each(true);
-            //pass_options.set_debug_logging(true);
-            pass_options.set_loop_interleaving(true);
-            pass_options.set_loop_vectorization(true);
-            pass_options.set_loop_slp_vectorization(true);
-            pass_options.set_loop_unrolling(true);
-            pass_options.set_forget_all_scev_in_loop_unroll(true);
-            pass_options.set_licm_mssa_opt_cap(1);
-            pass_options.set_licm_mssa_no_acc_for_promotion_cap(10);
-            pass_options.set_call_graph_profile(true);
-            pass_options.set_merge_functions(true);
-
-            let initialization_config = &InitializationConfig::default();
-            Target::initialize_all(initialization_config);
-
-            let triple = TargetMachine::get_default_triple();
-            let target = Target::from_triple(&triple).unwrap();
-            let machine = target
-                .create_target_machine(
-                    &triple,
-                    //TODO : Add cpu features as optionals
-                    "generic", //TargetMachine::get_host_cpu_name().to_string().as_str(),
-                    "",        //TargetMachine::get_host_cpu_features().to_string().as_str(),
-                    OptimizationLevel::Aggressive,
-                    RelocMode::Default,
-                    CodeModel::Default,
-                )
-                .ok_or(Error::UnableToCreateTargetMachine)?;
-            self.lifter
-                .module
-                .run_passes("default<O2>", &machine, pass_options)
-                .map_err(Error::OptimizationsError)?;
-        }
-
-        //Ok(&self.func_value)
         Ok(())
     }
 
@@ -270,29 +712,55 @@ pub(crate) fn create_func<'ctx>(
     context: &'ctx Context,
     module: &Module<'ctx>,
 ) -> FunctionValue<'ctx> {
-    let example_reg = Register::AX.largest_enclosing(*mode); // random rax for convenience
+    let example_reg = largest_enclosing_register(Register::AX, *mode); // random rax for convenience
     let int_type = context.custom_width_int_type(example_reg.width(*mode).into());
     //let int_type = get_int_type(context, &example_reg, mode);
 
-    const ARGS_COUNT: usize = ALL_REGS_IN_MIN_SIZE.len() + CPU_FLAGS.len();
+    const ARGS_COUNT: usize =
+        ALL_REGS_IN_MIN_SIZE.len() + CPU_FLAGS.len() + VECTOR_REGS.len() + VECTOR_HIGH_REGS.len();
     let regs_args: [BasicMetadataTypeEnum; ALL_REGS_IN_MIN_SIZE.len()] =
         core::array::from_fn(|_| int_type.into());
 
     //let flags_args: [BasicMetadataTypeEnum; CPU_FLAGS.len()] =
     //    core::array::from_fn(|_| context.i8_type().into());
     let flags_args: [BasicMetadataTypeEnum; CPU_FLAGS.len()] =
-        core::array::from_fn(|_| context.bool_type().into());
+        CPU_FLAGS.map(|flag| cpu_flag_type(context, flag).into());
+
+    let vector_args: [BasicMetadataTypeEnum; VECTOR_REGS.len() + VECTOR_HIGH_REGS.len()] =
+        core::array::from_fn(|_| context.i128_type().into());
+    let zmm_high_args: [BasicMetadataTypeEnum; ZMM_HIGH_REGS.len()] =
+        core::array::from_fn(|_| context.custom_width_int_type(256).into());
+    let mask_args: [BasicMetadataTypeEnum; MASK_REGS.len()] =
+        core::array::from_fn(|_| context.i64_type().into());
+
+    let segment_base_args: [BasicMetadataTypeEnum; SEGMENT_BASES.len()] =
+        core::array::from_fn(|_| int_type.into());
+    let segment_args: [BasicMetadataTypeEnum; SEGMENT_REGS.len()] =
+        core::array::from_fn(|_| context.i16_type().into());
+    let x87_args: [BasicMetadataTypeEnum; X87_REGS.len()] =
+        core::array::from_fn(|_| context.x86_f80_type().into());
+    let x87_word_args: [BasicMetadataTypeEnum; X87_WORDS.len()] =
+        core::array::from_fn(|_| context.i16_type().into());
 
     let mut args = Vec::with_capacity(ARGS_COUNT);
     args.extend_from_slice(&regs_args);
     args.extend_from_slice(&flags_args);
+    args.extend_from_slice(&vector_args);
+    args.extend_from_slice(&zmm_high_args);
+    args.extend_from_slice(&mask_args);
+    args.extend_from_slice(&segment_base_args);
+    args.extend_from_slice(&segment_args);
+    args.extend_from_slice(&x87_args);
+    args.extend_from_slice(&x87_word_args);
 
     let fn_type = int_type.fn_type(&args, false);
     let fn_val = module.add_function("protected", fn_type, None);
 
     /// Inner function for converting register names
     fn get_reg_name_for_mode(reg: Register, mode: MachineMode) -> &'static str {
-        reg.largest_enclosing(mode).static_string().unwrap()
+        largest_enclosing_register(reg, mode)
+            .static_string()
+            .unwrap()
     }
 
     // Set names for regular regs
@@ -311,5 +779,75 @@ pub(crate) fn create_func<'ctx>(
             .set_name(&format!("{cpu_flag:?}"));
     }
 
+    // Set names for vector registers
+    let first_vector_arg = ALL_REGS_IN_MIN_SIZE.len() + CPU_FLAGS.len();
+    for (id, reg) in VECTOR_REGS.into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_vector_arg + id) as u32)
+            .unwrap()
+            .set_name(reg.static_string().unwrap());
+    }
+
+    let first_vector_high_arg = first_vector_arg + VECTOR_REGS.len();
+    for (id, reg) in VECTOR_HIGH_REGS
+        .into_iter()
+        .chain(ZMM_HIGH_REGS)
+        .enumerate()
+    {
+        fn_val
+            .get_nth_param((first_vector_high_arg + id) as u32)
+            .unwrap()
+            .set_name(&format!("{}_high", reg.static_string().unwrap()));
+    }
+
+    let first_mask_arg = first_vector_high_arg + VECTOR_HIGH_REGS.len() + ZMM_HIGH_REGS.len();
+    for (id, reg) in MASK_REGS.into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_mask_arg + id) as u32)
+            .unwrap()
+            .set_name(reg.static_string().unwrap());
+    }
+
+    let first_segment_base_arg = first_mask_arg + MASK_REGS.len();
+    for (id, name) in ["fs_base", "gs_base"].into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_segment_base_arg + id) as u32)
+            .unwrap()
+            .set_name(name);
+    }
+
+    let first_segment_arg = first_segment_base_arg + SEGMENT_BASES.len();
+    for (id, reg) in SEGMENT_REGS.into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_segment_arg + id) as u32)
+            .unwrap()
+            .set_name(reg.static_string().unwrap());
+    }
+
+    let first_x87_arg = first_segment_arg + SEGMENT_REGS.len();
+    for (id, reg) in X87_REGS.into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_x87_arg + id) as u32)
+            .unwrap()
+            .set_name(reg.static_string().unwrap());
+    }
+
+    let first_x87_word_arg = first_x87_arg + X87_REGS.len();
+    for (id, word) in X87_WORDS.into_iter().enumerate() {
+        fn_val
+            .get_nth_param((first_x87_word_arg + id) as u32)
+            .unwrap()
+            .set_name(&format!("{word:?}"));
+    }
+
     fn_val
 }
+
+/// Whether `instruction` has a REP, REPE or REPNE prefix
+fn has_rep_prefix(instruction: &FullInstruction) -> bool {
+    instruction.attributes.intersects(
+        InstructionAttributes::HAS_REP
+            | InstructionAttributes::HAS_REPE
+            | InstructionAttributes::HAS_REPNE,
+    )
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use zydis::Mnemonic;
+
+/// What the compiler does with instructions the lifter doesn't support
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedPolicy {
+    /// Lift the rest as if the instruction was a no-op
+    #[default]
+    Skip,
+    /// Stop lifting, the function returns the state from before the instruction
+    Stop,
+    /// End the path with `unreachable`, so the optimizer may remove it
+    Unreachable,
+    /// End the path with a call to `llvm.trap`
+    Trap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionOutcome {
+    Lifted,
+    /// Unsupported and treated as a no-op
+    Skipped,
+    /// Unsupported, the path ends with `unreachable` or a trap. Following instructions of the same
+    /// block aren't lifted
+    Terminated,
+    /// Unsupported, nothing after it was lifted
+    Stopped,
+    /// Lifting failed for another reason than being unsupported, nothing after it was lifted
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct InstructionRecord {
+    /// Unknown when lifting without a runtime address
+    pub address: Option<u64>,
+    pub mnemonic: Mnemonic,
+    pub outcome: InstructionOutcome,
+    /// Why the instruction wasn't lifted
+    pub error: Option<crate::lifter::Error>,
+}
+
+/// Outcome of every instruction the compiler tried to lift, in lifting order
+#[derive(Debug, Default)]
+pub struct LiftReport {
+    pub instructions: Vec<InstructionRecord>,
+}
+
+impl LiftReport {
+    pub fn lifted_count(&self) -> usize {
+        self.instructions
+            .iter()
+            .filter(|record| record.outcome == InstructionOutcome::Lifted)
+            .count()
+    }
+
+    pub fn unsupported(&self) -> impl Iterator<Item = &InstructionRecord> {
+        self.instructions.iter().filter(|record| {
+            !matches!(
+                record.outcome,
+                InstructionOutcome::Lifted | InstructionOutcome::Failed
+            )
+        })
+    }
+
+    /// Instruction lifting failed at. It's the last one, as lifting stops there
+    pub fn failure(&self) -> Option<&InstructionRecord> {
+        self.instructions
+            .last()
+            .filter(|record| record.outcome == InstructionOutcome::Failed)
+    }
+
+    /// Whether every instruction was lifted
+    pub fn is_complete(&self) -> bool {
+        self.unsupported().next().is_none() && self.failure().is_none()
+    }
+
+    /// Unsupported mnemonics with their occurrence counts, most frequent first
+    pub fn most_frequent_unsupported(&self) -> Vec<(Mnemonic, usize)> {
+        let mut counts = HashMap::<Mnemonic, usize>::new();
+        for record in self.unsupported() {
+            *counts.entry(record.mnemonic).or_default() += 1;
+        }
+
+        let mut mnemonics: Vec<_> = counts.into_iter().collect();
+        mnemonics.sort_by(|(a_mnemonic, a_count), (b_mnemonic, b_count)| {
+            b_count
+                .cmp(a_count)
+                .then_with(|| a_mnemonic.to_string().cmp(&b_mnemonic.to_string()))
+        });
+        mnemonics
+    }
+
+    pub(super) fn record(
+        &mut self,
+        address: Option<u64>,
+        mnemonic: Mnemonic,
+        outcome: InstructionOutcome,
+        error: Option<crate::lifter::Error>,
+    ) {
+        self.instructions.push(InstructionRecord {
+            address,
+            mnemonic,
+            outcome,
+            error,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_frequent_unsupported_first() {
+        let mut report = LiftReport::default();
+        report.record(
+            Some(0x1000),
+            Mnemonic::MOV,
+            InstructionOutcome::Lifted,
+            None,
+        );
+        for (address, mnemonic) in [
+            (0x1003, Mnemonic::CPUID),
+            (0x1005, Mnemonic::RDTSC),
+            (0x1007, Mnemonic::RDTSC),
+        ] {
+            report.record(Some(address), mnemonic, InstructionOutcome::Skipped, None);
+        }
+
+        assert_eq!(report.lifted_count(), 1);
+        assert!(!report.is_complete());
+        assert_eq!(
+            report.most_frequent_unsupported(),
+            vec![(Mnemonic::RDTSC, 2), (Mnemonic::CPUID, 1)]
+        );
+    }
+
+    #[test]
+    fn failure_is_not_unsupported() {
+        let mut report = LiftReport::default();
+        report.record(
+            Some(0x1000),
+            Mnemonic::MOV,
+            InstructionOutcome::Lifted,
+            None,
+        );
+        report.record(
+            Some(0x1003),
+            Mnemonic::ADD,
+            InstructionOutcome::Failed,
+            Some(crate::lifter::Error::ConvertError),
+        );
+
+        assert_eq!(
+            report.failure().and_then(|record| record.address),
+            Some(0x1003)
+        );
+        assert_eq!(report.unsupported().count(), 0);
+        assert!(!report.is_complete());
+    }
+}
@@ -1,6 +1,7 @@
 //#![forbid(unsafe_code)]
 
 pub mod compiler;
+pub mod loader;
 //pub mod lifter;
 mod miscellaneous;
 mod util;
@@ -0,0 +1,250 @@
+use super::{Error, LifterX86, PossibleLLVMValueEnum, Result};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use std::collections::BTreeMap;
+
+use inkwell::{
+    basic_block::BasicBlock,
+    intrinsics::Intrinsic,
+    types::BasicTypeEnum,
+    values::{BasicValueEnum, IntValue, PhiValue},
+};
+
+const TRAP_INTRINSIC: &str = "llvm.trap";
+
+/// LLVM basic block created for some guest address.
+///
+/// Every register known at the moment of creation or at the function entry gets a phi node at the
+/// top of the block, so the register state of all predecessors is merged once the branches into
+/// the block are built. Paths which don't define a register pass its entry value.
+#[derive(Debug, Clone)]
+pub(crate) struct GuestBlock<'ctx> {
+    pub(crate) basic_block: BasicBlock<'ctx>,
+    phis: Vec<(ExtendedRegisterEnum, PhiValue<'ctx>)>,
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    #[allow(clippy::mut_from_ref)]
+    pub(super) fn blocks_mut(&self) -> &mut BTreeMap<u64, GuestBlock<'ctx>> {
+        unsafe { &mut (*self.blocks.get()) }
+    }
+
+    pub(crate) fn blocks(&self) -> &BTreeMap<u64, GuestBlock<'ctx>> {
+        unsafe { &(*self.blocks.get()) }
+    }
+
+    /// Remembers the current register values as the ones the function was entered with
+    pub(super) fn record_entry_registers(&self) {
+        let entry_registers = unsafe { &mut (*self.entry_registers.get()) };
+        entry_registers.clone_from(self.regs_hashmap());
+    }
+
+    /// Value `reg` had when the function was entered
+    pub(super) fn entry_register(
+        &self,
+        reg: ExtendedRegisterEnum,
+    ) -> Option<PossibleLLVMValueEnum<'ctx>> {
+        unsafe { (*self.entry_registers.get()).get(&reg).copied() }
+    }
+
+    /// Returns block for `address`, creating it with phi nodes for the current register state
+    pub(crate) fn get_or_create_block(&self, address: u64) -> Result<BasicBlock<'ctx>> {
+        if let Some(guest_block) = self.blocks().get(&address) {
+            return Ok(guest_block.basic_block);
+        }
+
+        let builder = &self.builder;
+        let current_block = builder.get_insert_block();
+
+        let basic_block = self
+            .context
+            .append_basic_block(self.func_value, &format!("bb_{address:x}"));
+        builder.position_at_end(basic_block);
+
+        let mut registers: BTreeMap<u32, (ExtendedRegisterEnum, PossibleLLVMValueEnum<'ctx>)> =
+            BTreeMap::new();
+        let entry_registers = unsafe { &(*self.entry_registers.get()) };
+        for (reg, value) in entry_registers.iter().chain(self.regs_hashmap()) {
+            registers.insert(*reg as u32, (*reg, *value));
+        }
+
+        // Sorted so the generated IR doesn't depend on the hashmap iteration order
+        let mut phis = Vec::with_capacity(registers.len());
+        for (reg, value) in registers.into_values() {
+            let phi = builder.build_phi(self.get_phi_type(value), &format!("{reg:?}_"))?;
+            phis.push((reg, phi));
+        }
+
+        if let Some(current_block) = current_block {
+            builder.position_at_end(current_block);
+        }
+
+        let guest_block = GuestBlock { basic_block, phis };
+        self.blocks_mut().insert(address, guest_block);
+
+        Ok(basic_block)
+    }
+
+    /// Ends current block with a jump to the block at `address`
+    pub(crate) fn build_guest_branch(&self, address: u64) -> Result<()> {
+        let destination = self.get_or_create_block(address)?;
+        self.add_block_incoming(address)?;
+        self.builder.build_unconditional_branch(destination)?;
+        Ok(())
+    }
+
+    /// Ends current block with a conditional jump to one of the blocks at the given addresses
+    pub(crate) fn build_guest_conditional_branch(
+        &self,
+        condition: IntValue<'ctx>,
+        then_address: u64,
+        else_address: u64,
+    ) -> Result<()> {
+        if then_address == else_address {
+            return self.build_guest_branch(then_address);
+        }
+
+        let then_block = self.get_or_create_block(then_address)?;
+        let else_block = self.get_or_create_block(else_address)?;
+
+        self.add_block_incoming(then_address)?;
+        self.add_block_incoming(else_address)?;
+
+        self.builder
+            .build_conditional_branch(condition, then_block, else_block)?;
+        Ok(())
+    }
+
+    /// Positions the builder at the block for `address` and replaces the register state with its
+    /// phi nodes
+    pub(crate) fn enter_block(&self, address: u64) -> Result<()> {
+        self.get_or_create_block(address)?;
+        let guest_block = &self.blocks()[&address];
+
+        if guest_block.basic_block.get_terminator().is_some() {
+            return Err(Error::BlockAlreadyLifted(address));
+        }
+
+        self.builder.position_at_end(guest_block.basic_block);
+
+        let regs_hashmap = self.regs_hashmap_mut();
+        regs_hashmap.clear();
+        for (reg, phi) in &guest_block.phis {
+            regs_hashmap.insert(*reg, phi.as_basic_value().try_into()?);
+        }
+
+        Ok(())
+    }
+
+    /// Used before lifting an instruction. If some branch already targets `address`, current block
+    /// falls through into that block
+    pub(crate) fn sync_block_at(&self, address: u64) -> Result<()> {
+        let Some(guest_block) = self.blocks().get(&address) else {
+            return Ok(());
+        };
+
+        let Some(current_block) = self.builder.get_insert_block() else {
+            return self.enter_block(address);
+        };
+
+        // Already lifted blocks are left alone, traces just keep going through them linearly
+        if current_block == guest_block.basic_block
+            || guest_block.basic_block.get_terminator().is_some()
+        {
+            return Ok(());
+        }
+
+        if current_block.get_terminator().is_none() {
+            self.build_guest_branch(address)?;
+        }
+
+        self.enter_block(address)
+    }
+
+    /// Guest address of `basic_block` if it was created for one
+    pub(crate) fn guest_address_of(&self, basic_block: BasicBlock<'ctx>) -> Option<u64> {
+        self.blocks()
+            .iter()
+            .find(|(_, guest_block)| guest_block.basic_block == basic_block)
+            .map(|(address, _)| *address)
+    }
+
+    /// Terminates current block with a call to `llvm.trap`
+    pub(crate) fn build_trap(&self) -> Result<()> {
+        let trap_intrinsic =
+            Intrinsic::find(TRAP_INTRINSIC).ok_or(Error::IntrinsicNotFound(TRAP_INTRINSIC))?;
+        let trap_func = trap_intrinsic.get_declaration(&self.module, &[]).unwrap();
+
+        self.builder.build_call(trap_func, &[], "")?;
+        self.builder.build_unreachable()?;
+        Ok(())
+    }
+
+    /// Addresses of blocks which were referenced by branches but never got a terminator
+    pub(crate) fn unterminated_blocks(&self) -> Vec<u64> {
+        self.blocks()
+            .iter()
+            .filter(|(_, guest_block)| guest_block.basic_block.get_terminator().is_none())
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    pub(super) fn add_block_incoming(&self, address: u64) -> Result<()> {
+        let current_block = self
+            .builder
+            .get_insert_block()
+            .expect("Builder must be positioned before branching");
+        let guest_block = &self.blocks()[&address];
+
+        for (reg, phi) in &guest_block.phis {
+            let value = self.regs_hashmap().get(reg).copied();
+            let incoming = self.phi_incoming_value(*reg, value, phi.as_basic_value().get_type())?;
+
+            phi.add_incoming(&[(&incoming, current_block)]);
+        }
+        self.forget_stack_offsets();
+
+        Ok(())
+    }
+
+    /// Converts the value of `reg` into the type of the phi node it flows into. Registers which
+    /// don't exist on that path keep their entry value
+    pub(super) fn phi_incoming_value(
+        &self,
+        reg: ExtendedRegisterEnum,
+        value: Option<PossibleLLVMValueEnum<'ctx>>,
+        phi_ty: BasicTypeEnum<'ctx>,
+    ) -> Result<BasicValueEnum<'ctx>> {
+        let value = value
+            .or_else(|| self.entry_register(reg))
+            .ok_or(Error::RegUnwrapError(reg))?;
+
+        let incoming = match (value, phi_ty) {
+            (PossibleLLVMValueEnum::IntValue(int_value), BasicTypeEnum::IntType(ty)) => {
+                self.create_z_ext_or_trunc(int_value, ty)?.into()
+            }
+            (value, _) => value.into(),
+        };
+
+        Ok(incoming)
+    }
+
+    /// Flags keep their `i1` type, registers are merged using at least the max int type, because
+    /// their values may be narrower on some paths (for example after `mov eax, ...`)
+    pub(super) fn get_phi_type(&self, value: PossibleLLVMValueEnum<'ctx>) -> BasicTypeEnum<'ctx> {
+        match value {
+            PossibleLLVMValueEnum::IntValue(int_value) => {
+                let int_ty = int_value.get_type();
+                let max_int_ty = self.get_max_int_type();
+                if int_ty.get_bit_width() == 1
+                    || int_ty.get_bit_width() >= max_int_ty.get_bit_width()
+                {
+                    int_ty.into()
+                } else {
+                    max_int_ty.into()
+                }
+            }
+            PossibleLLVMValueEnum::FloatValue(float_value) => float_value.get_type().into(),
+        }
+    }
+}
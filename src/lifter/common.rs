@@ -6,7 +6,7 @@ use std::collections::HashMap;
 use inkwell::values::IntValue;
 use zydis::{
     ffi::{DecodedOperand, DecodedOperandKind, MemoryInfo},
-    MachineMode, Register,
+    MachineMode, Register, RegisterClass,
 };
 
 use super::LifterX86;
@@ -26,22 +26,17 @@ impl<'ctx> LifterX86<'ctx> {
         unsafe { &(*self.regs_hashmap.get()) }
     }
 
-    /// Wrapper because of zydis largest_enclosing doesnt work correctly with SP
+    /// Lower 128 bits of vector registers are kept under their XMM name, higher bits are kept
+    /// under the YMM and ZMM names and are read and written explicitly by AVX instructions. Other
+    /// registers are kept in [largest_enclosing_register]
     pub(super) fn get_register_largest_enclosing(&self, register: &Register) -> Register {
-        if [Register::RBP, Register::EBP, Register::BP].contains(register) {
-            match self.mode {
-                MachineMode::LONG_64 => Register::RBP,
-                MachineMode::LEGACY_32 => Register::EBP,
-                _ => unimplemented!("Only 32 and 64 bit supported right now"),
-            }
-        } else if [Register::RSP, Register::ESP, Register::SP].contains(register) {
-            match self.mode {
-                MachineMode::LONG_64 => Register::RSP,
-                MachineMode::LEGACY_32 => Register::ESP,
-                _ => unimplemented!("Only 32 and 64 bit supported right now"),
-            }
+        if matches!(
+            register.class(),
+            RegisterClass::XMM | RegisterClass::YMM | RegisterClass::ZMM
+        ) {
+            RegisterClass::XMM.encode(register.id())
         } else {
-            register.largest_enclosing(self.mode)
+            largest_enclosing_register(*register, self.mode)
         }
     }
     pub(super) fn get_register(&self, r: Register) -> Result<PossibleLLVMValueEnum<'ctx>> {
@@ -58,3 +53,31 @@ impl<'ctx> LifterX86<'ctx> {
         Ok(reg_val)
     }
 }
+
+/// Register whose value contains `register`. Wrapper because of zydis largest_enclosing doesnt
+/// work correctly with SP. 32 bit registers stay usable in 16 bit modes through the operand size
+/// prefix, so they are kept there too. Segment registers are kept separately
+pub(crate) fn largest_enclosing_register(register: Register, mode: MachineMode) -> Register {
+    let mode = match mode {
+        MachineMode::LONG_COMPAT_16 | MachineMode::LEGACY_16 | MachineMode::REAL_16 => {
+            MachineMode::LEGACY_32
+        }
+        _ => mode,
+    };
+
+    if register.class() == RegisterClass::SEGMENT {
+        register
+    } else if [Register::RBP, Register::EBP, Register::BP].contains(&register) {
+        match mode {
+            MachineMode::LONG_64 => Register::RBP,
+            _ => Register::EBP,
+        }
+    } else if [Register::RSP, Register::ESP, Register::SP].contains(&register) {
+        match mode {
+            MachineMode::LONG_64 => Register::RSP,
+            _ => Register::ESP,
+        }
+    } else {
+        register.largest_enclosing(mode)
+    }
+}
@@ -101,3 +101,15 @@ impl<'ctx> TryFrom<PossibleLLVMValueEnum<'ctx>> for IntValue<'ctx> {
         }
     }
 }
+
+impl<'ctx> TryFrom<PossibleLLVMValueEnum<'ctx>> for FloatValue<'ctx> {
+    type Error = Error;
+
+    fn try_from(value: PossibleLLVMValueEnum<'ctx>) -> Result<Self> {
+        if let PossibleLLVMValueEnum::FloatValue(float_val) = value {
+            Ok(float_val)
+        } else {
+            Err(Error::ConvertError)
+        }
+    }
+}
@@ -25,4 +25,13 @@ pub enum Error {
 
     #[error("{0}")]
     UnsupportedInstr(&'static str),
+
+    #[error(transparent)]
+    Zydis(#[from] zydis::Status),
+
+    #[error("Runtime address is required to lift control flow instructions")]
+    UnknownRuntimeAddress,
+
+    #[error("Block at {0:#x} was already lifted")]
+    BlockAlreadyLifted(u64),
 }
@@ -2,7 +2,114 @@ use super::{Error, ExtendedRegisterEnum, LifterX86, Result};
 
 use inkwell::{values::IntValue, IntPredicate};
 
+/// Condition codes shared by Jcc, SETcc and CMOVcc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub(crate) enum ConditionCode {
+    /// Overflow (OF = 1)
+    O,
+    /// Not overflow (OF = 0)
+    NO,
+    /// Below (CF = 1)
+    B,
+    /// Not below (CF = 0)
+    NB,
+    /// Zero (ZF = 1)
+    Z,
+    /// Not zero (ZF = 0)
+    NZ,
+    /// Below or equal (CF = 1 or ZF = 1)
+    BE,
+    /// Not below or equal (CF = 0 and ZF = 0)
+    NBE,
+    /// Sign (SF = 1)
+    S,
+    /// Not sign (SF = 0)
+    NS,
+    /// Parity (PF = 1)
+    P,
+    /// Not parity (PF = 0)
+    NP,
+    /// Less (SF != OF)
+    L,
+    /// Not less (SF = OF)
+    NL,
+    /// Less or equal (ZF = 1 or SF != OF)
+    LE,
+    /// Not less or equal (ZF = 0 and SF = OF)
+    NLE,
+}
+
 impl<'ctx> LifterX86<'ctx> {
+    /// Builds `i1` value which is true when `condition_code` holds for current flags
+    pub(crate) fn compute_condition(
+        &self,
+        condition_code: ConditionCode,
+    ) -> Result<IntValue<'ctx>> {
+        let builder = &self.builder;
+
+        let condition = match condition_code {
+            ConditionCode::O => self.load_flag(ExtendedRegisterEnum::OF)?,
+            ConditionCode::NO => {
+                builder.build_not(self.load_flag(ExtendedRegisterEnum::OF)?, "not_of")?
+            }
+            ConditionCode::B => self.load_flag(ExtendedRegisterEnum::CF)?,
+            ConditionCode::NB => {
+                builder.build_not(self.load_flag(ExtendedRegisterEnum::CF)?, "not_cf")?
+            }
+            ConditionCode::Z => self.load_flag(ExtendedRegisterEnum::ZF)?,
+            ConditionCode::NZ => {
+                builder.build_not(self.load_flag(ExtendedRegisterEnum::ZF)?, "not_zf")?
+            }
+            ConditionCode::BE | ConditionCode::NBE => {
+                let cf = self.load_flag(ExtendedRegisterEnum::CF)?;
+                let zf = self.load_flag(ExtendedRegisterEnum::ZF)?;
+                let cf_or_zf = builder.build_or(cf, zf, "cf_or_zf")?;
+
+                if condition_code == ConditionCode::BE {
+                    cf_or_zf
+                } else {
+                    builder.build_not(cf_or_zf, "not_cf_or_zf")?
+                }
+            }
+            ConditionCode::S => self.load_flag(ExtendedRegisterEnum::SF)?,
+            ConditionCode::NS => {
+                builder.build_not(self.load_flag(ExtendedRegisterEnum::SF)?, "not_sf")?
+            }
+            ConditionCode::P => self.load_flag(ExtendedRegisterEnum::PF)?,
+            ConditionCode::NP => {
+                builder.build_not(self.load_flag(ExtendedRegisterEnum::PF)?, "not_pf")?
+            }
+            ConditionCode::L | ConditionCode::NL => {
+                let sf = self.load_flag(ExtendedRegisterEnum::SF)?;
+                let of = self.load_flag(ExtendedRegisterEnum::OF)?;
+                let predicate = if condition_code == ConditionCode::L {
+                    IntPredicate::NE
+                } else {
+                    IntPredicate::EQ
+                };
+
+                builder.build_int_compare(predicate, sf, of, "sf_cmp_of")?
+            }
+            ConditionCode::LE | ConditionCode::NLE => {
+                let zf = self.load_flag(ExtendedRegisterEnum::ZF)?;
+                let sf = self.load_flag(ExtendedRegisterEnum::SF)?;
+                let of = self.load_flag(ExtendedRegisterEnum::OF)?;
+
+                let sf_neq_of = builder.build_int_compare(IntPredicate::NE, sf, of, "sf_neq_of")?;
+                let less_or_equal = builder.build_or(zf, sf_neq_of, "zf_or_sf_neq_of")?;
+
+                if condition_code == ConditionCode::LE {
+                    less_or_equal
+                } else {
+                    builder.build_not(less_or_equal, "not_less_or_equal")?
+                }
+            }
+        };
+
+        Ok(condition)
+    }
+
     pub(super) fn compute_aux_flag(
         &self,
         lhs: IntValue<'ctx>,
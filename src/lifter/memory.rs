@@ -0,0 +1,417 @@
+//! Memory models decide what guest memory accesses are lifted into
+use super::{LifterX86, Result};
+
+use std::cell::OnceCell;
+
+use inkwell::{
+    types::IntType,
+    values::{IntValue, PointerValue},
+    AddressSpace, IntPredicate,
+};
+
+/// How guest memory is represented in the lifted function.
+///
+/// `address` is the effective address of the access (already including the segment base), the
+/// builder is positioned where the access has to be emitted.
+pub trait MemoryModel<'ctx> {
+    /// Called once when the lifter is created, with the builder positioned in the entry block
+    fn prepare(&self, _lifter: &LifterX86<'ctx>) -> Result<()> {
+        Ok(())
+    }
+
+    fn load(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        ty: IntType<'ctx>,
+    ) -> Result<IntValue<'ctx>>;
+
+    fn store(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        value: IntValue<'ctx>,
+    ) -> Result<()>;
+
+    /// Host pointer for `address`, if the model maps guest memory onto real memory. Locked
+    /// instructions are lifted as atomic operations on it, otherwise the LOCK prefix is ignored
+    fn pointer(
+        &self,
+        _lifter: &LifterX86<'ctx>,
+        _address: IntValue<'ctx>,
+    ) -> Result<Option<PointerValue<'ctx>>> {
+        Ok(None)
+    }
+}
+
+/// Memory operand of the locked instruction being lifted. Its loads return `loaded` and its
+/// stores only record the value, which is then written with a single `cmpxchg`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LockedAccess<'ctx> {
+    pub(crate) loaded: IntValue<'ctx>,
+    pub(crate) stored: Option<IntValue<'ctx>>,
+}
+
+/// Guest memory is a single array allocated on the stack of the lifted function. The stack pointer
+/// the function was entered with points to its middle, so addresses are offsets from it.
+///
+/// Works well for obfuscated code which mostly touches its own stack, LLVM can then promote the
+/// accesses to registers. Accesses outside of the array go to a scratch slot instead.
+#[derive(Debug)]
+pub struct FlatStackMemory<'ctx> {
+    /// Size in 16 byte elements
+    size: u64,
+    memory: OnceCell<PointerValue<'ctx>>,
+    /// Target of out of bounds accesses, large enough for any of them
+    scratch: OnceCell<PointerValue<'ctx>>,
+}
+
+impl<'ctx> FlatStackMemory<'ctx> {
+    pub const DEFAULT_SIZE: u64 = 0x1000;
+
+    /// Size of the scratch slot in 16 byte elements, the widest access is a ZMM register
+    const SCRATCH_SIZE: u64 = 4;
+
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            memory: OnceCell::new(),
+            scratch: OnceCell::new(),
+        }
+    }
+
+    /// Pointer to `size` bytes at `address`, or to the scratch slot if they aren't in the array
+    fn pointer_to(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        size: u32,
+    ) -> Result<PointerValue<'ctx>> {
+        let builder = &lifter.builder;
+        let memory = *self
+            .memory
+            .get()
+            .expect("Memory model must be prepared before use");
+        let scratch = *self
+            .scratch
+            .get()
+            .expect("Memory model must be prepared before use");
+
+        let address_ty = lifter.get_max_int_type();
+        let address = lifter.create_z_ext_or_trunc(address, address_ty)?;
+        let bytes = self.size * 16;
+        let offset = match lifter.entry_stack_pointer.get() {
+            Some(entry_stack_pointer) => {
+                let entry_stack_pointer =
+                    lifter.create_z_ext_or_trunc(entry_stack_pointer, address_ty)?;
+                let offset = builder.build_int_sub(address, entry_stack_pointer, "")?;
+                builder.build_int_add(offset, address_ty.const_int(bytes / 2, false), "")?
+            }
+            None => address,
+        };
+
+        let in_bounds = builder.build_int_compare(
+            IntPredicate::ULE,
+            offset,
+            address_ty.const_int(bytes.saturating_sub(size.into()), false),
+            "in_bounds",
+        )?;
+        let element =
+            unsafe { builder.build_gep(lifter.context.i8_type(), memory, &[offset], "")? };
+        let pointer = builder
+            .build_select(in_bounds, element, scratch, "")?
+            .into_pointer_value();
+        Ok(pointer)
+    }
+}
+
+impl Default for FlatStackMemory<'_> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_SIZE)
+    }
+}
+
+impl<'ctx> MemoryModel<'ctx> for FlatStackMemory<'ctx> {
+    fn prepare(&self, lifter: &LifterX86<'ctx>) -> Result<()> {
+        let i128_ty = lifter.context.i128_type();
+        let memory = lifter.builder.build_array_alloca(
+            i128_ty,
+            i128_ty.const_int(self.size, false),
+            "stackmemory",
+        )?;
+
+        let scratch = lifter.builder.build_array_alloca(
+            i128_ty,
+            i128_ty.const_int(Self::SCRATCH_SIZE, false),
+            "scratchmemory",
+        )?;
+
+        self.memory
+            .set(memory)
+            .expect("Memory model must be prepared only once");
+        self.scratch
+            .set(scratch)
+            .expect("Memory model must be prepared only once");
+        Ok(())
+    }
+
+    fn load(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        ty: IntType<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let pointer = self.pointer_to(lifter, address, ty.get_bit_width().div_ceil(8))?;
+        // NOTE: revisit AddressSpace in future maybe
+        let value = lifter.builder.build_load(ty, pointer, "")?.into_int_value();
+        Ok(value)
+    }
+
+    fn store(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let size = value.get_type().get_bit_width().div_ceil(8);
+        let pointer = self.pointer_to(lifter, address, size)?;
+        lifter.builder.build_store(pointer, value)?;
+        Ok(())
+    }
+}
+
+/// Guest addresses are host addresses (`inttoptr`). Meant for code which is recompiled and run in
+/// the same address space as the original.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawPointerMemory;
+
+impl RawPointerMemory {
+    fn pointer_to<'ctx>(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+    ) -> Result<PointerValue<'ctx>> {
+        let ptr_ty = lifter.context.ptr_type(AddressSpace::default());
+        let pointer = lifter.builder.build_int_to_ptr(address, ptr_ty, "")?;
+        Ok(pointer)
+    }
+}
+
+impl<'ctx> MemoryModel<'ctx> for RawPointerMemory {
+    fn load(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        ty: IntType<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let pointer = self.pointer_to(lifter, address)?;
+        let value = lifter.builder.build_load(ty, pointer, "")?.into_int_value();
+        Ok(value)
+    }
+
+    fn store(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let pointer = self.pointer_to(lifter, address)?;
+        lifter.builder.build_store(pointer, value)?;
+        Ok(())
+    }
+
+    fn pointer(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+    ) -> Result<Option<PointerValue<'ctx>>> {
+        self.pointer_to(lifter, address).map(Some)
+    }
+}
+
+/// Every access becomes a call to an external function, so memory can be instrumented or emulated
+/// after lifting:
+///
+/// ```llvm
+/// declare iN @__read_memory_N(i64 %address)
+/// declare void @__write_memory_N(i64 %address, iN %value)
+/// ```
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CallbackMemory;
+
+impl CallbackMemory {
+    pub const READ_PREFIX: &'static str = "__read_memory_";
+    pub const WRITE_PREFIX: &'static str = "__write_memory_";
+}
+
+impl<'ctx> MemoryModel<'ctx> for CallbackMemory {
+    fn load(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        ty: IntType<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let name = format!("{}{}", Self::READ_PREFIX, ty.get_bit_width());
+        let address = widen_address(lifter, address)?;
+
+        let read_func = lifter.module.get_function(&name).unwrap_or_else(|| {
+            let fn_ty = ty.fn_type(&[address.get_type().into()], false);
+            lifter.module.add_function(&name, fn_ty, None)
+        });
+
+        let value = lifter
+            .builder
+            .build_call(read_func, &[address.into()], "")?
+            .try_as_basic_value()
+            .left()
+            .expect("Memory read callback must return a value")
+            .into_int_value();
+        Ok(value)
+    }
+
+    fn store(
+        &self,
+        lifter: &LifterX86<'ctx>,
+        address: IntValue<'ctx>,
+        value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let ty = value.get_type();
+        let name = format!("{}{}", Self::WRITE_PREFIX, ty.get_bit_width());
+        let address = widen_address(lifter, address)?;
+
+        let write_func = lifter.module.get_function(&name).unwrap_or_else(|| {
+            let fn_ty = lifter
+                .context
+                .void_type()
+                .fn_type(&[address.get_type().into(), ty.into()], false);
+            lifter.module.add_function(&name, fn_ty, None)
+        });
+
+        lifter
+            .builder
+            .build_call(write_func, &[address.into(), value.into()], "")?;
+        Ok(())
+    }
+}
+
+/// Callbacks always take a 64 bit address, whatever the machine mode is
+fn widen_address<'ctx>(
+    lifter: &LifterX86<'ctx>,
+    address: IntValue<'ctx>,
+) -> Result<IntValue<'ctx>> {
+    lifter.create_z_ext_or_trunc(address, lifter.context.i64_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallbackMemory, FlatStackMemory, MemoryModel, RawPointerMemory};
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::{Execution, MemoryImage};
+    use crate::compiler::Compiler;
+
+    use inkwell::context::Context;
+    use zydis::{AllOperands, Decoder, MachineMode};
+
+    const CODE_ADDRESS: u64 = 0x400000;
+
+    /// mov rdx, [rcx]; mov [rcx], rax
+    const SWAP_THROUGH_RCX: [u8; 6] = [0x48, 0x8b, 0x11, 0x48, 0x89, 0x01];
+
+    fn run<'ctx>(
+        context: &'ctx Context,
+        memory_model: Box<dyn MemoryModel<'ctx> + 'ctx>,
+        code: &[u8],
+        start: StartContextX86<u64>,
+        memory: MemoryImage,
+    ) -> Execution<u64> {
+        let instructions = Decoder::new64()
+            .decode_all::<AllOperands>(code, CODE_ADDRESS)
+            .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let compiler =
+            Compiler::new_state_function(context, MachineMode::LONG_64, CODE_ADDRESS, memory_model)
+                .unwrap();
+        compiler.lift_function(&instructions, false).unwrap();
+        compiler.create_jit().unwrap().run(start, memory).unwrap()
+    }
+
+    fn start(rcx: u64) -> StartContextX86<u64> {
+        StartContextX86 {
+            rax: 0x1122_3344_5566_7788,
+            rcx,
+            rsp: 0x8000,
+            rip: CODE_ADDRESS,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flat_stack_memory_keeps_the_stack_in_the_function() {
+        let context = Context::create();
+
+        // push rax; pop rbx
+        let execution = run(
+            &context,
+            Box::new(FlatStackMemory::default()),
+            &[0x50, 0x5b],
+            start(0),
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.context.rbx, 0x1122_3344_5566_7788);
+        assert_eq!(execution.memory.pages().count(), 0);
+    }
+
+    #[test]
+    fn flat_stack_memory_sends_far_accesses_to_scratch() {
+        let context = Context::create();
+
+        let execution = run(
+            &context,
+            Box::new(FlatStackMemory::default()),
+            &SWAP_THROUGH_RCX,
+            start(0x1_4000_0000),
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.memory.pages().count(), 0);
+    }
+
+    #[test]
+    fn raw_pointer_memory_accesses_host_memory() {
+        let context = Context::create();
+        let mut host = Box::new(0xaaaa_bbbb_cccc_dddd_u64);
+        let address = &mut *host as *mut u64 as u64;
+
+        let execution = run(
+            &context,
+            Box::new(RawPointerMemory),
+            &SWAP_THROUGH_RCX,
+            start(address),
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.context.rdx, 0xaaaa_bbbb_cccc_dddd);
+        assert_eq!(*host, 0x1122_3344_5566_7788);
+    }
+
+    #[test]
+    fn callback_memory_calls_the_memory_functions() {
+        let context = Context::create();
+        let mut memory = MemoryImage::default();
+        memory.write_u64(0x1000, 0xaaaa_bbbb_cccc_dddd);
+
+        let execution = run(
+            &context,
+            Box::new(CallbackMemory),
+            &SWAP_THROUGH_RCX,
+            start(0x1000),
+            memory,
+        );
+
+        assert_eq!(execution.context.rdx, 0xaaaa_bbbb_cccc_dddd);
+        assert_eq!(execution.memory.read_u64(0x1000), 0x1122_3344_5566_7788);
+    }
+}
@@ -1,7 +1,10 @@
-use super::{definintions::PossibleLLVMValueEnum, LifterX86, Result};
+use super::{
+    definintions::PossibleLLVMValueEnum, memory::LockedAccess, Error, ExtendedRegisterEnum,
+    LifterX86, Result,
+};
 
-use inkwell::values::{BasicValueEnum, IntValue, PointerValue};
-use zydis::{ffi::MemoryInfo, Register};
+use inkwell::{types::IntType, values::IntValue};
+use zydis::{ffi::MemoryInfo, MachineMode, Register, RegisterClass};
 
 impl<'ctx> LifterX86<'ctx> {
     pub(super) fn mergen_store_mem(
@@ -9,12 +12,17 @@ impl<'ctx> LifterX86<'ctx> {
         mem: &MemoryInfo,
         val: PossibleLLVMValueEnum<'ctx>,
     ) -> Result<()> {
-        let pointer = self.mergen_calculate_memory_operand(mem)?;
-
-        self.builder
-            .build_store(pointer, BasicValueEnum::from(val))?;
+        if let Some(access) = self.locked_access.get() {
+            let stored = self.create_z_ext_or_trunc(val.try_into()?, access.loaded.get_type())?;
+            self.locked_access.set(Some(LockedAccess {
+                stored: Some(stored),
+                ..access
+            }));
+            return Ok(());
+        }
 
-        Ok(())
+        let address = self.mergen_calculate_memory_address(mem)?;
+        self.memory.store(self, address, val.try_into()?)
     }
 
     pub(super) fn mergen_load_mem(
@@ -22,16 +30,43 @@ impl<'ctx> LifterX86<'ctx> {
         mem: &MemoryInfo,
         possible_size: u32,
     ) -> Result<IntValue<'ctx>> {
-        let pointer = self.mergen_calculate_memory_operand(mem)?;
-
         let load_type = self.context.custom_width_int_type(possible_size);
-        // NOTE: revisit AddressSpace in future maybe
-        let retval = self
-            .builder
-            .build_load(load_type, pointer, "")?
-            .into_int_value();
+        if let Some(access) = self.locked_access.get() {
+            return self.create_z_ext_or_trunc(access.loaded, load_type);
+        }
+
+        let address = self.mergen_calculate_memory_address(mem)?;
+        if let Some(value) = self.load_image_constant(address, load_type) {
+            return Ok(value);
+        }
+
+        let value = self.memory.load(self, address, load_type)?;
+        if let Some(field) = self.environment_block_field(mem) {
+            value.set_name(&field);
+        }
+        Ok(value)
+    }
 
-        Ok(retval)
+    /// Value at a constant `address` inside read-only part of the loaded image
+    fn load_image_constant(
+        &self,
+        address: IntValue<'ctx>,
+        load_type: IntType<'ctx>,
+    ) -> Option<IntValue<'ctx>> {
+        let image = self.image.get()?;
+        let address = address.get_zero_extended_constant()?;
+        let size = load_type.get_bit_width().div_ceil(8) as usize;
+        let bytes = image.read_only_bytes(address, size)?;
+
+        let words: Vec<u64> = bytes
+            .chunks(8)
+            .map(|chunk| {
+                let mut word = [0; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u64::from_le_bytes(word)
+            })
+            .collect();
+        Some(load_type.const_int_arbitrary_precision(&words))
     }
 
     pub(crate) fn mergen_get_register(
@@ -54,28 +89,48 @@ impl<'ctx> LifterX86<'ctx> {
         Ok(value)
     }
 
-    pub(crate) fn mergen_calculate_memory_operand(
+    /// Address which is passed to the memory model. Real mode addresses are `segment * 16 +
+    /// offset`. In the other modes FS and GS accesses are relative to the segment bases and the
+    /// remaining segments are flat
+    pub(crate) fn mergen_calculate_memory_address(
         &self,
         mem: &MemoryInfo,
-    ) -> Result<PointerValue<'ctx>> {
+    ) -> Result<IntValue<'ctx>> {
         let effective_address = self.mergen_get_effective_address(mem)?;
 
-        let memory_opperand = if mem.segment == Register::GS {
-            unimplemented!("TEB support isn't added yet");
-        } else {
-            self.stackmemory
-        };
+        if self.mode == MachineMode::REAL_16 && mem.segment != Register::NONE {
+            let builder = &self.builder;
+            let i_64_ty = self.context.i64_type();
+
+            let segment_reg = mem.segment.into();
+            let Some(PossibleLLVMValueEnum::IntValue(segment)) =
+                self.regs_hashmap().get(&segment_reg).copied()
+            else {
+                return Err(Error::RegUnwrapError(segment_reg));
+            };
+            let segment = self.create_z_ext_or_trunc(segment, self.context.i16_type())?;
+            let segment = builder.build_int_z_extend(segment, i_64_ty, "")?;
+            let segment_base =
+                builder.build_left_shift(segment, i_64_ty.const_int(4, false), "segment_base_")?;
+            return Ok(builder.build_int_add(segment_base, effective_address, "")?);
+        }
 
-        let pointer = unsafe {
-            self.builder.build_gep(
-                self.context.i8_type(),
-                memory_opperand,
-                &[effective_address],
-                "",
-            )?
+        let segment_base_reg = match mem.segment {
+            Register::FS => ExtendedRegisterEnum::FSBASE,
+            Register::GS => ExtendedRegisterEnum::GSBASE,
+            _ => return Ok(effective_address),
+        };
+        let Some(PossibleLLVMValueEnum::IntValue(segment_base)) =
+            self.regs_hashmap().get(&segment_base_reg).copied()
+        else {
+            return Err(Error::RegUnwrapError(segment_base_reg));
         };
 
-        Ok(pointer)
+        let segment_base = self.create_z_ext_or_trunc(segment_base, self.context.i64_type())?;
+        let address = self
+            .builder
+            .build_int_add(segment_base, effective_address, "")?;
+        Ok(address)
     }
 
     // Used directly only here and by LEA
@@ -86,7 +141,12 @@ impl<'ctx> LifterX86<'ctx> {
         let i_64_ty = ctx.i64_type();
 
         let base_value = if mem.base != Register::NONE {
-            let base_value: IntValue<'_> = self.get_register(mem.base)?.try_into()?;
+            // RIP relative addresses are constant when the runtime address is known
+            let base_value: IntValue<'_> = if mem.base.class() == RegisterClass::IP {
+                self.load_register_value(&mem.base)?.try_into()?
+            } else {
+                self.get_register(mem.base)?.try_into()?
+            };
             // TODO: consider not hardcoding in future
             let base_value_z_ext = builder.build_int_z_extend(base_value, i_64_ty, "")?;
             Some(base_value_z_ext)
@@ -128,6 +188,35 @@ impl<'ctx> LifterX86<'ctx> {
             effective_address = builder.build_int_add(effective_address, disp_value, "")?;
         }
 
+        // Offsets wrap around at the address size, e.g. `[bp - 2]` with BP = 0 is 0xFFFE
+        let address_width = self.address_width(mem);
+        if address_width < 64 {
+            let address_ty = ctx.custom_width_int_type(address_width);
+            let wrapped = builder.build_int_truncate(effective_address, address_ty, "")?;
+            effective_address = builder.build_int_z_extend(wrapped, i_64_ty, "")?;
+        }
+
         Ok(effective_address)
     }
+
+    /// Width of the offsets computed by a memory operand, taken from its registers when it has
+    /// some
+    fn address_width(&self, mem: &MemoryInfo) -> u32 {
+        let register = [mem.base, mem.index]
+            .into_iter()
+            .find(|register| *register != Register::NONE);
+
+        match register {
+            Some(register) => match register.class() {
+                RegisterClass::GPR16 => 16,
+                RegisterClass::GPR32 => 32,
+                _ => 64,
+            },
+            None => match self.mode {
+                MachineMode::LONG_64 => 64,
+                MachineMode::LONG_COMPAT_32 | MachineMode::LEGACY_32 => 32,
+                MachineMode::LONG_COMPAT_16 | MachineMode::LEGACY_16 | MachineMode::REAL_16 => 16,
+            },
+        }
+    }
 }
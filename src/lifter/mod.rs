@@ -1,29 +1,42 @@
 pub(super) mod error;
-pub(crate) use error::{Error, Result};
+pub(crate) use common::largest_enclosing_register;
+pub use error::Error;
+pub(crate) use error::Result;
 
+use crate::compiler::jump_table::JumpTable;
+use crate::loader::LoadedImage;
 use crate::miscellaneous::ExtendedRegisterEnum;
 use std::{
-    cell::{Cell, UnsafeCell},
-    collections::HashMap,
+    cell::{Cell, OnceCell, UnsafeCell},
+    collections::{BTreeMap, HashMap},
 };
 
+use blocks::GuestBlock;
 use definintions::{PossibleLLVMTypeEnum, PossibleLLVMValueEnum};
 use inkwell::{
     builder::Builder,
     context::Context,
     module::Module,
     types::IntType,
-    values::{FunctionValue, IntValue, PointerValue},
+    values::{BasicValueEnum, FloatValue, FunctionValue, IntValue, PointerValue},
 };
+use memory::{LockedAccess, MemoryModel};
+use state::CallPolicy;
 use zydis::{ffi::MemoryInfo, MachineMode, Register, RegisterClass};
 
+mod blocks;
 mod common;
 mod getters;
 mod setters;
+mod stack;
+mod teb;
 
 mod mergen_getters_and_setters;
 
-mod flagops;
+pub mod memory;
+pub mod state;
+
+pub(crate) mod flagops;
 pub(crate) mod semantics;
 
 mod definintions;
@@ -37,8 +50,35 @@ pub struct LifterX86<'ctx> {
     //pub(super) regs_hashmap:
     //    RefCell<HashMap<ExtendedRegister, PossibleLLVMValueEnum<'ctx>>>,
     pub(super) regs_hashmap: UnsafeCell<HashMap<ExtendedRegisterEnum, PossibleLLVMValueEnum<'ctx>>>,
-    pub stackmemory: PointerValue<'ctx>,
-    pub runtime_address: Option<Cell<u64>>,
+    /// Register values the function was entered with, used for paths which don't define a
+    /// register, see [blocks]
+    pub(super) entry_registers:
+        UnsafeCell<HashMap<ExtendedRegisterEnum, PossibleLLVMValueEnum<'ctx>>>,
+    /// Decides what guest memory accesses are lifted into
+    pub memory: Box<dyn MemoryModel<'ctx> + 'ctx>,
+    pub runtime_address: Cell<Option<u64>>,
+    pub(crate) func_value: FunctionValue<'ctx>,
+    /// Basic blocks created for guest addresses (branch targets and fall-throughs)
+    pub(super) blocks: UnsafeCell<BTreeMap<u64, GuestBlock<'ctx>>>,
+    /// Loaded executable. Reads from its read-only sections at constant addresses become constants
+    pub(crate) image: OnceCell<LoadedImage>,
+    /// Set while a locked instruction is lifted as an atomic operation
+    pub(crate) locked_access: Cell<Option<LockedAccess<'ctx>>>,
+    pub(crate) call_policy: Cell<CallPolicy>,
+    /// Register state passed to called functions
+    pub(crate) call_state: OnceCell<PointerValue<'ctx>>,
+    /// Parameter of functions lifted with the state ABI, see [state]
+    pub(crate) state_param: Option<PointerValue<'ctx>>,
+    /// Stack pointer the function was entered with, see [stack]
+    pub(crate) entry_stack_pointer: Cell<Option<IntValue<'ctx>>>,
+    /// Offsets from [Self::entry_stack_pointer] which were already computed, see [stack]
+    pub(super) stack_offsets: UnsafeCell<HashMap<IntValue<'ctx>, stack::StackOffset>>,
+    /// Jump tables recovered with the control flow graph, by address of their jump
+    pub(crate) jump_tables: UnsafeCell<BTreeMap<u64, JumpTable>>,
+    /// Jump addresses of [Self::jump_tables], by address of the instruction reading their entries
+    pub(crate) jump_table_loads: UnsafeCell<BTreeMap<u64, u64>>,
+    /// Index of the jump table entry read by the last lifted table access
+    pub(crate) jump_table_index: Cell<Option<IntValue<'ctx>>>,
 }
 
 impl<'ctx> LifterX86<'ctx> {
@@ -48,25 +88,21 @@ impl<'ctx> LifterX86<'ctx> {
         func_value: FunctionValue<'ctx>,
         module: Module<'ctx>,
         runtime_address: Option<u64>,
+        memory: Box<dyn MemoryModel<'ctx> + 'ctx>,
     ) -> Result<Self> {
         let builder = context.create_builder();
 
-        let regs_hashmap = prep_regs_hashmap_experimental(&func_value, &mode);
+        let state_param = state::state_parameter(func_value);
+        let regs_hashmap = match state_param {
+            Some(_) => HashMap::new(),
+            None => prep_regs_hashmap_experimental(&func_value, &mode),
+        };
 
         let entry_basic_block = context.append_basic_block(func_value, "entry");
         builder.position_at_end(entry_basic_block);
 
-        const STACK_SIZE: u64 = 0x1000;
-        let stackmemory = builder.build_array_alloca(
-            context.i128_type(),
-            context.i128_type().const_int(STACK_SIZE, false),
-            "stackmemory",
-        )?;
-
         // TODO: Consider assigning SP and BP to half of the stack size
 
-        builder.position_at_end(entry_basic_block);
-
         let s = Self {
             context,
             builder,
@@ -74,32 +110,68 @@ impl<'ctx> LifterX86<'ctx> {
             mode,
             //regs_hashmap: RefCell::new(regs_hashmap),
             regs_hashmap: UnsafeCell::new(regs_hashmap),
+            entry_registers: UnsafeCell::new(HashMap::new()),
             //func_value,
-            stackmemory,
-            runtime_address: runtime_address.map(Cell::new),
+            memory,
+            runtime_address: Cell::new(runtime_address),
+            func_value,
+            blocks: UnsafeCell::new(BTreeMap::new()),
+            image: OnceCell::new(),
+            locked_access: Cell::new(None),
+            call_policy: Cell::default(),
+            call_state: OnceCell::new(),
+            state_param,
+            entry_stack_pointer: Cell::new(None),
+            stack_offsets: UnsafeCell::new(HashMap::new()),
+            jump_tables: UnsafeCell::new(BTreeMap::new()),
+            jump_table_loads: UnsafeCell::new(BTreeMap::new()),
+            jump_table_index: Cell::new(None),
         };
+        if let Some(state) = state_param {
+            s.load_state(state)?;
+        }
+        s.record_entry_stack_pointer();
+        s.record_entry_registers();
+        s.memory.prepare(&s)?;
+
         Ok(s)
     }
 
+    /// Replaces current values of the given registers and flags
+    pub(crate) fn set_register_values(
+        &self,
+        values: HashMap<ExtendedRegisterEnum, BasicValueEnum<'ctx>>,
+    ) -> Result<()> {
+        let regs_hashmap = self.regs_hashmap_mut();
+        for (reg, value) in values {
+            regs_hashmap.insert(reg, value.try_into()?);
+        }
+        self.record_entry_stack_pointer();
+        self.record_entry_registers();
+
+        Ok(())
+    }
+
     pub(crate) fn runtime_address(&self) -> Option<u64> {
-        self.runtime_address
-            .as_ref()
-            .map(|addr_cell| addr_cell.get())
+        self.runtime_address.get()
+    }
+
+    pub(crate) fn set_runtime_address(&self, address: u64) {
+        self.runtime_address.set(Some(address));
     }
 
     pub(crate) fn increase_ip(&self, instr_length: u8) {
-        let Some(current_ip_cell) = &self.runtime_address else {
+        let Some(current_ip) = self.runtime_address() else {
             return;
         };
 
-        let current_ip = current_ip_cell.get();
         let updated_ip = current_ip + instr_length as u64;
 
-        current_ip_cell.set(updated_ip);
+        self.set_runtime_address(updated_ip);
     }
 
     pub(super) fn get_max_int_type(&self) -> IntType<'ctx> {
-        let example_reg = Register::AX.largest_enclosing(self.mode); // random rax for convenience
+        let example_reg = largest_enclosing_register(Register::AX, self.mode); // random rax for convenience
         self.context
             .custom_width_int_type(example_reg.width(self.mode).into())
     }
@@ -113,8 +185,8 @@ impl<'ctx> LifterX86<'ctx> {
             RegisterClass::GPR64 => ctx.i64_type().into(),
             RegisterClass::MMX => ctx.f64_type().into(),
             RegisterClass::XMM => ctx.i128_type().into(),
-            RegisterClass::YMM => panic!("LLVM Doesn't support 256 bit floats"),
-            RegisterClass::ZMM => panic!("LLVM Doesn't support 512 bit floats"),
+            RegisterClass::YMM => ctx.custom_width_int_type(256).into(),
+            RegisterClass::ZMM => ctx.custom_width_int_type(512).into(),
             //RegisterClass::FLAGS => util::get_int_ty(ctx, reg.width(self.mode).into()).into(),
             //RegisterClass::IP => util::get_int_ty(ctx, reg.width(self.mode).into()).into(),
             RegisterClass::IP | RegisterClass::FLAGS => ctx
@@ -144,6 +216,30 @@ impl<'ctx> LifterX86<'ctx> {
         }
     }
 
+    /// Bits of `value` as an integer of the same width
+    pub(crate) fn float_as_int(&self, value: FloatValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let ctx = self.context;
+        let float_ty = value.get_type();
+
+        let width = if float_ty == ctx.f16_type() {
+            16
+        } else if float_ty == ctx.f32_type() {
+            32
+        } else if float_ty == ctx.f64_type() {
+            64
+        } else if float_ty == ctx.x86_f80_type() {
+            80
+        } else {
+            128
+        };
+
+        let int_value = self
+            .builder
+            .build_bit_cast(value, ctx.custom_width_int_type(width), "")?
+            .into_int_value();
+        Ok(int_value)
+    }
+
     pub fn retdec_get_default_type(&self) -> IntType<'ctx> {
         //self.retdec_get_integer_type_from_byte_size(self.retdec_get_arch_byte_size().into())
         let arch_byte_size = self.retdec_get_arch_byte_size();
@@ -166,7 +262,7 @@ fn prep_regs_hashmap_experimental<'ctx>(
 ) -> HashMap<ExtendedRegisterEnum, PossibleLLVMValueEnum<'ctx>> {
     let mut registers_hashmap = HashMap::new();
     let regs: [Register; 17] =
-        crate::compiler::ALL_REGS_IN_MIN_SIZE.map(|reg| reg.largest_enclosing(*mode));
+        crate::compiler::ALL_REGS_IN_MIN_SIZE.map(|reg| largest_enclosing_register(reg, *mode));
 
     for (id, reg) in regs.into_iter().enumerate() {
         registers_hashmap.insert(reg.into(), fn_val.get_nth_param(id as u32).unwrap());
@@ -177,6 +273,40 @@ fn prep_regs_hashmap_experimental<'ctx>(
         last_index += 1;
         registers_hashmap.insert(cpu_flag, fn_val.get_nth_param(last_index as u32).unwrap());
     }
+    for reg in crate::compiler::VECTOR_REGS {
+        last_index += 1;
+        registers_hashmap.insert(reg.into(), fn_val.get_nth_param(last_index as u32).unwrap());
+    }
+    let vector_parts = crate::compiler::VECTOR_HIGH_REGS
+        .into_iter()
+        .chain(crate::compiler::ZMM_HIGH_REGS)
+        .chain(crate::compiler::MASK_REGS);
+    for reg in vector_parts {
+        last_index += 1;
+        registers_hashmap.insert(reg.into(), fn_val.get_nth_param(last_index as u32).unwrap());
+    }
+    for segment_base in crate::compiler::SEGMENT_BASES {
+        last_index += 1;
+        registers_hashmap.insert(
+            segment_base,
+            fn_val.get_nth_param(last_index as u32).unwrap(),
+        );
+    }
+    for segment in crate::compiler::SEGMENT_REGS {
+        last_index += 1;
+        registers_hashmap.insert(
+            segment.into(),
+            fn_val.get_nth_param(last_index as u32).unwrap(),
+        );
+    }
+    for reg in crate::compiler::X87_REGS {
+        last_index += 1;
+        registers_hashmap.insert(reg.into(), fn_val.get_nth_param(last_index as u32).unwrap());
+    }
+    for word in crate::compiler::X87_WORDS {
+        last_index += 1;
+        registers_hashmap.insert(word, fn_val.get_nth_param(last_index as u32).unwrap());
+    }
 
     registers_hashmap
         .into_iter()
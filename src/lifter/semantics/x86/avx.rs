@@ -0,0 +1,613 @@
+use super::{
+    common::immediate,
+    sse::{packed_add_sub_kind, XMM_WIDTH},
+    LifterX86, Result,
+};
+use crate::lifter::Error;
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    values::{IntValue, VectorValue},
+    IntPredicate,
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, Mnemonic, Operands, RegisterClass,
+};
+
+/// Width of YMM registers. Their lower halves are the XMM registers, upper halves are kept under
+/// the YMM keys
+pub(super) const YMM_WIDTH: u32 = 256;
+/// Width of ZMM registers. Bits above [YMM_WIDTH] are kept under the ZMM keys
+pub(super) const ZMM_WIDTH: u32 = 512;
+
+impl<'ctx> LifterX86<'ctx> {
+    /// VMOVDQA, VMOVDQU, VMOVAPS, VMOVUPS, VMOVAPD and VMOVUPD
+    pub(super) fn lift_vmov<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+
+        let value = self.load_vector_operand(&ops[1])?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VMOVD and VMOVQ
+    pub(super) fn lift_vmovd_vmovq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let width = match instr.mnemonic {
+            Mnemonic::VMOVD => 32,
+            Mnemonic::VMOVQ => 64,
+            _ => unreachable!(),
+        };
+        let value_ty = self.context.custom_width_int_type(width);
+
+        let value = self.load_vector_operand(&ops[1])?;
+        let value = self.builder.build_int_truncate(value, value_ty, "")?;
+
+        if is_vector_register(dest) {
+            self.store_vector_operand(dest, value)
+        } else {
+            self.store_op(dest, value)
+        }
+    }
+
+    /// VPAND, VPANDN, VPOR, VPXOR and their floating point twins (VANDPS, VXORPS, ...)
+    pub(super) fn lift_vpacked_logical<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        self.build_vex_binary(instr, 64, |lhs, rhs| {
+            self.build_packed_logical(instr.mnemonic, lhs, rhs)
+        })
+    }
+
+    /// VPADD* and VPSUB*, including the saturating variants
+    pub(super) fn lift_vpacked_add_sub<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let (_, element_width, _) = packed_add_sub_kind(instr.mnemonic);
+
+        self.build_vex_binary(instr, element_width, |lhs, rhs| {
+            self.build_packed_add_sub(instr.mnemonic, lhs, rhs)
+        })
+    }
+
+    /// VADDPS, VADDPD, VSUBPS, VSUBPD, VMULPS, VMULPD, VDIVPS and VDIVPD
+    pub(super) fn lift_vpacked_float_arith<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+    ) -> Result<()> {
+        let element_width = packed_float_width(instr.mnemonic);
+
+        self.build_vex_binary(instr, element_width, |lhs, rhs| {
+            self.build_packed_float_arith(instr.mnemonic, lhs, rhs)
+        })
+    }
+
+    /// VPCMPEQ* and VPCMPGT*. Elements become all ones when they are equal or signed greater
+    pub(super) fn lift_vpcmp<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+
+        let (predicate, element_width) = match instr.mnemonic {
+            Mnemonic::VPCMPEQB => (IntPredicate::EQ, 8),
+            Mnemonic::VPCMPEQW => (IntPredicate::EQ, 16),
+            Mnemonic::VPCMPEQD => (IntPredicate::EQ, 32),
+            Mnemonic::VPCMPEQQ => (IntPredicate::EQ, 64),
+            Mnemonic::VPCMPGTB => (IntPredicate::SGT, 8),
+            Mnemonic::VPCMPGTW => (IntPredicate::SGT, 16),
+            Mnemonic::VPCMPGTD => (IntPredicate::SGT, 32),
+            Mnemonic::VPCMPGTQ => (IntPredicate::SGT, 64),
+            _ => unreachable!(),
+        };
+
+        self.build_vex_binary(instr, element_width, |lhs, rhs| {
+            let holds = builder.build_int_compare(predicate, lhs, rhs, "")?;
+            Ok(builder.build_int_s_extend(holds, lhs.get_type(), "")?)
+        })
+    }
+
+    /// VPMOVMSKB. Gathers the sign bits of all bytes
+    pub(super) fn lift_vpmovmskb<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let bytes = self.bits_as_vector(src, 8)?;
+        let negative = builder.build_int_compare(
+            IntPredicate::SLT,
+            bytes,
+            bytes.get_type().const_zero(),
+            "",
+        )?;
+
+        let mask_ty = self
+            .context
+            .custom_width_int_type(bytes.get_type().get_size());
+        let mask = builder
+            .build_bit_cast(negative, mask_ty, "")?
+            .into_int_value();
+        let mask = builder.build_int_z_extend(
+            mask,
+            self.context.custom_width_int_type(dest.size.into()),
+            "",
+        )?;
+
+        self.store_op(dest, mask)
+    }
+
+    /// VPTEST. ZF is set when the operands have no common bits, CF when the second one has no
+    /// bits outside of the first one
+    pub(super) fn lift_vptest<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let lhs = self.load_vector_operand(&ops[0])?;
+        let rhs = self.load_vector_operand(&ops[1])?;
+        let zero = lhs.get_type().const_zero();
+
+        let common = builder.build_and(lhs, rhs, "")?;
+        let zf = builder.build_int_compare(IntPredicate::EQ, common, zero, "")?;
+
+        let not_lhs = builder.build_not(lhs, "")?;
+        let outside = builder.build_and(not_lhs, rhs, "")?;
+        let cf = builder.build_int_compare(IntPredicate::EQ, outside, zero, "")?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, cf);
+
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::AF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::PF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::SF, false);
+        Ok(())
+    }
+
+    /// VPSHUFD. Shuffles dwords inside each 128 bit lane
+    pub(super) fn lift_vpshufd<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let order = immediate(&ops[2]);
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let src = self.bits_as_vector(src, 32)?;
+
+        let lanes = src.get_type().get_size() / 4;
+        let mask: Vec<u32> = (0..lanes)
+            .flat_map(|lane| (0..4).map(move |i| lane * 4 + ((order >> (i * 2)) as u32 & 3)))
+            .collect();
+        let shuffled = self.build_shuffle(src, src, &mask)?;
+
+        let value = self.vector_as_bits(shuffled)?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VPUNPCKL* and VPUNPCKH*. Like the SSE forms, but inside each 128 bit lane
+    pub(super) fn lift_vpunpck<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let (element_width, high) = match instr.mnemonic {
+            Mnemonic::VPUNPCKLBW => (8, false),
+            Mnemonic::VPUNPCKLWD => (16, false),
+            Mnemonic::VPUNPCKLDQ => (32, false),
+            Mnemonic::VPUNPCKLQDQ => (64, false),
+            Mnemonic::VPUNPCKHBW => (8, true),
+            Mnemonic::VPUNPCKHWD => (16, true),
+            Mnemonic::VPUNPCKHDQ => (32, true),
+            Mnemonic::VPUNPCKHQDQ => (64, true),
+            _ => unreachable!(),
+        };
+
+        self.build_vex_binary(instr, element_width, |lhs, rhs| {
+            let count = lhs.get_type().get_size();
+            let lane_count = XMM_WIDTH / element_width;
+
+            // Elements of the second shuffle operand are numbered after the ones of the first
+            let mask: Vec<u32> = (0..count / lane_count)
+                .flat_map(|lane| {
+                    let first = lane * lane_count + if high { lane_count / 2 } else { 0 };
+                    (first..first + lane_count / 2).flat_map(move |i| [i, count + i])
+                })
+                .collect();
+            self.build_shuffle(lhs, rhs, &mask)
+        })
+    }
+
+    /// VPERMQ and VPERMPD. Each 2 bits of the immediate select the source qword
+    pub(super) fn lift_vpermq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let order = immediate(&ops[2]);
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let src = self.bits_as_vector(src, 64)?;
+
+        let mask: Vec<u32> = (0..4).map(|i| (order >> (i * 2)) as u32 & 3).collect();
+        let shuffled = self.build_shuffle(src, src, &mask)?;
+
+        let value = self.vector_as_bits(shuffled)?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VPERM2I128 and VPERM2F128. Each half of the result is any half of the sources or zero
+    pub(super) fn lift_vperm2x128<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let control = immediate(&ops[3]);
+
+        let first = self.load_vector_operand(&ops[1])?;
+        let second = self.load_vector_operand(&ops[2])?;
+        let sources = [
+            self.lane(first, 0)?,
+            self.lane(first, 1)?,
+            self.lane(second, 0)?,
+            self.lane(second, 1)?,
+        ];
+
+        let [lower, upper] = [control, control >> 4].map(|selector| {
+            if selector & 8 != 0 {
+                self.context.i128_type().const_zero()
+            } else {
+                sources[(selector & 3) as usize]
+            }
+        });
+
+        let value = self.concat_bits(lower, upper)?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VINSERTI128 and VINSERTF128
+    pub(super) fn lift_vinsert128<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let index = immediate(&ops[3]) & 1;
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let inserted = self.load_vector_operand(&ops[2])?;
+        let inserted = self.create_z_ext_or_trunc(inserted, self.context.i128_type())?;
+
+        let value = if index == 0 {
+            self.concat_bits(inserted, self.lane(src, 1)?)?
+        } else {
+            self.concat_bits(self.lane(src, 0)?, inserted)?
+        };
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VEXTRACTI128 and VEXTRACTF128
+    pub(super) fn lift_vextract128<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let index = immediate(&ops[2]) & 1;
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let value = self.lane(src, index as u32)?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// VPBROADCAST*, VBROADCASTSS, VBROADCASTSD, VBROADCASTI128 and VBROADCASTF128. The lowest
+    /// element of the source is copied to all elements of the destination
+    pub(super) fn lift_vbroadcast<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let element_width = match instr.mnemonic {
+            Mnemonic::VPBROADCASTB => 8,
+            Mnemonic::VPBROADCASTW => 16,
+            Mnemonic::VPBROADCASTD | Mnemonic::VBROADCASTSS => 32,
+            Mnemonic::VPBROADCASTQ | Mnemonic::VBROADCASTSD => 64,
+            Mnemonic::VBROADCASTI128 | Mnemonic::VBROADCASTF128 => 128,
+            _ => unreachable!(),
+        };
+        let element_ty = self.context.custom_width_int_type(element_width);
+
+        let src = self.load_vector_operand(&ops[1])?;
+        let element = self.create_z_ext_or_trunc(src, element_ty)?;
+        let broadcast = self.build_splat(element, vector_register_width(dest) / element_width)?;
+
+        let value = self.vector_as_bits(broadcast)?;
+        self.store_vector_operand(dest, value)
+    }
+
+    /// VZEROUPPER clears bits above 128 of the first 16 vector registers, VZEROALL whole registers
+    pub(super) fn lift_vzero<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let zero = self.context.i128_type().const_zero();
+        let wide_zero = self.context.custom_width_int_type(256).const_zero();
+        let regs_hashmap = self.regs_hashmap_mut();
+
+        for id in 0..16 {
+            regs_hashmap.insert(high_half_key(id), zero.into());
+            regs_hashmap.insert(zmm_high_key(id), wide_zero.into());
+            if instr.mnemonic == Mnemonic::VZEROALL {
+                regs_hashmap.insert(RegisterClass::XMM.encode(id).into(), zero.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the two sources of a VEX encoded instruction as vectors of `element_width` bit
+    /// integers and stores the result of `op` to the destination
+    fn build_vex_binary<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        element_width: u32,
+        op: impl FnOnce(VectorValue<'ctx>, VectorValue<'ctx>) -> Result<VectorValue<'ctx>>,
+    ) -> Result<()> {
+        let ops = instr.operands();
+
+        let lhs = self.load_vector_operand(&ops[1])?;
+        let rhs = self.load_vector_operand(&ops[2])?;
+
+        let lhs = self.bits_as_vector(lhs, element_width)?;
+        let rhs = self.bits_as_vector(rhs, element_width)?;
+        let result = op(lhs, rhs)?;
+
+        let value = self.vector_as_bits(result)?;
+        self.store_vector_operand(&ops[0], value)
+    }
+
+    /// Addition, subtraction, multiplication or division of packed floats. Elements of `lhs` and
+    /// `rhs` are integers of the float width
+    pub(super) fn build_packed_float_arith(
+        &self,
+        mnemonic: Mnemonic,
+        lhs: VectorValue<'ctx>,
+        rhs: VectorValue<'ctx>,
+    ) -> Result<VectorValue<'ctx>> {
+        let builder = &self.builder;
+
+        let element_width = packed_float_width(mnemonic);
+        let float_ty = match element_width {
+            32 => self.context.f32_type(),
+            _ => self.context.f64_type(),
+        };
+        let float_vector_ty = float_ty.vec_type(lhs.get_type().get_size());
+        let lhs = builder
+            .build_bit_cast(lhs, float_vector_ty, "")?
+            .into_vector_value();
+        let rhs = builder
+            .build_bit_cast(rhs, float_vector_ty, "")?
+            .into_vector_value();
+
+        let value = match mnemonic {
+            Mnemonic::VADDPS | Mnemonic::VADDPD => builder.build_float_add(lhs, rhs, "")?,
+            Mnemonic::VSUBPS | Mnemonic::VSUBPD => builder.build_float_sub(lhs, rhs, "")?,
+            Mnemonic::VMULPS | Mnemonic::VMULPD => builder.build_float_mul(lhs, rhs, "")?,
+            Mnemonic::VDIVPS | Mnemonic::VDIVPD => builder.build_float_div(lhs, rhs, "")?,
+            _ => unreachable!(),
+        };
+
+        let int_vector_ty = self
+            .context
+            .custom_width_int_type(element_width)
+            .vec_type(value.get_type().get_size());
+        Ok(builder
+            .build_bit_cast(value, int_vector_ty, "")?
+            .into_vector_value())
+    }
+
+    /// Vector of `count` copies of `element`
+    pub(super) fn build_splat(
+        &self,
+        element: IntValue<'ctx>,
+        count: u32,
+    ) -> Result<VectorValue<'ctx>> {
+        let vector_ty = element.get_type().vec_type(count);
+        let vector = self.builder.build_insert_element(
+            vector_ty.get_undef(),
+            element,
+            self.context.i32_type().const_zero(),
+            "",
+        )?;
+
+        let mask = vec![0; count as usize];
+        self.build_shuffle(vector, vector, &mask)
+    }
+
+    /// Whole vector register, or `operand.size` bits of memory or a general purpose register
+    pub(super) fn load_vector_operand(&self, operand: &DecodedOperand) -> Result<IntValue<'ctx>> {
+        let DecodedOperandKind::Reg(reg) = operand.kind else {
+            return self.load_single_int_op(operand, operand.size);
+        };
+        if !is_vector_register(operand) {
+            return self.load_single_int_op(operand, operand.size);
+        }
+
+        let i128_ty = self.context.i128_type();
+        let lower: IntValue<'_> = self.load_register_value(&reg)?.try_into()?;
+        let lower = self.create_z_ext_or_trunc(lower, i128_ty)?;
+        if reg.class() == RegisterClass::XMM {
+            return Ok(lower);
+        }
+
+        let high = self.load_register_part(high_half_key(reg.id()))?;
+        let high = self.create_z_ext_or_trunc(high, i128_ty)?;
+        let ymm = self.concat_bits(lower, high)?;
+        if reg.class() == RegisterClass::YMM {
+            return Ok(ymm);
+        }
+
+        let zmm_high_ty = self.context.custom_width_int_type(ZMM_WIDTH - YMM_WIDTH);
+        let zmm_high = self.load_register_part(zmm_high_key(reg.id()))?;
+        let zmm_high = self.create_z_ext_or_trunc(zmm_high, zmm_high_ty)?;
+        self.concat_bits(ymm, zmm_high)
+    }
+
+    /// Value kept under `key` which isn't a general purpose register, like the upper bits of a
+    /// vector register
+    pub(super) fn load_register_part(&self, key: ExtendedRegisterEnum) -> Result<IntValue<'ctx>> {
+        let value = self
+            .regs_hashmap()
+            .get(&key)
+            .copied()
+            .ok_or(Error::RegUnwrapError(key))?;
+        value.try_into()
+    }
+
+    /// Stores to memory or a general purpose register, or replaces a whole vector register. VEX
+    /// and EVEX encoded instructions clear the bits above the written register
+    pub(super) fn store_vector_operand(
+        &self,
+        operand: &DecodedOperand,
+        value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let DecodedOperandKind::Reg(reg) = operand.kind else {
+            return self.store_sized_operand(operand, value);
+        };
+        if !is_vector_register(operand) {
+            return self.store_sized_operand(operand, value);
+        }
+
+        let value =
+            self.create_z_ext_or_trunc(value, self.context.custom_width_int_type(ZMM_WIDTH))?;
+
+        self.store_reg(reg, self.extract_bits(value, 0, XMM_WIDTH)?)?;
+        let high = self.extract_bits(value, XMM_WIDTH, YMM_WIDTH - XMM_WIDTH)?;
+        let zmm_high = self.extract_bits(value, YMM_WIDTH, ZMM_WIDTH - YMM_WIDTH)?;
+
+        let regs_hashmap = self.regs_hashmap_mut();
+        regs_hashmap.insert(high_half_key(reg.id()), high.into());
+        regs_hashmap.insert(zmm_high_key(reg.id()), zmm_high.into());
+        Ok(())
+    }
+
+    /// Stores `operand.size` lower bits of `value`
+    fn store_sized_operand(&self, operand: &DecodedOperand, value: IntValue<'ctx>) -> Result<()> {
+        let value_ty = self.context.custom_width_int_type(operand.size.into());
+        let value = self.create_z_ext_or_trunc(value, value_ty)?;
+        self.store_op(operand, value)
+    }
+
+    /// 128 bit lane `index` of a vector value
+    fn lane(&self, value: IntValue<'ctx>, index: u32) -> Result<IntValue<'ctx>> {
+        self.extract_bits(value, index * XMM_WIDTH, XMM_WIDTH)
+    }
+
+    /// `width` bits of `value` starting at bit `offset`
+    pub(super) fn extract_bits(
+        &self,
+        value: IntValue<'ctx>,
+        offset: u32,
+        width: u32,
+    ) -> Result<IntValue<'ctx>> {
+        let builder = &self.builder;
+
+        let shift = value.get_type().const_int(offset.into(), false);
+        let shifted = builder.build_right_shift(value, shift, false, "")?;
+        let part_ty = self.context.custom_width_int_type(width);
+        Ok(builder.build_int_truncate(shifted, part_ty, "")?)
+    }
+
+    /// `upper` placed right above the bits of `lower`
+    pub(super) fn concat_bits(
+        &self,
+        lower: IntValue<'ctx>,
+        upper: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let builder = &self.builder;
+
+        let lower_width = lower.get_type().get_bit_width();
+        let width = lower_width + upper.get_type().get_bit_width();
+        let int_ty = self.context.custom_width_int_type(width);
+
+        let lower = builder.build_int_z_extend(lower, int_ty, "")?;
+        let upper = builder.build_int_z_extend(upper, int_ty, "")?;
+        let upper =
+            builder.build_left_shift(upper, int_ty.const_int(lower_width.into(), false), "")?;
+        Ok(builder.build_or(lower, upper, "")?)
+    }
+}
+
+/// Key of the bits 128..255 of the vector register with the given id
+fn high_half_key(id: u8) -> ExtendedRegisterEnum {
+    RegisterClass::YMM.encode(id).into()
+}
+
+/// Key of the bits 256..511 of the vector register with the given id
+fn zmm_high_key(id: u8) -> ExtendedRegisterEnum {
+    RegisterClass::ZMM.encode(id).into()
+}
+
+pub(super) fn packed_float_width(mnemonic: Mnemonic) -> u32 {
+    match mnemonic {
+        Mnemonic::VADDPS | Mnemonic::VSUBPS | Mnemonic::VMULPS | Mnemonic::VDIVPS => 32,
+        Mnemonic::VADDPD | Mnemonic::VSUBPD | Mnemonic::VMULPD | Mnemonic::VDIVPD => 64,
+        _ => unreachable!(),
+    }
+}
+
+pub(super) fn is_vector_register(operand: &DecodedOperand) -> bool {
+    matches!(
+        operand.kind,
+        DecodedOperandKind::Reg(reg)
+            if matches!(reg.class(), RegisterClass::XMM | RegisterClass::YMM | RegisterClass::ZMM)
+    )
+}
+
+fn vector_register_width(operand: &DecodedOperand) -> u32 {
+    match operand.kind {
+        DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::YMM => YMM_WIDTH,
+        DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::ZMM => ZMM_WIDTH,
+        _ => XMM_WIDTH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    /// YMM1 and YMM2 hold the dwords 0..8 and 8..16, the upper bits of ZMM0 aren't zero
+    fn vector_context() -> StartContextX86<u64> {
+        let mut context = StartContextX86 {
+            rip: 0x1000,
+            ..Default::default()
+        };
+        context.xmm[1] = 0x0000_0003_0000_0002_0000_0001_0000_0000;
+        context.ymm_high[1] = 0x0000_0007_0000_0006_0000_0005_0000_0004;
+        context.xmm[2] = 0x0000_000b_0000_000a_0000_0009_0000_0008;
+        context.ymm_high[2] = 0x0000_000f_0000_000e_0000_000d_0000_000c;
+        context.zmm_high[0] = [u128::MAX; 2];
+        context
+    }
+
+    fn ymm0(code: &[u8]) -> (u128, u128, [u128; 2]) {
+        let result = execute(code, vector_context(), MemoryImage::default()).context;
+        (result.xmm[0], result.ymm_high[0], result.zmm_high[0])
+    }
+
+    #[test]
+    fn vpshufd_shuffles_both_lanes() {
+        // vpshufd ymm0, ymm1, 0x1b
+        assert_eq!(
+            ymm0(&[0xc5, 0xfd, 0x70, 0xc1, 0x1b]),
+            (
+                0x0000_0000_0000_0001_0000_0002_0000_0003,
+                0x0000_0004_0000_0005_0000_0006_0000_0007,
+                [0; 2]
+            )
+        );
+    }
+
+    #[test]
+    fn vperm2i128_selects_and_zeroes_lanes() {
+        let context = vector_context();
+
+        // vperm2i128 ymm0, ymm1, ymm2, 0x21
+        assert_eq!(
+            ymm0(&[0xc4, 0xe3, 0x75, 0x46, 0xc2, 0x21]),
+            (context.ymm_high[1], context.xmm[2], [0; 2])
+        );
+        // vperm2i128 ymm0, ymm1, ymm2, 0x38
+        assert_eq!(
+            ymm0(&[0xc4, 0xe3, 0x75, 0x46, 0xc2, 0x38]),
+            (0, context.ymm_high[2], [0; 2])
+        );
+    }
+
+    #[test]
+    fn vinserti128_replaces_one_lane() {
+        let context = vector_context();
+
+        // vinserti128 ymm0, ymm1, xmm2, 1
+        assert_eq!(
+            ymm0(&[0xc4, 0xe3, 0x75, 0x38, 0xc2, 0x01]),
+            (context.xmm[1], context.xmm[2], [0; 2])
+        );
+        // vinserti128 ymm0, ymm1, xmm2, 0
+        assert_eq!(
+            ymm0(&[0xc4, 0xe3, 0x75, 0x38, 0xc2, 0x00]),
+            (context.xmm[2], context.ymm_high[1], [0; 2])
+        );
+    }
+}
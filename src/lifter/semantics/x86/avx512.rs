@@ -0,0 +1,560 @@
+use super::{
+    avx::packed_float_width, common::immediate, sse::packed_add_sub_kind, Error, LifterX86, Result,
+};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    values::{IntValue, VectorValue},
+    IntPredicate,
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    BroadcastMode, Instruction, MaskMode, Mnemonic, Operands, Register, RegisterClass,
+};
+
+/// Width of the opmask registers
+const MASK_WIDTH: u32 = 64;
+
+impl<'ctx> LifterX86<'ctx> {
+    /// EVEX encoded instructions. Their operands are the destination, the opmask register (`k0`
+    /// when masking is disabled) and the sources
+    pub(super) fn lift_evex<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        match instr.mnemonic {
+            Mnemonic::VMOVDQU8 => self.lift_evex_mov(instr, 8),
+            Mnemonic::VMOVDQU16 => self.lift_evex_mov(instr, 16),
+            Mnemonic::VMOVDQA32 | Mnemonic::VMOVDQU32 | Mnemonic::VMOVAPS | Mnemonic::VMOVUPS => {
+                self.lift_evex_mov(instr, 32)
+            }
+            Mnemonic::VMOVDQA64 | Mnemonic::VMOVDQU64 | Mnemonic::VMOVAPD | Mnemonic::VMOVUPD => {
+                self.lift_evex_mov(instr, 64)
+            }
+            Mnemonic::VPANDD
+            | Mnemonic::VPANDND
+            | Mnemonic::VPORD
+            | Mnemonic::VPXORD
+            | Mnemonic::VANDPS
+            | Mnemonic::VANDNPS
+            | Mnemonic::VORPS
+            | Mnemonic::VXORPS => self.build_evex_binary(instr, 32, |lhs, rhs| {
+                self.build_packed_logical(instr.mnemonic, lhs, rhs)
+            }),
+            Mnemonic::VPANDQ
+            | Mnemonic::VPANDNQ
+            | Mnemonic::VPORQ
+            | Mnemonic::VPXORQ
+            | Mnemonic::VANDPD
+            | Mnemonic::VANDNPD
+            | Mnemonic::VORPD
+            | Mnemonic::VXORPD => self.build_evex_binary(instr, 64, |lhs, rhs| {
+                self.build_packed_logical(instr.mnemonic, lhs, rhs)
+            }),
+            Mnemonic::VPADDB
+            | Mnemonic::VPADDW
+            | Mnemonic::VPADDD
+            | Mnemonic::VPADDQ
+            | Mnemonic::VPADDSB
+            | Mnemonic::VPADDSW
+            | Mnemonic::VPADDUSB
+            | Mnemonic::VPADDUSW
+            | Mnemonic::VPSUBB
+            | Mnemonic::VPSUBW
+            | Mnemonic::VPSUBD
+            | Mnemonic::VPSUBQ
+            | Mnemonic::VPSUBSB
+            | Mnemonic::VPSUBSW
+            | Mnemonic::VPSUBUSB
+            | Mnemonic::VPSUBUSW => {
+                let (_, element_width, _) = packed_add_sub_kind(instr.mnemonic);
+                self.build_evex_binary(instr, element_width, |lhs, rhs| {
+                    self.build_packed_add_sub(instr.mnemonic, lhs, rhs)
+                })
+            }
+            Mnemonic::VADDPS
+            | Mnemonic::VADDPD
+            | Mnemonic::VSUBPS
+            | Mnemonic::VSUBPD
+            | Mnemonic::VMULPS
+            | Mnemonic::VMULPD
+            | Mnemonic::VDIVPS
+            | Mnemonic::VDIVPD => {
+                let element_width = packed_float_width(instr.mnemonic);
+                self.build_evex_binary(instr, element_width, |lhs, rhs| {
+                    self.build_packed_float_arith(instr.mnemonic, lhs, rhs)
+                })
+            }
+            Mnemonic::VPCMPEQB
+            | Mnemonic::VPCMPEQW
+            | Mnemonic::VPCMPEQD
+            | Mnemonic::VPCMPEQQ
+            | Mnemonic::VPCMPGTB
+            | Mnemonic::VPCMPGTW
+            | Mnemonic::VPCMPGTD
+            | Mnemonic::VPCMPGTQ
+            | Mnemonic::VPCMPB
+            | Mnemonic::VPCMPW
+            | Mnemonic::VPCMPD
+            | Mnemonic::VPCMPQ
+            | Mnemonic::VPCMPUB
+            | Mnemonic::VPCMPUW
+            | Mnemonic::VPCMPUD
+            | Mnemonic::VPCMPUQ => self.lift_evex_compare(instr),
+            Mnemonic::VPTERNLOGD | Mnemonic::VPTERNLOGQ => self.lift_vpternlog(instr),
+            _ => Err(Error::UnsupportedInstr(
+                "EVEX form of the instruction isnt implemented",
+            )),
+        }
+    }
+
+    /// KMOVB, KMOVW, KMOVD and KMOVQ
+    pub(super) fn lift_kmov<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let src = &ops[1];
+        let value_ty = self
+            .context
+            .custom_width_int_type(mask_instruction_width(instr.mnemonic));
+
+        let value = match src.kind {
+            DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::MASK => {
+                self.load_mask_register(reg)?
+            }
+            _ => self.load_single_int_op(src, src.size)?,
+        };
+        let value = self.create_z_ext_or_trunc(value, value_ty)?;
+
+        match dest.kind {
+            DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::MASK => {
+                self.store_mask_register(reg, value)
+            }
+            DecodedOperandKind::Reg(_) => {
+                let dest_ty = self.context.custom_width_int_type(dest.size.into());
+                let value = self.builder.build_int_z_extend(value, dest_ty, "")?;
+                self.store_op(dest, value)
+            }
+            _ => self.store_op(dest, value),
+        }
+    }
+
+    /// KAND*, KANDN*, KOR*, KXOR*, KXNOR* and KNOT*. Bits above the operation width are cleared
+    pub(super) fn lift_mask_logical<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let value_ty = self
+            .context
+            .custom_width_int_type(mask_instruction_width(instr.mnemonic));
+
+        let lhs = self.load_mask_operand(&ops[1])?;
+        let lhs = builder.build_int_truncate(lhs, value_ty, "")?;
+        if matches!(
+            instr.mnemonic,
+            Mnemonic::KNOTB | Mnemonic::KNOTW | Mnemonic::KNOTD | Mnemonic::KNOTQ
+        ) {
+            let value = builder.build_not(lhs, "")?;
+            return self.store_mask_operand(&ops[0], value);
+        }
+
+        let rhs = self.load_mask_operand(&ops[2])?;
+        let rhs = builder.build_int_truncate(rhs, value_ty, "")?;
+
+        let value = match instr.mnemonic {
+            Mnemonic::KANDB | Mnemonic::KANDW | Mnemonic::KANDD | Mnemonic::KANDQ => {
+                builder.build_and(lhs, rhs, "")?
+            }
+            Mnemonic::KANDNB | Mnemonic::KANDNW | Mnemonic::KANDND | Mnemonic::KANDNQ => {
+                let not_lhs = builder.build_not(lhs, "")?;
+                builder.build_and(not_lhs, rhs, "")?
+            }
+            Mnemonic::KORB | Mnemonic::KORW | Mnemonic::KORD | Mnemonic::KORQ => {
+                builder.build_or(lhs, rhs, "")?
+            }
+            Mnemonic::KXORB | Mnemonic::KXORW | Mnemonic::KXORD | Mnemonic::KXORQ => {
+                builder.build_xor(lhs, rhs, "")?
+            }
+            Mnemonic::KXNORB | Mnemonic::KXNORW | Mnemonic::KXNORD | Mnemonic::KXNORQ => {
+                let xor = builder.build_xor(lhs, rhs, "")?;
+                builder.build_not(xor, "")?
+            }
+            _ => unreachable!(),
+        };
+
+        self.store_mask_operand(&ops[0], value)
+    }
+
+    /// KORTESTB, KORTESTW, KORTESTD and KORTESTQ. ZF is set when the OR of both masks is zero, CF
+    /// when it's all ones
+    pub(super) fn lift_kortest<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let value_ty = self
+            .context
+            .custom_width_int_type(mask_instruction_width(instr.mnemonic));
+
+        let lhs = self.load_mask_operand(&ops[0])?;
+        let rhs = self.load_mask_operand(&ops[1])?;
+        let or = builder.build_or(lhs, rhs, "")?;
+        let or = builder.build_int_truncate(or, value_ty, "")?;
+
+        let zf = builder.build_int_compare(IntPredicate::EQ, or, value_ty.const_zero(), "")?;
+        let cf = builder.build_int_compare(IntPredicate::EQ, or, value_ty.const_all_ones(), "")?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, cf);
+
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::AF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::PF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::SF, false);
+        Ok(())
+    }
+
+    /// VMOVDQA*, VMOVDQU*, VMOVAPS, VMOVUPS, VMOVAPD and VMOVUPD. The element width decides the
+    /// granularity of masking
+    fn lift_evex_mov<O: Operands>(&self, instr: &Instruction<O>, element_width: u32) -> Result<()> {
+        let value = self.load_evex_source(instr, &instr.operands()[2], element_width)?;
+        self.store_masked(instr, value)
+    }
+
+    /// VPCMPEQ*, VPCMPGT*, VPCMP* and VPCMPU*. The result goes to an opmask register, elements
+    /// masked off by the instruction opmask are cleared
+    fn lift_evex_compare<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let (element_width, signed) = match instr.mnemonic {
+            Mnemonic::VPCMPEQB | Mnemonic::VPCMPGTB | Mnemonic::VPCMPB => (8, true),
+            Mnemonic::VPCMPEQW | Mnemonic::VPCMPGTW | Mnemonic::VPCMPW => (16, true),
+            Mnemonic::VPCMPEQD | Mnemonic::VPCMPGTD | Mnemonic::VPCMPD => (32, true),
+            Mnemonic::VPCMPEQQ | Mnemonic::VPCMPGTQ | Mnemonic::VPCMPQ => (64, true),
+            Mnemonic::VPCMPUB => (8, false),
+            Mnemonic::VPCMPUW => (16, false),
+            Mnemonic::VPCMPUD => (32, false),
+            Mnemonic::VPCMPUQ => (64, false),
+            _ => unreachable!(),
+        };
+        let pick = |signed_predicate, unsigned_predicate| {
+            Some(if signed {
+                signed_predicate
+            } else {
+                unsigned_predicate
+            })
+        };
+
+        // `None` is the "always false" (3) and "always true" (7) predicate
+        let predicate = match instr.mnemonic {
+            Mnemonic::VPCMPEQB | Mnemonic::VPCMPEQW | Mnemonic::VPCMPEQD | Mnemonic::VPCMPEQQ => {
+                Some(IntPredicate::EQ)
+            }
+            Mnemonic::VPCMPGTB | Mnemonic::VPCMPGTW | Mnemonic::VPCMPGTD | Mnemonic::VPCMPGTQ => {
+                Some(IntPredicate::SGT)
+            }
+            _ => match immediate(&ops[4]) & 7 {
+                0 => Some(IntPredicate::EQ),
+                1 => pick(IntPredicate::SLT, IntPredicate::ULT),
+                2 => pick(IntPredicate::SLE, IntPredicate::ULE),
+                4 => Some(IntPredicate::NE),
+                5 => pick(IntPredicate::SGE, IntPredicate::UGE),
+                6 => pick(IntPredicate::SGT, IntPredicate::UGT),
+                _ => None,
+            },
+        };
+
+        let lhs = self.load_evex_source(instr, &ops[2], element_width)?;
+        let rhs = self.load_evex_source(instr, &ops[3], element_width)?;
+        let count = lhs.get_type().get_size();
+        let bits_ty = self.context.custom_width_int_type(count);
+
+        let bits = match predicate {
+            Some(predicate) => {
+                let holds = builder.build_int_compare(predicate, lhs, rhs, "")?;
+                builder.build_bit_cast(holds, bits_ty, "")?.into_int_value()
+            }
+            None if immediate(&ops[4]) & 7 == 3 => bits_ty.const_zero(),
+            None => bits_ty.const_all_ones(),
+        };
+        let mut bits =
+            builder.build_int_z_extend(bits, self.context.custom_width_int_type(MASK_WIDTH), "")?;
+
+        if is_masked(instr) {
+            let mask = self.load_mask_register(instr.avx.mask_reg)?;
+            bits = builder.build_and(bits, mask, "")?;
+        }
+
+        let DecodedOperandKind::Reg(dest) = ops[0].kind else {
+            unreachable!("EVEX compares always write to an opmask register")
+        };
+        self.store_mask_register(dest, bits)
+    }
+
+    /// VPTERNLOGD and VPTERNLOGQ. For bits `a`, `b` and `c` of the destination and both sources,
+    /// the result is bit `a << 2 | b << 1 | c` of the immediate
+    fn lift_vpternlog<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let element_width = match instr.mnemonic {
+            Mnemonic::VPTERNLOGD => 32,
+            _ => 64,
+        };
+        let table = immediate(&ops[4]);
+
+        let inputs = [
+            self.load_evex_source(instr, &ops[0], element_width)?,
+            self.load_evex_source(instr, &ops[2], element_width)?,
+            self.load_evex_source(instr, &ops[3], element_width)?,
+        ];
+        let vector_ty = inputs[0].get_type();
+
+        // Sum of the minterms selected by the truth table
+        let mut result = vector_ty.const_zero();
+        for index in 0..8 {
+            if table >> index & 1 == 0 {
+                continue;
+            }
+
+            let mut term = self.bits_as_vector(
+                self.context
+                    .custom_width_int_type(element_width * vector_ty.get_size())
+                    .const_all_ones(),
+                element_width,
+            )?;
+            for (position, input) in inputs.into_iter().enumerate() {
+                let bit = 1 << (2 - position);
+                let input = if index & bit != 0 {
+                    input
+                } else {
+                    builder.build_not(input, "")?
+                };
+                term = builder.build_and(term, input, "")?;
+            }
+            result = builder.build_or(result, term, "")?;
+        }
+
+        self.store_masked(instr, result)
+    }
+
+    /// Loads the two sources of an EVEX encoded instruction as vectors of `element_width` bit
+    /// integers and stores the result of `op` with masking applied
+    fn build_evex_binary<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        element_width: u32,
+        op: impl FnOnce(VectorValue<'ctx>, VectorValue<'ctx>) -> Result<VectorValue<'ctx>>,
+    ) -> Result<()> {
+        let ops = instr.operands();
+
+        let lhs = self.load_evex_source(instr, &ops[2], element_width)?;
+        let rhs = self.load_evex_source(instr, &ops[3], element_width)?;
+        let result = op(lhs, rhs)?;
+
+        self.store_masked(instr, result)
+    }
+
+    /// Source as a vector of `element_width` bit integers with the vector length of the
+    /// instruction. Memory operands with embedded broadcast (`{1toN}`) hold a single element
+    fn load_evex_source<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        operand: &DecodedOperand,
+        element_width: u32,
+    ) -> Result<VectorValue<'ctx>> {
+        let width = u32::from(instr.avx.vector_length);
+
+        let is_broadcast = matches!(operand.kind, DecodedOperandKind::Mem(_))
+            && instr.avx.broadcast_mode != BroadcastMode::INVALID;
+        if is_broadcast {
+            let element = self.load_single_int_op(operand, operand.size)?;
+            let element = self.create_z_ext_or_trunc(
+                element,
+                self.context.custom_width_int_type(element_width),
+            )?;
+            return self.build_splat(element, width / element_width);
+        }
+
+        let value = self.load_vector_operand(operand)?;
+        let value = self.create_z_ext_or_trunc(value, self.context.custom_width_int_type(width))?;
+        self.bits_as_vector(value, element_width)
+    }
+
+    /// Stores `result` to the destination. With merge masking elements whose opmask bit is clear
+    /// keep the old value, with zero masking they are cleared
+    fn store_masked<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        result: VectorValue<'ctx>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+        let dest = &instr.operands()[0];
+
+        let value = if is_masked(instr) {
+            let vector_ty = result.get_type();
+            let count = vector_ty.get_size();
+
+            let mask = self.load_mask_register(instr.avx.mask_reg)?;
+            let mask =
+                builder.build_int_truncate(mask, self.context.custom_width_int_type(count), "")?;
+            let mask = builder
+                .build_bit_cast(mask, self.context.bool_type().vec_type(count), "")?
+                .into_vector_value();
+
+            let fallback = if instr.avx.mask_mode == MaskMode::ZEROING {
+                vector_ty.const_zero()
+            } else {
+                let element_width = vector_ty.get_element_type().into_int_type().get_bit_width();
+                let old_ty = self.context.custom_width_int_type(element_width * count);
+
+                let old_value = self.load_vector_operand(dest)?;
+                let old_value = self.create_z_ext_or_trunc(old_value, old_ty)?;
+                self.bits_as_vector(old_value, element_width)?
+            };
+
+            builder
+                .build_select(mask, result, fallback, "")?
+                .into_vector_value()
+        } else {
+            result
+        };
+
+        let value = self.vector_as_bits(value)?;
+        self.store_vector_operand(dest, value)
+    }
+
+    fn load_mask_register(&self, reg: Register) -> Result<IntValue<'ctx>> {
+        let value = self.load_register_part(reg.into())?;
+        self.create_z_ext_or_trunc(value, self.context.custom_width_int_type(MASK_WIDTH))
+    }
+
+    /// Replaces the whole opmask register, zero extending `value`
+    fn store_mask_register(&self, reg: Register, value: IntValue<'ctx>) -> Result<()> {
+        let value = self.builder.build_int_z_extend(
+            value,
+            self.context.custom_width_int_type(MASK_WIDTH),
+            "",
+        )?;
+        self.regs_hashmap_mut().insert(reg.into(), value.into());
+        Ok(())
+    }
+
+    fn load_mask_operand(&self, operand: &DecodedOperand) -> Result<IntValue<'ctx>> {
+        let DecodedOperandKind::Reg(reg) = operand.kind else {
+            unreachable!("Opmask instructions only take opmask registers here")
+        };
+        self.load_mask_register(reg)
+    }
+
+    fn store_mask_operand(&self, operand: &DecodedOperand, value: IntValue<'ctx>) -> Result<()> {
+        let DecodedOperandKind::Reg(reg) = operand.kind else {
+            unreachable!("Opmask instructions only take opmask registers here")
+        };
+        self.store_mask_register(reg, value)
+    }
+}
+
+fn is_masked<O: Operands>(instr: &Instruction<O>) -> bool {
+    matches!(instr.avx.mask_mode, MaskMode::MERGING | MaskMode::ZEROING)
+}
+
+/// Number of mask bits the K* instructions work on
+fn mask_instruction_width(mnemonic: Mnemonic) -> u32 {
+    match mnemonic {
+        Mnemonic::KMOVB
+        | Mnemonic::KANDB
+        | Mnemonic::KANDNB
+        | Mnemonic::KORB
+        | Mnemonic::KXORB
+        | Mnemonic::KXNORB
+        | Mnemonic::KNOTB
+        | Mnemonic::KORTESTB => 8,
+        Mnemonic::KMOVW
+        | Mnemonic::KANDW
+        | Mnemonic::KANDNW
+        | Mnemonic::KORW
+        | Mnemonic::KXORW
+        | Mnemonic::KXNORW
+        | Mnemonic::KNOTW
+        | Mnemonic::KORTESTW => 16,
+        Mnemonic::KMOVD
+        | Mnemonic::KANDD
+        | Mnemonic::KANDND
+        | Mnemonic::KORD
+        | Mnemonic::KXORD
+        | Mnemonic::KXNORD
+        | Mnemonic::KNOTD
+        | Mnemonic::KORTESTD => 32,
+        Mnemonic::KMOVQ
+        | Mnemonic::KANDQ
+        | Mnemonic::KANDNQ
+        | Mnemonic::KORQ
+        | Mnemonic::KXORQ
+        | Mnemonic::KXNORQ
+        | Mnemonic::KNOTQ
+        | Mnemonic::KORTESTQ => 64,
+        _ => unreachable!("{mnemonic:?} isn't an opmask instruction"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    /// XMM1 holds the dwords 1..=4, XMM2 the dwords 10, 20, 30 and 40 and K1 selects the even
+    /// elements
+    fn masked_context() -> StartContextX86<u64> {
+        let mut context = StartContextX86 {
+            rip: 0x1000,
+            ..Default::default()
+        };
+        context.xmm[0] = 0xaaaa_aaaa_aaaa_aaaa_aaaa_aaaa_aaaa_aaaa;
+        context.ymm_high[0] = u128::MAX;
+        context.xmm[1] = 0x0000_0004_0000_0003_0000_0002_0000_0001;
+        context.xmm[2] = 0x0000_0028_0000_001e_0000_0014_0000_000a;
+        context.k[1] = 0b0101;
+        context
+    }
+
+    #[test]
+    fn merge_masking_keeps_unselected_elements() {
+        // vpaddd xmm0{k1}, xmm1, xmm2
+        let result = execute(
+            &[0x62, 0xf1, 0x75, 0x09, 0xfe, 0xc2],
+            masked_context(),
+            MemoryImage::default(),
+        )
+        .context;
+
+        assert_eq!(result.xmm[0], 0xaaaa_aaaa_0000_0021_aaaa_aaaa_0000_000b);
+        assert_eq!(result.ymm_high[0], 0);
+    }
+
+    #[test]
+    fn zero_masking_clears_unselected_elements() {
+        // vpaddd xmm0{k1}{z}, xmm1, xmm2
+        let result = execute(
+            &[0x62, 0xf1, 0x75, 0x89, 0xfe, 0xc2],
+            masked_context(),
+            MemoryImage::default(),
+        )
+        .context;
+
+        assert_eq!(result.xmm[0], 0x0000_0000_0000_0021_0000_0000_0000_000b);
+        assert_eq!(result.ymm_high[0], 0);
+    }
+
+    #[test]
+    fn masked_compare_ands_with_the_mask() {
+        let mut context = masked_context();
+        context.xmm[2] = 0x0000_0004_0000_0000_0000_0002_0000_0001;
+        context.k[2] = u64::MAX;
+
+        // vpcmpeqd k2{k1}, xmm1, xmm2
+        let result = execute(
+            &[0x62, 0xf1, 0x75, 0x09, 0x76, 0xd2],
+            context,
+            MemoryImage::default(),
+        )
+        .context;
+
+        assert_eq!(result.k[2], 0b0001);
+    }
+}
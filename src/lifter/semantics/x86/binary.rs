@@ -123,12 +123,22 @@ impl<'ctx> LifterX86<'ctx> {
     }
 
     pub(super) fn lift_cmp<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
-        let builder = &self.builder;
         let ops = instr.operands();
 
         let l_value: IntValue<'_> = self.load_single_op(&ops[0], ops[0].size)?.try_into()?;
         let r_value: IntValue<'_> = self.load_single_op(&ops[1], ops[0].size)?.try_into()?;
 
+        self.store_cmp_flags(l_value, r_value)
+    }
+
+    /// Sets flags the same way as `CMP l_value, r_value`
+    pub(super) fn store_cmp_flags(
+        &self,
+        l_value: IntValue<'ctx>,
+        r_value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+
         let cmp_result = builder.build_int_sub(l_value, r_value, "cmp_result")?;
 
         let of = {
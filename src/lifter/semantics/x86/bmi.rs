@@ -0,0 +1,304 @@
+use super::{common::immediate, Error, LifterX86, Result};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{intrinsics::Intrinsic, values::IntValue, IntPredicate};
+use zydis::{Instruction, Mnemonic, Operands};
+
+const CTPOP_INTRINSIC: &str = "llvm.ctpop";
+const CTLZ_INTRINSIC: &str = "llvm.ctlz";
+const CTTZ_INTRINSIC: &str = "llvm.cttz";
+
+impl<'ctx> LifterX86<'ctx> {
+    /// ANDN. The first source is inverted before the AND
+    pub(super) fn lift_andn<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let lhs = self.load_single_int_op(&ops[1], dest.size)?;
+        let rhs = self.load_single_int_op(&ops[2], dest.size)?;
+
+        let not_lhs = builder.build_not(lhs, "")?;
+        let result = builder.build_and(not_lhs, rhs, "")?;
+
+        let sf = self.compute_sign_flag(result)?;
+        let zf = self.compute_zero_flag(result)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::SF, sf);
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::CF, false);
+
+        self.store_op(dest, result)
+    }
+
+    /// BLSI, BLSMSK and BLSR
+    pub(super) fn lift_bls<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let value_ty = value.get_type();
+        let zero = value_ty.const_zero();
+        let is_zero = builder.build_int_compare(IntPredicate::EQ, value, zero, "")?;
+
+        let (result, cf) = match instr.mnemonic {
+            Mnemonic::BLSI => {
+                let negated = builder.build_int_neg(value, "")?;
+                let is_not_zero = builder.build_not(is_zero, "")?;
+                (builder.build_and(value, negated, "")?, is_not_zero)
+            }
+            Mnemonic::BLSMSK => {
+                let decremented = builder.build_int_sub(value, value_ty.const_int(1, false), "")?;
+                (builder.build_xor(value, decremented, "")?, is_zero)
+            }
+            Mnemonic::BLSR => {
+                let decremented = builder.build_int_sub(value, value_ty.const_int(1, false), "")?;
+                (builder.build_and(value, decremented, "")?, is_zero)
+            }
+            _ => unreachable!(),
+        };
+
+        let sf = self.compute_sign_flag(result)?;
+        let zf = self.compute_zero_flag(result)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, cf);
+        self.store_cpu_flag(ExtendedRegisterEnum::SF, sf);
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+
+        self.store_op(dest, result)
+    }
+
+    /// BEXTR. Bits 0..8 of the second source are the start, bits 8..16 the length
+    pub(super) fn lift_bextr<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let control = self.load_single_int_op(&ops[2], dest.size)?;
+        let value_ty = value.get_type();
+        let width = value_ty.const_int(dest.size.into(), false);
+        let byte_mask = value_ty.const_int(0xff, false);
+
+        let start = builder.build_and(control, byte_mask, "")?;
+        let length = builder.build_right_shift(control, value_ty.const_int(8, false), false, "")?;
+        let length = builder.build_and(length, byte_mask, "")?;
+
+        let start_in_range = builder.build_int_compare(IntPredicate::ULT, start, width, "")?;
+        let shifted = builder.build_right_shift(value, start, false, "")?;
+        let shifted = builder
+            .build_select(start_in_range, shifted, value_ty.const_zero(), "")?
+            .into_int_value();
+
+        let result = self.build_zero_high_bits(shifted, length)?;
+
+        let zf = self.compute_zero_flag(result)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::CF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+
+        self.store_op(dest, result)
+    }
+
+    /// BZHI. CF is set when the index is out of range, the value is kept as is then
+    pub(super) fn lift_bzhi<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let index = self.load_single_int_op(&ops[2], dest.size)?;
+        let value_ty = value.get_type();
+
+        let index = builder.build_and(index, value_ty.const_int(0xff, false), "")?;
+        let result = self.build_zero_high_bits(value, index)?;
+
+        let width = value_ty.const_int(dest.size.into(), false);
+        let cf = builder.build_int_compare(IntPredicate::UGE, index, width, "")?;
+        let sf = self.compute_sign_flag(result)?;
+        let zf = self.compute_zero_flag(result)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, cf);
+        self.store_cpu_flag(ExtendedRegisterEnum::SF, sf);
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+
+        self.store_op(dest, result)
+    }
+
+    /// PDEP and PEXT. Both walk the mask bit by bit, counting the set bits seen so far
+    pub(super) fn lift_pdep_pext<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let mask = self.load_single_int_op(&ops[2], dest.size)?;
+        let value_ty = value.get_type();
+        let one = value_ty.const_int(1, false);
+
+        let mut result = value_ty.const_zero();
+        let mut set_bits = value_ty.const_zero();
+        for i in 0..value_ty.get_bit_width() {
+            let position = value_ty.const_int(i.into(), false);
+            let mask_bit = builder.build_right_shift(mask, position, false, "")?;
+            let mask_bit = builder.build_and(mask_bit, one, "")?;
+
+            // PDEP moves the next low bit of the value to this position, PEXT the other way around
+            let (from, to) = match instr.mnemonic {
+                Mnemonic::PDEP => (set_bits, position),
+                Mnemonic::PEXT => (position, set_bits),
+                _ => unreachable!(),
+            };
+
+            let bit = builder.build_right_shift(value, from, false, "")?;
+            let bit = builder.build_and(bit, mask_bit, "")?;
+            let bit = builder.build_left_shift(bit, to, "")?;
+            result = builder.build_or(result, bit, "")?;
+
+            set_bits = builder.build_int_add(set_bits, mask_bit, "")?;
+        }
+
+        self.store_op(dest, result)
+    }
+
+    /// RORX. Unlike ROR, no flags are affected
+    pub(super) fn lift_rorx<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let value_ty = value.get_type();
+        let width = u64::from(dest.size);
+
+        let count = immediate(&ops[2]) & (width - 1);
+        if count == 0 {
+            return self.store_op(dest, value);
+        }
+
+        let low = builder.build_right_shift(value, value_ty.const_int(count, false), false, "")?;
+        let high = builder.build_left_shift(value, value_ty.const_int(width - count, false), "")?;
+        let result = builder.build_or(low, high, "")?;
+
+        self.store_op(dest, result)
+    }
+
+    /// MULX. The implicit source is RDX/EDX, the high half goes to the first operand and no flags
+    /// are affected
+    pub(super) fn lift_mulx<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let high_dest = &ops[0];
+        let low_dest = &ops[1];
+        let width = high_dest.size;
+
+        let lhs = self.load_single_int_op(&ops[3], width)?;
+        let rhs = self.load_single_int_op(&ops[2], width)?;
+
+        let wide_ty = self.context.custom_width_int_type(u32::from(width) * 2);
+        let lhs = builder.build_int_z_extend(lhs, wide_ty, "")?;
+        let rhs = builder.build_int_z_extend(rhs, wide_ty, "")?;
+        let product = builder.build_int_mul(lhs, rhs, "")?;
+
+        let value_ty = self.context.custom_width_int_type(width.into());
+        let low = builder.build_int_truncate(product, value_ty, "")?;
+        let high = builder.build_right_shift(
+            product,
+            wide_ty.const_int(width.into(), false),
+            false,
+            "",
+        )?;
+        let high = builder.build_int_truncate(high, value_ty, "")?;
+
+        // When both destinations are the same register, it ends up holding the high half
+        self.store_op(low_dest, low)?;
+        self.store_op(high_dest, high)
+    }
+
+    /// POPCNT, LZCNT and TZCNT
+    pub(super) fn lift_bit_count<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let value = self.load_single_int_op(&ops[1], dest.size)?;
+        let zero = value.get_type().const_zero();
+        let is_zero = builder.build_int_compare(IntPredicate::EQ, value, zero, "")?;
+
+        let intrinsic_name = match instr.mnemonic {
+            Mnemonic::POPCNT => CTPOP_INTRINSIC,
+            Mnemonic::LZCNT => CTLZ_INTRINSIC,
+            Mnemonic::TZCNT => CTTZ_INTRINSIC,
+            _ => unreachable!(),
+        };
+        let result = self.build_int_intrinsic(intrinsic_name, value)?;
+
+        if instr.mnemonic == Mnemonic::POPCNT {
+            self.store_cpu_flag(ExtendedRegisterEnum::ZF, is_zero);
+            self.store_cpu_flag_bool(ExtendedRegisterEnum::CF, false);
+            self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+            self.store_cpu_flag_bool(ExtendedRegisterEnum::SF, false);
+            self.store_cpu_flag_bool(ExtendedRegisterEnum::AF, false);
+            self.store_cpu_flag_bool(ExtendedRegisterEnum::PF, false);
+        } else {
+            let zf = self.compute_zero_flag(result)?;
+            self.store_cpu_flag(ExtendedRegisterEnum::CF, is_zero);
+            self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        }
+
+        self.store_op(dest, result)
+    }
+
+    /// Keeps the lowest `count` bits of `value`, all of them when `count` is at least the width
+    fn build_zero_high_bits(
+        &self,
+        value: IntValue<'ctx>,
+        count: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let builder = &self.builder;
+        let value_ty = value.get_type();
+        let width = value_ty.const_int(value_ty.get_bit_width().into(), false);
+
+        let count_in_range = builder.build_int_compare(IntPredicate::ULT, count, width, "")?;
+        let mask = builder.build_left_shift(value_ty.const_int(1, false), count, "")?;
+        let mask = builder.build_int_sub(mask, value_ty.const_int(1, false), "")?;
+        let mask = builder
+            .build_select(count_in_range, mask, value_ty.const_all_ones(), "")?
+            .into_int_value();
+
+        Ok(builder.build_and(value, mask, "")?)
+    }
+
+    /// Calls `llvm.ctpop`, `llvm.ctlz` or `llvm.cttz`. A zero input gives the bit width
+    fn build_int_intrinsic(
+        &self,
+        name: &'static str,
+        value: IntValue<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let intrinsic = Intrinsic::find(name).ok_or(Error::IntrinsicNotFound(name))?;
+        let intrinsic_func = intrinsic
+            .get_declaration(&self.module, &[value.get_type().into()])
+            .unwrap();
+
+        let is_zero_poison = self.context.bool_type().const_zero();
+        let args = match name {
+            CTPOP_INTRINSIC => vec![value.into()],
+            _ => vec![value.into(), is_zero_poison.into()],
+        };
+
+        let result = self
+            .builder
+            .build_call(intrinsic_func, &args, "")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_int_value();
+        Ok(result)
+    }
+}
@@ -1,49 +1,329 @@
-use super::{LifterX86, Result};
+use super::{Error, LifterX86, Result};
+use crate::lifter::state::{lifted_function_name, CallPolicy, CALL_HOOK};
+use crate::loader::{ImageFormat, Import};
 
-use inkwell::{values::IntValue, AddressSpace};
-use zydis::{ffi::DecodedOperandKind, Instruction, Operands, Register};
+use inkwell::{module::Linkage, values::IntValue};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, MachineMode, Operands, Register,
+};
 
-impl LifterX86<'_> {
+/// Arguments passed in registers by the System V AMD64 ABI
+const SYSV_ARGUMENT_REGISTERS: [Register; 6] = [
+    Register::RDI,
+    Register::RSI,
+    Register::RDX,
+    Register::RCX,
+    Register::R8,
+    Register::R9,
+];
+
+/// Arguments passed in registers by the Microsoft x64 calling convention
+const MICROSOFT_X64_ARGUMENT_REGISTERS: [Register; 4] =
+    [Register::RCX, Register::RDX, Register::R8, Register::R9];
+
+impl<'ctx> LifterX86<'ctx> {
     pub(super) fn lift_call<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
-        let builder = &self.builder;
         let ops = instr.operands();
 
+        if let Some(import) = self.resolve_imported_callee(instr)? {
+            return self.build_import_call(import);
+        }
+
         let src = &ops[0];
         let rsp = &ops[2];
         let rsp_memory = &ops[3];
 
+        // Loaded before RSP changes, `call [rsp + 8]` reads the slot above the return address
+        let target = match self.call_policy.get() {
+            CallPolicy::Inline => None,
+            CallPolicy::Call => Some(self.load_call_target(instr, src)?),
+        };
+
         let rsp_value: IntValue<'_> = self.load_single_op(rsp, rsp.size)?.try_into()?;
 
-        // FIXME: Replace 8 with actual calculations
-        let val = self
-            .context
-            .i64_type()
-            .const_int(self.retdec_get_arch_byte_size().into(), true);
+        let val = rsp_value
+            .get_type()
+            .const_int(u64::from(rsp_memory.size / 8), false);
 
         let result = self
             .builder
             .build_int_sub(rsp_value, val, "pushing_new_rsp_")?;
 
-        if let DecodedOperandKind::Reg(register) = &src.kind {
-            let register_value: IntValue<'_> = self.load_register_value(register)?.try_into()?;
+        self.store_op(rsp, result)?;
+        // 16 bit code pushes only IP of EIP
+        let push_into_rsp: IntValue<'_> = self.load_register_value(&Register::IP)?.try_into()?;
+        let return_address_ty = self.context.custom_width_int_type(rsp_memory.size.into());
+        let push_into_rsp = self.create_z_ext_or_trunc(push_into_rsp, return_address_ty)?;
+        self.store_op(rsp_memory, push_into_rsp)?;
+
+        match target {
+            Some(target) => self.build_state_call(target),
+            None => Ok(()),
+        }
+    }
+
+    /// Absolute address of the callee. Relative targets need the runtime address
+    fn load_call_target<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        callee: &DecodedOperand,
+    ) -> Result<IntValue<'ctx>> {
+        let int_ty = self.context.i64_type();
+
+        if let DecodedOperandKind::Imm(_) = callee.kind {
+            let next_address = self.runtime_address().ok_or(Error::UnknownRuntimeAddress)?;
+            let instr_address = next_address - u64::from(instr.length);
+            let target = instr.calc_absolute_address(instr_address, callee)?;
+            return Ok(int_ty.const_int(target, false));
+        }
+
+        let target = self.load_single_int_op(callee, callee.size)?;
+        self.create_z_ext_or_trunc(target, int_ty)
+    }
+
+    /// Calls the lifted function of a constant `target`, or [CALL_HOOK] if it isn't constant. The
+    /// register state is written before the call and read back after it
+    fn build_state_call(&self, target: IntValue<'ctx>) -> Result<()> {
+        let state = self.get_or_create_call_state()?;
+        self.store_state(state)?;
 
-            if !register_value.is_constant_int() {
-                let id_llvm = builder.build_int_to_ptr(
-                    register_value,
-                    self.context.ptr_type(AddressSpace::default()),
-                    "",
-                );
-                // TODO: implement later
+        match target.get_zero_extended_constant() {
+            Some(address) => {
+                let function = self.state_function(&lifted_function_name(address));
+                self.builder.build_call(function, &[state.into()], "")?;
+            }
+            None => {
+                let hook = self.hook_function(CALL_HOOK);
+                self.builder
+                    .build_call(hook, &[target.into(), state.into()], "")?;
             }
-            let register_c_value = register_value.get_zero_extended_constant().unwrap();
-            #[cfg(debug_assertions)]
-            println!("CALL: jump address : {register_value}");
         }
 
-        self.store_op(rsp, result)?;
-        let push_into_rsp: IntValue<'_> = self.load_register_value(&Register::IP)?.try_into()?;
-        self.store_op(rsp_memory, push_into_rsp)?;
+        self.load_state(state)
+    }
+
+    /// Import called either through its stub (`call puts@plt`) or its slot (`call [__imp_puts]`)
+    fn resolve_imported_callee<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+    ) -> Result<Option<&Import>> {
+        let Some(image) = self.image.get() else {
+            return Ok(None);
+        };
+        let callee = &instr.operands()[0];
+
+        let import = match &callee.kind {
+            DecodedOperandKind::Imm(_) => {
+                // IP was already increased, so runtime address points to the next instruction
+                let Some(next_address) = self.runtime_address() else {
+                    return Ok(None);
+                };
+                let instr_address = next_address - u64::from(instr.length);
+                let target = instr.calc_absolute_address(instr_address, callee)?;
+                image.import_for_stub(target)
+            }
+            DecodedOperandKind::Mem(memory_info) => self
+                .mergen_calculate_memory_address(memory_info)?
+                .get_zero_extended_constant()
+                .and_then(|slot| image.import_at(slot)),
+            _ => None,
+        };
+
+        Ok(import)
+    }
+
+    /// Calls external declaration named after `import`. Arguments are taken from the registers of
+    /// the image's calling convention and the result is stored in the accumulator. 32 and 16 bit
+    /// conventions pass every argument on the stack, so the callee gets the stack pointer, which
+    /// points at the first one
+    fn build_import_call(&self, import: &Import) -> Result<()> {
+        let int_ty = self.get_max_int_type();
+        let argument_registers = self.argument_registers();
+
+        let function = self.module.get_function(&import.name).unwrap_or_else(|| {
+            let param_types = vec![int_ty.into(); argument_registers.len()];
+            let fn_ty = int_ty.fn_type(&param_types, false);
+            self.module
+                .add_function(&import.name, fn_ty, Some(Linkage::External))
+        });
+
+        let arguments = argument_registers
+            .iter()
+            .map(|reg| {
+                let value: IntValue<'ctx> = self
+                    .mergen_get_register(reg, int_ty.get_bit_width())?
+                    .try_into()?;
+                Ok(value.into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let result = self
+            .builder
+            .build_call(function, &arguments, &import.name)?
+            .try_as_basic_value()
+            .left()
+            .expect("Imported functions are declared with a return value")
+            .into_int_value();
+
+        let accumulator = self.get_register_largest_enclosing(&Register::AX);
+        self.store_reg(accumulator, result)
+    }
+
+    fn argument_registers(&self) -> Vec<Register> {
+        let format = self.image.get().map(|image| image.format);
+        match (self.mode, format) {
+            (MachineMode::LONG_64, Some(ImageFormat::Pe)) => {
+                MICROSOFT_X64_ARGUMENT_REGISTERS.to_vec()
+            }
+            (MachineMode::LONG_64, _) => SYSV_ARGUMENT_REGISTERS.to_vec(),
+            _ => vec![self.get_register_largest_enclosing(&Register::SP)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::{Hook, HookCall, MemoryImage};
+    use crate::compiler::Compiler;
+    use crate::lifter::memory::CallbackMemory;
+    use crate::lifter::state::CallPolicy;
+    use crate::loader::{ImageFormat, Import, LoadedImage, Section};
+
+    use std::collections::BTreeMap;
+
+    use inkwell::context::Context;
+    use inkwell::values::{FunctionValue, InstructionOpcode};
+    use zydis::{AllOperands, Decoder, MachineMode};
+
+    const CODE_ADDRESS: u64 = 0x401000;
+
+    fn state_function<'ctx>(context: &'ctx Context, code: &[u8]) -> Compiler<'ctx> {
+        let instructions = Decoder::new64()
+            .decode_all::<AllOperands>(code, CODE_ADDRESS)
+            .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let compiler = Compiler::new_state_function(
+            context,
+            MachineMode::LONG_64,
+            CODE_ADDRESS,
+            Box::new(CallbackMemory),
+        )
+        .unwrap();
+        compiler.set_call_policy(CallPolicy::Call);
+        compiler.lift_function(&instructions, false).unwrap();
+        compiler
+    }
+
+    /// Names of the functions called by `function`, in order
+    fn called_functions(function: FunctionValue<'_>) -> Vec<String> {
+        function
+            .get_basic_block_iter()
+            .flat_map(|block| block.get_instructions())
+            .filter(|instruction| instruction.get_opcode() == InstructionOpcode::Call)
+            .filter_map(|call| {
+                let callee = call.get_operand(call.get_num_operands() - 1)?.left()?;
+                Some(callee.get_name().to_string_lossy().into_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn direct_call_calls_the_lifted_function() {
+        let context = Context::create();
+
+        // call 0x401100
+        let compiler = state_function(&context, &[0xe8, 0xfb, 0x00, 0x00, 0x00]);
+
+        let module = &compiler.lifter.module;
+        let callee = module.get_function("sub_401100").unwrap();
+        assert_eq!(callee.count_basic_blocks(), 0);
+        assert_eq!(callee.count_params(), 1);
+        let caller = module.get_function("sub_401000").unwrap();
+        assert!(called_functions(caller).contains(&"sub_401100".to_string()));
+    }
+
+    #[test]
+    fn indirect_call_passes_the_state_to_the_hook() {
+        let context = Context::create();
+        let start: StartContextX86<u64> = StartContextX86 {
+            rax: 0x5000,
+            rsp: 0x8000,
+            rip: CODE_ADDRESS,
+            ..Default::default()
+        };
+
+        // fld1; call rax
+        let compiler = state_function(&context, &[0xd9, 0xe8, 0xff, 0xd0]);
+        let execution = compiler
+            .create_jit()
+            .unwrap()
+            .run(start, MemoryImage::default())
+            .unwrap();
+
+        assert_eq!(
+            execution.hook_calls,
+            [HookCall {
+                hook: Hook::Call,
+                target: 0x5000
+            }]
+        );
+        assert_eq!(execution.context.rsp, 0x7ff8);
+        assert_eq!(execution.memory.read_u64(0x7ff8), CODE_ADDRESS + 4);
+        // ST0 is written to the state before the call and read back after it
+        assert_eq!(execution.context.st[0], 0x3fff_8000_0000_0000_0000);
+    }
+
+    #[test]
+    fn import_call_passes_the_stack_pointer_in_32_bit_code() {
+        let context = Context::create();
+        // call dword ptr [0x402000]; ret
+        let code = vec![0xff, 0x15, 0x00, 0x20, 0x40, 0x00, 0xc3];
+        let image = LoadedImage {
+            format: ImageFormat::Pe,
+            mode: MachineMode::LEGACY_32,
+            image_base: 0x400000,
+            entry_point: CODE_ADDRESS,
+            sections: vec![
+                Section {
+                    name: ".text".to_string(),
+                    virtual_address: CODE_ADDRESS,
+                    data: code,
+                    executable: true,
+                    writable: false,
+                },
+                Section {
+                    name: ".idata".to_string(),
+                    virtual_address: 0x402000,
+                    data: vec![0; 8],
+                    executable: false,
+                    writable: true,
+                },
+            ],
+            imports: vec![Import {
+                library: Some("kernel32.dll".to_string()),
+                name: "Sleep".to_string(),
+                address: 0x402000,
+            }],
+            import_stubs: BTreeMap::new(),
+            relocations: Vec::new(),
+            symbols: BTreeMap::new(),
+        };
+
+        let compiler = Compiler::new_for_image(&context, image).unwrap();
+        compiler.lift_image_function(CODE_ADDRESS, false).unwrap();
 
-        Ok(())
+        let module = &compiler.lifter.module;
+        let import = module.get_function("Sleep").unwrap();
+        assert_eq!(import.count_params(), 1);
+        assert_eq!(
+            import.get_type().get_param_types()[0],
+            context.i32_type().into()
+        );
+        let function = module.get_function("protected").unwrap();
+        assert!(called_functions(function).contains(&"Sleep".to_string()));
     }
 }
@@ -0,0 +1,9 @@
+use zydis::ffi::{DecodedOperand, DecodedOperandKind};
+
+/// Value of an immediate operand
+pub(super) fn immediate(operand: &DecodedOperand) -> u64 {
+    let DecodedOperandKind::Imm(imm) = &operand.kind else {
+        unreachable!("Expected an immediate operand")
+    };
+    imm.value
+}
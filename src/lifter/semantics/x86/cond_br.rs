@@ -0,0 +1,105 @@
+use super::{Error, LifterX86, Result};
+use crate::lifter::flagops::ConditionCode;
+
+use inkwell::{values::IntValue, IntPredicate};
+use zydis::{Instruction, Mnemonic, Operands, Register};
+
+impl<'ctx> LifterX86<'ctx> {
+    pub(super) fn lift_jcc<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        condition_code: ConditionCode,
+    ) -> Result<()> {
+        let condition = self.compute_condition(condition_code)?;
+
+        self.cond_br_helper(instr, condition)
+    }
+
+    /// JCXZ, JECXZ and JRCXZ
+    pub(super) fn lift_jrcxz<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let counter_register = match instr.mnemonic {
+            Mnemonic::JCXZ => Register::CX,
+            Mnemonic::JECXZ => Register::ECX,
+            Mnemonic::JRCXZ => Register::RCX,
+            _ => unreachable!(),
+        };
+
+        let counter: IntValue<'_> = self
+            .mergen_get_register(&counter_register, counter_register.width(self.mode).into())?
+            .try_into()?;
+
+        let condition = self.builder.build_int_compare(
+            IntPredicate::EQ,
+            counter,
+            counter.get_type().const_zero(),
+            "jrcxz_condition",
+        )?;
+
+        self.cond_br_helper(instr, condition)
+    }
+
+    /// Branches to the target block when `condition` holds, otherwise continues lifting in the
+    /// fall-through block
+    fn cond_br_helper<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        condition: IntValue<'ctx>,
+    ) -> Result<()> {
+        // IP was already increased, so runtime address points to the next instruction
+        let fallthrough_address = self.runtime_address().ok_or(Error::UnknownRuntimeAddress)?;
+        let instr_address = fallthrough_address - u64::from(instr.length);
+        let target_address = instr.calc_absolute_address(instr_address, &instr.operands()[0])?;
+
+        self.build_guest_conditional_branch(condition, target_address, fallthrough_address)?;
+        self.enter_block(fallthrough_address)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+    use crate::compiler::Compiler;
+    use crate::lifter::memory::CallbackMemory;
+
+    use inkwell::context::Context;
+    use zydis::MachineMode;
+
+    const CODE_ADDRESS: u64 = 0x40_0000;
+
+    /// Carry flag after running the 32 bit `code` with the counter `ecx`, starting with CF cleared
+    fn carry_after(code: &[u8], ecx: u32) -> u8 {
+        let context = Context::create();
+        let compiler = Compiler::new_state_function(
+            &context,
+            MachineMode::LEGACY_32,
+            CODE_ADDRESS,
+            Box::new(CallbackMemory),
+        )
+        .unwrap();
+        compiler
+            .lift_function_at(code, CODE_ADDRESS, CODE_ADDRESS, false)
+            .unwrap();
+
+        let start: StartContextX86<u32> = StartContextX86 {
+            rcx: ecx,
+            rsp: 0x8000,
+            rip: CODE_ADDRESS as u32,
+            ..Default::default()
+        };
+        let jit = compiler.create_jit().unwrap();
+        let execution = jit.run(start, MemoryImage::default()).unwrap();
+        execution.context.cf
+    }
+
+    #[test]
+    fn jcxz_tests_only_cx() {
+        // jcxz $+4; cmc
+        let code = [0x67, 0xe3, 0x01, 0xf5];
+
+        assert_eq!(carry_after(&code, 0x1_0000), 0);
+        assert_eq!(carry_after(&code, 0x1_0001), 1);
+    }
+}
@@ -1,9 +1,7 @@
-use crate::miscellaneous::ExtendedRegisterEnum;
-
 use super::{LifterX86, Result};
 
 use inkwell::values::IntValue;
-use zydis::{ffi::DecodedOperandKind, Instruction, InstructionAttributes, Mnemonic, Operands};
+use zydis::{ffi::DecodedOperandKind, Instruction, Mnemonic, Operands};
 
 impl LifterX86<'_> {
     // NOTE: checked
@@ -80,90 +78,85 @@ impl LifterX86<'_> {
         Ok(())
     }
 
-    pub(super) fn lift_movs_x<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
-        let builder = &self.builder;
-        let ctx = self.context;
+    pub(super) fn lift_xchg<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let ops = instr.operands();
-        let size = &ops[1].size.clone();
+        let dst = &ops[0];
+        let src = &ops[1];
 
-        let mut dst_ptr_value = self.load_single_op(&ops[1], *size)?;
-        self.store_op(&ops[0], dst_ptr_value)?;
+        let rhs: IntValue<'_> = self.load_single_op(src, src.size)?.try_into()?;
+        let lhs: IntValue<'_> = self.load_single_op(dst, dst.size)?.try_into()?;
 
-        let is_rep = instr.attributes.contains(InstructionAttributes::HAS_REP);
+        self.store_op(dst, rhs)?;
+        self.store_op(src, lhs)?;
 
-        let df = self.load_flag(ExtendedRegisterEnum::DF)?;
+        Ok(())
+    }
+}
 
-        let byte_size_value: u64 = (*size).into();
+#[cfg(test)]
+mod tests {
+    use super::super::execute_in_mode;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    use zydis::MachineMode;
+
+    fn real_mode_context() -> StartContextX86<u32> {
+        StartContextX86 {
+            rip: 0x100,
+            ds: 0x1000,
+            es: 0x3000,
+            ss: 0x2000,
+            ..Default::default()
+        }
+    }
 
-        let src_op = if is_rep { &ops[2 + 1] } else { &ops[2] };
-        let dst_op = if is_rep { &ops[3 + 1] } else { &ops[3] };
+    #[test]
+    fn real_mode_load_uses_ds() {
+        let mut memory = MemoryImage::default();
+        memory.write(0x10012, &[0x34, 0x12]);
+        let start = StartContextX86 {
+            rbx: 0x10,
+            ..real_mode_context()
+        };
 
-        let src_op_ty = ctx.custom_width_int_type(src_op.size.into());
-        let direction = builder
-            .build_select(
-                df,
-                src_op_ty.const_int(byte_size_value, false),
-                src_op_ty.const_int(u64::MAX.wrapping_mul(byte_size_value), false),
-                "",
-            )?
-            .into_int_value();
-
-        //let direction = builder
-        //    .build_select(
-        //        df,
-        //        ctx.custom_width_int_type(src_op.size.into())
-        //            .const_int(1 * byte_size_value, false),
-        //        ctx.custom_width_int_type(src_op.size.into())
-        //            //.const_int(u64::MAX * byte_size_value, false),
-        //            .const_int(u64::MAX.wrapping_mul(byte_size_value), false),
-        //        "",
-        //    )?
-        //    .into_int_value();
-
-        let src_value: IntValue<'_> = self.load_single_op(src_op, src_op.size)?.try_into()?;
-        let dst_value: IntValue<'_> = self.load_single_op(dst_op, dst_op.size)?.try_into()?;
-
-        if is_rep {
-            let count_ci: IntValue<'_> = self.load_single_op(&ops[2], ops[2].size)?.try_into()?;
-            debug_assert!(count_ci.is_constant_int(), "fix rep");
-            let mut update_src_value = src_value;
-            let mut update_dst_value = dst_value;
-            let looptime = count_ci.get_zero_extended_constant().unwrap();
-
-            for _ in 0..looptime {
-                dst_ptr_value = self.load_single_op(&ops[1], *size)?;
-                self.store_op(&ops[0], dst_ptr_value)?;
-
-                update_src_value = builder.build_int_add(update_src_value, direction, "")?;
-                update_dst_value = builder.build_int_add(update_dst_value, direction, "")?;
-
-                self.store_op(src_op, update_src_value)?;
-                self.store_op(dst_op, update_dst_value)?;
-            }
+        // mov ax, word ptr [bx+2]
+        let execution = execute_in_mode(MachineMode::REAL_16, &[0x8b, 0x47, 0x02], start, memory);
 
-            self.store_op(&ops[2], count_ci.get_type().const_zero())?;
-        }
+        assert_eq!(execution.context.rax, 0x1234);
+    }
 
-        let update_src_value = builder.build_int_add(src_value, direction, "")?;
-        let update_dst_value = builder.build_int_add(dst_value, direction, "")?;
+    #[test]
+    fn real_mode_store_through_bp_uses_ss() {
+        let start = StartContextX86 {
+            rax: 0xbeef,
+            rbp: 0x100,
+            ..real_mode_context()
+        };
 
-        self.store_op(src_op, update_src_value)?;
-        self.store_op(dst_op, update_dst_value)?;
+        // mov word ptr [bp-2], ax
+        let execution = execute_in_mode(
+            MachineMode::REAL_16,
+            &[0x89, 0x46, 0xfe],
+            start,
+            MemoryImage::default(),
+        );
 
-        Ok(())
+        assert_eq!(execution.memory.read(0x200fe, 2), [0xef, 0xbe]);
     }
 
-    pub(super) fn lift_xchg<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
-        let ops = instr.operands();
-        let dst = &ops[0];
-        let src = &ops[1];
-
-        let rhs: IntValue<'_> = self.load_single_op(src, src.size)?.try_into()?;
-        let lhs: IntValue<'_> = self.load_single_op(dst, dst.size)?.try_into()?;
+    #[test]
+    fn real_mode_segment_override() {
+        let mut memory = MemoryImage::default();
+        memory.write(0x30004, &[0x78, 0x56]);
+        let start = StartContextX86 {
+            rsi: 4,
+            ..real_mode_context()
+        };
 
-        self.store_op(dst, rhs)?;
-        self.store_op(src, lhs)?;
+        // mov ax, word ptr es:[si]
+        let execution = execute_in_mode(MachineMode::REAL_16, &[0x26, 0x8b, 0x04], start, memory);
 
-        Ok(())
+        assert_eq!(execution.context.rax, 0x5678);
     }
 }
@@ -0,0 +1,1286 @@
+//! Differential tests of the instruction semantics against the host CPU.
+//!
+//! Every case is executed natively in a forked child with random registers, flags and stack, then
+//! lifted with the state ABI at the same address, JIT compiled and run with the same state. The
+//! general purpose registers, the arithmetic flags and DF, XMM0-15, the opmask registers and the
+//! stack have to agree. Flags which an instruction of the case leaves undefined aren't compared.
+//!
+//! Cases of branches jump over a CMC, so the taken path shows in CF. x87 cases start with an
+//! empty register stack and the control word set by FNINIT, and store their results to the stack
+//! area.
+//!
+//! Cases the host CPU doesn't support fail the test, unless `BIN_LIFT_ALLOW_UNSUPPORTED` is set.
+use crate::compiler::contexts::StartContextX86;
+use crate::compiler::jit::{Jit, MemoryImage};
+use crate::compiler::Compiler;
+use crate::lifter::memory::CallbackMemory;
+
+use std::mem::offset_of;
+
+use inkwell::context::Context;
+use zydis::{AllOperands, CpuFlag, Decoder, FullInstruction, MachineMode, Register};
+
+/// Random states each case is run with
+const ITERATIONS: usize = 32;
+
+/// Size of the stack area. The stack pointer starts in its middle
+const STACK_SIZE: usize = 0x400;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Environment variable which skips the cases the host CPU doesn't support, instead of failing
+const ALLOW_UNSUPPORTED: &str = "BIN_LIFT_ALLOW_UNSUPPORTED";
+
+/// Flags which are compared, unless the instruction leaves them undefined
+const COMPARED_FLAGS: CpuFlag = CpuFlag::CF
+    .union(CpuFlag::PF)
+    .union(CpuFlag::AF)
+    .union(CpuFlag::ZF)
+    .union(CpuFlag::SF)
+    .union(CpuFlag::OF)
+    .union(CpuFlag::DF);
+
+/// General purpose registers by their encoding
+const GPRS: [Register; 16] = [
+    Register::RAX,
+    Register::RCX,
+    Register::RDX,
+    Register::RBX,
+    Register::RSP,
+    Register::RBP,
+    Register::RSI,
+    Register::RDI,
+    Register::R8,
+    Register::R9,
+    Register::R10,
+    Register::R11,
+    Register::R12,
+    Register::R13,
+    Register::R14,
+    Register::R15,
+];
+
+/// Where an [Input] is placed
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    Register(Register),
+    /// Qword at this offset into the stack area
+    Stack(u64),
+}
+
+/// Start value of a register or stack slot which can't be random
+#[derive(Debug, Clone, Copy)]
+enum Input {
+    Zero,
+    /// Address of this offset into the stack area
+    Stack(u64),
+    /// Random qword at this offset into the stack area
+    StackValue(u64),
+    /// Random count below this, so REP string instructions stay inside the stack area
+    Count(u64),
+    /// Address right after the case code, where the native stub continues
+    Next,
+    /// Random RFLAGS with only the compared flags, so POPF can't set TF
+    Flags,
+    /// Random finite float of this many bits, so no NaN payloads are compared
+    Float(u32),
+}
+
+struct Case {
+    asm: &'static str,
+    code: &'static [u8],
+    inputs: &'static [(Location, Input)],
+}
+
+const fn case(asm: &'static str, code: &'static [u8]) -> Case {
+    Case {
+        asm,
+        code,
+        inputs: &[],
+    }
+}
+
+const fn with(
+    asm: &'static str,
+    code: &'static [u8],
+    inputs: &'static [(Location, Input)],
+) -> Case {
+    Case { asm, code, inputs }
+}
+
+const STRING_INPUTS: &[(Location, Input)] = &[
+    (Location::Register(Register::RSI), Input::Stack(0x40)),
+    (Location::Register(Register::RDI), Input::Stack(0x300)),
+];
+
+/// [STRING_INPUTS] with fewer than 0x20 elements for REP prefixed instructions
+const REP_INPUTS: &[(Location, Input)] = &[
+    (Location::Register(Register::RSI), Input::Stack(0x40)),
+    (Location::Register(Register::RDI), Input::Stack(0x300)),
+    (Location::Register(Register::RCX), Input::Count(0x20)),
+];
+
+/// Doubles at `[rsp]` and `[rsp + 8]`
+const FLOAT_INPUTS: &[(Location, Input)] = &[
+    (Location::Stack(0x200), Input::Float(64)),
+    (Location::Stack(0x208), Input::Float(64)),
+];
+
+/// Singles at `[rsp]` and `[rsp + 8]`
+const SINGLE_INPUTS: &[(Location, Input)] = &[
+    (Location::Stack(0x200), Input::Float(32)),
+    (Location::Stack(0x208), Input::Float(32)),
+];
+
+/// Return address of RET, the stack pointer starts at the middle of the stack area
+const RETURN_INPUTS: &[(Location, Input)] = &[(Location::Stack(0x200), Input::Next)];
+
+/// Cases by the module lifting them. Lifting any of their instructions mustn't be unsupported, so
+/// x87 cases can load their operands and store the result
+#[rustfmt::skip]
+const CASES: &[Case] = &[
+    // binary
+    case("adc rax, rbx", &[0x48, 0x11, 0xd8]),
+    case("adc al, ch", &[0x10, 0xe8]),
+    case("add rax, rbx", &[0x48, 0x01, 0xd8]),
+    case("add ecx, 0x7fffffff", &[0x81, 0xc1, 0xff, 0xff, 0xff, 0x7f]),
+    case("add ah, dl", &[0x00, 0xd4]),
+    case("sub rax, rbx", &[0x48, 0x29, 0xd8]),
+    case("sub cx, 0x8000", &[0x66, 0x81, 0xe9, 0x00, 0x80]),
+    case("cmp rax, rbx", &[0x48, 0x39, 0xd8]),
+    case("cmp bl, 0x80", &[0x80, 0xfb, 0x80]),
+    case("sbb rax, rbx", &[0x48, 0x19, 0xd8]),
+    case("sbb edx, esi", &[0x19, 0xf2]),
+    case("dec rax", &[0x48, 0xff, 0xc8]),
+    case("dec cx", &[0x66, 0xff, 0xc9]),
+    case("inc rax", &[0x48, 0xff, 0xc0]),
+    case("inc bh", &[0xfe, 0xc7]),
+    case("neg rax", &[0x48, 0xf7, 0xd8]),
+    case("neg si", &[0x66, 0xf7, 0xde]),
+
+    // bitbyte
+    case("bsr rax, rbx", &[0x48, 0x0f, 0xbd, 0xc3]),
+    case("bsf rax, rbx", &[0x48, 0x0f, 0xbc, 0xc3]),
+    case("bsf cx, dx", &[0x66, 0x0f, 0xbc, 0xca]),
+    case("bt rax, rbx", &[0x48, 0x0f, 0xa3, 0xd8]),
+    case("bt ecx, 35", &[0x0f, 0xba, 0xe1, 0x23]),
+    case("btc rax, rbx", &[0x48, 0x0f, 0xbb, 0xd8]),
+    case("btr rax, rbx", &[0x48, 0x0f, 0xb3, 0xd8]),
+    case("bts rax, rbx", &[0x48, 0x0f, 0xab, 0xd8]),
+    case("bts dx, 9", &[0x66, 0x0f, 0xba, 0xea, 0x09]),
+
+    // bmi
+    case("andn rax, rbx, rcx", &[0xc4, 0xe2, 0xe0, 0xf2, 0xc1]),
+    case("blsi rax, rbx", &[0xc4, 0xe2, 0xf8, 0xf3, 0xdb]),
+    case("blsmsk rax, rbx", &[0xc4, 0xe2, 0xf8, 0xf3, 0xd3]),
+    case("blsr eax, ebx", &[0xc4, 0xe2, 0x78, 0xf3, 0xcb]),
+    case("bextr rax, rbx, rcx", &[0xc4, 0xe2, 0xf0, 0xf7, 0xc3]),
+    case("bzhi rax, rbx, rcx", &[0xc4, 0xe2, 0xf0, 0xf5, 0xc3]),
+    case("pdep rax, rbx, rcx", &[0xc4, 0xe2, 0xe3, 0xf5, 0xc1]),
+    case("pext rax, rbx, rcx", &[0xc4, 0xe2, 0xe2, 0xf5, 0xc1]),
+    case("rorx rax, rbx, 13", &[0xc4, 0xe3, 0xfb, 0xf0, 0xc3, 0x0d]),
+    case("mulx rax, rbx, rcx", &[0xc4, 0xe2, 0xe3, 0xf6, 0xc1]),
+    case("popcnt rax, rbx", &[0xf3, 0x48, 0x0f, 0xb8, 0xc3]),
+    case("lzcnt rax, rbx", &[0xf3, 0x48, 0x0f, 0xbd, 0xc3]),
+    case("tzcnt ecx, edx", &[0xf3, 0x0f, 0xbc, 0xca]),
+
+    // call
+    case("call $+5", &[0xe8, 0x00, 0x00, 0x00, 0x00]),
+    with("call rax", &[0xff, 0xd0], &[(Location::Register(Register::RAX), Input::Next)]),
+
+    // cmov
+    case("cmovb rax, rbx", &[0x48, 0x0f, 0x42, 0xc3]),
+    case("cmovbe rax, rbx", &[0x48, 0x0f, 0x46, 0xc3]),
+    case("cmovl rax, rbx", &[0x48, 0x0f, 0x4c, 0xc3]),
+    case("cmovle rax, rbx", &[0x48, 0x0f, 0x4e, 0xc3]),
+    case("cmovae rax, rbx", &[0x48, 0x0f, 0x43, 0xc3]),
+    case("cmova rax, rbx", &[0x48, 0x0f, 0x47, 0xc3]),
+    case("cmovge rax, rbx", &[0x48, 0x0f, 0x4d, 0xc3]),
+    case("cmovg rax, rbx", &[0x48, 0x0f, 0x4f, 0xc3]),
+    case("cmovno rax, rbx", &[0x48, 0x0f, 0x41, 0xc3]),
+    case("cmovnp rax, rbx", &[0x48, 0x0f, 0x4b, 0xc3]),
+    case("cmovns rax, rbx", &[0x48, 0x0f, 0x49, 0xc3]),
+    case("cmovne rax, rbx", &[0x48, 0x0f, 0x45, 0xc3]),
+    case("cmovo rax, rbx", &[0x48, 0x0f, 0x40, 0xc3]),
+    case("cmovp rax, rbx", &[0x48, 0x0f, 0x4a, 0xc3]),
+    case("cmovs rax, rbx", &[0x48, 0x0f, 0x48, 0xc3]),
+    case("cmove ecx, edx", &[0x0f, 0x44, 0xca]),
+
+    // cond_br
+    case("jo $+3; cmc", &[0x70, 0x01, 0xf5]),
+    case("jno $+3; cmc", &[0x71, 0x01, 0xf5]),
+    case("jb $+3; cmc", &[0x72, 0x01, 0xf5]),
+    case("jnb $+3; cmc", &[0x73, 0x01, 0xf5]),
+    case("jz $+3; cmc", &[0x74, 0x01, 0xf5]),
+    case("jz $+7; cmc", &[0x0f, 0x84, 0x01, 0x00, 0x00, 0x00, 0xf5]),
+    case("jnz $+3; cmc", &[0x75, 0x01, 0xf5]),
+    case("jbe $+3; cmc", &[0x76, 0x01, 0xf5]),
+    case("jnbe $+3; cmc", &[0x77, 0x01, 0xf5]),
+    case("js $+3; cmc", &[0x78, 0x01, 0xf5]),
+    case("jns $+3; cmc", &[0x79, 0x01, 0xf5]),
+    case("jp $+3; cmc", &[0x7a, 0x01, 0xf5]),
+    case("jnp $+3; cmc", &[0x7b, 0x01, 0xf5]),
+    case("jl $+3; cmc", &[0x7c, 0x01, 0xf5]),
+    case("jnl $+3; cmc", &[0x7d, 0x01, 0xf5]),
+    case("jle $+3; cmc", &[0x7e, 0x01, 0xf5]),
+    case("jnle $+3; cmc", &[0x7f, 0x01, 0xf5]),
+    case("jecxz $+4; cmc", &[0x67, 0xe3, 0x01, 0xf5]),
+    case("jrcxz $+3; cmc", &[0xe3, 0x01, 0xf5]),
+
+    // convert
+    case("cbw", &[0x66, 0x98]),
+    case("cdq", &[0x99]),
+    case("cdqe", &[0x48, 0x98]),
+    case("cqo", &[0x48, 0x99]),
+    case("cwd", &[0x66, 0x99]),
+    case("cwde", &[0x98]),
+
+    // dataxfer
+    case("bswap rax", &[0x48, 0x0f, 0xc8]),
+    case("bswap ecx", &[0x0f, 0xc9]),
+    case("movzx rax, bl", &[0x48, 0x0f, 0xb6, 0xc3]),
+    case("movzx ecx, dx", &[0x0f, 0xb7, 0xca]),
+    case("movsx rax, bl", &[0x48, 0x0f, 0xbe, 0xc3]),
+    case("movsx ecx, dx", &[0x0f, 0xbf, 0xca]),
+    case("movsxd rax, ebx", &[0x48, 0x63, 0xc3]),
+    case("mov rax, rbx", &[0x48, 0x89, 0xd8]),
+    case("mov ah, cl", &[0x88, 0xcc]),
+    case("mov ecx, edx", &[0x89, 0xd1]),
+    case("xchg rax, rbx", &[0x48, 0x93]),
+    case("xchg ah, al", &[0x86, 0xe0]),
+
+    // logical
+    case("and rax, rbx", &[0x48, 0x21, 0xd8]),
+    case("and cl, 0x0f", &[0x80, 0xe1, 0x0f]),
+    case("not rax", &[0x48, 0xf7, 0xd0]),
+    case("or rax, rbx", &[0x48, 0x09, 0xd8]),
+    case("test rax, rbx", &[0x48, 0x85, 0xd8]),
+    case("test cl, 0x81", &[0xf6, 0xc1, 0x81]),
+    case("xor rax, rbx", &[0x48, 0x31, 0xd8]),
+    case("xor edx, 0x80000000", &[0x81, 0xf2, 0x00, 0x00, 0x00, 0x80]),
+
+    // flagop
+    case("cmc", &[0xf5]),
+    case("clc", &[0xf8]),
+    case("cld", &[0xfc]),
+    case("stc", &[0xf9]),
+    case("std", &[0xfd]),
+    case("lahf", &[0x9f]),
+    case("sahf", &[0x9e]),
+
+    // misc
+    case("lea rax, [rbx + 4*rcx + 0x10]", &[0x48, 0x8d, 0x44, 0x8b, 0x10]),
+    case("lea ecx, [rdx + rsi]", &[0x8d, 0x0c, 0x32]),
+    with("leave", &[0xc9], &[(Location::Register(Register::RBP), Input::Stack(0x40))]),
+    case("enter 0x20, 0", &[0xc8, 0x20, 0x00, 0x00]),
+
+    // muldiv
+    case("mul rbx", &[0x48, 0xf7, 0xe3]),
+    case("mul cl", &[0xf6, 0xe1]),
+    case("imul rbx", &[0x48, 0xf7, 0xeb]),
+    case("imul rax, rbx", &[0x48, 0x0f, 0xaf, 0xc3]),
+    case("imul ecx, edx, -7", &[0x6b, 0xca, 0xf9]),
+    with("div rbx", &[0x48, 0xf7, 0xf3], &[(Location::Register(Register::RDX), Input::Zero)]),
+    with("div ecx", &[0xf7, 0xf1], &[(Location::Register(Register::RDX), Input::Zero)]),
+    with("idiv rbx", &[0x48, 0xf7, 0xfb], &[(Location::Register(Register::RDX), Input::Zero)]),
+
+    // nop
+    case("nop", &[0x90]),
+    case("nop dword ptr [rax]", &[0x0f, 0x1f, 0x00]),
+
+    // pop
+    case("pop rax", &[0x58]),
+    case("pop bx", &[0x66, 0x5b]),
+    with("popfq", &[0x9d], &[(Location::Stack(0x200), Input::Flags)]),
+    with("popfw", &[0x66, 0x9d], &[(Location::Stack(0x200), Input::Flags)]),
+
+    // push
+    case("push rax", &[0x50]),
+    case("push -2", &[0x6a, 0xfe]),
+    case("pushfq", &[0x9c]),
+    case("pushfw", &[0x66, 0x9c]),
+
+    // ret
+    with("ret", &[0xc3], RETURN_INPUTS),
+    with("ret 0x10", &[0xc2, 0x10, 0x00], RETURN_INPUTS),
+
+    // rotate
+    case("rcl rax, cl", &[0x48, 0xd3, 0xd0]),
+    case("rcl bl, 1", &[0xd0, 0xd3]),
+    case("rcr rax, cl", &[0x48, 0xd3, 0xd8]),
+    case("rcr edx, 5", &[0xc1, 0xda, 0x05]),
+    case("rol rax, cl", &[0x48, 0xd3, 0xc0]),
+    case("rol cx, 3", &[0x66, 0xc1, 0xc1, 0x03]),
+    case("ror rax, cl", &[0x48, 0xd3, 0xc8]),
+    case("ror bl, 1", &[0xd0, 0xcb]),
+
+    // semaphore
+    case("xadd rax, rbx", &[0x48, 0x0f, 0xc1, 0xd8]),
+    case("cmpxchg rbx, rcx", &[0x48, 0x0f, 0xb1, 0xcb]),
+    case("cmpxchg cl, dl", &[0x0f, 0xb0, 0xd1]),
+    // The compare mostly fails, which loads ECX into RAX with its upper half cleared
+    case("cmpxchg ecx, edx", &[0x0f, 0xb1, 0xd1]),
+    case("cmpxchg8b qword ptr [rsp]", &[0x0f, 0xc7, 0x0c, 0x24]),
+    with("cmpxchg8b qword ptr [rsp]", &[0x0f, 0xc7, 0x0c, 0x24], &[
+        (Location::Register(Register::RAX), Input::StackValue(0x200)),
+        (Location::Register(Register::RDX), Input::StackValue(0x204)),
+    ]),
+    case("cmpxchg16b xmmword ptr [rsp]", &[0x48, 0x0f, 0xc7, 0x0c, 0x24]),
+    with("cmpxchg16b xmmword ptr [rsp]", &[0x48, 0x0f, 0xc7, 0x0c, 0x24], &[
+        (Location::Register(Register::RAX), Input::StackValue(0x200)),
+        (Location::Register(Register::RDX), Input::StackValue(0x208)),
+    ]),
+    case("lock xadd qword ptr [rsp], rax", &[0xf0, 0x48, 0x0f, 0xc1, 0x04, 0x24]),
+
+    // setcc
+    case("setb al", &[0x0f, 0x92, 0xc0]),
+    case("setbe al", &[0x0f, 0x96, 0xc0]),
+    case("setl al", &[0x0f, 0x9c, 0xc0]),
+    case("setle al", &[0x0f, 0x9e, 0xc0]),
+    case("setae al", &[0x0f, 0x93, 0xc0]),
+    case("seta al", &[0x0f, 0x97, 0xc0]),
+    case("setge al", &[0x0f, 0x9d, 0xc0]),
+    case("setg al", &[0x0f, 0x9f, 0xc0]),
+    case("setno al", &[0x0f, 0x91, 0xc0]),
+    case("setnp al", &[0x0f, 0x9b, 0xc0]),
+    case("setns al", &[0x0f, 0x99, 0xc0]),
+    case("setne al", &[0x0f, 0x95, 0xc0]),
+    case("seto al", &[0x0f, 0x90, 0xc0]),
+    case("setp al", &[0x0f, 0x9a, 0xc0]),
+    case("sets al", &[0x0f, 0x98, 0xc0]),
+    case("sete bh", &[0x0f, 0x94, 0xc7]),
+
+    // shift
+    case("sar rax, cl", &[0x48, 0xd3, 0xf8]),
+    case("sar edx, 7", &[0xc1, 0xfa, 0x07]),
+    case("sarx rax, rbx, rcx", &[0xc4, 0xe2, 0xf2, 0xf7, 0xc3]),
+    case("shl rax, cl", &[0x48, 0xd3, 0xe0]),
+    case("shl bl, 1", &[0xd0, 0xe3]),
+    case("shlx rax, rbx, rcx", &[0xc4, 0xe2, 0xf1, 0xf7, 0xc3]),
+    case("shld rax, rbx, cl", &[0x48, 0x0f, 0xa5, 0xd8]),
+    case("shld edx, esi, 9", &[0x0f, 0xa4, 0xf2, 0x09]),
+    case("shr rax, cl", &[0x48, 0xd3, 0xe8]),
+    case("shr cx, 3", &[0x66, 0xc1, 0xe9, 0x03]),
+    case("shrx rax, rbx, rcx", &[0xc4, 0xe2, 0xf3, 0xf7, 0xc3]),
+    case("shrd rax, rbx, cl", &[0x48, 0x0f, 0xad, 0xd8]),
+    case("shrd edx, esi, 9", &[0x0f, 0xac, 0xf2, 0x09]),
+
+    // sse
+    case("movdqa xmm0, xmm1", &[0x66, 0x0f, 0x6f, 0xc1]),
+    case("movdqu xmm2, xmm9", &[0xf3, 0x41, 0x0f, 0x6f, 0xd1]),
+    case("movaps xmm0, xmm1", &[0x0f, 0x28, 0xc1]),
+    case("movups xmm0, xmm1", &[0x0f, 0x10, 0xc1]),
+    case("movapd xmm0, xmm1", &[0x66, 0x0f, 0x28, 0xc1]),
+    case("movupd xmm0, xmm1", &[0x66, 0x0f, 0x10, 0xc1]),
+    case("movd xmm0, eax", &[0x66, 0x0f, 0x6e, 0xc0]),
+    case("movd ecx, xmm1", &[0x66, 0x0f, 0x7e, 0xc9]),
+    case("movq xmm0, rax", &[0x66, 0x48, 0x0f, 0x6e, 0xc0]),
+    case("movq rcx, xmm1", &[0x66, 0x48, 0x0f, 0x7e, 0xc9]),
+    case("movq xmm0, xmm1", &[0xf3, 0x0f, 0x7e, 0xc1]),
+    case("pand xmm0, xmm1", &[0x66, 0x0f, 0xdb, 0xc1]),
+    case("pandn xmm0, xmm1", &[0x66, 0x0f, 0xdf, 0xc1]),
+    case("por xmm0, xmm1", &[0x66, 0x0f, 0xeb, 0xc1]),
+    case("pxor xmm0, xmm1", &[0x66, 0x0f, 0xef, 0xc1]),
+    case("andps xmm0, xmm1", &[0x0f, 0x54, 0xc1]),
+    case("andpd xmm0, xmm1", &[0x66, 0x0f, 0x54, 0xc1]),
+    case("andnps xmm0, xmm1", &[0x0f, 0x55, 0xc1]),
+    case("andnpd xmm0, xmm1", &[0x66, 0x0f, 0x55, 0xc1]),
+    case("orps xmm0, xmm1", &[0x0f, 0x56, 0xc1]),
+    case("orpd xmm0, xmm1", &[0x66, 0x0f, 0x56, 0xc1]),
+    case("xorps xmm0, xmm1", &[0x0f, 0x57, 0xc1]),
+    case("xorpd xmm0, xmm1", &[0x66, 0x0f, 0x57, 0xc1]),
+    case("paddb xmm0, xmm1", &[0x66, 0x0f, 0xfc, 0xc1]),
+    case("paddw xmm0, xmm1", &[0x66, 0x0f, 0xfd, 0xc1]),
+    case("paddd xmm0, xmm1", &[0x66, 0x0f, 0xfe, 0xc1]),
+    case("paddq xmm0, xmm1", &[0x66, 0x0f, 0xd4, 0xc1]),
+    case("paddsb xmm0, xmm1", &[0x66, 0x0f, 0xec, 0xc1]),
+    case("paddsw xmm0, xmm1", &[0x66, 0x0f, 0xed, 0xc1]),
+    case("paddusb xmm0, xmm1", &[0x66, 0x0f, 0xdc, 0xc1]),
+    case("paddusw xmm0, xmm1", &[0x66, 0x0f, 0xdd, 0xc1]),
+    case("psubb xmm0, xmm1", &[0x66, 0x0f, 0xf8, 0xc1]),
+    case("psubw xmm0, xmm1", &[0x66, 0x0f, 0xf9, 0xc1]),
+    case("psubd xmm0, xmm1", &[0x66, 0x0f, 0xfa, 0xc1]),
+    case("psubq xmm0, xmm1", &[0x66, 0x0f, 0xfb, 0xc1]),
+    case("psubsb xmm0, xmm1", &[0x66, 0x0f, 0xe8, 0xc1]),
+    case("psubsw xmm0, xmm1", &[0x66, 0x0f, 0xe9, 0xc1]),
+    case("psubusb xmm0, xmm1", &[0x66, 0x0f, 0xd8, 0xc1]),
+    case("psubusw xmm0, xmm1", &[0x66, 0x0f, 0xd9, 0xc1]),
+    case("pshufd xmm0, xmm1, 0x1b", &[0x66, 0x0f, 0x70, 0xc1, 0x1b]),
+    case("punpcklbw xmm0, xmm1", &[0x66, 0x0f, 0x60, 0xc1]),
+    case("punpcklwd xmm0, xmm1", &[0x66, 0x0f, 0x61, 0xc1]),
+    case("punpckldq xmm0, xmm1", &[0x66, 0x0f, 0x62, 0xc1]),
+    case("punpcklqdq xmm0, xmm1", &[0x66, 0x0f, 0x6c, 0xc1]),
+    case("punpckhbw xmm0, xmm1", &[0x66, 0x0f, 0x68, 0xc1]),
+    case("punpckhwd xmm0, xmm1", &[0x66, 0x0f, 0x69, 0xc1]),
+    case("punpckhdq xmm0, xmm1", &[0x66, 0x0f, 0x6a, 0xc1]),
+    case("punpckhqdq xmm0, xmm1", &[0x66, 0x0f, 0x6d, 0xc1]),
+
+    // sse_scalar
+    case("addss xmm0, xmm1", &[0xf3, 0x0f, 0x58, 0xc1]),
+    case("addsd xmm0, xmm1", &[0xf2, 0x0f, 0x58, 0xc1]),
+    case("subss xmm0, xmm1", &[0xf3, 0x0f, 0x5c, 0xc1]),
+    case("subsd xmm0, xmm1", &[0xf2, 0x0f, 0x5c, 0xc1]),
+    case("mulss xmm0, xmm1", &[0xf3, 0x0f, 0x59, 0xc1]),
+    case("mulsd xmm0, xmm1", &[0xf2, 0x0f, 0x59, 0xc1]),
+    case("divss xmm0, xmm1", &[0xf3, 0x0f, 0x5e, 0xc1]),
+    case("divsd xmm0, xmm1", &[0xf2, 0x0f, 0x5e, 0xc1]),
+    case("minss xmm0, xmm1", &[0xf3, 0x0f, 0x5d, 0xc1]),
+    case("minsd xmm0, xmm1", &[0xf2, 0x0f, 0x5d, 0xc1]),
+    case("maxss xmm0, xmm1", &[0xf3, 0x0f, 0x5f, 0xc1]),
+    case("maxsd xmm0, xmm1", &[0xf2, 0x0f, 0x5f, 0xc1]),
+    case("sqrtss xmm0, xmm1", &[0xf3, 0x0f, 0x51, 0xc1]),
+    case("sqrtsd xmm0, xmm1", &[0xf2, 0x0f, 0x51, 0xc1]),
+    case("ucomiss xmm0, xmm1", &[0x0f, 0x2e, 0xc1]),
+    case("ucomisd xmm0, xmm1", &[0x66, 0x0f, 0x2e, 0xc1]),
+    case("comiss xmm0, xmm1", &[0x0f, 0x2f, 0xc1]),
+    case("comisd xmm0, xmm1", &[0x66, 0x0f, 0x2f, 0xc1]),
+    case("cmpss xmm0, xmm1, 1", &[0xf3, 0x0f, 0xc2, 0xc1, 0x01]),
+    case("cmpsd xmm0, xmm1, 4", &[0xf2, 0x0f, 0xc2, 0xc1, 0x04]),
+    case("cvtsi2ss xmm0, rax", &[0xf3, 0x48, 0x0f, 0x2a, 0xc0]),
+    case("cvtsi2sd xmm0, ecx", &[0xf2, 0x0f, 0x2a, 0xc1]),
+    case("cvtss2sd xmm0, xmm1", &[0xf3, 0x0f, 0x5a, 0xc1]),
+    case("cvtsd2ss xmm0, xmm1", &[0xf2, 0x0f, 0x5a, 0xc1]),
+    case("cvtss2si rax, xmm1", &[0xf3, 0x48, 0x0f, 0x2d, 0xc1]),
+    case("cvtsd2si ecx, xmm1", &[0xf2, 0x0f, 0x2d, 0xc9]),
+    case("cvttss2si rax, xmm1", &[0xf3, 0x48, 0x0f, 0x2c, 0xc1]),
+    case("cvttsd2si rax, xmm1", &[0xf2, 0x48, 0x0f, 0x2c, 0xc1]),
+    case("movss xmm0, xmm1", &[0xf3, 0x0f, 0x10, 0xc1]),
+    case("movsd xmm0, xmm1", &[0xf2, 0x0f, 0x10, 0xc1]),
+
+    // stringop
+    with("cmpsb byte ptr [rsi], byte ptr es:[rdi]", &[0xa6], STRING_INPUTS),
+    with("cmpsw word ptr [rsi], word ptr es:[rdi]", &[0x66, 0xa7], STRING_INPUTS),
+    with("cmpsd dword ptr [rsi], dword ptr es:[rdi]", &[0xa7], STRING_INPUTS),
+    with("cmpsq qword ptr [rsi], qword ptr es:[rdi]", &[0x48, 0xa7], STRING_INPUTS),
+    with("lodsb al, byte ptr [rsi]", &[0xac], STRING_INPUTS),
+    with("lodsw ax, word ptr [rsi]", &[0x66, 0xad], STRING_INPUTS),
+    with("lodsd eax, dword ptr [rsi]", &[0xad], STRING_INPUTS),
+    with("lodsq rax, qword ptr [rsi]", &[0x48, 0xad], STRING_INPUTS),
+    with("movsb byte ptr es:[rdi], byte ptr [rsi]", &[0xa4], STRING_INPUTS),
+    with("movsw word ptr es:[rdi], word ptr [rsi]", &[0x66, 0xa5], STRING_INPUTS),
+    with("movsd dword ptr es:[rdi], dword ptr [rsi]", &[0xa5], STRING_INPUTS),
+    with("movsq qword ptr es:[rdi], qword ptr [rsi]", &[0x48, 0xa5], STRING_INPUTS),
+    with("scasb al, byte ptr es:[rdi]", &[0xae], STRING_INPUTS),
+    with("scasw ax, word ptr es:[rdi]", &[0x66, 0xaf], STRING_INPUTS),
+    with("scasd eax, dword ptr es:[rdi]", &[0xaf], STRING_INPUTS),
+    with("scasq rax, qword ptr es:[rdi]", &[0x48, 0xaf], STRING_INPUTS),
+    with("stosb byte ptr es:[rdi], al", &[0xaa], STRING_INPUTS),
+    with("stosw word ptr es:[rdi], ax", &[0x66, 0xab], STRING_INPUTS),
+    with("stosd dword ptr es:[rdi], eax", &[0xab], STRING_INPUTS),
+    with("stosq qword ptr es:[rdi], rax", &[0x48, 0xab], STRING_INPUTS),
+    with("rep movsb byte ptr es:[rdi], byte ptr [rsi]", &[0xf3, 0xa4], REP_INPUTS),
+    with("rep movsq qword ptr es:[rdi], qword ptr [rsi]", &[0xf3, 0x48, 0xa5], REP_INPUTS),
+    with("rep stosb byte ptr es:[rdi], al", &[0xf3, 0xaa], REP_INPUTS),
+    with("rep stosd dword ptr es:[rdi], eax", &[0xf3, 0xab], REP_INPUTS),
+    with("rep lodsb al, byte ptr [rsi]", &[0xf3, 0xac], REP_INPUTS),
+    with("repe cmpsb byte ptr [rsi], byte ptr es:[rdi]", &[0xf3, 0xa6], REP_INPUTS),
+    with("repne cmpsb byte ptr [rsi], byte ptr es:[rdi]", &[0xf2, 0xa6], REP_INPUTS),
+    with("repe scasb al, byte ptr es:[rdi]", &[0xf3, 0xae], REP_INPUTS),
+    with("repne scasb al, byte ptr es:[rdi]", &[0xf2, 0xae], REP_INPUTS),
+    with("rep movsb byte ptr es:[rdi], byte ptr [rsi]", &[0xf3, 0xa4], &[
+        (Location::Register(Register::RSI), Input::Stack(0x40)),
+        (Location::Register(Register::RDI), Input::Stack(0x300)),
+        (Location::Register(Register::RCX), Input::Zero),
+    ]),
+
+    // uncond_br
+    case("jmp $+3; cmc", &[0xeb, 0x01, 0xf5]),
+    case("jmp $+6; cmc", &[0xe9, 0x01, 0x00, 0x00, 0x00, 0xf5]),
+    with("jmp rax", &[0xff, 0xe0], &[(Location::Register(Register::RAX), Input::Next)]),
+
+    // avx
+    case("vmovdqa xmm0, xmm1", &[0xc5, 0xf9, 0x6f, 0xc1]),
+    case("vmovdqu xmm0, xmm1", &[0xc5, 0xfa, 0x6f, 0xc1]),
+    case("vmovaps xmm0, xmm1", &[0xc5, 0xf8, 0x28, 0xc1]),
+    case("vmovups xmm0, xmm1", &[0xc5, 0xf8, 0x10, 0xc1]),
+    case("vmovapd xmm0, xmm1", &[0xc5, 0xf9, 0x28, 0xc1]),
+    case("vmovupd xmm0, xmm1", &[0xc5, 0xf9, 0x10, 0xc1]),
+    case("vmovd xmm0, eax", &[0xc5, 0xf9, 0x6e, 0xc0]),
+    case("vmovq rcx, xmm1", &[0xc4, 0xe1, 0xf9, 0x7e, 0xc9]),
+    case("vpand xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xdb, 0xc2]),
+    case("vpandn xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xdf, 0xc2]),
+    case("vpor xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xeb, 0xc2]),
+    case("vpxor xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xef, 0xc2]),
+    case("vandps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x54, 0xc2]),
+    case("vandpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x54, 0xc2]),
+    case("vandnps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x55, 0xc2]),
+    case("vandnpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x55, 0xc2]),
+    case("vorps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x56, 0xc2]),
+    case("vorpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x56, 0xc2]),
+    case("vxorps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x57, 0xc2]),
+    case("vxorpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x57, 0xc2]),
+    case("vpaddb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xfc, 0xc2]),
+    case("vpaddw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xfd, 0xc2]),
+    case("vpaddd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xfe, 0xc2]),
+    case("vpaddq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xd4, 0xc2]),
+    case("vpaddsb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xec, 0xc2]),
+    case("vpaddsw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xed, 0xc2]),
+    case("vpaddusb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xdc, 0xc2]),
+    case("vpaddusw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xdd, 0xc2]),
+    case("vpsubb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xf8, 0xc2]),
+    case("vpsubw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xf9, 0xc2]),
+    case("vpsubd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xfa, 0xc2]),
+    case("vpsubq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xfb, 0xc2]),
+    case("vpsubsb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xe8, 0xc2]),
+    case("vpsubsw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xe9, 0xc2]),
+    case("vpsubusb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xd8, 0xc2]),
+    case("vpsubusw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0xd9, 0xc2]),
+    case("vaddps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x58, 0xc2]),
+    case("vaddpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x58, 0xc2]),
+    case("vsubps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x5c, 0xc2]),
+    case("vsubpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x5c, 0xc2]),
+    case("vmulps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x59, 0xc2]),
+    case("vmulpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x59, 0xc2]),
+    case("vdivps xmm0, xmm1, xmm2", &[0xc5, 0xf0, 0x5e, 0xc2]),
+    case("vdivpd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x5e, 0xc2]),
+    case("vpcmpeqb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x74, 0xc2]),
+    case("vpcmpeqw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x75, 0xc2]),
+    case("vpcmpeqd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x76, 0xc2]),
+    case("vpcmpeqq xmm0, xmm1, xmm2", &[0xc4, 0xe2, 0x71, 0x29, 0xc2]),
+    case("vpcmpgtb xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x64, 0xc2]),
+    case("vpcmpgtw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x65, 0xc2]),
+    case("vpcmpgtd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x66, 0xc2]),
+    case("vpcmpgtq xmm0, xmm1, xmm2", &[0xc4, 0xe2, 0x71, 0x37, 0xc2]),
+    case("vpmovmskb eax, xmm1", &[0xc5, 0xf9, 0xd7, 0xc1]),
+    case("vptest xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x17, 0xc1]),
+    case("vpshufd xmm0, xmm1, 0x1b", &[0xc5, 0xf9, 0x70, 0xc1, 0x1b]),
+    case("vpunpcklbw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x60, 0xc2]),
+    case("vpunpcklwd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x61, 0xc2]),
+    case("vpunpckldq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x62, 0xc2]),
+    case("vpunpcklqdq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x6c, 0xc2]),
+    case("vpunpckhbw xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x68, 0xc2]),
+    case("vpunpckhwd xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x69, 0xc2]),
+    case("vpunpckhdq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x6a, 0xc2]),
+    case("vpunpckhqdq xmm0, xmm1, xmm2", &[0xc5, 0xf1, 0x6d, 0xc2]),
+    case("vpermq ymm0, ymm1, 0x1b", &[0xc4, 0xe3, 0xfd, 0x00, 0xc1, 0x1b]),
+    case("vpermpd ymm0, ymm1, 0x4e", &[0xc4, 0xe3, 0xfd, 0x01, 0xc1, 0x4e]),
+    case("vperm2i128 ymm0, ymm1, ymm2, 0x21", &[0xc4, 0xe3, 0x75, 0x46, 0xc2, 0x21]),
+    case("vperm2f128 ymm0, ymm1, ymm2, 0x03", &[0xc4, 0xe3, 0x75, 0x06, 0xc2, 0x03]),
+    case("vinserti128 ymm0, ymm1, xmm2, 1", &[0xc4, 0xe3, 0x75, 0x38, 0xc2, 0x01]),
+    case("vinsertf128 ymm0, ymm1, xmm2, 0", &[0xc4, 0xe3, 0x75, 0x18, 0xc2, 0x00]),
+    case("vextracti128 xmm0, ymm1, 1", &[0xc4, 0xe3, 0x7d, 0x39, 0xc8, 0x01]),
+    case("vextractf128 xmm0, ymm1, 0", &[0xc4, 0xe3, 0x7d, 0x19, 0xc8, 0x00]),
+    case("vpbroadcastb xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x78, 0xc1]),
+    case("vpbroadcastw xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x79, 0xc1]),
+    case("vpbroadcastd xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x58, 0xc1]),
+    case("vpbroadcastq xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x59, 0xc1]),
+    case("vbroadcastss xmm0, xmm1", &[0xc4, 0xe2, 0x79, 0x18, 0xc1]),
+    case("vbroadcastsd ymm0, xmm1", &[0xc4, 0xe2, 0x7d, 0x19, 0xc1]),
+    case("vbroadcasti128 ymm0, xmmword ptr [rsp]", &[0xc4, 0xe2, 0x7d, 0x5a, 0x04, 0x24]),
+    case("vbroadcastf128 ymm0, xmmword ptr [rsp]", &[0xc4, 0xe2, 0x7d, 0x1a, 0x04, 0x24]),
+    case("vzeroupper", &[0xc5, 0xf8, 0x77]),
+    case("vzeroall", &[0xc5, 0xfc, 0x77]),
+
+    // avx512
+    case("kmovw k1, k2", &[0xc5, 0xf8, 0x90, 0xca]),
+    case("kmovb k1, k2", &[0xc5, 0xf9, 0x90, 0xca]),
+    case("kmovq k1, k2", &[0xc4, 0xe1, 0xf8, 0x90, 0xca]),
+    case("kmovd k1, k2", &[0xc4, 0xe1, 0xf9, 0x90, 0xca]),
+    case("kmovw k1, eax", &[0xc5, 0xf8, 0x92, 0xc8]),
+    case("kmovq rax, k1", &[0xc4, 0xe1, 0xfb, 0x93, 0xc1]),
+    case("kandw k1, k2, k3", &[0xc5, 0xec, 0x41, 0xcb]),
+    case("kandb k1, k2, k3", &[0xc5, 0xed, 0x41, 0xcb]),
+    case("kandq k1, k2, k3", &[0xc4, 0xe1, 0xec, 0x41, 0xcb]),
+    case("kandd k1, k2, k3", &[0xc4, 0xe1, 0xed, 0x41, 0xcb]),
+    case("kandnw k1, k2, k3", &[0xc5, 0xec, 0x42, 0xcb]),
+    case("kandnb k1, k2, k3", &[0xc5, 0xed, 0x42, 0xcb]),
+    case("kandnq k1, k2, k3", &[0xc4, 0xe1, 0xec, 0x42, 0xcb]),
+    case("kandnd k1, k2, k3", &[0xc4, 0xe1, 0xed, 0x42, 0xcb]),
+    case("korw k1, k2, k3", &[0xc5, 0xec, 0x45, 0xcb]),
+    case("korb k1, k2, k3", &[0xc5, 0xed, 0x45, 0xcb]),
+    case("korq k1, k2, k3", &[0xc4, 0xe1, 0xec, 0x45, 0xcb]),
+    case("kord k1, k2, k3", &[0xc4, 0xe1, 0xed, 0x45, 0xcb]),
+    case("kxnorw k1, k2, k3", &[0xc5, 0xec, 0x46, 0xcb]),
+    case("kxnorb k1, k2, k3", &[0xc5, 0xed, 0x46, 0xcb]),
+    case("kxnorq k1, k2, k3", &[0xc4, 0xe1, 0xec, 0x46, 0xcb]),
+    case("kxnord k1, k2, k3", &[0xc4, 0xe1, 0xed, 0x46, 0xcb]),
+    case("kxorw k1, k2, k3", &[0xc5, 0xec, 0x47, 0xcb]),
+    case("kxorb k1, k2, k3", &[0xc5, 0xed, 0x47, 0xcb]),
+    case("kxorq k1, k2, k3", &[0xc4, 0xe1, 0xec, 0x47, 0xcb]),
+    case("kxord k1, k2, k3", &[0xc4, 0xe1, 0xed, 0x47, 0xcb]),
+    case("knotw k1, k2", &[0xc5, 0xf8, 0x44, 0xca]),
+    case("knotb k1, k2", &[0xc5, 0xf9, 0x44, 0xca]),
+    case("knotq k1, k2", &[0xc4, 0xe1, 0xf8, 0x44, 0xca]),
+    case("knotd k1, k2", &[0xc4, 0xe1, 0xf9, 0x44, 0xca]),
+    case("kortestw k1, k2", &[0xc5, 0xf8, 0x98, 0xca]),
+    case("kortestb k1, k2", &[0xc5, 0xf9, 0x98, 0xca]),
+    case("kortestq k1, k2", &[0xc4, 0xe1, 0xf8, 0x98, 0xca]),
+    case("kortestd k1, k2", &[0xc4, 0xe1, 0xf9, 0x98, 0xca]),
+
+    // x87
+    with("fld qword ptr [rsp]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld dword ptr [rsp]; fstp qword ptr [rsp+0x10]", &[0xd9, 0x04, 0x24, 0xdd, 0x5c, 0x24, 0x10], SINGLE_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fld st1; fstp qword ptr [rsp+0x10]; fstp st0; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xd9, 0xc1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fst qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x04, 0x24, 0xdd, 0x54, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fst dword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x04, 0x24, 0xd9, 0x54, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    case("fild dword ptr [rsp]; fstp qword ptr [rsp+0x10]", &[0xdb, 0x04, 0x24, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fild qword ptr [rsp]; fstp qword ptr [rsp+0x10]", &[0xdf, 0x2c, 0x24, 0xdd, 0x5c, 0x24, 0x10]),
+    with("fld qword ptr [rsp]; fist dword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x04, 0x24, 0xdb, 0x54, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fistp word ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdf, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fistp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdf, 0x7c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fisttp dword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdb, 0x4c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fisttp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdd, 0x4c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fadd qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x44, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fadd st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xd8, 0xc1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fadd st1, st0; fstp st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdc, 0xc1, 0xdd, 0xd8, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; faddp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xc1, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fiadd dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x44, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fsub qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x64, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fsubp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xe9, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fisub dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x64, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fsubr qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x6c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fsubrp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xe1, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fisubr dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x6c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fmul qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x4c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fmulp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xc9, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fimul dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x4c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fdiv qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x74, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fdiv st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xd8, 0xf1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fdivp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xf9, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fidiv dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x74, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fdivr qword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xdc, 0x7c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fdivrp st1, st0; fstp qword ptr [rsp+0x10]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xf1, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fidivr dword ptr [rsp+8]; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xda, 0x7c, 0x24, 0x08, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fchs; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xd9, 0xe0, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fabs; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xd9, 0xe1, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fabs; fsqrt; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xd9, 0xe1, 0xd9, 0xfa, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; frndint; fstp qword ptr [rsp+0x10]", &[0xdd, 0x04, 0x24, 0xd9, 0xfc, 0xdd, 0x5c, 0x24, 0x10], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fxch st1; fstp qword ptr [rsp+0x10]; fstp qword ptr [rsp+0x18]", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xd9, 0xc9, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0x5c, 0x24, 0x18], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcomi st0, st1; fstp st0; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xf1, 0xdd, 0xd8, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcomip st0, st1; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdf, 0xf1, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fucomi st0, st1; fstp st0; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xe9, 0xdd, 0xd8, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fucomip st0, st1; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdf, 0xe9, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fcom qword ptr [rsp+8]; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0", &[0xdd, 0x04, 0x24, 0xdc, 0x54, 0x24, 0x08, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcom st1; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xd8, 0xd1, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; fcomp qword ptr [rsp+8]; fnstsw ax; and ah, 0x45; movzx eax, ah", &[0xdd, 0x04, 0x24, 0xdc, 0x5c, 0x24, 0x08, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcompp; fnstsw ax; and ah, 0x45; movzx eax, ah", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xde, 0xd9, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fucom st1; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdd, 0xe1, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fucomp st1; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdd, 0xe9, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fucompp; fnstsw ax; and ah, 0x45; movzx eax, ah", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xda, 0xe9, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; ficom dword ptr [rsp+8]; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0", &[0xdd, 0x04, 0x24, 0xda, 0x54, 0x24, 0x08, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; ficomp dword ptr [rsp+8]; fnstsw ax; and ah, 0x45; movzx eax, ah", &[0xdd, 0x04, 0x24, 0xda, 0x5c, 0x24, 0x08, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4], FLOAT_INPUTS),
+    with("fld qword ptr [rsp]; ftst; fnstsw ax; and ah, 0x45; movzx eax, ah; fstp st0", &[0xdd, 0x04, 0x24, 0xd9, 0xe4, 0xdf, 0xe0, 0x80, 0xe4, 0x45, 0x0f, 0xb6, 0xc4, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovb st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xda, 0xc1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmove st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xda, 0xc9, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovbe st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xda, 0xd1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovu st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xda, 0xd9, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovnb st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xc1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovne st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xc9, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovnbe st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xd1, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    with("fld qword ptr [rsp+8]; fld qword ptr [rsp]; fcmovnu st0, st1; fstp qword ptr [rsp+0x10]; fstp st0", &[0xdd, 0x44, 0x24, 0x08, 0xdd, 0x04, 0x24, 0xdb, 0xd9, 0xdd, 0x5c, 0x24, 0x10, 0xdd, 0xd8], FLOAT_INPUTS),
+    case("fldz; fstp qword ptr [rsp+0x10]", &[0xd9, 0xee, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fld1; fstp qword ptr [rsp+0x10]", &[0xd9, 0xe8, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fldpi; fstp qword ptr [rsp+0x10]", &[0xd9, 0xeb, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fldl2e; fstp qword ptr [rsp+0x10]", &[0xd9, 0xea, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fldl2t; fstp qword ptr [rsp+0x10]", &[0xd9, 0xe9, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fldlg2; fstp qword ptr [rsp+0x10]", &[0xd9, 0xec, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fldln2; fstp qword ptr [rsp+0x10]", &[0xd9, 0xed, 0xdd, 0x5c, 0x24, 0x10]),
+    case("fnstsw word ptr [rsp+0x10]", &[0xdd, 0x7c, 0x24, 0x10]),
+    case("fnstcw word ptr [rsp+0x10]", &[0xd9, 0x7c, 0x24, 0x10]),
+    with("fnstcw word ptr [rsp+0x10]; or word ptr [rsp+0x10], 0x400; fldcw word ptr [rsp+0x10]; fld qword ptr [rsp]; frndint; fstp qword ptr [rsp+8]", &[0xd9, 0x7c, 0x24, 0x10, 0x66, 0x81, 0x4c, 0x24, 0x10, 0x00, 0x04, 0xd9, 0x6c, 0x24, 0x10, 0xdd, 0x04, 0x24, 0xd9, 0xfc, 0xdd, 0x5c, 0x24, 0x08], FLOAT_INPUTS),
+    with("fnstcw word ptr [rsp+0x10]; or word ptr [rsp+0x10], 0x800; fldcw word ptr [rsp+0x10]; fld qword ptr [rsp]; fistp dword ptr [rsp+8]", &[0xd9, 0x7c, 0x24, 0x10, 0x66, 0x81, 0x4c, 0x24, 0x10, 0x00, 0x08, 0xd9, 0x6c, 0x24, 0x10, 0xdd, 0x04, 0x24, 0xdb, 0x5c, 0x24, 0x08], FLOAT_INPUTS),
+    with("fnstcw word ptr [rsp+0x10]; or word ptr [rsp+0x10], 0xc00; fldcw word ptr [rsp+0x10]; fld qword ptr [rsp]; fistp qword ptr [rsp+8]", &[0xd9, 0x7c, 0x24, 0x10, 0x66, 0x81, 0x4c, 0x24, 0x10, 0x00, 0x0c, 0xd9, 0x6c, 0x24, 0x10, 0xdd, 0x04, 0x24, 0xdf, 0x7c, 0x24, 0x08], FLOAT_INPUTS),
+    case("fwait", &[0x9b]),
+    case("fnclex", &[0xdb, 0xe2]),
+    case("fninit", &[0xdb, 0xe3]),
+];
+
+/// Register state of the native stub. Offsets are used by the generated code
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy, Default)]
+struct NativeState {
+    xmm: [u128; 16],
+    gprs: [u64; 16],
+    rflags: u64,
+    /// Stack pointer of the test process while the instruction runs
+    host_rsp: u64,
+    /// Only loaded and stored on hosts with AVX-512
+    k: [u64; 8],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    state: NativeState,
+    stack: [u8; STACK_SIZE],
+}
+
+/// Memory shared with the forked child
+#[repr(C)]
+struct Shared {
+    scratch: NativeState,
+    completed: usize,
+    results: [Snapshot; ITERATIONS],
+}
+
+/// Executes an instruction on the host CPU
+struct NativeRunner {
+    code: *mut u8,
+    stack: *mut u8,
+    shared: *mut Shared,
+    /// Whether the stub loads and stores the opmask registers
+    masks: bool,
+}
+
+impl NativeRunner {
+    fn new() -> Self {
+        // SAFETY: fresh anonymous mappings
+        unsafe {
+            let code = map(
+                PAGE_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+                false,
+            );
+            let stack = map(PAGE_SIZE, libc::PROT_READ | libc::PROT_WRITE, true);
+            let shared = map(
+                std::mem::size_of::<Shared>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                true,
+            );
+            Self {
+                code,
+                stack,
+                shared: shared.cast(),
+                masks: std::arch::is_x86_feature_detected!("avx512bw"),
+            }
+        }
+    }
+
+    fn stack_base(&self) -> u64 {
+        self.stack as u64
+    }
+
+    /// Address of the case code in the stub
+    fn case_address(&self) -> u64 {
+        self.code as u64 + self.stub_prologue().len() as u64
+    }
+
+    /// Runs `code` with every input, giving `None` for the inputs which faulted. `None` if the host
+    /// doesn't support the instruction
+    fn run(&self, code: &[u8], inputs: &[Snapshot]) -> Option<Vec<Option<Snapshot>>> {
+        self.write_stub(code);
+
+        let mut outcomes = Vec::with_capacity(inputs.len());
+        while outcomes.len() < inputs.len() {
+            let start = outcomes.len();
+            // SAFETY: the child only runs the stub and copies plain data before exiting
+            unsafe {
+                (*self.shared).completed = start;
+                let pid = libc::fork();
+                assert!(pid >= 0, "fork failed");
+                if pid == 0 {
+                    self.run_child(&inputs[start..], start);
+                }
+
+                let mut status = 0;
+                libc::waitpid(pid, &mut status, 0);
+
+                let completed = std::ptr::read_volatile(&(*self.shared).completed);
+                for index in start..completed {
+                    outcomes.push(Some((*self.shared).results[index]));
+                }
+                if libc::WIFSIGNALED(status) {
+                    if libc::WTERMSIG(status) == libc::SIGILL {
+                        return None;
+                    }
+                    outcomes.push(None);
+                }
+            }
+        }
+        Some(outcomes)
+    }
+
+    unsafe fn run_child(&self, inputs: &[Snapshot], start: usize) -> ! {
+        let stub: extern "C" fn(*mut NativeState) = std::mem::transmute(self.code);
+        let shared = &mut *self.shared;
+
+        for (index, input) in (start..).zip(inputs) {
+            shared.scratch = input.state;
+            std::ptr::copy_nonoverlapping(input.stack.as_ptr(), self.stack, STACK_SIZE);
+
+            stub(&mut shared.scratch);
+
+            let result = &mut shared.results[index];
+            result.state = shared.scratch;
+            std::ptr::copy_nonoverlapping(self.stack, result.stack.as_mut_ptr(), STACK_SIZE);
+            std::ptr::write_volatile(&mut shared.completed, index + 1);
+        }
+        libc::_exit(0)
+    }
+
+    /// `extern "C" fn(*mut NativeState)` loading the state, running `code` and storing the state
+    /// again
+    fn write_stub(&self, code: &[u8]) {
+        let scratch = unsafe { &raw const (*self.shared).scratch } as u64;
+        let xmm = offset_of!(NativeState, xmm) as u32;
+        let gprs = offset_of!(NativeState, gprs) as u32;
+        let rflags = offset_of!(NativeState, rflags) as u32;
+        let host_rsp = offset_of!(NativeState, host_rsp) as u32;
+        let k = offset_of!(NativeState, k) as u32;
+
+        let mut stub = self.stub_prologue();
+        stub.extend_from_slice(code);
+
+        // mov [scratch.gprs], rax; mov rax, scratch
+        stub.extend_from_slice(&[0x48, 0xa3]);
+        stub.extend_from_slice(&(scratch + u64::from(gprs)).to_le_bytes());
+        stub.extend_from_slice(&[0x48, 0xb8]);
+        stub.extend_from_slice(&scratch.to_le_bytes());
+        for register in 1..16u8 {
+            // mov [rax + offset], reg
+            modrm_access(
+                &mut stub,
+                &rex_w(register, &[0x89]),
+                register,
+                0,
+                gprs + u32::from(register) * 8,
+            );
+        }
+        for register in 0..16u8 {
+            // movdqu [rax + offset], xmmN
+            vector_access(&mut stub, 0x7f, register, 0, xmm + u32::from(register) * 16);
+        }
+        if self.masks {
+            for register in 0..8u8 {
+                // kmovq [rax + offset], kN
+                modrm_access(
+                    &mut stub,
+                    &KMOVQ_STORE,
+                    register,
+                    0,
+                    k + u32::from(register) * 8,
+                );
+            }
+        }
+        // mov rsp, [rax + host_rsp]; pushfq; pop qword ptr [rax + rflags]; cld
+        modrm_access(&mut stub, &[0x48, 0x8b], 4, 0, host_rsp);
+        stub.push(0x9c);
+        modrm_access(&mut stub, &[0x8f], 0, 0, rflags);
+        stub.push(0xfc);
+        // fninit, the case may have changed the control word
+        stub.extend_from_slice(&[0xdb, 0xe3]);
+        stub.extend_from_slice(&[
+            0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5d, 0x5b, 0xc3,
+        ]);
+
+        assert!(stub.len() <= PAGE_SIZE);
+        // SAFETY: the code page is writable and large enough
+        unsafe { std::ptr::copy_nonoverlapping(stub.as_ptr(), self.code, stub.len()) };
+    }
+
+    /// Part of the stub before the case code, loading the state from the `*mut NativeState` in
+    /// RDI. It doesn't depend on the state's address
+    fn stub_prologue(&self) -> Vec<u8> {
+        let xmm = offset_of!(NativeState, xmm) as u32;
+        let gprs = offset_of!(NativeState, gprs) as u32;
+        let rflags = offset_of!(NativeState, rflags) as u32;
+        let host_rsp = offset_of!(NativeState, host_rsp) as u32;
+        let k = offset_of!(NativeState, k) as u32;
+
+        let mut stub = Vec::new();
+        // Callee saved registers
+        stub.extend_from_slice(&[0x53, 0x55, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+        // mov [rdi + host_rsp], rsp
+        modrm_access(&mut stub, &[0x48, 0x89], 4, 7, host_rsp);
+        // fninit, x87 cases start with an empty register stack like lifted functions
+        stub.extend_from_slice(&[0xdb, 0xe3]);
+        for register in 0..16u8 {
+            // movdqu xmmN, [rdi + offset]
+            vector_access(&mut stub, 0x6f, register, 7, xmm + u32::from(register) * 16);
+        }
+        if self.masks {
+            for register in 0..8u8 {
+                // kmovq kN, [rdi + offset]
+                modrm_access(
+                    &mut stub,
+                    &KMOVQ_LOAD,
+                    register,
+                    7,
+                    k + u32::from(register) * 8,
+                );
+            }
+        }
+        // push qword ptr [rdi + rflags]; popfq
+        modrm_access(&mut stub, &[0xff], 6, 7, rflags);
+        stub.push(0x9d);
+        for register in (0..16u8).filter(|&register| register != 7).chain([7]) {
+            // mov reg, [rdi + offset]
+            modrm_access(
+                &mut stub,
+                &rex_w(register, &[0x8b]),
+                register,
+                7,
+                gprs + u32::from(register) * 8,
+            );
+        }
+        stub
+    }
+}
+
+impl Drop for NativeRunner {
+    fn drop(&mut self) {
+        // SAFETY: the mappings were created in `new` with these sizes
+        unsafe {
+            libc::munmap(self.code.cast(), PAGE_SIZE);
+            libc::munmap(self.stack.cast(), PAGE_SIZE);
+            libc::munmap(self.shared.cast(), std::mem::size_of::<Shared>());
+        }
+    }
+}
+
+unsafe fn map(size: usize, protection: i32, shared: bool) -> *mut u8 {
+    let sharing = if shared {
+        libc::MAP_SHARED
+    } else {
+        libc::MAP_PRIVATE
+    };
+    let address = libc::mmap(
+        std::ptr::null_mut(),
+        size,
+        protection,
+        sharing | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    assert_ne!(address, libc::MAP_FAILED, "mmap failed");
+    address.cast()
+}
+
+/// VEX prefix and opcode of `kmovq k, m64`
+const KMOVQ_LOAD: [u8; 4] = [0xc4, 0xe1, 0xf8, 0x90];
+/// VEX prefix and opcode of `kmovq m64, k`
+const KMOVQ_STORE: [u8; 4] = [0xc4, 0xe1, 0xf8, 0x91];
+
+/// Opcode with REX.W and REX.R for `register`
+fn rex_w(register: u8, opcode: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x48 | ((register >> 3) << 2)];
+    bytes.extend_from_slice(opcode);
+    bytes
+}
+
+/// `opcode` with a `[base + disp32]` operand, `reg` is the ModRM reg field
+fn modrm_access(stub: &mut Vec<u8>, opcode: &[u8], reg: u8, base: u8, displacement: u32) {
+    stub.extend_from_slice(opcode);
+    stub.push(0x80 | ((reg & 7) << 3) | base);
+    stub.extend_from_slice(&displacement.to_le_bytes());
+}
+
+/// MOVDQU between XMM `register` and `[base + disp32]`
+fn vector_access(stub: &mut Vec<u8>, opcode: u8, register: u8, base: u8, displacement: u32) {
+    stub.push(0xf3);
+    if register >= 8 {
+        stub.push(0x44);
+    }
+    modrm_access(stub, &[0x0f, opcode], register, base, displacement);
+}
+
+/// xorshift64*, so failures can be reproduced
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Random value biased towards the edge cases of arithmetic
+    fn value(&mut self) -> u64 {
+        match self.next() % 8 {
+            0 => 0,
+            1 => u64::MAX,
+            2 => self.next() % 64,
+            3 => 1u64 << (self.next() % 64),
+            4 => (1u64 << (self.next() % 64)).wrapping_sub(1),
+            _ => self.next(),
+        }
+    }
+
+    /// Random finite float with `bits` bits. Small multiples of a half test rounding ties
+    fn float(&mut self, bits: u32) -> u64 {
+        let (exponent_bits, mantissa_bits) = if bits == 32 { (8, 23) } else { (11, 52) };
+        if self.next().is_multiple_of(4) {
+            let half = (self.next() % 64) as f64 / 2.0 - 16.0;
+            return if bits == 32 {
+                u64::from((half as f32).to_bits())
+            } else {
+                half.to_bits()
+            };
+        }
+        let value = self.value() & (u64::MAX >> (64 - bits));
+        let exponent_mask = ((1 << exponent_bits) - 1) << mantissa_bits;
+        if value & exponent_mask == exponent_mask {
+            // Infinities and NaNs become large finite values
+            value ^ (1 << (bits - 2))
+        } else {
+            value
+        }
+    }
+}
+
+fn random_input(random: &mut Random, case: &Case, stack_base: u64, next_address: u64) -> Snapshot {
+    let mut state = NativeState::default();
+    for gpr in &mut state.gprs {
+        *gpr = random.value();
+    }
+    for xmm in &mut state.xmm {
+        *xmm = u128::from(random.value()) | (u128::from(random.value()) << 64);
+    }
+    for k in &mut state.k {
+        *k = random.value();
+    }
+    state.gprs[4] = stack_base + STACK_SIZE as u64 / 2;
+
+    // Bit 1 is always set, DF starts cleared like the ABI requires
+    let arithmetic_flags = COMPARED_FLAGS.difference(CpuFlag::DF).bits();
+    state.rflags = 0x2 | (random.next() & u64::from(arithmetic_flags));
+
+    let mut stack = [0; STACK_SIZE];
+    for chunk in stack.chunks_mut(8) {
+        chunk.copy_from_slice(&random.value().to_le_bytes());
+    }
+
+    for (location, input) in case.inputs {
+        let value = match *input {
+            Input::Zero => 0,
+            Input::Stack(offset) => stack_base + offset,
+            Input::StackValue(offset) => {
+                let offset = offset as usize;
+                u64::from_le_bytes(stack[offset..offset + 8].try_into().unwrap())
+            }
+            Input::Count(limit) => random.next() % limit,
+            Input::Next => next_address,
+            Input::Flags => 0x2 | (random.next() & u64::from(COMPARED_FLAGS.bits())),
+            Input::Float(bits) => random.float(bits),
+        };
+        match *location {
+            Location::Register(register) => {
+                let index = GPRS
+                    .iter()
+                    .position(|gpr| *gpr == register)
+                    .expect("Inputs are general purpose registers");
+                state.gprs[index] = value;
+            }
+            Location::Stack(offset) => {
+                let offset = offset as usize;
+                stack[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    Snapshot { state, stack }
+}
+
+fn start_context(input: &NativeState, rip: u64) -> StartContextX86<u64> {
+    let [rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15] = input.gprs;
+    let flag = |flag: CpuFlag| u8::from(input.rflags & u64::from(flag.bits()) != 0);
+
+    let mut context = StartContextX86 {
+        rax,
+        rbx,
+        rcx,
+        rdx,
+        rsi,
+        rdi,
+        rbp,
+        rsp,
+        r8,
+        r9,
+        r10,
+        r11,
+        r12,
+        r13,
+        r14,
+        r15,
+        rip,
+        cf: flag(CpuFlag::CF),
+        pf: flag(CpuFlag::PF),
+        af: flag(CpuFlag::AF),
+        zf: flag(CpuFlag::ZF),
+        sf: flag(CpuFlag::SF),
+        df: flag(CpuFlag::DF),
+        of: flag(CpuFlag::OF),
+        fpu_control: 0x37F,
+        fpu_tag: 0xFFFF,
+        ..Default::default()
+    };
+    context.xmm[..16].copy_from_slice(&input.xmm);
+    context.k = input.k;
+    context
+}
+
+/// State of `context` in the layout of the native stub
+fn native_state(context: &StartContextX86<u64>) -> NativeState {
+    let c = context;
+    let flags = [
+        (c.cf, CpuFlag::CF),
+        (c.pf, CpuFlag::PF),
+        (c.af, CpuFlag::AF),
+        (c.zf, CpuFlag::ZF),
+        (c.sf, CpuFlag::SF),
+        (c.df, CpuFlag::DF),
+        (c.of, CpuFlag::OF),
+    ];
+
+    let mut state = NativeState {
+        gprs: [
+            c.rax, c.rcx, c.rdx, c.rbx, c.rsp, c.rbp, c.rsi, c.rdi, c.r8, c.r9, c.r10, c.r11,
+            c.r12, c.r13, c.r14, c.r15,
+        ],
+        rflags: flags
+            .into_iter()
+            .filter(|(value, _)| *value != 0)
+            .fold(0, |rflags, (_, flag)| rflags | u64::from(flag.bits())),
+        k: c.k,
+        ..Default::default()
+    };
+    state.xmm.copy_from_slice(&c.xmm[..16]);
+    state
+}
+
+/// Lifts `code` mapped at `address`, following its branches. Fails if any instruction is
+/// unsupported
+fn lift<'ctx>(
+    context: &'ctx Context,
+    code: &[u8],
+    address: u64,
+) -> Result<(Compiler<'ctx>, Jit<'ctx>), String> {
+    let compiler = Compiler::new_state_function(
+        context,
+        MachineMode::LONG_64,
+        address,
+        Box::new(CallbackMemory),
+    )
+    .map_err(|error| error.to_string())?;
+    let (_, report) = compiler
+        .lift_function_at(code, address, address, false)
+        .map_err(|error| error.to_string())?;
+    if let Some(record) = report.unsupported().next() {
+        return Err(format!("{:?} is unsupported", record.mnemonic));
+    }
+    let jit = compiler.create_jit().map_err(|error| error.to_string())?;
+    Ok((compiler, jit))
+}
+
+/// Every instruction of `case`
+fn decode(case: &Case) -> Vec<FullInstruction> {
+    Decoder::new64()
+        .decode_all::<AllOperands>(case.code, 0)
+        .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|_| panic!("{} doesn't decode", case.asm))
+}
+
+/// Differences between the native and the lifted execution of `case`, `None` if the host doesn't
+/// support it
+fn check_case(runner: &NativeRunner, seed: u64, case: &Case) -> Option<Vec<String>> {
+    let compared_flags = decode(case)
+        .iter()
+        .fold(COMPARED_FLAGS, |flags, instruction| {
+            flags.difference(instruction.cpu_flags.undefined)
+        });
+
+    let address = runner.case_address();
+    let next_address = address + case.code.len() as u64;
+    let mut random = Random(seed);
+    let inputs: Vec<_> = (0..ITERATIONS)
+        .map(|_| random_input(&mut random, case, runner.stack_base(), next_address))
+        .collect();
+
+    // Lifted first, so unsupported instructions fail even if the host can't run them
+    let context = Context::create();
+    let (_compiler, jit) = match lift(&context, case.code, address) {
+        Ok(lifted) => lifted,
+        Err(error) => return Some(vec![format!("{}: lifting failed: {error}", case.asm)]),
+    };
+
+    let outcomes = runner.run(case.code, &inputs)?;
+
+    let mut differences = Vec::new();
+    for (input, outcome) in inputs.iter().zip(outcomes) {
+        // Faults like #DE aren't modelled by the lifter
+        let Some(expected) = outcome else {
+            continue;
+        };
+
+        let mut memory = MemoryImage::default();
+        memory.write(runner.stack_base(), &input.stack);
+        let start = start_context(&input.state, address);
+        let execution = match jit.run(start, memory) {
+            Ok(execution) => execution,
+            Err(error) => {
+                differences.push(format!("{}: running failed: {error}", case.asm));
+                continue;
+            }
+        };
+        let lifted = native_state(&execution.context);
+        let lifted_stack = execution.memory.read(runner.stack_base(), STACK_SIZE);
+
+        let mut difference = |what: String, lifted: u128, native: u128| {
+            differences.push(format!(
+                "{}: {what} is {lifted:#x} instead of {native:#x}, input {:x?}",
+                case.asm, input.state
+            ));
+        };
+        for ((register, lifted), native) in GPRS.iter().zip(lifted.gprs).zip(expected.state.gprs) {
+            if lifted != native {
+                difference(format!("{register:?}"), lifted.into(), native.into());
+            }
+        }
+        let flags_mask = u64::from(compared_flags.bits());
+        if lifted.rflags & flags_mask != expected.state.rflags & flags_mask {
+            difference(
+                format!("RFLAGS & {flags_mask:#x}"),
+                (lifted.rflags & flags_mask).into(),
+                (expected.state.rflags & flags_mask).into(),
+            );
+        }
+        for (index, (lifted, native)) in lifted.xmm.iter().zip(expected.state.xmm).enumerate() {
+            if *lifted != native {
+                difference(format!("XMM{index}"), *lifted, native);
+            }
+        }
+        for (index, (lifted, native)) in lifted.k.iter().zip(expected.state.k).enumerate() {
+            if *lifted != native {
+                difference(format!("K{index}"), (*lifted).into(), native.into());
+            }
+        }
+        if let Some(offset) =
+            (0..STACK_SIZE).find(|&offset| lifted_stack[offset] != expected.stack[offset])
+        {
+            difference(
+                format!("stack byte {offset:#x}"),
+                lifted_stack[offset].into(),
+                expected.stack[offset].into(),
+            );
+        }
+    }
+    Some(differences)
+}
+
+#[test]
+fn lifted_semantics_match_the_host() {
+    let runner = NativeRunner::new();
+
+    let mut differences = Vec::new();
+    let mut unsupported = Vec::new();
+    for (index, case) in CASES.iter().enumerate() {
+        match check_case(&runner, 0x5eed + index as u64, case) {
+            Some(case_differences) => differences.extend(case_differences),
+            None => unsupported.push(case.asm),
+        }
+    }
+
+    if !unsupported.is_empty() {
+        let unsupported = unsupported.join("\n");
+        if std::env::var_os(ALLOW_UNSUPPORTED).is_some() {
+            eprintln!("Not supported by the host, skipped:\n{unsupported}");
+        } else {
+            differences.push(format!(
+                "Not supported by the host, set {ALLOW_UNSUPPORTED} to skip:\n{unsupported}"
+            ));
+        }
+    }
+
+    assert!(differences.is_empty(), "{}", differences.join("\n"));
+}
@@ -243,3 +243,35 @@ impl LifterX86<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_in_mode;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    use zydis::MachineMode;
+
+    #[test]
+    fn salc_sets_al_from_the_carry_flag() {
+        for (cf, rax) in [(0, 0x1234_5600), (1, 0x1234_56ff)] {
+            let start: StartContextX86<u32> = StartContextX86 {
+                rax: 0x1234_5678,
+                rip: 0x40_0000,
+                cf,
+                ..Default::default()
+            };
+
+            // salc
+            let execution = execute_in_mode(
+                MachineMode::LEGACY_32,
+                &[0xd6],
+                start,
+                MemoryImage::default(),
+            );
+
+            assert_eq!(execution.context.rax, rax);
+            assert_eq!(execution.context.cf, cf);
+        }
+    }
+}
@@ -5,7 +5,7 @@ use inkwell::{values::IntValue, IntPredicate};
 use zydis::{Instruction, Operands};
 
 impl LifterX86<'_> {
-    pub(super) fn lift_and_andn<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+    pub(super) fn lift_and<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let builder = &self.builder;
 
         let operands = instr.operands();
@@ -15,13 +15,7 @@ impl LifterX86<'_> {
         let lhs_int = self.load_single_int_op(dest, dest.size)?;
         let rhs_int = self.load_single_int_op(src, dest.size)?;
 
-        let lhs_final = match instr.mnemonic {
-            zydis::Mnemonic::AND => lhs_int,
-            zydis::Mnemonic::ANDN => builder.build_not(lhs_int, "andn_not")?,
-            _ => unreachable!(),
-        };
-
-        let value = builder.build_and(lhs_final, rhs_int, "and_op")?;
+        let value = builder.build_and(lhs_int, rhs_int, "and_op")?;
 
         let sf = self.compute_sign_flag(value)?;
         let zf = self.compute_zero_flag(value)?;
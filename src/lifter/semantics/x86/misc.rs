@@ -1,5 +1,6 @@
-use super::{LifterX86, Result};
+use super::{common::immediate, push::stack_slot, LifterX86, Result};
 
+use inkwell::values::IntValue;
 use zydis::{ffi::DecodedOperandKind, Instruction, Operands};
 
 impl LifterX86<'_> {
@@ -17,4 +18,96 @@ impl LifterX86<'_> {
 
         Ok(())
     }
+
+    /// LEAVE. The stack pointer is set to the frame pointer and the old frame pointer is popped
+    pub(super) fn lift_leave<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let operands = instr.operands();
+        let frame_pointer = &operands[1];
+        let DecodedOperandKind::Reg(stack_pointer) = operands[2].kind else {
+            unreachable!("LEAVE always updates the stack pointer")
+        };
+
+        let frame = self.load_single_int_op(frame_pointer, frame_pointer.size)?;
+        self.store_reg(stack_pointer, frame)?;
+
+        let old_frame = self.pop_value(stack_pointer, frame_pointer.size.into())?;
+        self.store_op(frame_pointer, old_frame)
+    }
+
+    /// ENTER. With a nesting level above zero, frame pointers of the enclosing frames are copied
+    /// onto the new frame
+    pub(super) fn lift_enter<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let operands = instr.operands();
+        let frame_size = immediate(&operands[0]);
+        let nesting_level = immediate(&operands[1]) % 32;
+        let frame_pointer = &operands[2];
+        let DecodedOperandKind::Reg(stack_pointer) = operands[3].kind else {
+            unreachable!("ENTER always updates the stack pointer")
+        };
+
+        let width = u32::from(frame_pointer.size);
+        let old_frame = self.load_single_int_op(frame_pointer, frame_pointer.size)?;
+        self.push_value(stack_pointer, old_frame)?;
+
+        let new_frame: IntValue<'_> = self
+            .mergen_get_register(&stack_pointer, width)?
+            .try_into()?;
+
+        if nesting_level > 0 {
+            let DecodedOperandKind::Reg(frame_register) = frame_pointer.kind else {
+                unreachable!("ENTER always updates the frame pointer")
+            };
+            let size = i64::from(width / 8);
+
+            for level in 1..nesting_level as i64 {
+                let value =
+                    self.mergen_load_mem(&stack_slot(frame_register, -level * size), width)?;
+                self.push_value(stack_pointer, value)?;
+            }
+            self.push_value(stack_pointer, new_frame)?;
+        }
+
+        self.store_op(frame_pointer, new_frame)?;
+        self.adjust_stack_pointer(stack_pointer, -(frame_size as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_in_mode;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    use zydis::MachineMode;
+
+    #[test]
+    fn enter_copies_the_enclosing_frame_pointers() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rsp: 0xf00,
+            rbp: 0x1000,
+            rip: 0x40_0000,
+            ..Default::default()
+        };
+        let mut memory = MemoryImage::default();
+        memory.write(0xffc, &0xaaaa_aaaa_u32.to_le_bytes());
+
+        // enter 8, 2
+        let execution = execute_in_mode(
+            MachineMode::LEGACY_32,
+            &[0xc8, 0x08, 0x00, 0x02],
+            start,
+            memory,
+        );
+
+        assert_eq!(execution.context.rbp, 0xefc);
+        assert_eq!(execution.context.rsp, 0xeec);
+        let slots: Vec<u32> = execution
+            .memory
+            .read(0xef4, 12)
+            .chunks(4)
+            .map(|slot| u32::from_le_bytes(slot.try_into().unwrap()))
+            .collect();
+        // New frame pointer, the copied frame pointer and the old frame pointer
+        assert_eq!(slots, [0xefc, 0xaaaa_aaaa, 0x1000]);
+    }
 }
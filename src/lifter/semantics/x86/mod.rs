@@ -1,18 +1,27 @@
 use super::{Error, Lifter, Result};
-use crate::lifter::LifterX86;
+use crate::lifter::{flagops::ConditionCode, LifterX86};
+use semaphore::is_locked;
+use sse_scalar::is_sse_form;
 
-use zydis::{FullInstruction, Mnemonic};
+use zydis::{FullInstruction, InstructionEncoding, Mnemonic};
 
+mod avx;
+mod avx512;
 mod binary;
 mod bitbyte;
+mod bmi;
 mod call;
 mod cmov;
+mod common;
 mod cond_br;
 mod convert;
 mod dataxfer;
+#[cfg(all(test, target_arch = "x86_64", target_os = "linux"))]
+mod differential;
 mod flagop;
 mod logical;
 mod misc;
+mod muldiv;
 mod nop;
 mod pop;
 mod push;
@@ -21,9 +30,12 @@ mod rotate;
 mod semaphore;
 mod setcc;
 mod shift;
+mod sse;
+mod sse_scalar;
 mod stringop;
 mod system;
 mod uncond_br;
+mod x87;
 
 impl Lifter for LifterX86<'_> {
     fn lift_instr(&self, instr: &FullInstruction) -> Result<()> {
@@ -40,6 +52,12 @@ impl Lifter for LifterX86<'_> {
         //    return Ok(());
         //}
 
+        // Instruction may be a target of some already lifted branch
+        if let Some(runtime_address) = self.runtime_address() {
+            self.sync_block_at(runtime_address)?;
+            self.capture_jump_table_index(runtime_address)?;
+        }
+
         self.increase_ip(instr.length);
 
         #[cfg(debug_assertions)]
@@ -57,7 +75,126 @@ impl Lifter for LifterX86<'_> {
                 .build_alloca(self.context.i128_type(), &disassembly)?;
         }
 
+        if is_locked(instr) {
+            return self.build_locked(instr, || self.lift_semantics(instr));
+        }
+        self.lift_semantics(instr)
+    }
+}
+
+impl LifterX86<'_> {
+    fn lift_semantics(&self, instr: &FullInstruction) -> Result<()> {
         match instr.mnemonic {
+            // avx512
+            _ if instr.encoding == InstructionEncoding::EVEX => self.lift_evex(instr),
+            Mnemonic::KMOVB | Mnemonic::KMOVW | Mnemonic::KMOVD | Mnemonic::KMOVQ => {
+                self.lift_kmov(instr)
+            }
+            Mnemonic::KANDB
+            | Mnemonic::KANDW
+            | Mnemonic::KANDD
+            | Mnemonic::KANDQ
+            | Mnemonic::KANDNB
+            | Mnemonic::KANDNW
+            | Mnemonic::KANDND
+            | Mnemonic::KANDNQ
+            | Mnemonic::KORB
+            | Mnemonic::KORW
+            | Mnemonic::KORD
+            | Mnemonic::KORQ
+            | Mnemonic::KXORB
+            | Mnemonic::KXORW
+            | Mnemonic::KXORD
+            | Mnemonic::KXORQ
+            | Mnemonic::KXNORB
+            | Mnemonic::KXNORW
+            | Mnemonic::KXNORD
+            | Mnemonic::KXNORQ
+            | Mnemonic::KNOTB
+            | Mnemonic::KNOTW
+            | Mnemonic::KNOTD
+            | Mnemonic::KNOTQ => self.lift_mask_logical(instr),
+            Mnemonic::KORTESTB | Mnemonic::KORTESTW | Mnemonic::KORTESTD | Mnemonic::KORTESTQ => {
+                self.lift_kortest(instr)
+            }
+
+            // avx
+            Mnemonic::VMOVDQA
+            | Mnemonic::VMOVDQU
+            | Mnemonic::VMOVAPS
+            | Mnemonic::VMOVUPS
+            | Mnemonic::VMOVAPD
+            | Mnemonic::VMOVUPD => self.lift_vmov(instr),
+            Mnemonic::VMOVD | Mnemonic::VMOVQ => self.lift_vmovd_vmovq(instr),
+            Mnemonic::VPAND
+            | Mnemonic::VPANDN
+            | Mnemonic::VPOR
+            | Mnemonic::VPXOR
+            | Mnemonic::VANDPS
+            | Mnemonic::VANDPD
+            | Mnemonic::VANDNPS
+            | Mnemonic::VANDNPD
+            | Mnemonic::VORPS
+            | Mnemonic::VORPD
+            | Mnemonic::VXORPS
+            | Mnemonic::VXORPD => self.lift_vpacked_logical(instr),
+            Mnemonic::VPADDB
+            | Mnemonic::VPADDW
+            | Mnemonic::VPADDD
+            | Mnemonic::VPADDQ
+            | Mnemonic::VPADDSB
+            | Mnemonic::VPADDSW
+            | Mnemonic::VPADDUSB
+            | Mnemonic::VPADDUSW
+            | Mnemonic::VPSUBB
+            | Mnemonic::VPSUBW
+            | Mnemonic::VPSUBD
+            | Mnemonic::VPSUBQ
+            | Mnemonic::VPSUBSB
+            | Mnemonic::VPSUBSW
+            | Mnemonic::VPSUBUSB
+            | Mnemonic::VPSUBUSW => self.lift_vpacked_add_sub(instr),
+            Mnemonic::VADDPS
+            | Mnemonic::VADDPD
+            | Mnemonic::VSUBPS
+            | Mnemonic::VSUBPD
+            | Mnemonic::VMULPS
+            | Mnemonic::VMULPD
+            | Mnemonic::VDIVPS
+            | Mnemonic::VDIVPD => self.lift_vpacked_float_arith(instr),
+            Mnemonic::VPCMPEQB
+            | Mnemonic::VPCMPEQW
+            | Mnemonic::VPCMPEQD
+            | Mnemonic::VPCMPEQQ
+            | Mnemonic::VPCMPGTB
+            | Mnemonic::VPCMPGTW
+            | Mnemonic::VPCMPGTD
+            | Mnemonic::VPCMPGTQ => self.lift_vpcmp(instr),
+            Mnemonic::VPMOVMSKB => self.lift_vpmovmskb(instr),
+            Mnemonic::VPTEST => self.lift_vptest(instr),
+            Mnemonic::VPSHUFD => self.lift_vpshufd(instr),
+            Mnemonic::VPUNPCKLBW
+            | Mnemonic::VPUNPCKLWD
+            | Mnemonic::VPUNPCKLDQ
+            | Mnemonic::VPUNPCKLQDQ
+            | Mnemonic::VPUNPCKHBW
+            | Mnemonic::VPUNPCKHWD
+            | Mnemonic::VPUNPCKHDQ
+            | Mnemonic::VPUNPCKHQDQ => self.lift_vpunpck(instr),
+            Mnemonic::VPERMQ | Mnemonic::VPERMPD => self.lift_vpermq(instr),
+            Mnemonic::VPERM2I128 | Mnemonic::VPERM2F128 => self.lift_vperm2x128(instr),
+            Mnemonic::VINSERTI128 | Mnemonic::VINSERTF128 => self.lift_vinsert128(instr),
+            Mnemonic::VEXTRACTI128 | Mnemonic::VEXTRACTF128 => self.lift_vextract128(instr),
+            Mnemonic::VPBROADCASTB
+            | Mnemonic::VPBROADCASTW
+            | Mnemonic::VPBROADCASTD
+            | Mnemonic::VPBROADCASTQ
+            | Mnemonic::VBROADCASTSS
+            | Mnemonic::VBROADCASTSD
+            | Mnemonic::VBROADCASTI128
+            | Mnemonic::VBROADCASTF128 => self.lift_vbroadcast(instr),
+            Mnemonic::VZEROUPPER | Mnemonic::VZEROALL => self.lift_vzero(instr),
+
             // binary
             // NOTE: checked
             Mnemonic::ADC => self.lift_adc(instr),
@@ -78,27 +215,56 @@ impl Lifter for LifterX86<'_> {
             Mnemonic::BTR => self.lift_btr(instr),
             Mnemonic::BTS => self.lift_bts(instr),
 
+            // bmi
+            Mnemonic::ANDN => self.lift_andn(instr),
+            Mnemonic::BLSI | Mnemonic::BLSMSK | Mnemonic::BLSR => self.lift_bls(instr),
+            Mnemonic::BEXTR => self.lift_bextr(instr),
+            Mnemonic::BZHI => self.lift_bzhi(instr),
+            Mnemonic::PDEP | Mnemonic::PEXT => self.lift_pdep_pext(instr),
+            Mnemonic::RORX => self.lift_rorx(instr),
+            Mnemonic::MULX => self.lift_mulx(instr),
+            Mnemonic::POPCNT | Mnemonic::LZCNT | Mnemonic::TZCNT => self.lift_bit_count(instr),
+
             // call
             Mnemonic::CALL => self.lift_call(instr),
 
             // cmov
             // NOTE: checked
-            Mnemonic::CMOVB => self.lift_cmovb(instr),
-            Mnemonic::CMOVBE => self.lift_cmovbe(instr),
-            Mnemonic::CMOVL => self.lift_cmovl(instr),
-            Mnemonic::CMOVLE => self.lift_cmovle(instr),
-            Mnemonic::CMOVNB => self.lift_cmovnb(instr),
-            Mnemonic::CMOVNBE => self.lift_cmovnbe(instr),
-            Mnemonic::CMOVNL => self.lift_cmovnl(instr),
-            Mnemonic::CMOVNLE => self.lift_cmovnle(instr),
-            Mnemonic::CMOVNO => self.lift_cmovno(instr),
-            Mnemonic::CMOVNP => self.lift_cmovnp(instr),
-            Mnemonic::CMOVNS => self.lift_cmovns(instr),
-            Mnemonic::CMOVNZ => self.lift_cmovnz(instr),
-            Mnemonic::CMOVO => self.lift_cmovo(instr),
-            Mnemonic::CMOVP => self.lift_cmovp(instr),
-            Mnemonic::CMOVS => self.lift_cmovs(instr),
-            Mnemonic::CMOVZ => self.lift_cmovz(instr),
+            Mnemonic::CMOVB => self.lift_cmovcc(instr, ConditionCode::B),
+            Mnemonic::CMOVBE => self.lift_cmovcc(instr, ConditionCode::BE),
+            Mnemonic::CMOVL => self.lift_cmovcc(instr, ConditionCode::L),
+            Mnemonic::CMOVLE => self.lift_cmovcc(instr, ConditionCode::LE),
+            Mnemonic::CMOVNB => self.lift_cmovcc(instr, ConditionCode::NB),
+            Mnemonic::CMOVNBE => self.lift_cmovcc(instr, ConditionCode::NBE),
+            Mnemonic::CMOVNL => self.lift_cmovcc(instr, ConditionCode::NL),
+            Mnemonic::CMOVNLE => self.lift_cmovcc(instr, ConditionCode::NLE),
+            Mnemonic::CMOVNO => self.lift_cmovcc(instr, ConditionCode::NO),
+            Mnemonic::CMOVNP => self.lift_cmovcc(instr, ConditionCode::NP),
+            Mnemonic::CMOVNS => self.lift_cmovcc(instr, ConditionCode::NS),
+            Mnemonic::CMOVNZ => self.lift_cmovcc(instr, ConditionCode::NZ),
+            Mnemonic::CMOVO => self.lift_cmovcc(instr, ConditionCode::O),
+            Mnemonic::CMOVP => self.lift_cmovcc(instr, ConditionCode::P),
+            Mnemonic::CMOVS => self.lift_cmovcc(instr, ConditionCode::S),
+            Mnemonic::CMOVZ => self.lift_cmovcc(instr, ConditionCode::Z),
+
+            // cond_br
+            Mnemonic::JB => self.lift_jcc(instr, ConditionCode::B),
+            Mnemonic::JBE => self.lift_jcc(instr, ConditionCode::BE),
+            Mnemonic::JL => self.lift_jcc(instr, ConditionCode::L),
+            Mnemonic::JLE => self.lift_jcc(instr, ConditionCode::LE),
+            Mnemonic::JNB => self.lift_jcc(instr, ConditionCode::NB),
+            Mnemonic::JNBE => self.lift_jcc(instr, ConditionCode::NBE),
+            Mnemonic::JNL => self.lift_jcc(instr, ConditionCode::NL),
+            Mnemonic::JNLE => self.lift_jcc(instr, ConditionCode::NLE),
+            Mnemonic::JNO => self.lift_jcc(instr, ConditionCode::NO),
+            Mnemonic::JNP => self.lift_jcc(instr, ConditionCode::NP),
+            Mnemonic::JNS => self.lift_jcc(instr, ConditionCode::NS),
+            Mnemonic::JNZ => self.lift_jcc(instr, ConditionCode::NZ),
+            Mnemonic::JO => self.lift_jcc(instr, ConditionCode::O),
+            Mnemonic::JP => self.lift_jcc(instr, ConditionCode::P),
+            Mnemonic::JS => self.lift_jcc(instr, ConditionCode::S),
+            Mnemonic::JZ => self.lift_jcc(instr, ConditionCode::Z),
+            Mnemonic::JCXZ | Mnemonic::JECXZ | Mnemonic::JRCXZ => self.lift_jrcxz(instr),
 
             // convert
             // NOTE: checked
@@ -119,7 +285,7 @@ impl Lifter for LifterX86<'_> {
 
             // logical
             // NOTE: checked
-            Mnemonic::AND | Mnemonic::ANDN => self.lift_and_andn(instr),
+            Mnemonic::AND => self.lift_and(instr),
             Mnemonic::NOT => self.lift_not(instr),
             Mnemonic::OR => self.lift_or(instr),
             Mnemonic::TEST => self.lift_test(instr),
@@ -138,6 +304,12 @@ impl Lifter for LifterX86<'_> {
 
             // misc
             Mnemonic::LEA => self.lift_lea(instr),
+            Mnemonic::LEAVE => self.lift_leave(instr),
+            Mnemonic::ENTER => self.lift_enter(instr),
+
+            // muldiv
+            Mnemonic::MUL | Mnemonic::IMUL => self.lift_mul(instr),
+            Mnemonic::DIV | Mnemonic::IDIV => self.lift_div(instr),
 
             // nop
             Mnemonic::NOP => self.lift_nop(),
@@ -145,12 +317,14 @@ impl Lifter for LifterX86<'_> {
             // pop
             // NOTE: checked
             Mnemonic::POP => self.lift_pop(instr),
-            Mnemonic::POPFQ => self.lift_popfq(instr),
+            Mnemonic::POPF | Mnemonic::POPFD | Mnemonic::POPFQ => self.lift_popf(instr),
+            Mnemonic::POPA | Mnemonic::POPAD => self.lift_popa(instr),
 
             // push
             // NOTE: checked
             Mnemonic::PUSH => self.lift_push(instr),
-            Mnemonic::PUSHFQ => self.lift_pushfq(instr),
+            Mnemonic::PUSHF | Mnemonic::PUSHFD | Mnemonic::PUSHFQ => self.lift_pushf(instr),
+            Mnemonic::PUSHA | Mnemonic::PUSHAD => self.lift_pusha(instr),
 
             // ret
             // NOTE: NOT FULLY checked (didn't do the solving of path)
@@ -165,25 +339,27 @@ impl Lifter for LifterX86<'_> {
 
             // semaphore
             Mnemonic::XADD => self.lift_xadd(instr),
+            Mnemonic::CMPXCHG => self.lift_cmpxchg(instr),
+            Mnemonic::CMPXCHG8B | Mnemonic::CMPXCHG16B => self.lift_cmpxchg8b_16b(instr),
 
             // setcc
             // NOTE: checked
-            Mnemonic::SETB => self.lift_setb(instr),
-            Mnemonic::SETBE => self.lift_setbe(instr),
-            Mnemonic::SETL => self.lift_setl(instr),
-            Mnemonic::SETLE => self.lift_setle(instr),
-            Mnemonic::SETNB => self.lift_setnb(instr),
-            Mnemonic::SETNBE => self.lift_setnbe(instr),
-            Mnemonic::SETNL => self.lift_setnl(instr),
-            Mnemonic::SETNLE => self.lift_setnle(instr),
-            Mnemonic::SETNO => self.lift_setno(instr),
-            Mnemonic::SETNP => self.lift_setnp(instr),
-            Mnemonic::SETNS => self.lift_setns(instr),
-            Mnemonic::SETNZ => self.lift_setnz(instr),
-            Mnemonic::SETO => self.lift_seto(instr),
-            Mnemonic::SETP => self.lift_setp(instr),
-            Mnemonic::SETS => self.lift_sets(instr),
-            Mnemonic::SETZ => self.lift_setz(instr),
+            Mnemonic::SETB => self.lift_setcc(instr, ConditionCode::B),
+            Mnemonic::SETBE => self.lift_setcc(instr, ConditionCode::BE),
+            Mnemonic::SETL => self.lift_setcc(instr, ConditionCode::L),
+            Mnemonic::SETLE => self.lift_setcc(instr, ConditionCode::LE),
+            Mnemonic::SETNB => self.lift_setcc(instr, ConditionCode::NB),
+            Mnemonic::SETNBE => self.lift_setcc(instr, ConditionCode::NBE),
+            Mnemonic::SETNL => self.lift_setcc(instr, ConditionCode::NL),
+            Mnemonic::SETNLE => self.lift_setcc(instr, ConditionCode::NLE),
+            Mnemonic::SETNO => self.lift_setcc(instr, ConditionCode::NO),
+            Mnemonic::SETNP => self.lift_setcc(instr, ConditionCode::NP),
+            Mnemonic::SETNS => self.lift_setcc(instr, ConditionCode::NS),
+            Mnemonic::SETNZ => self.lift_setcc(instr, ConditionCode::NZ),
+            Mnemonic::SETO => self.lift_setcc(instr, ConditionCode::O),
+            Mnemonic::SETP => self.lift_setcc(instr, ConditionCode::P),
+            Mnemonic::SETS => self.lift_setcc(instr, ConditionCode::S),
+            Mnemonic::SETZ => self.lift_setcc(instr, ConditionCode::Z),
 
             // Shift
             // NOTE: checked
@@ -193,10 +369,95 @@ impl Lifter for LifterX86<'_> {
             Mnemonic::SHR | Mnemonic::SHRX => self.lift_shr(instr),
             Mnemonic::SHRD => self.lift_shrd(instr),
 
+            // sse
+            Mnemonic::MOVDQA
+            | Mnemonic::MOVDQU
+            | Mnemonic::MOVAPS
+            | Mnemonic::MOVUPS
+            | Mnemonic::MOVAPD
+            | Mnemonic::MOVUPD => self.lift_movdq(instr),
+            Mnemonic::MOVD | Mnemonic::MOVQ => self.lift_movd_movq(instr),
+            Mnemonic::PAND
+            | Mnemonic::PANDN
+            | Mnemonic::POR
+            | Mnemonic::PXOR
+            | Mnemonic::ANDPS
+            | Mnemonic::ANDPD
+            | Mnemonic::ANDNPS
+            | Mnemonic::ANDNPD
+            | Mnemonic::ORPS
+            | Mnemonic::ORPD
+            | Mnemonic::XORPS
+            | Mnemonic::XORPD => self.lift_packed_logical(instr),
+            Mnemonic::PADDB
+            | Mnemonic::PADDW
+            | Mnemonic::PADDD
+            | Mnemonic::PADDQ
+            | Mnemonic::PADDSB
+            | Mnemonic::PADDSW
+            | Mnemonic::PADDUSB
+            | Mnemonic::PADDUSW
+            | Mnemonic::PSUBB
+            | Mnemonic::PSUBW
+            | Mnemonic::PSUBD
+            | Mnemonic::PSUBQ
+            | Mnemonic::PSUBSB
+            | Mnemonic::PSUBSW
+            | Mnemonic::PSUBUSB
+            | Mnemonic::PSUBUSW => self.lift_packed_add_sub(instr),
+            Mnemonic::PSHUFD => self.lift_pshufd(instr),
+            Mnemonic::PUNPCKLBW
+            | Mnemonic::PUNPCKLWD
+            | Mnemonic::PUNPCKLDQ
+            | Mnemonic::PUNPCKLQDQ
+            | Mnemonic::PUNPCKHBW
+            | Mnemonic::PUNPCKHWD
+            | Mnemonic::PUNPCKHDQ
+            | Mnemonic::PUNPCKHQDQ => self.lift_punpck(instr),
+
+            // sse_scalar
+            Mnemonic::ADDSS
+            | Mnemonic::ADDSD
+            | Mnemonic::SUBSS
+            | Mnemonic::SUBSD
+            | Mnemonic::MULSS
+            | Mnemonic::MULSD
+            | Mnemonic::DIVSS
+            | Mnemonic::DIVSD
+            | Mnemonic::MINSS
+            | Mnemonic::MINSD
+            | Mnemonic::MAXSS
+            | Mnemonic::MAXSD => self.lift_scalar_arith(instr),
+            Mnemonic::SQRTSS | Mnemonic::SQRTSD => self.lift_scalar_sqrt(instr),
+            Mnemonic::UCOMISS | Mnemonic::UCOMISD | Mnemonic::COMISS | Mnemonic::COMISD => {
+                self.lift_scalar_compare_flags(instr)
+            }
+            Mnemonic::CMPSS => self.lift_scalar_compare_mask(instr),
+            // CMPSD and MOVSD are also string instructions
+            Mnemonic::CMPSD if is_sse_form(instr) => self.lift_scalar_compare_mask(instr),
+            Mnemonic::CVTSI2SS | Mnemonic::CVTSI2SD => self.lift_cvtsi2s(instr),
+            Mnemonic::CVTSS2SD | Mnemonic::CVTSD2SS => self.lift_cvt_scalar_precision(instr),
+            Mnemonic::CVTSS2SI | Mnemonic::CVTSD2SI | Mnemonic::CVTTSS2SI | Mnemonic::CVTTSD2SI => {
+                self.lift_cvt_scalar_to_int(instr)
+            }
+            Mnemonic::MOVSS => self.lift_movss_movsd(instr),
+            Mnemonic::MOVSD if is_sse_form(instr) => self.lift_movss_movsd(instr),
+
             // stringop
-            // NOTE: checked
+            Mnemonic::CMPSB | Mnemonic::CMPSW | Mnemonic::CMPSD | Mnemonic::CMPSQ => {
+                self.lift_cmps(instr)
+            }
+            Mnemonic::LODSB | Mnemonic::LODSW | Mnemonic::LODSD | Mnemonic::LODSQ => {
+                self.lift_lods(instr)
+            }
             Mnemonic::MOVSB | Mnemonic::MOVSW | Mnemonic::MOVSD | Mnemonic::MOVSQ => {
-                self.lift_movs_x(instr)
+                self.lift_movs(instr)
+            }
+            Mnemonic::SCASB | Mnemonic::SCASW | Mnemonic::SCASD | Mnemonic::SCASQ => {
+                self.lift_scas(instr)
+            }
+            Mnemonic::STOSB | Mnemonic::STOSW | Mnemonic::STOSD | Mnemonic::STOSQ => {
+                self.lift_stos(instr)
             }
 
             // system
@@ -205,9 +466,111 @@ impl Lifter for LifterX86<'_> {
             // uncond_br
             Mnemonic::JMP => self.lift_jmp(instr),
 
+            // x87
+            Mnemonic::FLD => self.lift_fld(instr),
+            Mnemonic::FILD => self.lift_fild(instr),
+            Mnemonic::FLDZ
+            | Mnemonic::FLD1
+            | Mnemonic::FLDPI
+            | Mnemonic::FLDL2E
+            | Mnemonic::FLDL2T
+            | Mnemonic::FLDLG2
+            | Mnemonic::FLDLN2 => self.lift_fld_constant(instr),
+            Mnemonic::FST | Mnemonic::FSTP => self.lift_fst(instr),
+            Mnemonic::FIST | Mnemonic::FISTP | Mnemonic::FISTTP => self.lift_fist(instr),
+            Mnemonic::FADD
+            | Mnemonic::FADDP
+            | Mnemonic::FIADD
+            | Mnemonic::FSUB
+            | Mnemonic::FSUBP
+            | Mnemonic::FISUB
+            | Mnemonic::FSUBR
+            | Mnemonic::FSUBRP
+            | Mnemonic::FISUBR
+            | Mnemonic::FMUL
+            | Mnemonic::FMULP
+            | Mnemonic::FIMUL
+            | Mnemonic::FDIV
+            | Mnemonic::FDIVP
+            | Mnemonic::FIDIV
+            | Mnemonic::FDIVR
+            | Mnemonic::FDIVRP
+            | Mnemonic::FIDIVR => self.lift_x87_arith(instr),
+            Mnemonic::FCHS | Mnemonic::FABS | Mnemonic::FSQRT | Mnemonic::FRNDINT => {
+                self.lift_x87_unary(instr)
+            }
+            Mnemonic::FXCH => self.lift_fxch(instr),
+            Mnemonic::FCOMI | Mnemonic::FCOMIP | Mnemonic::FUCOMI | Mnemonic::FUCOMIP => {
+                self.lift_fcomi(instr)
+            }
+            Mnemonic::FCOM
+            | Mnemonic::FCOMP
+            | Mnemonic::FCOMPP
+            | Mnemonic::FUCOM
+            | Mnemonic::FUCOMP
+            | Mnemonic::FUCOMPP
+            | Mnemonic::FICOM
+            | Mnemonic::FICOMP
+            | Mnemonic::FTST => self.lift_fcom(instr),
+            Mnemonic::FCMOVB => self.lift_fcmovcc(instr, ConditionCode::B),
+            Mnemonic::FCMOVBE => self.lift_fcmovcc(instr, ConditionCode::BE),
+            Mnemonic::FCMOVE => self.lift_fcmovcc(instr, ConditionCode::Z),
+            Mnemonic::FCMOVNB => self.lift_fcmovcc(instr, ConditionCode::NB),
+            Mnemonic::FCMOVNBE => self.lift_fcmovcc(instr, ConditionCode::NBE),
+            Mnemonic::FCMOVNE => self.lift_fcmovcc(instr, ConditionCode::NZ),
+            Mnemonic::FCMOVU => self.lift_fcmovcc(instr, ConditionCode::P),
+            Mnemonic::FCMOVNU => self.lift_fcmovcc(instr, ConditionCode::NP),
+            Mnemonic::FNSTSW => self.lift_fnstsw(instr),
+            Mnemonic::FNSTCW => self.lift_fnstcw(instr),
+            Mnemonic::FLDCW => self.lift_fldcw(instr),
+            Mnemonic::FNCLEX => self.lift_fnclex(),
+            Mnemonic::FNINIT => self.lift_fninit(),
+            Mnemonic::FWAIT => self.lift_nop(),
+
             // TODO: Add  flagops
             //_ => unimplemented!("{} isn't implemented yet", instruction.mnemonic),
             _ => Err(Error::UnsupportedInstr("Instruction isnt implemented")),
         }
     }
 }
+
+/// Lifts the 64 bit instruction `code` at `start.rip` with the state ABI and runs it
+#[cfg(test)]
+fn execute(
+    code: &[u8],
+    start: crate::compiler::contexts::StartContextX86<u64>,
+    memory: crate::compiler::jit::MemoryImage,
+) -> crate::compiler::jit::Execution<u64> {
+    execute_in_mode(zydis::MachineMode::LONG_64, code, start, memory)
+}
+
+/// Same as [execute] for an instruction of `mode`. `I` must be as wide as the registers of `mode`
+#[cfg(test)]
+fn execute_in_mode<I>(
+    mode: zydis::MachineMode,
+    code: &[u8],
+    start: crate::compiler::contexts::StartContextX86<I>,
+    memory: crate::compiler::jit::MemoryImage,
+) -> crate::compiler::jit::Execution<I>
+where
+    I: crate::compiler::contexts::SupportedIntTypesX86 + TryFrom<u64> + Copy,
+    u64: From<I>,
+{
+    let context = inkwell::context::Context::create();
+    let decoder =
+        zydis::Decoder::new(mode, crate::compiler::cfg::stack_width_for_mode(mode)).unwrap();
+    let instruction: FullInstruction = decoder
+        .decode_first(code)
+        .unwrap()
+        .expect("Code is a whole instruction");
+    let compiler = crate::compiler::Compiler::new_state_function(
+        &context,
+        mode,
+        start.rip.into(),
+        Box::new(crate::lifter::memory::CallbackMemory),
+    )
+    .unwrap();
+    compiler.lift_function(&vec![instruction], false).unwrap();
+    let jit = compiler.create_jit().unwrap();
+    jit.run(start, memory).unwrap()
+}
@@ -0,0 +1,258 @@
+use super::{LifterX86, Result};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{values::IntValue, IntPredicate};
+use zydis::{Instruction, Mnemonic, Operands, Register};
+
+impl<'ctx> LifterX86<'ctx> {
+    /// MUL and one operand IMUL. Result goes into the accumulator register pair
+    pub(super) fn lift_mul<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+
+        if instr.mnemonic == Mnemonic::IMUL && instr.operand_count_visible > 1 {
+            return self.lift_imul_truncated(instr);
+        }
+
+        let src = &ops[0];
+        let is_signed = instr.mnemonic == Mnemonic::IMUL;
+        let (low_register, high_register) = accumulator_pair(src.size);
+
+        let lhs = self.load_single_int_op(src, src.size)?;
+        let rhs: IntValue<'_> = self
+            .mergen_get_register(&low_register, src.size.into())?
+            .try_into()?;
+
+        let [low, high, overflow] = self.build_full_product(lhs, rhs, is_signed)?;
+
+        self.store_reg(low_register, low)?;
+        self.store_reg(high_register, high)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, overflow);
+        self.store_cpu_flag(ExtendedRegisterEnum::OF, overflow);
+
+        Ok(())
+    }
+
+    /// Two and three operand IMUL. Only lower half of the product is kept
+    fn lift_imul_truncated<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let (lhs, rhs) = if instr.operand_count_visible == 3 {
+            (
+                self.load_single_int_op(&ops[1], dest.size)?,
+                self.load_single_int_op(&ops[2], dest.size)?,
+            )
+        } else {
+            (
+                self.load_single_int_op(dest, dest.size)?,
+                self.load_single_int_op(&ops[1], dest.size)?,
+            )
+        };
+
+        let [low, _, overflow] = self.build_full_product(lhs, rhs, true)?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, overflow);
+        self.store_cpu_flag(ExtendedRegisterEnum::OF, overflow);
+
+        self.store_op(dest, low)?;
+        Ok(())
+    }
+
+    /// DIV and IDIV. Dividend is the accumulator register pair, quotient goes into the lower
+    /// register and remainder into the upper one.
+    ///
+    /// Division by zero and quotients which don't fit into the destination raise #DE, which is
+    /// modelled as a branch to a block calling `llvm.trap`
+    pub(super) fn lift_div<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let src = &ops[0];
+        let is_signed = instr.mnemonic == Mnemonic::IDIV;
+        let (low_register, high_register) = accumulator_pair(src.size);
+
+        let half_ty = self.context.custom_width_int_type(src.size.into());
+        let full_ty = self.context.custom_width_int_type(u32::from(src.size) * 2);
+
+        let divisor = self.load_single_int_op(src, src.size)?;
+        let low: IntValue<'_> = self
+            .mergen_get_register(&low_register, src.size.into())?
+            .try_into()?;
+        let high: IntValue<'_> = self
+            .mergen_get_register(&high_register, src.size.into())?
+            .try_into()?;
+
+        let dividend = builder.build_or(
+            builder.build_left_shift(
+                builder.build_int_z_extend(high, full_ty, "")?,
+                full_ty.const_int(src.size.into(), false),
+                "",
+            )?,
+            builder.build_int_z_extend(low, full_ty, "")?,
+            "dividend",
+        )?;
+
+        let is_zero_divisor =
+            builder.build_int_compare(IntPredicate::EQ, divisor, half_ty.const_zero(), "")?;
+
+        // Divisor is replaced by 1 on the #DE path, so LLVM never sees a division by zero
+        let safe_divisor = builder
+            .build_select(is_zero_divisor, half_ty.const_int(1, false), divisor, "")?
+            .into_int_value();
+
+        let [quotient, remainder, overflow] = if is_signed {
+            let divisor_ext = builder.build_int_s_extend(safe_divisor, full_ty, "")?;
+
+            // -1 is handled separately, because MIN / -1 overflows the wide division too
+            let is_minus_one = builder.build_int_compare(
+                IntPredicate::EQ,
+                safe_divisor,
+                half_ty.const_all_ones(),
+                "",
+            )?;
+            let non_minus_one_divisor = builder
+                .build_select(is_minus_one, full_ty.const_int(1, false), divisor_ext, "")?
+                .into_int_value();
+
+            let quotient = builder
+                .build_select(
+                    is_minus_one,
+                    builder.build_int_neg(dividend, "")?,
+                    builder.build_int_signed_div(dividend, non_minus_one_divisor, "")?,
+                    "idiv_quotient",
+                )?
+                .into_int_value();
+            let remainder = builder
+                .build_select(
+                    is_minus_one,
+                    full_ty.const_zero(),
+                    builder.build_int_signed_rem(dividend, non_minus_one_divisor, "")?,
+                    "idiv_remainder",
+                )?
+                .into_int_value();
+
+            let truncated_quotient = builder.build_int_truncate(quotient, half_ty, "")?;
+            let overflow = builder.build_int_compare(
+                IntPredicate::NE,
+                builder.build_int_s_extend(truncated_quotient, full_ty, "")?,
+                quotient,
+                "",
+            )?;
+
+            [quotient, remainder, overflow]
+        } else {
+            let divisor_ext = builder.build_int_z_extend(safe_divisor, full_ty, "")?;
+
+            let quotient = builder.build_int_unsigned_div(dividend, divisor_ext, "div_quotient")?;
+            let remainder =
+                builder.build_int_unsigned_rem(dividend, divisor_ext, "div_remainder")?;
+
+            let overflow = builder.build_int_compare(
+                IntPredicate::NE,
+                builder.build_right_shift(
+                    quotient,
+                    full_ty.const_int(src.size.into(), false),
+                    false,
+                    "",
+                )?,
+                full_ty.const_zero(),
+                "",
+            )?;
+
+            [quotient, remainder, overflow]
+        };
+
+        let raises_divide_error = builder.build_or(is_zero_divisor, overflow, "raises_de")?;
+        self.build_divide_error_check(raises_divide_error)?;
+
+        self.store_reg(
+            low_register,
+            builder.build_int_truncate(quotient, half_ty, "")?,
+        )?;
+        self.store_reg(
+            high_register,
+            builder.build_int_truncate(remainder, half_ty, "")?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns lower half, upper half and whether upper half is significant (CF/OF of MUL/IMUL)
+    fn build_full_product(
+        &self,
+        lhs: IntValue<'ctx>,
+        rhs: IntValue<'ctx>,
+        is_signed: bool,
+    ) -> Result<[IntValue<'ctx>; 3]> {
+        let builder = &self.builder;
+
+        let half_ty = lhs.get_type();
+        let half_width = half_ty.get_bit_width();
+        let full_ty = self.context.custom_width_int_type(half_width * 2);
+
+        let extend = |value: IntValue<'ctx>| {
+            if is_signed {
+                builder.build_int_s_extend(value, full_ty, "")
+            } else {
+                builder.build_int_z_extend(value, full_ty, "")
+            }
+        };
+
+        let product = builder.build_int_mul(extend(lhs)?, extend(rhs)?, "full_product")?;
+
+        let low = builder.build_int_truncate(product, half_ty, "product_low")?;
+        let high = builder.build_int_truncate(
+            builder.build_right_shift(
+                product,
+                full_ty.const_int(half_width.into(), false),
+                false,
+                "",
+            )?,
+            half_ty,
+            "product_high",
+        )?;
+
+        let overflow = if is_signed {
+            builder.build_int_compare(IntPredicate::NE, extend(low)?, product, "imul_overflow")?
+        } else {
+            builder.build_int_compare(
+                IntPredicate::NE,
+                high,
+                half_ty.const_zero(),
+                "mul_overflow",
+            )?
+        };
+
+        Ok([low, high, overflow])
+    }
+
+    /// Splits current block: when `condition` holds, execution ends in a trap
+    fn build_divide_error_check(&self, condition: IntValue<'ctx>) -> Result<()> {
+        let builder = &self.builder;
+
+        let divide_error_block = self
+            .context
+            .append_basic_block(self.func_value, "divide_error");
+        let continue_block = self.context.append_basic_block(self.func_value, "div_ok");
+
+        builder.build_conditional_branch(condition, divide_error_block, continue_block)?;
+
+        builder.position_at_end(divide_error_block);
+        self.build_trap()?;
+
+        builder.position_at_end(continue_block);
+        Ok(())
+    }
+}
+
+/// Registers holding (lower, upper) halves of MUL/DIV operands for each operand size
+fn accumulator_pair(size: u16) -> (Register, Register) {
+    match size {
+        8 => (Register::AL, Register::AH),
+        16 => (Register::AX, Register::DX),
+        32 => (Register::EAX, Register::EDX),
+        64 => (Register::RAX, Register::RDX),
+        _ => unreachable!("Unexpected MUL/DIV operand size {size}"),
+    }
+}
@@ -1,9 +1,9 @@
-use super::{LifterX86, Result};
+use super::{push::stack_slot, LifterX86, Result};
 
 use inkwell::values::IntValue;
-use zydis::{Instruction, Operands};
+use zydis::{ffi::DecodedOperandKind, Instruction, Operands, Register};
 
-impl LifterX86<'_> {
+impl<'ctx> LifterX86<'ctx> {
     pub(super) fn lift_pop<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let operands = instr.operands();
 
@@ -19,9 +19,8 @@ impl LifterX86<'_> {
         //    .custom_width_int_type(dest.size.into())
         //    .const_int((dest.size / 8).into(), true);
 
-        let val = self
-            .context
-            .i64_type()
+        let val = rsp_value
+            .get_type()
             .const_int((dest.size / 8).into(), false);
         let result = self
             .builder
@@ -33,26 +32,18 @@ impl LifterX86<'_> {
         Ok(())
     }
 
-    pub(super) fn lift_popfq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+    /// POPF, POPFD and POPFQ. Only the modelled flags are restored
+    pub(super) fn lift_popf<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let operands = instr.operands();
 
         let dest = &operands[2];
         let src = &operands[1];
         let rsp = &operands[0];
 
-        let r_value = self.load_single_op(src, dest.size)?;
+        let r_value = self.load_single_op(src, src.size)?;
         let rsp_value: IntValue<'_> = self.load_single_op(rsp, rsp.size)?.try_into()?;
 
-        //let val = self
-        //    .context
-        //    // TODO: check this. Different from Mergen
-        //    .custom_width_int_type(dest.size.into())
-        //    .const_int((dest.size / 8).into(), true);
-
-        let val = self
-            .context
-            .i64_type()
-            .const_int((dest.size / 8).into(), false);
+        let val = rsp_value.get_type().const_int((src.size / 8).into(), false);
         let result = self.builder.build_int_add(rsp_value, val, "popfq")?;
 
         self.store_op(dest, r_value)?;
@@ -60,4 +51,145 @@ impl LifterX86<'_> {
 
         Ok(())
     }
+
+    /// POPA and POPAD. The saved stack pointer is skipped
+    pub(super) fn lift_popa<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let operands = instr.operands();
+        let registers = &operands[..7];
+        let DecodedOperandKind::Reg(stack_pointer) = operands[7].kind else {
+            unreachable!("POPA always updates the stack pointer")
+        };
+
+        let size = i64::from(registers[0].size / 8);
+        // Slots from the top of the stack for EAX, ECX, EDX, EBX, EBP, ESI and EDI. The stack
+        // pointer pushed by PUSHA is in slot 3
+        const SLOTS: [i64; 7] = [7, 6, 5, 4, 2, 1, 0];
+
+        for (register, slot) in registers.iter().zip(SLOTS) {
+            let value = self.mergen_load_mem(
+                &stack_slot(stack_pointer, slot * size),
+                register.size.into(),
+            )?;
+            self.store_op(register, value)?;
+        }
+        self.adjust_stack_pointer(stack_pointer, 8 * size)
+    }
+
+    /// Loads a `width` bit value from the top of the stack and increments the stack pointer
+    pub(super) fn pop_value(&self, stack_pointer: Register, width: u32) -> Result<IntValue<'ctx>> {
+        let value = self.mergen_load_mem(&stack_slot(stack_pointer, 0), width)?;
+        self.adjust_stack_pointer(stack_pointer, i64::from(width / 8))?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_in_mode;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    use zydis::MachineMode;
+
+    #[test]
+    fn popad_restores_pushad_except_the_stack_pointer() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rax: 1,
+            rcx: 2,
+            rdx: 3,
+            rbx: 4,
+            rsp: 0x1000,
+            rbp: 5,
+            rsi: 6,
+            rdi: 7,
+            rip: 0x40_0000,
+            ..Default::default()
+        };
+
+        // pushad
+        let pushed = execute_in_mode(
+            MachineMode::LEGACY_32,
+            &[0x60],
+            start,
+            MemoryImage::default(),
+        );
+        let mut memory = pushed.memory;
+        // The saved ESP is skipped by POPAD
+        memory.write(0xfec, &0xdead_beef_u32.to_le_bytes());
+        let clobbered = StartContextX86 {
+            rsp: pushed.context.rsp,
+            rip: 0x40_0001,
+            ..Default::default()
+        };
+
+        // popad
+        let popped = execute_in_mode(MachineMode::LEGACY_32, &[0x61], clobbered, memory);
+
+        let gprs =
+            |c: StartContextX86<u32>| [c.rax, c.rcx, c.rdx, c.rbx, c.rsp, c.rbp, c.rsi, c.rdi];
+        assert_eq!(gprs(popped.context), gprs(start));
+    }
+
+    #[test]
+    fn operand_size_prefix_pops_16_bit_registers() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rax: 0x1111_0000,
+            rcx: 0x2222_0000,
+            rdx: 0x3333_0000,
+            rbx: 0x4444_0000,
+            rsp: 0x1000,
+            rbp: 0x5555_0000,
+            rsi: 0x6666_0000,
+            rdi: 0x7777_0000,
+            rip: 0x40_0000,
+            ..Default::default()
+        };
+        let mut memory = MemoryImage::default();
+        // DI, SI, BP, the skipped SP, BX, DX, CX and AX
+        for (slot, value) in (0..).zip([7_u16, 6, 5, 0xdead, 4, 3, 2, 1]) {
+            memory.write(0x1000 + slot * 2, &value.to_le_bytes());
+        }
+
+        // popaw
+        let execution = execute_in_mode(MachineMode::LEGACY_32, &[0x66, 0x61], start, memory);
+
+        let c = execution.context;
+        assert_eq!(
+            [c.rax, c.rcx, c.rdx, c.rbx, c.rbp, c.rsi, c.rdi],
+            [
+                0x1111_0001,
+                0x2222_0002,
+                0x3333_0003,
+                0x4444_0004,
+                0x5555_0005,
+                0x6666_0006,
+                0x7777_0007
+            ]
+        );
+        assert_eq!(c.rsp, 0x1010);
+    }
+
+    #[test]
+    fn popfd_restores_the_flags() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rsp: 0x1000,
+            rip: 0x40_0000,
+            sf: 1,
+            ..Default::default()
+        };
+        let mut memory = MemoryImage::default();
+        // CF, PF, ZF, DF and OF with the always set bit 1
+        memory.write(0x1000, &0x0c47_u32.to_le_bytes());
+
+        // popfd
+        let execution = execute_in_mode(MachineMode::LEGACY_32, &[0x9d], start, memory);
+
+        let context = execution.context;
+        assert_eq!(context.rsp, 0x1004);
+        assert_eq!(
+            [context.cf, context.pf, context.af, context.zf, context.sf],
+            [1, 1, 0, 1, 0]
+        );
+        assert_eq!([context.df, context.of], [1, 1]);
+    }
 }
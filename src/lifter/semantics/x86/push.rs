@@ -1,9 +1,12 @@
 use super::{LifterX86, Result};
 
 use inkwell::values::IntValue;
-use zydis::{Instruction, Operands};
+use zydis::{
+    ffi::{DecodedOperandKind, DisplacementInfo, MemoryInfo},
+    Instruction, MemoryOperandType, Operands, Register,
+};
 
-impl LifterX86<'_> {
+impl<'ctx> LifterX86<'ctx> {
     pub(super) fn lift_push<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let operands = instr.operands();
 
@@ -14,12 +17,9 @@ impl LifterX86<'_> {
         let r_value = self.load_single_op(src, dest.size)?;
         let rsp_value: IntValue<'_> = self.load_single_op(rsp, rsp.size)?.try_into()?;
 
-        let val = self
-            .context
-            // // TODO: check this. Different from Mergen
-            // .custom_width_int_type(dest.size.into())
-            .i64_type()
-            .const_int((dest.size / 8).into(), true);
+        let val = rsp_value
+            .get_type()
+            .const_int((dest.size / 8).into(), false);
         let result = self
             .builder
             .build_int_sub(rsp_value, val, "pushing_new_rsp_")?;
@@ -30,7 +30,8 @@ impl LifterX86<'_> {
         Ok(())
     }
 
-    pub(super) fn lift_pushfq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+    /// PUSHF, PUSHFD and PUSHFQ
+    pub(super) fn lift_pushf<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let operands = instr.operands();
 
         let src = &operands[2];
@@ -40,12 +41,9 @@ impl LifterX86<'_> {
         let r_value = self.load_single_op(src, dest.size)?;
         let rsp_value: IntValue<'_> = self.load_single_op(rsp, rsp.size)?.try_into()?;
 
-        let val = self
-            .context
-            // // TODO: check this. Different from Mergen
-            // .custom_width_int_type(dest.size.into())
-            .i64_type()
-            .const_int((dest.size / 8).into(), true);
+        let val = rsp_value
+            .get_type()
+            .const_int((dest.size / 8).into(), false);
         let result = self
             .builder
             .build_int_sub(rsp_value, val, "pushing_new_rsp_")?;
@@ -55,4 +53,177 @@ impl LifterX86<'_> {
 
         Ok(())
     }
+
+    /// PUSHA and PUSHAD. The stack pointer is pushed with its value before the instruction
+    pub(super) fn lift_pusha<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let operands = instr.operands();
+        let registers = &operands[..8];
+        let DecodedOperandKind::Reg(stack_pointer) = operands[8].kind else {
+            unreachable!("PUSHA always updates the stack pointer")
+        };
+
+        let size = i64::from(registers[0].size / 8);
+        let values = registers
+            .iter()
+            .map(|register| self.load_single_int_op(register, register.size))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (slot, value) in (1..).zip(values) {
+            self.mergen_store_mem(&stack_slot(stack_pointer, -slot * size), value.into())?;
+        }
+        self.adjust_stack_pointer(stack_pointer, -8 * size)
+    }
+
+    /// Decrements the stack pointer by the size of `value` and stores it at the new top
+    pub(super) fn push_value(&self, stack_pointer: Register, value: IntValue<'ctx>) -> Result<()> {
+        let size = i64::from(value.get_type().get_bit_width() / 8);
+
+        self.adjust_stack_pointer(stack_pointer, -size)?;
+        self.mergen_store_mem(&stack_slot(stack_pointer, 0), value.into())
+    }
+
+    /// Adds `offset` to the stack pointer
+    pub(super) fn adjust_stack_pointer(&self, stack_pointer: Register, offset: i64) -> Result<()> {
+        let width = stack_pointer.width(self.mode);
+        let value: IntValue<'_> = self
+            .mergen_get_register(&stack_pointer, width.into())?
+            .try_into()?;
+
+        let offset = value.get_type().const_int(offset as u64, true);
+        let value = self.builder.build_int_add(value, offset, "")?;
+        self.store_reg(stack_pointer, value)
+    }
+}
+
+/// Stack memory `displacement` bytes away from the current value of `base`
+pub(super) fn stack_slot(base: Register, displacement: i64) -> MemoryInfo {
+    MemoryInfo {
+        ty: MemoryOperandType::MEM,
+        segment: Register::SS,
+        base,
+        index: Register::NONE,
+        scale: 0,
+        disp: DisplacementInfo {
+            has_displacement: displacement != 0,
+            displacement,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute_in_mode;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    use zydis::MachineMode;
+
+    #[test]
+    fn real_mode_push_uses_ss() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rax: 0xbeef,
+            rsp: 0x100,
+            rip: 0x100,
+            ss: 0x2000,
+            ..Default::default()
+        };
+
+        // push ax
+        let execution =
+            execute_in_mode(MachineMode::REAL_16, &[0x50], start, MemoryImage::default());
+
+        assert_eq!(execution.context.rsp, 0xfe);
+        assert_eq!(execution.memory.read(0x200fe, 2), [0xef, 0xbe]);
+    }
+
+    #[test]
+    fn pushad_stores_the_stack_pointer_before_the_instruction() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rax: 1,
+            rcx: 2,
+            rdx: 3,
+            rbx: 4,
+            rsp: 0x1000,
+            rbp: 5,
+            rsi: 6,
+            rdi: 7,
+            rip: 0x40_0000,
+            ..Default::default()
+        };
+
+        // pushad
+        let execution = execute_in_mode(
+            MachineMode::LEGACY_32,
+            &[0x60],
+            start,
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.context.rsp, 0xfe0);
+        let slots: Vec<u32> = execution
+            .memory
+            .read(0xfe0, 32)
+            .chunks(4)
+            .map(|slot| u32::from_le_bytes(slot.try_into().unwrap()))
+            .collect();
+        assert_eq!(slots, [7, 6, 5, 0x1000, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn operand_size_prefix_pushes_16_bit_registers() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rax: 0x1111_0001,
+            rcx: 0x2222_0002,
+            rdx: 0x3333_0003,
+            rbx: 0x4444_0004,
+            rsp: 0x1000,
+            rbp: 0x5555_0005,
+            rsi: 0x6666_0006,
+            rdi: 0x7777_0007,
+            rip: 0x40_0000,
+            ..Default::default()
+        };
+
+        // pushaw
+        let execution = execute_in_mode(
+            MachineMode::LEGACY_32,
+            &[0x66, 0x60],
+            start,
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.context.rsp, 0xff0);
+        let slots: Vec<u16> = execution
+            .memory
+            .read(0xff0, 16)
+            .chunks(2)
+            .map(|slot| u16::from_le_bytes(slot.try_into().unwrap()))
+            .collect();
+        assert_eq!(slots, [7, 6, 5, 0x1000, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn pushfd_stores_the_flags() {
+        let start: StartContextX86<u32> = StartContextX86 {
+            rsp: 0x1000,
+            rip: 0x40_0000,
+            cf: 1,
+            zf: 1,
+            of: 1,
+            ..Default::default()
+        };
+
+        // pushfd
+        let execution = execute_in_mode(
+            MachineMode::LEGACY_32,
+            &[0x9c],
+            start,
+            MemoryImage::default(),
+        );
+
+        assert_eq!(execution.context.rsp, 0xffc);
+        let slot = u32::from_le_bytes(execution.memory.read(0xffc, 4).try_into().unwrap());
+        // CF, PF, AF, ZF, SF, DF and OF
+        assert_eq!(slot & 0xcd5, 0x841);
+    }
 }
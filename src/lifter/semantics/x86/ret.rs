@@ -1,9 +1,9 @@
 use super::{LifterX86, Result};
 
-use inkwell::values::IntValue;
+use inkwell::{types::BasicTypeEnum, values::IntValue};
 use zydis::{ffi::DecodedOperandKind, Instruction, Operands, Register};
 
-impl LifterX86<'_> {
+impl<'ctx> LifterX86<'ctx> {
     //pub(super) fn lift_ret<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
     //    let builder = &self.builder;
     //    let context = &self.context;
@@ -52,78 +52,65 @@ impl LifterX86<'_> {
     //    Ok(())
     //}
 
+    /// RET is a genuine return only when the stack pointer is back at the return address pushed
+    /// by the caller. Other RETs (push-ret, handler dispatch of virtualizers) are jumps to the
+    /// popped address
     pub(super) fn lift_ret<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
-        let builder = &self.builder;
         let ops = instr.operands();
 
-        let rsp_value: IntValue<'_> = self.get_register(Register::SP)?.try_into()?;
-
-        let mut rsp_addr = if let DecodedOperandKind::Imm(_) = &ops[0].kind {
-            &ops[3]
-        } else {
-            &ops[2]
+        let ret_kind = match self.stack_depth() {
+            Some(0) => RetKind::Return,
+            _ => RetKind::Jump,
         };
 
-        let real_val: IntValue<'_> = self.load_single_op(rsp_addr, rsp_addr.size)?.try_into()?;
-
-        let block = builder.get_insert_block().unwrap();
-        block.set_name("ret_check");
-        let function = block.get_parent().unwrap();
-        let last_inst = builder.build_return(Some(&real_val))?;
-
-        let rop_result = if let Some(const_int) = rsp_value.get_sign_extended_constant() {
-            let rsp_val = const_int;
-            // TODO: compare with Stackp_value
-            RopResult::RealReturn
+        let (stack_pointer_op, return_address_op) = if let DecodedOperandKind::Imm(_) = &ops[0].kind
+        {
+            (&ops[2], &ops[3])
         } else {
-            RopResult::RopReturn
+            (&ops[1], &ops[2])
         };
-
-        if rop_result == RopResult::RealReturn {
-            last_inst.erase_from_basic_block();
-            block.set_name("real_ret");
-            let rax: IntValue<'_> = self.get_register(Register::AX)?.try_into()?;
-            builder.build_return(Some(&builder.build_int_z_extend(
-                rax,
-                self.context.i64_type(),
-                "",
-            )?))?;
-
-            let orignal_func_final_opt = builder.get_insert_block().unwrap().get_parent().unwrap();
-            return Ok(());
+        let DecodedOperandKind::Reg(stack_pointer) = stack_pointer_op.kind else {
+            unreachable!("RET always updates the stack pointer")
+        };
+        let target = self.pop_value(stack_pointer, return_address_op.size.into())?;
+        if let DecodedOperandKind::Imm(immediate) = &ops[0].kind {
+            self.adjust_stack_pointer(stack_pointer, immediate.value as i64)?;
         }
 
-        block.set_name("previousret_block");
-
-        last_inst.erase_from_basic_block();
-        block.set_name("fake_ret");
-
-        let val = self
-            .context
-            .i64_type()
-            .const_int((self.retdec_get_arch_byte_size() / 8).into(), true);
+        match ret_kind {
+            RetKind::Return => self.build_guest_return(),
+            RetKind::Jump => self.build_jump(target),
+        }
+    }
 
-        let mut rsp_result = builder.build_int_add(rsp_value, val, "ret_new_rsp_")?;
+    /// Returns from the lifted function. Functions with the state ABI write the state back,
+    /// others return RAX
+    pub(super) fn build_guest_return(&self) -> Result<()> {
+        let builder = &self.builder;
 
-        if let DecodedOperandKind::Imm(immediate) = &ops[0].kind {
-            rsp_addr = &ops[3];
-            rsp_result = builder.build_int_add(
-                rsp_result,
-                rsp_result
-                    .get_type()
-                    .const_int(immediate.value, immediate.is_signed),
-                "",
-            )?;
+        if let Some(state) = self.state_param {
+            self.store_state(state)?;
+            builder.build_return(None)?;
+            return Ok(());
         }
 
-        self.store_reg(Register::SP, rsp_result)?;
+        let rax: IntValue<'_> = self.get_register(Register::AX)?.try_into()?;
+        let rax = match self.func_value.get_type().get_return_type() {
+            Some(BasicTypeEnum::IntType(return_ty)) => {
+                self.create_z_ext_or_trunc(rax, return_ty)?
+            }
+            _ => rax,
+        };
+        builder.build_return(Some(&rax))?;
 
         Ok(())
     }
 }
 
 #[derive(Debug, PartialEq)]
-enum RopResult {
-    RealReturn,
-    RopReturn,
+enum RetKind {
+    /// Stack pointer points at the return address of the caller
+    Return,
+    /// RET used as an indirect jump
+    Jump,
 }
@@ -1,10 +1,13 @@
 use super::{LifterX86, Result};
-use crate::miscellaneous::ExtendedRegisterEnum;
+use crate::{lifter::memory::LockedAccess, miscellaneous::ExtendedRegisterEnum};
 
-use inkwell::IntPredicate;
-use zydis::{Instruction, Operands};
+use inkwell::{values::IntValue, AtomicOrdering, IntPredicate};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, InstructionAttributes, Mnemonic, Operands,
+};
 
-impl LifterX86<'_> {
+impl<'ctx> LifterX86<'ctx> {
     pub(super) fn lift_xadd<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let builder = &self.builder;
 
@@ -71,4 +74,263 @@ impl LifterX86<'_> {
 
         Ok(())
     }
+
+    /// CMPXCHG. The destination is always written, with its old value when the comparison fails
+    pub(super) fn lift_cmpxchg<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let src = &ops[1];
+        let accumulator = &ops[2];
+
+        let dest_value = self.load_single_int_op(dest, dest.size)?;
+        let src_value = self.load_single_int_op(src, dest.size)?;
+        let accumulator_value = self.load_single_int_op(accumulator, dest.size)?;
+
+        self.store_cmp_flags(accumulator_value, dest_value)?;
+        let is_equal =
+            builder.build_int_compare(IntPredicate::EQ, accumulator_value, dest_value, "")?;
+
+        let new_dest = builder
+            .build_select(is_equal, src_value, dest_value, "")?
+            .into_int_value();
+        self.store_op(dest, new_dest)?;
+
+        self.store_reg_on_failure(accumulator, dest_value, is_equal)
+    }
+
+    /// CMPXCHG8B and CMPXCHG16B. Memory is compared with EDX:EAX/RDX:RAX and replaced with
+    /// ECX:EBX/RCX:RBX on success, only ZF is affected
+    pub(super) fn lift_cmpxchg8b_16b<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let [high_expected, low_expected, high_new, low_new] = [&ops[1], &ops[2], &ops[3], &ops[4]];
+
+        let half_width = dest.size / 2;
+        let wide_ty = self.context.custom_width_int_type(dest.size.into());
+        let concat = |high, low| -> Result<IntValue<'ctx>> {
+            let high = builder.build_int_z_extend(high, wide_ty, "")?;
+            let high =
+                builder.build_left_shift(high, wide_ty.const_int(half_width.into(), false), "")?;
+            let low = builder.build_int_z_extend(low, wide_ty, "")?;
+            Ok(builder.build_or(high, low, "")?)
+        };
+
+        let dest_value = self.load_single_int_op(dest, dest.size)?;
+        let expected = concat(
+            self.load_single_int_op(high_expected, half_width)?,
+            self.load_single_int_op(low_expected, half_width)?,
+        )?;
+        let new_value = concat(
+            self.load_single_int_op(high_new, half_width)?,
+            self.load_single_int_op(low_new, half_width)?,
+        )?;
+
+        let is_equal = builder.build_int_compare(IntPredicate::EQ, expected, dest_value, "")?;
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, is_equal);
+
+        let new_dest = builder
+            .build_select(is_equal, new_value, dest_value, "")?
+            .into_int_value();
+        self.store_op(dest, new_dest)?;
+
+        let half_ty = self.context.custom_width_int_type(half_width.into());
+        let high = builder.build_right_shift(
+            dest_value,
+            wide_ty.const_int(half_width.into(), false),
+            false,
+            "",
+        )?;
+        self.store_reg_on_failure(
+            high_expected,
+            builder.build_int_truncate(high, half_ty, "")?,
+            is_equal,
+        )?;
+        self.store_reg_on_failure(
+            low_expected,
+            builder.build_int_truncate(dest_value, half_ty, "")?,
+            is_equal,
+        )
+    }
+
+    /// Stores `value` to the register `operand` unless the comparison of a CMPXCHG succeeded.
+    /// Without the write a 32 bit register keeps the upper half of its 64 bit register
+    fn store_reg_on_failure(
+        &self,
+        operand: &DecodedOperand,
+        value: IntValue<'ctx>,
+        is_equal: IntValue<'ctx>,
+    ) -> Result<()> {
+        let DecodedOperandKind::Reg(reg) = operand.kind else {
+            unreachable!("CMPXCHG always compares with registers")
+        };
+        let enclosing_reg = self.get_register_largest_enclosing(&reg);
+        let old_enclosing: IntValue<'_> = self.load_register_value(&enclosing_reg)?.try_into()?;
+
+        self.store_reg(reg, value)?;
+        let failed_enclosing: IntValue<'_> =
+            self.load_register_value(&enclosing_reg)?.try_into()?;
+
+        let new_enclosing = self
+            .builder
+            .build_select(is_equal, old_enclosing, failed_enclosing, "")?
+            .into_int_value();
+        self.store_reg(enclosing_reg, new_enclosing)?;
+        Ok(())
+    }
+
+    /// Lifts a locked read-modify-write instruction as a `cmpxchg` loop when the memory model
+    /// gives real pointers. `lift` sees the atomically loaded value and its store to the memory
+    /// operand is only committed if memory still holds that value, otherwise the whole
+    /// instruction is retried
+    pub(super) fn build_locked<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        lift: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+
+        let Some((mem, size)) = instr
+            .operands()
+            .iter()
+            .find_map(|operand| match &operand.kind {
+                DecodedOperandKind::Mem(mem) => Some((mem, operand.size)),
+                _ => None,
+            })
+        else {
+            return lift();
+        };
+        let address = self.mergen_calculate_memory_address(mem)?;
+        let Some(pointer) = self.memory.pointer(self, address)? else {
+            return lift();
+        };
+
+        let value_ty = self.context.custom_width_int_type(size.into());
+        let retry_block = self
+            .context
+            .append_basic_block(self.func_value, "locked_retry");
+        builder.build_unconditional_branch(retry_block)?;
+        builder.position_at_end(retry_block);
+
+        // Every retry starts over from the register state before the instruction, so no phis
+        // are needed
+        let loaded = builder
+            .build_load(value_ty, pointer, "locked_load")?
+            .into_int_value();
+        let load_instr = loaded.as_instruction().expect("Load is an instruction");
+        load_instr
+            .set_atomic_ordering(AtomicOrdering::Monotonic)
+            .expect("Monotonic ordering is valid for loads");
+        load_instr
+            .set_alignment(u32::from(size / 8))
+            .expect("Alignment is a power of two");
+
+        self.locked_access.set(Some(LockedAccess {
+            loaded,
+            stored: None,
+        }));
+        let lifted = lift();
+        let access = self
+            .locked_access
+            .take()
+            .expect("Locked access is only reset here");
+        lifted?;
+
+        let stored = access.stored.unwrap_or(loaded);
+        let exchange = builder.build_cmpxchg(
+            pointer,
+            loaded,
+            stored,
+            AtomicOrdering::SequentiallyConsistent,
+            AtomicOrdering::SequentiallyConsistent,
+        )?;
+        let succeeded = builder
+            .build_extract_value(exchange, 1, "locked_succeeded")?
+            .into_int_value();
+        let exit_block = self
+            .context
+            .append_basic_block(self.func_value, "locked_exit");
+        builder.build_conditional_branch(succeeded, exit_block, retry_block)?;
+
+        builder.position_at_end(exit_block);
+        Ok(())
+    }
+}
+
+/// Whether the instruction must be lifted as an atomic read-modify-write. XCHG with memory is
+/// locked even without the prefix
+pub(super) fn is_locked<O: Operands>(instr: &Instruction<O>) -> bool {
+    instr.attributes.contains(InstructionAttributes::HAS_LOCK)
+        || (instr.mnemonic == Mnemonic::XCHG
+            && instr
+                .operands()
+                .iter()
+                .any(|operand| matches!(operand.kind, DecodedOperandKind::Mem(_))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    const STACK: u64 = 0x8000;
+
+    fn exchange_context() -> StartContextX86<u64> {
+        StartContextX86 {
+            rax: 0xaaaa_aaaa_1111_1111,
+            rdx: 0xbbbb_bbbb_2222_2222,
+            rbx: 0xcccc_cccc_3333_3333,
+            rcx: 0xdddd_dddd_4444_4444,
+            rsp: STACK,
+            rip: 0x1000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cmpxchg8b_success_keeps_edx_eax() {
+        let mut memory = MemoryImage::default();
+        memory.write_u64(STACK, 0x2222_2222_1111_1111);
+
+        // cmpxchg8b qword ptr [rsp]
+        let execution = execute(&[0x0f, 0xc7, 0x0c, 0x24], exchange_context(), memory);
+
+        assert_eq!(execution.context.zf, 1);
+        assert_eq!(execution.context.rax, 0xaaaa_aaaa_1111_1111);
+        assert_eq!(execution.context.rdx, 0xbbbb_bbbb_2222_2222);
+        assert_eq!(execution.memory.read_u64(STACK), 0x4444_4444_3333_3333);
+    }
+
+    #[test]
+    fn cmpxchg8b_failure_loads_edx_eax() {
+        let mut memory = MemoryImage::default();
+        memory.write_u64(STACK, 0x6666_6666_5555_5555);
+
+        // cmpxchg8b qword ptr [rsp]
+        let execution = execute(&[0x0f, 0xc7, 0x0c, 0x24], exchange_context(), memory);
+
+        assert_eq!(execution.context.zf, 0);
+        assert_eq!(execution.context.rax, 0x5555_5555);
+        assert_eq!(execution.context.rdx, 0x6666_6666);
+        assert_eq!(execution.memory.read_u64(STACK), 0x6666_6666_5555_5555);
+    }
+
+    #[test]
+    fn cmpxchg16b_success_stores_rcx_rbx() {
+        let context = exchange_context();
+        let mut memory = MemoryImage::default();
+        memory.write_u64(STACK, context.rax);
+        memory.write_u64(STACK + 8, context.rdx);
+
+        // cmpxchg16b xmmword ptr [rsp]
+        let execution = execute(&[0x48, 0x0f, 0xc7, 0x0c, 0x24], context, memory);
+
+        assert_eq!(execution.context.zf, 1);
+        assert_eq!(execution.context.rax, context.rax);
+        assert_eq!(execution.context.rdx, context.rdx);
+        assert_eq!(execution.memory.read_u64(STACK), context.rbx);
+        assert_eq!(execution.memory.read_u64(STACK + 8), context.rcx);
+    }
 }
@@ -2,7 +2,7 @@ use super::{LifterX86, Result};
 use crate::miscellaneous::ExtendedRegisterEnum;
 
 use inkwell::{values::IntValue, IntPredicate};
-use zydis::{Instruction, Mnemonic, Operands};
+use zydis::{ffi::DecodedOperand, Instruction, Mnemonic, Operands};
 
 impl LifterX86<'_> {
     //pub(super) fn lift_sar<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
@@ -126,14 +126,10 @@ impl LifterX86<'_> {
         let int_1_ty = self.context.bool_type();
         let ops = instr.operands();
 
-        let [dest, count] = match instr.mnemonic {
-            Mnemonic::SAR => [&ops[0], &ops[1]],
-            Mnemonic::SARX => [&ops[1], &ops[2]],
-            _ => unreachable!(),
-        };
+        let [shifted, count] = shift_operands(instr);
 
-        let l_value = IntValue::try_from(self.load_single_op(dest, dest.size)?)?;
-        let count_value = IntValue::try_from(self.load_single_op(count, dest.size)?)?;
+        let l_value = IntValue::try_from(self.load_single_op(shifted, shifted.size)?)?;
+        let count_value = IntValue::try_from(self.load_single_op(count, shifted.size)?)?;
 
         let l_value_ty = l_value.get_type();
         let count_value_ty = count_value.get_type();
@@ -206,7 +202,7 @@ impl LifterX86<'_> {
             self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
         }
 
-        self.store_op(dest, result)?;
+        self.store_op(&ops[0], result)?;
         Ok(())
     }
 
@@ -218,14 +214,10 @@ impl LifterX86<'_> {
 
         let int_1_ty = self.context.custom_width_int_type(1);
 
-        let [dest, count] = match instr.mnemonic {
-            Mnemonic::SHL => [&ops[0], &ops[1]],
-            Mnemonic::SHLX => [&ops[1], &ops[2]],
-            _ => unreachable!(),
-        };
+        let [shifted, count] = shift_operands(instr);
 
-        let l_value = IntValue::try_from(self.load_single_op(dest, dest.size)?)?;
-        let count_value = IntValue::try_from(self.load_single_op(count, dest.size)?)?;
+        let l_value = IntValue::try_from(self.load_single_op(shifted, shifted.size)?)?;
+        let count_value = IntValue::try_from(self.load_single_op(count, shifted.size)?)?;
 
         //let [l_value, count_value] = self.load_two_first_ints(ops)?;
         let l_value_ty = l_value.get_type();
@@ -387,7 +379,7 @@ impl LifterX86<'_> {
             self.store_cpu_flag(ExtendedRegisterEnum::PF, pf);
             self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
         }
-        self.store_op(dest, result)?;
+        self.store_op(&ops[0], result)?;
 
         Ok(())
     }
@@ -522,14 +514,10 @@ impl LifterX86<'_> {
 
         let mnemonic = &instr.mnemonic;
 
-        let [dest, count] = match mnemonic {
-            Mnemonic::SHR => [&ops[0], &ops[1]],
-            Mnemonic::SHRX => [&ops[1], &ops[2]],
-            _ => unreachable!(),
-        };
+        let [shifted, count] = shift_operands(instr);
 
-        let l_value = IntValue::try_from(self.load_single_op(dest, dest.size)?)?;
-        let count_value = IntValue::try_from(self.load_single_op(count, dest.size)?)?;
+        let l_value = IntValue::try_from(self.load_single_op(shifted, shifted.size)?)?;
+        let count_value = IntValue::try_from(self.load_single_op(count, shifted.size)?)?;
 
         let l_value_ty = l_value.get_type();
         let count_value_ty = count_value.get_type();
@@ -636,7 +624,7 @@ impl LifterX86<'_> {
             self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
             self.store_cpu_flag(ExtendedRegisterEnum::PF, pf);
         }
-        self.store_op(dest, result)?;
+        self.store_op(&ops[0], result)?;
 
         Ok(())
     }
@@ -752,3 +740,13 @@ impl LifterX86<'_> {
         Ok(())
     }
 }
+
+/// Shifted value and count. SAR, SHL and SHR shift their destination, the BMI2 forms SARX, SHLX
+/// and SHRX shift their second operand and take the count from the third one
+fn shift_operands<O: Operands>(instr: &Instruction<O>) -> [&DecodedOperand; 2] {
+    let ops = instr.operands();
+    match instr.mnemonic {
+        Mnemonic::SARX | Mnemonic::SHLX | Mnemonic::SHRX => [&ops[1], &ops[2]],
+        _ => [&ops[0], &ops[1]],
+    }
+}
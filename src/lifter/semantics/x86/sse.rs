@@ -0,0 +1,337 @@
+use super::{Error, LifterX86, Result};
+
+use inkwell::{
+    intrinsics::Intrinsic,
+    types::VectorType,
+    values::{IntValue, VectorValue},
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, Mnemonic, Operands, RegisterClass,
+};
+
+/// Width of the modelled part of vector registers
+pub(super) const XMM_WIDTH: u32 = 128;
+
+/// How packed additions and subtractions handle overflow
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Overflow {
+    Wrap,
+    SignedSaturation,
+    UnsignedSaturation,
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    /// MOVDQA, MOVDQU, MOVAPS, MOVUPS, MOVAPD and MOVUPD. Alignment isn't checked
+    pub(super) fn lift_movdq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+
+        let value = self.load_xmm_source(&ops[1])?;
+        self.store_xmm_operand(&ops[0], value)
+    }
+
+    /// MOVD and MOVQ. Writes to XMM registers clear the bits above the moved value
+    pub(super) fn lift_movd_movq<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let src = &ops[1];
+
+        let width = match instr.mnemonic {
+            Mnemonic::MOVD => 32,
+            Mnemonic::MOVQ => 64,
+            _ => unreachable!(),
+        };
+        let value_ty = self.context.custom_width_int_type(width);
+
+        let value = self.load_xmm_source(src)?;
+        let value = self.builder.build_int_truncate(value, value_ty, "")?;
+
+        if is_xmm_operand(dest)? {
+            let value = self
+                .builder
+                .build_int_z_extend(value, self.context.i128_type(), "")?;
+            self.store_xmm_operand(dest, value)
+        } else {
+            self.store_op(dest, value)
+        }
+    }
+
+    /// PAND, PANDN, POR, PXOR and their floating point twins (ANDPS, XORPS, ...)
+    pub(super) fn lift_packed_logical<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        self.build_packed_binary(instr, 64, |lhs, rhs| {
+            self.build_packed_logical(instr.mnemonic, lhs, rhs)
+        })
+    }
+
+    /// PADD* and PSUB*, including the saturating variants
+    pub(super) fn lift_packed_add_sub<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let (_, element_width, _) = packed_add_sub_kind(instr.mnemonic);
+
+        self.build_packed_binary(instr, element_width, |lhs, rhs| {
+            self.build_packed_add_sub(instr.mnemonic, lhs, rhs)
+        })
+    }
+
+    /// PSHUFD. Each 2 bits of the immediate select the source dword of a destination dword
+    pub(super) fn lift_pshufd<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let DecodedOperandKind::Imm(order) = &ops[2].kind else {
+            unreachable!("PSHUFD always has an immediate order operand")
+        };
+
+        let src = self.load_xmm_source(&ops[1])?;
+        let src = self.bits_as_vector(src, 32)?;
+
+        let mask: Vec<u32> = (0..4)
+            .map(|i| (order.value >> (i * 2)) as u32 & 3)
+            .collect();
+        let shuffled = self.build_shuffle(src, src, &mask)?;
+
+        let value = self.vector_as_bits(shuffled)?;
+        self.store_xmm_operand(&ops[0], value)
+    }
+
+    /// PUNPCKL* and PUNPCKH*. Interleave elements of the lower or upper halves of both operands
+    pub(super) fn lift_punpck<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let (element_width, high) = match instr.mnemonic {
+            Mnemonic::PUNPCKLBW => (8, false),
+            Mnemonic::PUNPCKLWD => (16, false),
+            Mnemonic::PUNPCKLDQ => (32, false),
+            Mnemonic::PUNPCKLQDQ => (64, false),
+            Mnemonic::PUNPCKHBW => (8, true),
+            Mnemonic::PUNPCKHWD => (16, true),
+            Mnemonic::PUNPCKHDQ => (32, true),
+            Mnemonic::PUNPCKHQDQ => (64, true),
+            _ => unreachable!(),
+        };
+
+        let count = XMM_WIDTH / element_width;
+        let first = if high { count / 2 } else { 0 };
+        // Elements of the second shuffle operand are numbered after the ones of the first
+        let mask: Vec<u32> = (first..first + count / 2)
+            .flat_map(|i| [i, count + i])
+            .collect();
+
+        self.build_packed_binary(instr, element_width, |lhs, rhs| {
+            self.build_shuffle(lhs, rhs, &mask)
+        })
+    }
+
+    /// Loads both operands as vectors of `element_width` bit integers and stores the result of `op`
+    /// to the first one
+    fn build_packed_binary<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        element_width: u32,
+        op: impl FnOnce(VectorValue<'ctx>, VectorValue<'ctx>) -> Result<VectorValue<'ctx>>,
+    ) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let src = &ops[1];
+
+        let lhs = self.load_xmm_source(dest)?;
+        let rhs = self.load_xmm_source(src)?;
+
+        let lhs = self.bits_as_vector(lhs, element_width)?;
+        let rhs = self.bits_as_vector(rhs, element_width)?;
+        let result = op(lhs, rhs)?;
+
+        let value = self.vector_as_bits(result)?;
+        self.store_xmm_operand(dest, value)
+    }
+
+    /// Logical operation of a PAND-like instruction or its VEX form
+    pub(super) fn build_packed_logical(
+        &self,
+        mnemonic: Mnemonic,
+        lhs: VectorValue<'ctx>,
+        rhs: VectorValue<'ctx>,
+    ) -> Result<VectorValue<'ctx>> {
+        let builder = &self.builder;
+
+        let value = match mnemonic {
+            Mnemonic::PAND
+            | Mnemonic::ANDPS
+            | Mnemonic::ANDPD
+            | Mnemonic::VPAND
+            | Mnemonic::VPANDD
+            | Mnemonic::VPANDQ
+            | Mnemonic::VANDPS
+            | Mnemonic::VANDPD => builder.build_and(lhs, rhs, "")?,
+            Mnemonic::PANDN
+            | Mnemonic::ANDNPS
+            | Mnemonic::ANDNPD
+            | Mnemonic::VPANDN
+            | Mnemonic::VPANDND
+            | Mnemonic::VPANDNQ
+            | Mnemonic::VANDNPS
+            | Mnemonic::VANDNPD => {
+                let not_lhs = builder.build_not(lhs, "")?;
+                builder.build_and(not_lhs, rhs, "")?
+            }
+            Mnemonic::POR
+            | Mnemonic::ORPS
+            | Mnemonic::ORPD
+            | Mnemonic::VPOR
+            | Mnemonic::VPORD
+            | Mnemonic::VPORQ
+            | Mnemonic::VORPS
+            | Mnemonic::VORPD => builder.build_or(lhs, rhs, "")?,
+            Mnemonic::PXOR
+            | Mnemonic::XORPS
+            | Mnemonic::XORPD
+            | Mnemonic::VPXOR
+            | Mnemonic::VPXORD
+            | Mnemonic::VPXORQ
+            | Mnemonic::VXORPS
+            | Mnemonic::VXORPD => builder.build_xor(lhs, rhs, "")?,
+            _ => unreachable!(),
+        };
+        Ok(value)
+    }
+
+    /// Addition or subtraction of a PADD*/PSUB* instruction or its VEX form
+    pub(super) fn build_packed_add_sub(
+        &self,
+        mnemonic: Mnemonic,
+        lhs: VectorValue<'ctx>,
+        rhs: VectorValue<'ctx>,
+    ) -> Result<VectorValue<'ctx>> {
+        let builder = &self.builder;
+        let (is_add, _, overflow) = packed_add_sub_kind(mnemonic);
+
+        let intrinsic_name = match (overflow, is_add) {
+            (Overflow::Wrap, true) => return Ok(builder.build_int_add(lhs, rhs, "")?),
+            (Overflow::Wrap, false) => return Ok(builder.build_int_sub(lhs, rhs, "")?),
+            (Overflow::SignedSaturation, true) => "llvm.sadd.sat",
+            (Overflow::SignedSaturation, false) => "llvm.ssub.sat",
+            (Overflow::UnsignedSaturation, true) => "llvm.uadd.sat",
+            (Overflow::UnsignedSaturation, false) => "llvm.usub.sat",
+        };
+
+        let intrinsic =
+            Intrinsic::find(intrinsic_name).ok_or(Error::IntrinsicNotFound(intrinsic_name))?;
+        let intrinsic_func = intrinsic
+            .get_declaration(&self.module, &[lhs.get_type().into()])
+            .unwrap();
+
+        let value = builder
+            .build_call(intrinsic_func, &[lhs.into(), rhs.into()], "")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_vector_value();
+        Ok(value)
+    }
+
+    pub(super) fn build_shuffle(
+        &self,
+        lhs: VectorValue<'ctx>,
+        rhs: VectorValue<'ctx>,
+        mask: &[u32],
+    ) -> Result<VectorValue<'ctx>> {
+        let i32_ty = self.context.i32_type();
+        let mask: Vec<_> = mask
+            .iter()
+            .map(|index| i32_ty.const_int((*index).into(), false))
+            .collect();
+        let mask = VectorType::const_vector(&mask);
+
+        Ok(self.builder.build_shuffle_vector(lhs, rhs, mask, "")?)
+    }
+
+    /// SSE operand as 128 bit integer. XMM registers are read whole, narrower operands (general
+    /// purpose registers and memory) are zero extended
+    pub(super) fn load_xmm_source(&self, operand: &DecodedOperand) -> Result<IntValue<'ctx>> {
+        let size = if is_xmm_operand(operand)? {
+            XMM_WIDTH as u16
+        } else {
+            operand.size
+        };
+
+        let value: IntValue<'_> = self.load_single_op(operand, size)?.try_into()?;
+        self.create_z_ext_or_trunc(value, self.context.i128_type())
+    }
+
+    /// Replaces whole XMM register, or stores `operand.size` lower bits of `value` to memory
+    pub(super) fn store_xmm_operand(
+        &self,
+        operand: &DecodedOperand,
+        value: IntValue<'ctx>,
+    ) -> Result<()> {
+        let width = if is_xmm_operand(operand)? {
+            XMM_WIDTH
+        } else {
+            operand.size.into()
+        };
+
+        let value_ty = self.context.custom_width_int_type(width);
+        let value = self.create_z_ext_or_trunc(value, value_ty)?;
+        self.store_op(operand, value)
+    }
+
+    /// Reinterprets a vector register value as a vector of `element_width` bit integers
+    pub(super) fn bits_as_vector(
+        &self,
+        value: IntValue<'ctx>,
+        element_width: u32,
+    ) -> Result<VectorValue<'ctx>> {
+        let width = value.get_type().get_bit_width();
+        let vector_ty = self
+            .context
+            .custom_width_int_type(element_width)
+            .vec_type(width / element_width);
+
+        let vector = self.builder.build_bit_cast(value, vector_ty, "")?;
+        Ok(vector.into_vector_value())
+    }
+
+    pub(super) fn vector_as_bits(&self, vector: VectorValue<'ctx>) -> Result<IntValue<'ctx>> {
+        let vector_ty = vector.get_type();
+        let element_width = vector_ty.get_element_type().into_int_type().get_bit_width();
+        let int_ty = self
+            .context
+            .custom_width_int_type(element_width * vector_ty.get_size());
+
+        let value = self.builder.build_bit_cast(vector, int_ty, "")?;
+        Ok(value.into_int_value())
+    }
+}
+
+pub(super) fn is_xmm_operand(operand: &DecodedOperand) -> Result<bool> {
+    ensure_not_mmx(operand)?;
+    Ok(matches!(operand.kind, DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::XMM))
+}
+
+/// Legacy forms of the packed instructions operate on MMX registers, which aren't modelled
+fn ensure_not_mmx(operand: &DecodedOperand) -> Result<()> {
+    match operand.kind {
+        DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::MMX => {
+            Err(Error::UnsupportedInstr("MMX registers aren't supported"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the instruction adds, element width and overflow handling
+pub(super) fn packed_add_sub_kind(mnemonic: Mnemonic) -> (bool, u32, Overflow) {
+    match mnemonic {
+        Mnemonic::PADDB | Mnemonic::VPADDB => (true, 8, Overflow::Wrap),
+        Mnemonic::PADDW | Mnemonic::VPADDW => (true, 16, Overflow::Wrap),
+        Mnemonic::PADDD | Mnemonic::VPADDD => (true, 32, Overflow::Wrap),
+        Mnemonic::PADDQ | Mnemonic::VPADDQ => (true, 64, Overflow::Wrap),
+        Mnemonic::PADDSB | Mnemonic::VPADDSB => (true, 8, Overflow::SignedSaturation),
+        Mnemonic::PADDSW | Mnemonic::VPADDSW => (true, 16, Overflow::SignedSaturation),
+        Mnemonic::PADDUSB | Mnemonic::VPADDUSB => (true, 8, Overflow::UnsignedSaturation),
+        Mnemonic::PADDUSW | Mnemonic::VPADDUSW => (true, 16, Overflow::UnsignedSaturation),
+        Mnemonic::PSUBB | Mnemonic::VPSUBB => (false, 8, Overflow::Wrap),
+        Mnemonic::PSUBW | Mnemonic::VPSUBW => (false, 16, Overflow::Wrap),
+        Mnemonic::PSUBD | Mnemonic::VPSUBD => (false, 32, Overflow::Wrap),
+        Mnemonic::PSUBQ | Mnemonic::VPSUBQ => (false, 64, Overflow::Wrap),
+        Mnemonic::PSUBSB | Mnemonic::VPSUBSB => (false, 8, Overflow::SignedSaturation),
+        Mnemonic::PSUBSW | Mnemonic::VPSUBSW => (false, 16, Overflow::SignedSaturation),
+        Mnemonic::PSUBUSB | Mnemonic::VPSUBUSB => (false, 8, Overflow::UnsignedSaturation),
+        Mnemonic::PSUBUSW | Mnemonic::VPSUBUSW => (false, 16, Overflow::UnsignedSaturation),
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,374 @@
+use super::{sse::is_xmm_operand, Error, LifterX86, Result};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    intrinsics::Intrinsic,
+    types::{FloatType, IntType},
+    values::{FloatValue, IntValue},
+    FloatPredicate,
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, Mnemonic, Operands, RegisterClass,
+};
+
+/// Precision of the lower lane an instruction works on
+#[derive(Debug, Clone, Copy)]
+enum Precision {
+    /// `*SS` instructions
+    Single,
+    /// `*SD` instructions
+    Double,
+}
+
+impl Precision {
+    fn width(self) -> u32 {
+        match self {
+            Self::Single => 32,
+            Self::Double => 64,
+        }
+    }
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    /// ADD, SUB, MUL, DIV, MIN and MAX of single and double precision scalars.
+    ///
+    /// MIN and MAX return the second operand when either is NaN or both are zeros. `llvm.minnum`
+    /// and `llvm.maxnum` don't behave like that, so these are lifted as compare and select
+    pub(super) fn lift_scalar_arith<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let precision = precision_of(instr.mnemonic);
+
+        let lhs = self.load_scalar(&ops[0], precision)?;
+        let rhs = self.load_scalar(&ops[1], precision)?;
+
+        let value = match instr.mnemonic {
+            Mnemonic::ADDSS | Mnemonic::ADDSD => builder.build_float_add(lhs, rhs, "")?,
+            Mnemonic::SUBSS | Mnemonic::SUBSD => builder.build_float_sub(lhs, rhs, "")?,
+            Mnemonic::MULSS | Mnemonic::MULSD => builder.build_float_mul(lhs, rhs, "")?,
+            Mnemonic::DIVSS | Mnemonic::DIVSD => builder.build_float_div(lhs, rhs, "")?,
+            Mnemonic::MINSS | Mnemonic::MINSD | Mnemonic::MAXSS | Mnemonic::MAXSD => {
+                let predicate = match instr.mnemonic {
+                    Mnemonic::MINSS | Mnemonic::MINSD => FloatPredicate::OLT,
+                    _ => FloatPredicate::OGT,
+                };
+                let keep_lhs = builder.build_float_compare(predicate, lhs, rhs, "")?;
+                builder
+                    .build_select(keep_lhs, lhs, rhs, "")?
+                    .into_float_value()
+            }
+            _ => unreachable!(),
+        };
+
+        self.store_scalar(&ops[0], value)
+    }
+
+    /// SQRTSS and SQRTSD
+    pub(super) fn lift_scalar_sqrt<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let precision = precision_of(instr.mnemonic);
+
+        let src = self.load_scalar(&ops[1], precision)?;
+        let value = self.build_float_intrinsic("llvm.sqrt", src)?;
+
+        self.store_scalar(&ops[0], value)
+    }
+
+    /// UCOMISS, UCOMISD, COMISS and COMISD. Unordered operands set ZF, PF and CF, otherwise ZF
+    /// means equal and CF means less
+    pub(super) fn lift_scalar_compare_flags<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+    ) -> Result<()> {
+        let ops = instr.operands();
+        let precision = precision_of(instr.mnemonic);
+
+        let lhs = self.load_scalar(&ops[0], precision)?;
+        let rhs = self.load_scalar(&ops[1], precision)?;
+
+        self.store_float_compare_flags(lhs, rhs)
+    }
+
+    /// CMPSS and CMPSD. The lower lane becomes all ones when the predicate selected by the
+    /// immediate holds and zero otherwise
+    pub(super) fn lift_scalar_compare_mask<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+        let precision = precision_of(instr.mnemonic);
+
+        let DecodedOperandKind::Imm(predicate) = &ops[2].kind else {
+            unreachable!("CMPSS/CMPSD always have an immediate predicate")
+        };
+        let predicate = match predicate.value & 7 {
+            0 => FloatPredicate::OEQ,
+            1 => FloatPredicate::OLT,
+            2 => FloatPredicate::OLE,
+            3 => FloatPredicate::UNO,
+            4 => FloatPredicate::UNE,
+            5 => FloatPredicate::UGE,
+            6 => FloatPredicate::UGT,
+            _ => FloatPredicate::ORD,
+        };
+
+        let lhs = self.load_scalar(&ops[0], precision)?;
+        let rhs = self.load_scalar(&ops[1], precision)?;
+        let holds = builder.build_float_compare(predicate, lhs, rhs, "")?;
+
+        let lane_ty = self.context.custom_width_int_type(precision.width());
+        let mask = builder
+            .build_select(holds, lane_ty.const_all_ones(), lane_ty.const_zero(), "")?
+            .into_int_value();
+
+        self.store_lower_lane(&ops[0], mask)
+    }
+
+    /// CVTSI2SS and CVTSI2SD
+    pub(super) fn lift_cvtsi2s<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let float_ty = self.scalar_float_type(precision_of(instr.mnemonic));
+
+        let src = self.load_single_int_op(&ops[1], ops[1].size)?;
+        let value = self.builder.build_signed_int_to_float(src, float_ty, "")?;
+
+        self.store_scalar(&ops[0], value)
+    }
+
+    /// CVTSS2SD and CVTSD2SS
+    pub(super) fn lift_cvt_scalar_precision<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let value = match instr.mnemonic {
+            Mnemonic::CVTSS2SD => {
+                let src = self.load_scalar(&ops[1], Precision::Single)?;
+                builder.build_float_ext(src, self.context.f64_type(), "")?
+            }
+            Mnemonic::CVTSD2SS => {
+                let src = self.load_scalar(&ops[1], Precision::Double)?;
+                builder.build_float_trunc(src, self.context.f32_type(), "")?
+            }
+            _ => unreachable!(),
+        };
+
+        self.store_scalar(&ops[0], value)
+    }
+
+    /// CVTSS2SI, CVTSD2SI and their truncating CVTT* forms. Rounding follows the default MXCSR
+    /// mode (to nearest even). NaN and values out of range produce the "integer indefinite" value
+    pub(super) fn lift_cvt_scalar_to_int<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+
+        let src = self.load_scalar(&ops[1], precision_of(instr.mnemonic))?;
+        let rounding_intrinsic = match instr.mnemonic {
+            Mnemonic::CVTTSS2SI | Mnemonic::CVTTSD2SI => "llvm.trunc",
+            _ => "llvm.rint",
+        };
+        let rounded = self.build_float_intrinsic(rounding_intrinsic, src)?;
+
+        let int_ty = self.context.custom_width_int_type(dest.size.into());
+        let value = self.build_float_to_int(rounded, int_ty)?;
+
+        self.store_op(dest, value)
+    }
+
+    /// SSE forms of MOVSS and MOVSD. Register to register moves keep the upper part of the
+    /// destination, loads from memory clear it
+    pub(super) fn lift_movss_movsd<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+        let dest = &ops[0];
+        let src = &ops[1];
+        let lane_ty = self
+            .context
+            .custom_width_int_type(precision_of(instr.mnemonic).width());
+
+        let value = self.load_xmm_source(src)?;
+        if is_xmm_operand(dest)? && !is_xmm_operand(src)? {
+            return self.store_xmm_operand(dest, value);
+        }
+
+        let lane = self.builder.build_int_truncate(value, lane_ty, "")?;
+        self.store_lower_lane(dest, lane)
+    }
+
+    fn scalar_float_type(&self, precision: Precision) -> FloatType<'ctx> {
+        match precision {
+            Precision::Single => self.context.f32_type(),
+            Precision::Double => self.context.f64_type(),
+        }
+    }
+
+    /// Lower lane of an XMM register or a scalar in memory
+    fn load_scalar(
+        &self,
+        operand: &DecodedOperand,
+        precision: Precision,
+    ) -> Result<FloatValue<'ctx>> {
+        let lane_ty = self.context.custom_width_int_type(precision.width());
+
+        let value = self.load_xmm_source(operand)?;
+        let lane = self.builder.build_int_truncate(value, lane_ty, "")?;
+
+        let scalar = self
+            .builder
+            .build_bit_cast(lane, self.scalar_float_type(precision), "")?;
+        Ok(scalar.into_float_value())
+    }
+
+    fn store_scalar(&self, operand: &DecodedOperand, value: FloatValue<'ctx>) -> Result<()> {
+        let lane = self.float_as_int(value)?;
+        self.store_lower_lane(operand, lane)
+    }
+
+    /// Replaces lower bits of an XMM register with `lane`, keeping the rest. Memory operands just
+    /// get `lane` stored
+    fn store_lower_lane(&self, operand: &DecodedOperand, lane: IntValue<'ctx>) -> Result<()> {
+        if !is_xmm_operand(operand)? {
+            return self.store_op(operand, lane);
+        }
+
+        let builder = &self.builder;
+        let i128_ty = self.context.i128_type();
+
+        let lane_width = lane.get_type().get_bit_width();
+        let upper_mask = builder.build_left_shift(
+            i128_ty.const_all_ones(),
+            i128_ty.const_int(lane_width.into(), false),
+            "",
+        )?;
+
+        let old_value = self.load_xmm_source(operand)?;
+        let upper = builder.build_and(old_value, upper_mask, "")?;
+        let lane = builder.build_int_z_extend(lane, i128_ty, "")?;
+        let value = builder.build_or(upper, lane, "")?;
+
+        self.store_xmm_operand(operand, value)
+    }
+
+    /// Sets ZF, PF and CF like UCOMISS does. Unordered operands set all three, otherwise ZF means
+    /// equal and CF means less
+    pub(super) fn store_float_compare_flags(
+        &self,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+    ) -> Result<()> {
+        let builder = &self.builder;
+
+        let zf = builder.build_float_compare(FloatPredicate::UEQ, lhs, rhs, "")?;
+        let pf = builder.build_float_compare(FloatPredicate::UNO, lhs, rhs, "")?;
+        let cf = builder.build_float_compare(FloatPredicate::ULT, lhs, rhs, "")?;
+
+        self.store_cpu_flag(ExtendedRegisterEnum::ZF, zf);
+        self.store_cpu_flag(ExtendedRegisterEnum::PF, pf);
+        self.store_cpu_flag(ExtendedRegisterEnum::CF, cf);
+
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::OF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::AF, false);
+        self.store_cpu_flag_bool(ExtendedRegisterEnum::SF, false);
+        Ok(())
+    }
+
+    /// Converts already rounded `value` to a signed integer. NaN and values out of range produce
+    /// the "integer indefinite" value
+    pub(super) fn build_float_to_int(
+        &self,
+        rounded: FloatValue<'ctx>,
+        int_ty: IntType<'ctx>,
+    ) -> Result<IntValue<'ctx>> {
+        let builder = &self.builder;
+        let width = int_ty.get_bit_width();
+        let float_ty = rounded.get_type();
+
+        // Both bounds are powers of two, so they are exact in single precision too
+        let limit = 2f64.powi(width as i32 - 1);
+        let above_min = builder.build_float_compare(
+            FloatPredicate::OGE,
+            rounded,
+            float_ty.const_float(-limit),
+            "",
+        )?;
+        let below_max = builder.build_float_compare(
+            FloatPredicate::OLT,
+            rounded,
+            float_ty.const_float(limit),
+            "",
+        )?;
+        let in_range = builder.build_and(above_min, below_max, "")?;
+
+        let converted = builder.build_float_to_signed_int(rounded, int_ty, "")?;
+        let indefinite = int_ty.const_int(1 << (width - 1), false);
+        let value = builder
+            .build_select(in_range, converted, indefinite, "")?
+            .into_int_value();
+
+        Ok(value)
+    }
+
+    pub(super) fn build_float_intrinsic(
+        &self,
+        name: &'static str,
+        value: FloatValue<'ctx>,
+    ) -> Result<FloatValue<'ctx>> {
+        let intrinsic = Intrinsic::find(name).ok_or(Error::IntrinsicNotFound(name))?;
+        let intrinsic_func = intrinsic
+            .get_declaration(&self.module, &[value.get_type().into()])
+            .unwrap();
+
+        let result = self
+            .builder
+            .build_call(intrinsic_func, &[value.into()], "")?
+            .try_as_basic_value()
+            .left()
+            .unwrap()
+            .into_float_value();
+        Ok(result)
+    }
+}
+
+/// Whether MOVSD/CMPSD is the SSE instruction and not the string one of the same name
+pub(super) fn is_sse_form<O: Operands>(instr: &Instruction<O>) -> bool {
+    instr.operands().iter().any(|operand| {
+        matches!(operand.kind, DecodedOperandKind::Reg(reg) if reg.class() == RegisterClass::XMM)
+    })
+}
+
+fn precision_of(mnemonic: Mnemonic) -> Precision {
+    match mnemonic {
+        Mnemonic::ADDSS
+        | Mnemonic::SUBSS
+        | Mnemonic::MULSS
+        | Mnemonic::DIVSS
+        | Mnemonic::MINSS
+        | Mnemonic::MAXSS
+        | Mnemonic::SQRTSS
+        | Mnemonic::UCOMISS
+        | Mnemonic::COMISS
+        | Mnemonic::CMPSS
+        | Mnemonic::CVTSI2SS
+        | Mnemonic::CVTSS2SI
+        | Mnemonic::CVTTSS2SI
+        | Mnemonic::MOVSS => Precision::Single,
+        Mnemonic::ADDSD
+        | Mnemonic::SUBSD
+        | Mnemonic::MULSD
+        | Mnemonic::DIVSD
+        | Mnemonic::MINSD
+        | Mnemonic::MAXSD
+        | Mnemonic::SQRTSD
+        | Mnemonic::UCOMISD
+        | Mnemonic::COMISD
+        | Mnemonic::CMPSD
+        | Mnemonic::CVTSI2SD
+        | Mnemonic::CVTSD2SI
+        | Mnemonic::CVTTSD2SI
+        | Mnemonic::MOVSD => Precision::Double,
+        _ => unreachable!("{mnemonic:?} doesn't work on scalars"),
+    }
+}
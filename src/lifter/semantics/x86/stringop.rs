@@ -0,0 +1,498 @@
+use super::{LifterX86, Result};
+use crate::lifter::{Error, PossibleLLVMValueEnum};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    basic_block::BasicBlock,
+    values::{IntValue, PhiValue},
+    IntPredicate,
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind, MemoryInfo},
+    Instruction, InstructionAttributes, Operands, Register,
+};
+
+/// Flags written by `SCAS` and `CMPS`
+const CMP_FLAGS: [ExtendedRegisterEnum; 6] = [
+    ExtendedRegisterEnum::AF,
+    ExtendedRegisterEnum::CF,
+    ExtendedRegisterEnum::OF,
+    ExtendedRegisterEnum::PF,
+    ExtendedRegisterEnum::SF,
+    ExtendedRegisterEnum::ZF,
+];
+
+/// Registers used implicitly by string instructions, picked by the address size
+struct StringRegisters {
+    source: Register,
+    destination: Register,
+    count: Register,
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    pub(super) fn lift_movs<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let regs = string_registers(instr.address_width);
+        let src = self.string_memory_operand(instr, regs.source)?;
+        let dst = self.string_memory_operand(instr, regs.destination)?;
+
+        let touched = self.register_keys(&[regs.source, regs.destination]);
+        self.build_rep_loop(instr, &touched, || {
+            let value = self.load_string_element(src)?;
+            self.store_string_element(dst, value)?;
+
+            self.advance_string_index(instr, regs.source, src.size)?;
+            self.advance_string_index(instr, regs.destination, dst.size)
+        })
+    }
+
+    pub(super) fn lift_stos<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let regs = string_registers(instr.address_width);
+        let dst = self.string_memory_operand(instr, regs.destination)?;
+
+        let touched = self.register_keys(&[regs.destination]);
+        self.build_rep_loop(instr, &touched, || {
+            let value: IntValue<'_> = self
+                .mergen_get_register(&accumulator(dst.size), dst.size.into())?
+                .try_into()?;
+            self.store_string_element(dst, value)?;
+
+            self.advance_string_index(instr, regs.destination, dst.size)
+        })
+    }
+
+    pub(super) fn lift_lods<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let regs = string_registers(instr.address_width);
+        let src = self.string_memory_operand(instr, regs.source)?;
+
+        let touched = self.register_keys(&[regs.source, accumulator(src.size)]);
+        self.build_rep_loop(instr, &touched, || {
+            let value = self.load_string_element(src)?;
+            self.store_reg(accumulator(src.size), value)?;
+
+            self.advance_string_index(instr, regs.source, src.size)
+        })
+    }
+
+    pub(super) fn lift_scas<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let regs = string_registers(instr.address_width);
+        let dst = self.string_memory_operand(instr, regs.destination)?;
+
+        let mut touched = self.register_keys(&[regs.destination]);
+        touched.extend(CMP_FLAGS);
+        self.build_rep_loop(instr, &touched, || {
+            let l_value: IntValue<'_> = self
+                .mergen_get_register(&accumulator(dst.size), dst.size.into())?
+                .try_into()?;
+            let r_value = self.load_string_element(dst)?;
+            self.store_cmp_flags(l_value, r_value)?;
+
+            self.advance_string_index(instr, regs.destination, dst.size)
+        })
+    }
+
+    pub(super) fn lift_cmps<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let regs = string_registers(instr.address_width);
+        let src = self.string_memory_operand(instr, regs.source)?;
+        let dst = self.string_memory_operand(instr, regs.destination)?;
+
+        let mut touched = self.register_keys(&[regs.source, regs.destination]);
+        touched.extend(CMP_FLAGS);
+        self.build_rep_loop(instr, &touched, || {
+            let l_value = self.load_string_element(src)?;
+            let r_value = self.load_string_element(dst)?;
+            self.store_cmp_flags(l_value, r_value)?;
+
+            self.advance_string_index(instr, regs.source, src.size)?;
+            self.advance_string_index(instr, regs.destination, dst.size)
+        })
+    }
+
+    /// Lifts `iteration` once, or as a loop if the instruction has a REP/REPE/REPNE prefix.
+    ///
+    /// The loop is guarded by a zero count check, so the count may be symbolic. `touched` must
+    /// contain every register and flag written by `iteration`, they get merged with phi nodes.
+    fn build_rep_loop<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        touched: &[ExtendedRegisterEnum],
+        iteration: impl Fn() -> Result<()>,
+    ) -> Result<()> {
+        let attributes = instr.attributes;
+        let is_repe = attributes.contains(InstructionAttributes::HAS_REPE);
+        let is_repne = attributes.contains(InstructionAttributes::HAS_REPNE);
+
+        if !(is_repe || is_repne || attributes.contains(InstructionAttributes::HAS_REP)) {
+            return iteration();
+        }
+
+        let builder = &self.builder;
+        let count_register = string_registers(instr.address_width).count;
+        let count_width = u32::from(instr.address_width);
+        let count_ty = self.context.custom_width_int_type(count_width);
+
+        let mut touched = touched.to_vec();
+        touched.extend(self.register_keys(&[count_register]));
+
+        let count: IntValue<'_> = self
+            .mergen_get_register(&count_register, count_width)?
+            .try_into()?;
+        let is_count_zero = builder.build_int_compare(
+            IntPredicate::EQ,
+            count,
+            count_ty.const_zero(),
+            "rep_count_zero",
+        )?;
+
+        let preheader = builder
+            .get_insert_block()
+            .expect("Builder must be positioned before lifting");
+        let entry_state = self.touched_state(&touched);
+
+        let body_block = self.context.append_basic_block(self.func_value, "rep_body");
+        let exit_block = self.context.append_basic_block(self.func_value, "rep_exit");
+        builder.build_conditional_branch(is_count_zero, exit_block, body_block)?;
+
+        builder.position_at_end(body_block);
+        let body_phis = self.build_state_phis(&touched, &entry_state, preheader)?;
+
+        iteration()?;
+
+        let count: IntValue<'_> = self
+            .mergen_get_register(&count_register, count_width)?
+            .try_into()?;
+        let count = builder.build_int_sub(count, count_ty.const_int(1, false), "rep_count")?;
+        self.store_reg(count_register, count)?;
+
+        let mut should_continue = builder.build_int_compare(
+            IntPredicate::NE,
+            count,
+            count_ty.const_zero(),
+            "rep_continue",
+        )?;
+        if is_repe || is_repne {
+            let zf = self.load_flag(ExtendedRegisterEnum::ZF)?;
+            let zf_matches = if is_repe {
+                zf
+            } else {
+                builder.build_not(zf, "")?
+            };
+            should_continue = builder.build_and(should_continue, zf_matches, "rep_continue")?;
+        }
+
+        let latch = builder
+            .get_insert_block()
+            .expect("Builder must be positioned after lifting");
+        let latch_state = self.touched_state(&touched);
+        for ((key, phi), value) in touched.iter().zip(&body_phis).zip(&latch_state) {
+            let incoming =
+                self.phi_incoming_value(*key, *value, phi.as_basic_value().get_type())?;
+            phi.add_incoming(&[(&incoming, latch)]);
+        }
+        builder.build_conditional_branch(should_continue, body_block, exit_block)?;
+
+        builder.position_at_end(exit_block);
+        let exit_phis = self.build_state_phis(&touched, &entry_state, preheader)?;
+        for ((key, phi), value) in touched.iter().zip(&exit_phis).zip(&latch_state) {
+            let incoming =
+                self.phi_incoming_value(*key, *value, phi.as_basic_value().get_type())?;
+            phi.add_incoming(&[(&incoming, latch)]);
+        }
+
+        Ok(())
+    }
+
+    /// Creates a phi for each of `touched` with `state` flowing in from `block` and makes the
+    /// phis the current register values
+    fn build_state_phis(
+        &self,
+        touched: &[ExtendedRegisterEnum],
+        state: &[Option<PossibleLLVMValueEnum<'ctx>>],
+        block: BasicBlock<'ctx>,
+    ) -> Result<Vec<PhiValue<'ctx>>> {
+        let mut phis = Vec::with_capacity(touched.len());
+
+        for (key, value) in touched.iter().zip(state) {
+            let phi_ty = match value.or_else(|| self.entry_register(*key)) {
+                Some(value) => self.get_phi_type(value),
+                None => self.get_max_int_type().into(),
+            };
+
+            let phi = self.builder.build_phi(phi_ty, &format!("{key:?}_"))?;
+            let incoming = self.phi_incoming_value(*key, *value, phi_ty)?;
+            phi.add_incoming(&[(&incoming, block)]);
+
+            self.regs_hashmap_mut()
+                .insert(*key, phi.as_basic_value().try_into()?);
+            phis.push(phi);
+        }
+
+        Ok(phis)
+    }
+
+    fn touched_state(
+        &self,
+        touched: &[ExtendedRegisterEnum],
+    ) -> Vec<Option<PossibleLLVMValueEnum<'ctx>>> {
+        let regs_hashmap = self.regs_hashmap();
+        touched
+            .iter()
+            .map(|key| regs_hashmap.get(key).copied())
+            .collect()
+    }
+
+    fn register_keys(&self, registers: &[Register]) -> Vec<ExtendedRegisterEnum> {
+        registers
+            .iter()
+            .map(|reg| self.get_register_largest_enclosing(reg).into())
+            .collect()
+    }
+
+    /// Memory operand addressed by `index_register` (`[rsi]` or `[rdi]`)
+    fn string_memory_operand<'a, O: Operands>(
+        &self,
+        instr: &'a Instruction<O>,
+        index_register: Register,
+    ) -> Result<&'a DecodedOperand> {
+        instr
+            .operands()
+            .iter()
+            .find(
+                |op| matches!(&op.kind, DecodedOperandKind::Mem(mem) if mem.base == index_register),
+            )
+            .ok_or(Error::UnsupportedInstr(
+                "String instruction without memory operand",
+            ))
+    }
+
+    fn load_string_element(&self, op: &DecodedOperand) -> Result<IntValue<'ctx>> {
+        self.mergen_load_mem(string_memory_info(op), op.size.into())
+    }
+
+    fn store_string_element(&self, op: &DecodedOperand, value: IntValue<'ctx>) -> Result<()> {
+        self.mergen_store_mem(string_memory_info(op), value.into())
+    }
+
+    /// Moves `index_register` to the next element. Decrements when DF is set
+    fn advance_string_index<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        index_register: Register,
+        element_size: u16,
+    ) -> Result<()> {
+        let builder = &self.builder;
+        let width = u32::from(instr.address_width);
+        let index_ty = self.context.custom_width_int_type(width);
+        let element_bytes = u64::from(element_size / 8);
+
+        let df = self.load_flag(ExtendedRegisterEnum::DF)?;
+        let delta = builder
+            .build_select(
+                df,
+                index_ty.const_int(element_bytes.wrapping_neg(), false),
+                index_ty.const_int(element_bytes, false),
+                "",
+            )?
+            .into_int_value();
+
+        let index: IntValue<'_> = self
+            .mergen_get_register(&index_register, width)?
+            .try_into()?;
+        let updated_index = builder.build_int_add(index, delta, "")?;
+        self.store_reg(index_register, updated_index)
+    }
+}
+
+fn string_registers(address_width: u8) -> StringRegisters {
+    let [source, destination, count] = match address_width {
+        16 => [Register::SI, Register::DI, Register::CX],
+        32 => [Register::ESI, Register::EDI, Register::ECX],
+        64 => [Register::RSI, Register::RDI, Register::RCX],
+        _ => unreachable!("Unexpected address width {address_width}"),
+    };
+
+    StringRegisters {
+        source,
+        destination,
+        count,
+    }
+}
+
+fn string_memory_info(op: &DecodedOperand) -> &MemoryInfo {
+    match &op.kind {
+        DecodedOperandKind::Mem(mem) => mem,
+        _ => unreachable!("String operand must be in memory"),
+    }
+}
+
+fn accumulator(size: u16) -> Register {
+    match size {
+        8 => Register::AL,
+        16 => Register::AX,
+        32 => Register::EAX,
+        64 => Register::RAX,
+        _ => unreachable!("Unexpected string element size {size}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+    use crate::compiler::Compiler;
+    use crate::lifter::memory::CallbackMemory;
+
+    use inkwell::context::Context;
+    use zydis::{AllOperands, Decoder, MachineMode};
+
+    const SOURCE: u64 = 0x1000;
+    const DESTINATION: u64 = 0x2000;
+
+    fn string_context(rcx: u64) -> StartContextX86<u64> {
+        StartContextX86 {
+            rsi: SOURCE,
+            rdi: DESTINATION,
+            rcx,
+            rsp: 0x8000,
+            rip: 0x400000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rep_movsb_copies_a_symbolic_count() {
+        let mut memory = MemoryImage::default();
+        memory.write(SOURCE, b"hello");
+
+        // rep movsb
+        let execution = execute(&[0xf3, 0xa4], string_context(5), memory);
+
+        assert_eq!(execution.memory.read(DESTINATION, 6), b"hello\0");
+        assert_eq!(execution.context.rcx, 0);
+        assert_eq!(execution.context.rsi, SOURCE + 5);
+        assert_eq!(execution.context.rdi, DESTINATION + 5);
+    }
+
+    #[test]
+    fn rep_movsb_with_zero_count_does_nothing() {
+        let mut memory = MemoryImage::default();
+        memory.write(SOURCE, b"hello");
+
+        // rep movsb
+        let execution = execute(&[0xf3, 0xa4], string_context(0), memory);
+
+        assert_eq!(execution.memory.read(DESTINATION, 5), [0; 5]);
+        assert_eq!(execution.context.rsi, SOURCE);
+        assert_eq!(execution.context.rdi, DESTINATION);
+    }
+
+    #[test]
+    fn rep_movsb_copies_backwards_with_df() {
+        let mut memory = MemoryImage::default();
+        memory.write(SOURCE, b"hello");
+        let start = StartContextX86 {
+            rsi: SOURCE + 4,
+            rdi: DESTINATION + 4,
+            df: 1,
+            ..string_context(3)
+        };
+
+        // rep movsb
+        let execution = execute(&[0xf3, 0xa4], start, memory);
+
+        assert_eq!(execution.memory.read(DESTINATION, 5), b"\0\0llo");
+        assert_eq!(execution.context.rsi, SOURCE + 1);
+        assert_eq!(execution.context.rdi, DESTINATION + 1);
+    }
+
+    #[test]
+    fn rep_stosq_fills_backwards_with_df() {
+        let start = StartContextX86 {
+            rax: 0x1122_3344_5566_7788,
+            rdi: DESTINATION + 0x10,
+            df: 1,
+            ..string_context(3)
+        };
+
+        // rep stosq
+        let execution = execute(&[0xf3, 0x48, 0xab], start, MemoryImage::default());
+
+        for offset in [0, 8, 0x10] {
+            assert_eq!(
+                execution.memory.read_u64(DESTINATION + offset),
+                0x1122_3344_5566_7788
+            );
+        }
+        assert_eq!(execution.memory.read_u64(DESTINATION + 0x18), 0);
+        assert_eq!(execution.context.rcx, 0);
+        assert_eq!(execution.context.rdi, DESTINATION - 8);
+    }
+
+    #[test]
+    fn repe_cmpsb_stops_at_the_first_difference() {
+        let mut memory = MemoryImage::default();
+        memory.write(SOURCE, b"abcXe");
+        memory.write(DESTINATION, b"abcYe");
+
+        // repe cmpsb
+        let execution = execute(&[0xf3, 0xa6], string_context(5), memory);
+
+        assert_eq!(execution.context.zf, 0);
+        assert_eq!(execution.context.rcx, 1);
+        assert_eq!(execution.context.rsi, SOURCE + 4);
+        assert_eq!(execution.context.rdi, DESTINATION + 4);
+    }
+
+    #[test]
+    fn repne_scasb_stops_at_the_match() {
+        let mut memory = MemoryImage::default();
+        memory.write(DESTINATION, b"abcd");
+        let start = StartContextX86 {
+            rax: u64::from(b'c'),
+            ..string_context(10)
+        };
+
+        // repne scasb
+        let execution = execute(&[0xf2, 0xae], start, memory);
+
+        assert_eq!(execution.context.zf, 1);
+        assert_eq!(execution.context.rcx, 7);
+        assert_eq!(execution.context.rdi, DESTINATION + 3);
+    }
+
+    #[test]
+    fn trace_copies_of_a_rep_instruction_are_skipped() {
+        let mut memory = MemoryImage::default();
+        memory.write(SOURCE, b"abcXe");
+        memory.write(DESTINATION, b"abcYe");
+
+        // The trace of repe cmpsb running four times, then inc rax
+        let code = [
+            0xf3, 0xa6, 0xf3, 0xa6, 0xf3, 0xa6, 0xf3, 0xa6, 0x48, 0xff, 0xc0,
+        ];
+        let instructions = Decoder::new64()
+            .decode_all::<AllOperands>(&code, 0)
+            .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let context = Context::create();
+        let compiler = Compiler::new_state_function(
+            &context,
+            MachineMode::LONG_64,
+            0x400000,
+            Box::new(CallbackMemory),
+        )
+        .unwrap();
+        compiler.lift_function(&instructions, false).unwrap();
+        let execution = compiler
+            .create_jit()
+            .unwrap()
+            .run(string_context(5), memory)
+            .unwrap();
+
+        assert_eq!(execution.context.rcx, 1);
+        assert_eq!(execution.context.rsi, SOURCE + 4);
+        assert_eq!(execution.context.rax, 1);
+    }
+}
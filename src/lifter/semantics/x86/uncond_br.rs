@@ -2,34 +2,152 @@ use inkwell::values::IntValue;
 use zydis::{ffi::DecodedOperandKind, Instruction, Operands, Register};
 
 use super::Result;
+use crate::compiler::jump_table::JumpTable;
+use crate::lifter::state::JUMP_HOOK;
 use crate::lifter::LifterX86;
 
-impl LifterX86<'_> {
+use std::collections::BTreeMap;
+
+impl<'ctx> LifterX86<'ctx> {
     pub(super) fn lift_jmp<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
         let dst_op = &instr.operands()[0];
+        let jump_address = self
+            .runtime_address()
+            .map(|next_address| next_address - u64::from(instr.length));
+
+        if let DecodedOperandKind::Imm(_) = dst_op.kind {
+            // Without the runtime address IP isn't known and the trace just goes on
+            let Some(jump_address) = jump_address else {
+                return Ok(());
+            };
+            let target = instr.calc_absolute_address(jump_address, dst_op)?;
+            return self.build_jump(self.context.i64_type().const_int(target, false));
+        }
+
+        let target = self.load_single_int_op(dst_op, dst_op.size)?;
+
+        let table = jump_address.and_then(|address| self.jump_tables().get(&address));
+        match table {
+            Some(table) => self.build_jump_table_switch(table, target),
+            None => self.build_jump(target),
+        }
+    }
+
+    pub(crate) fn add_jump_table(&self, table: JumpTable) {
+        self.jump_table_loads_mut()
+            .insert(table.load_address, table.jump_address);
+        self.jump_tables_mut().insert(table.jump_address, table);
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn jump_table_loads_mut(&self) -> &mut BTreeMap<u64, u64> {
+        unsafe { &mut (*self.jump_table_loads.get()) }
+    }
+
+    fn jump_table_loads(&self) -> &BTreeMap<u64, u64> {
+        unsafe { &(*self.jump_table_loads.get()) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn jump_tables_mut(&self) -> &mut BTreeMap<u64, JumpTable> {
+        unsafe { &mut (*self.jump_tables.get()) }
+    }
+
+    fn jump_tables(&self) -> &BTreeMap<u64, JumpTable> {
+        unsafe { &(*self.jump_tables.get()) }
+    }
+
+    /// Remembers the index register before the instruction at `address` reads a jump table entry
+    pub(super) fn capture_jump_table_index(&self, address: u64) -> Result<()> {
+        let Some(table) = self
+            .jump_table_loads()
+            .get(&address)
+            .and_then(|jump_address| self.jump_tables().get(jump_address))
+        else {
+            return Ok(());
+        };
 
-        let destination: IntValue<'_> = self.load_single_op(dst_op, dst_op.size)?.try_into()?;
-        let rip_reg = self.get_register_largest_enclosing(&Register::IP);
-        let rip_val: IntValue<'_> = self.load_register_value(&rip_reg)?.try_into()?;
-        let updated_rip_val = self.builder.build_int_add(rip_val, destination, "")?;
-        self.store_reg(rip_reg, updated_rip_val)?;
-
-        //#[cfg(debug_assertions)]
-        //{
-        //    if let Some(constant) = destination.get_sign_extended_constant() {
-        //        eprintln!("JMP destination: {constant}");
-        //        if let Some(old_ip) = self.runtime_address() {
-        //            let new_ip = old_ip.wrapping_sub(constant as u64);
-        //            self.runtime_address.set(new_ip);
-        //        }
-        //    } else {
-        //        dbg!(dst_op);
-        //        dbg!(destination);
-        //        eprintln!("ERROR: JMP operand is not a constant")
-        //    }
-        //}
-
-        //panic!("{:#?}", instr.operands());
+        let index = self
+            .mergen_get_register(&table.index, table.index.width(self.mode).into())?
+            .try_into()?;
+        self.jump_table_index.set(Some(index));
         Ok(())
     }
+
+    /// Continues at `target`. A constant target gets its own block, otherwise lifting continues
+    /// in the current block with whatever comes next in the trace
+    pub(super) fn build_jump(&self, target: IntValue<'ctx>) -> Result<()> {
+        let ip_reg = self.get_register_largest_enclosing(&Register::IP);
+        let ip_ty = self
+            .context
+            .custom_width_int_type(ip_reg.width(self.mode).into());
+        self.store_reg(ip_reg, self.create_z_ext_or_trunc(target, ip_ty)?)?;
+
+        let Some(target) = target.get_zero_extended_constant() else {
+            return Ok(());
+        };
+        self.set_runtime_address(target);
+
+        // Like other traces, jumps to already lifted code keep going linearly
+        let current_block = self.builder.get_insert_block();
+        let already_lifted = self.blocks().get(&target).is_some_and(|guest_block| {
+            Some(guest_block.basic_block) == current_block
+                || guest_block.basic_block.get_terminator().is_some()
+        });
+        if already_lifted {
+            return Ok(());
+        }
+
+        self.build_guest_branch(target)?;
+        self.enter_block(target)
+    }
+
+    /// Switches on the captured table index. Indices out of the recovered range go to
+    /// [JUMP_HOOK] with the computed `target`
+    fn build_jump_table_switch(&self, table: &JumpTable, target: IntValue<'ctx>) -> Result<()> {
+        let builder = &self.builder;
+
+        let Some(index) = self.jump_table_index.take() else {
+            return self.build_jump(target);
+        };
+
+        let ip_reg = self.get_register_largest_enclosing(&Register::IP);
+        let ip_ty = self
+            .context
+            .custom_width_int_type(ip_reg.width(self.mode).into());
+        self.store_reg(ip_reg, self.create_z_ext_or_trunc(target, ip_ty)?)?;
+
+        let mut cases = Vec::with_capacity(table.targets.len());
+        for (case, &case_target) in table.targets.iter().enumerate() {
+            let block = self.get_or_create_block(case_target)?;
+            // One incoming value per edge, even when several cases share a target
+            self.add_block_incoming(case_target)?;
+            cases.push((index.get_type().const_int(case as u64, false), block));
+        }
+
+        let default_block = self
+            .context
+            .append_basic_block(self.func_value, "jump_table_default");
+        builder.build_switch(index, default_block, &cases)?;
+
+        builder.position_at_end(default_block);
+        self.build_jump_dispatch()
+    }
+
+    /// Hands the register state to [JUMP_HOOK] with IP as the target and returns the state it
+    /// leaves. Used for jumps whose targets weren't recovered
+    pub(crate) fn build_jump_dispatch(&self) -> Result<()> {
+        let ip_reg = self.get_register_largest_enclosing(&Register::IP);
+        let target: IntValue<'_> = self.load_register_value(&ip_reg)?.try_into()?;
+        let target = self.create_z_ext_or_trunc(target, self.context.i64_type())?;
+
+        let state = self.get_or_create_call_state()?;
+        self.store_state(state)?;
+        let hook = self.hook_function(JUMP_HOOK);
+        self.builder
+            .build_call(hook, &[target.into(), state.into()], "")?;
+        self.load_state(state)?;
+
+        self.build_guest_return()
+    }
 }
@@ -0,0 +1,544 @@
+use super::{LifterX86, Result};
+use crate::lifter::flagops::ConditionCode;
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    values::{FloatValue, IntValue},
+    FloatPredicate, IntPredicate,
+};
+use zydis::{
+    ffi::{DecodedOperand, DecodedOperandKind},
+    Instruction, Mnemonic, Operands, Register,
+};
+
+/// Stack registers as seen by instructions, `ST(i)` is `i` slots below the top. Pushes and pops
+/// move the values between them, while the TOP field of the status word tracks the physical top
+const STACK: [ExtendedRegisterEnum; 8] = [
+    ExtendedRegisterEnum::ST0,
+    ExtendedRegisterEnum::ST1,
+    ExtendedRegisterEnum::ST2,
+    ExtendedRegisterEnum::ST3,
+    ExtendedRegisterEnum::ST4,
+    ExtendedRegisterEnum::ST5,
+    ExtendedRegisterEnum::ST6,
+    ExtendedRegisterEnum::ST7,
+];
+
+/// Control word after FNINIT: all exceptions masked, extended precision, round to nearest
+const DEFAULT_CONTROL_WORD: u64 = 0x037F;
+/// Tag word with all registers empty
+const EMPTY_TAG_WORD: u64 = 0xFFFF;
+
+const TOP_SHIFT: u64 = 11;
+const TOP_MASK: u64 = 7 << TOP_SHIFT;
+const ROUNDING_CONTROL_SHIFT: u64 = 10;
+
+const C0: u64 = 1 << 8;
+const C1: u64 = 1 << 9;
+const C2: u64 = 1 << 10;
+const C3: u64 = 1 << 14;
+
+impl<'ctx> LifterX86<'ctx> {
+    /// FLD. Loading `ST(i)` pushes a copy of it
+    pub(super) fn lift_fld<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let value = self.load_x87_operand(&instr.operands()[0], false)?;
+        self.x87_push(value)
+    }
+
+    /// FILD
+    pub(super) fn lift_fild<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let value = self.load_x87_operand(&instr.operands()[0], true)?;
+        self.x87_push(value)
+    }
+
+    /// FLDZ, FLD1, FLDPI, FLDL2E, FLDL2T, FLDLG2 and FLDLN2
+    pub(super) fn lift_fld_constant<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let digits = match instr.mnemonic {
+            Mnemonic::FLDZ => "0",
+            Mnemonic::FLD1 => "1",
+            Mnemonic::FLDPI => "3.14159265358979323846264338327950288",
+            Mnemonic::FLDL2E => "1.44269504088896340735992468100189214",
+            Mnemonic::FLDL2T => "3.32192809488736234787031942948939018",
+            Mnemonic::FLDLG2 => "0.301029995663981195213738894724493027",
+            Mnemonic::FLDLN2 => "0.693147180559945309417232121458176568",
+            _ => unreachable!(),
+        };
+
+        // Parsed by LLVM, so the constants keep all 64 bits of the x87 significand. The strings
+        // above are valid decimal numbers
+        let value = unsafe { self.context.x86_f80_type().const_float_from_string(digits) };
+        self.x87_push(value)
+    }
+
+    /// FST and FSTP. Memory destinations get the value rounded to their precision
+    pub(super) fn lift_fst<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let value = self.load_st(0)?;
+        self.store_x87_operand(&instr.operands()[0], value)?;
+
+        if instr.mnemonic == Mnemonic::FSTP {
+            self.x87_pop()?;
+        }
+        Ok(())
+    }
+
+    /// FIST, FISTP and FISTTP. FISTTP always truncates, the others round as set in the control
+    /// word
+    pub(super) fn lift_fist<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let dest = &instr.operands()[0];
+
+        let value = self.load_st(0)?;
+        let rounded = match instr.mnemonic {
+            Mnemonic::FISTTP => self.build_float_intrinsic("llvm.trunc", value)?,
+            _ => self.build_x87_round(value)?,
+        };
+
+        let int_ty = self.context.custom_width_int_type(dest.size.into());
+        let int_value = self.build_float_to_int(rounded, int_ty)?;
+        self.store_op(dest, int_value)?;
+
+        if instr.mnemonic != Mnemonic::FIST {
+            self.x87_pop()?;
+        }
+        Ok(())
+    }
+
+    /// FADD, FSUB, FSUBR, FMUL, FDIV, FDIVR, their popping `*P` forms and `FI*` forms taking an
+    /// integer from memory. Forms with one explicit operand work on ST0
+    pub(super) fn lift_x87_arith<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let ops = instr.operands();
+
+        let is_integer = matches!(
+            instr.mnemonic,
+            Mnemonic::FIADD
+                | Mnemonic::FISUB
+                | Mnemonic::FISUBR
+                | Mnemonic::FIMUL
+                | Mnemonic::FIDIV
+                | Mnemonic::FIDIVR
+        );
+        let (dest, src) = if instr.operand_count_visible == 2 {
+            (&ops[0], &ops[1])
+        } else {
+            // Hidden ST0 follows the memory operand
+            (&ops[1], &ops[0])
+        };
+
+        let lhs = self.load_x87_operand(dest, false)?;
+        let rhs = self.load_x87_operand(src, is_integer)?;
+
+        let value = match instr.mnemonic {
+            Mnemonic::FADD | Mnemonic::FADDP | Mnemonic::FIADD => {
+                builder.build_float_add(lhs, rhs, "")?
+            }
+            Mnemonic::FSUB | Mnemonic::FSUBP | Mnemonic::FISUB => {
+                builder.build_float_sub(lhs, rhs, "")?
+            }
+            Mnemonic::FSUBR | Mnemonic::FSUBRP | Mnemonic::FISUBR => {
+                builder.build_float_sub(rhs, lhs, "")?
+            }
+            Mnemonic::FMUL | Mnemonic::FMULP | Mnemonic::FIMUL => {
+                builder.build_float_mul(lhs, rhs, "")?
+            }
+            Mnemonic::FDIV | Mnemonic::FDIVP | Mnemonic::FIDIV => {
+                builder.build_float_div(lhs, rhs, "")?
+            }
+            Mnemonic::FDIVR | Mnemonic::FDIVRP | Mnemonic::FIDIVR => {
+                builder.build_float_div(rhs, lhs, "")?
+            }
+            _ => unreachable!(),
+        };
+        self.store_x87_operand(dest, value)?;
+
+        if matches!(
+            instr.mnemonic,
+            Mnemonic::FADDP
+                | Mnemonic::FSUBP
+                | Mnemonic::FSUBRP
+                | Mnemonic::FMULP
+                | Mnemonic::FDIVP
+                | Mnemonic::FDIVRP
+        ) {
+            self.x87_pop()?;
+        }
+        Ok(())
+    }
+
+    /// FCHS, FABS, FSQRT and FRNDINT
+    pub(super) fn lift_x87_unary<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let value = self.load_st(0)?;
+
+        let result = match instr.mnemonic {
+            Mnemonic::FCHS => self.builder.build_float_neg(value, "")?,
+            Mnemonic::FABS => self.build_float_intrinsic("llvm.fabs", value)?,
+            Mnemonic::FSQRT => self.build_float_intrinsic("llvm.sqrt", value)?,
+            Mnemonic::FRNDINT => self.build_x87_round(value)?,
+            _ => unreachable!(),
+        };
+
+        self.store_st(0, result);
+        Ok(())
+    }
+
+    /// FXCH
+    pub(super) fn lift_fxch<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let DecodedOperandKind::Reg(reg) = instr.operands()[0].kind else {
+            unreachable!("FXCH always exchanges with a stack register")
+        };
+        let index = stack_index(reg);
+
+        let top = self.load_st(0)?;
+        let other = self.load_st(index)?;
+        self.store_st(0, other);
+        self.store_st(index, top);
+        Ok(())
+    }
+
+    /// FCOMI, FCOMIP, FUCOMI and FUCOMIP. Set EFLAGS like UCOMISD
+    pub(super) fn lift_fcomi<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let ops = instr.operands();
+
+        let lhs = self.load_x87_operand(&ops[0], false)?;
+        let rhs = self.load_x87_operand(&ops[1], false)?;
+        self.store_float_compare_flags(lhs, rhs)?;
+
+        if matches!(instr.mnemonic, Mnemonic::FCOMIP | Mnemonic::FUCOMIP) {
+            self.x87_pop()?;
+        }
+        Ok(())
+    }
+
+    /// FCOM, FUCOM, FICOM, their popping forms and FTST. ST0 is compared with the operand (ST1 if
+    /// there is none, zero for FTST) and the result goes to C0, C2 and C3 of the status word
+    pub(super) fn lift_fcom<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let builder = &self.builder;
+        let i16_ty = self.context.i16_type();
+
+        let lhs = self.load_st(0)?;
+        let rhs = match instr.mnemonic {
+            Mnemonic::FTST => lhs.get_type().const_zero(),
+            _ if instr.operand_count_visible == 0 => self.load_st(1)?,
+            _ => {
+                let is_integer = matches!(instr.mnemonic, Mnemonic::FICOM | Mnemonic::FICOMP);
+                self.load_x87_operand(&instr.operands()[0], is_integer)?
+            }
+        };
+
+        let conditions = [
+            (C0, FloatPredicate::ULT),
+            (C2, FloatPredicate::UNO),
+            (C3, FloatPredicate::UEQ),
+        ];
+        let status = self.load_x87_word(ExtendedRegisterEnum::X87STATUS)?;
+        let mut status =
+            builder.build_and(status, i16_ty.const_int(!(C0 | C1 | C2 | C3), false), "")?;
+        for (bit, predicate) in conditions {
+            let holds = builder.build_float_compare(predicate, lhs, rhs, "")?;
+            let holds = builder.build_int_z_extend(holds, i16_ty, "")?;
+            let bit = builder.build_int_mul(holds, i16_ty.const_int(bit, false), "")?;
+            status = builder.build_or(status, bit, "")?;
+        }
+        self.store_x87_word(ExtendedRegisterEnum::X87STATUS, status);
+
+        let pops = match instr.mnemonic {
+            Mnemonic::FCOMP | Mnemonic::FUCOMP | Mnemonic::FICOMP => 1,
+            Mnemonic::FCOMPP | Mnemonic::FUCOMPP => 2,
+            _ => 0,
+        };
+        for _ in 0..pops {
+            self.x87_pop()?;
+        }
+        Ok(())
+    }
+
+    /// FCMOVcc. Copies `ST(i)` to ST0 when the condition holds
+    pub(super) fn lift_fcmovcc<O: Operands>(
+        &self,
+        instr: &Instruction<O>,
+        condition_code: ConditionCode,
+    ) -> Result<()> {
+        let ops = instr.operands();
+
+        let condition = self.compute_condition(condition_code)?;
+        let lhs = self.load_x87_operand(&ops[0], false)?;
+        let rhs = self.load_x87_operand(&ops[1], false)?;
+
+        let value = self
+            .builder
+            .build_select(condition, rhs, lhs, "")?
+            .into_float_value();
+        self.store_x87_operand(&ops[0], value)
+    }
+
+    /// FNSTSW. Stores the status word to AX or memory
+    pub(super) fn lift_fnstsw<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let status = self.load_x87_word(ExtendedRegisterEnum::X87STATUS)?;
+        self.store_op(&instr.operands()[0], status)
+    }
+
+    /// FNSTCW
+    pub(super) fn lift_fnstcw<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let control = self.load_x87_word(ExtendedRegisterEnum::X87CONTROL)?;
+        self.store_op(&instr.operands()[0], control)
+    }
+
+    /// FLDCW. The rounding control bits are honored by FIST, FISTP and FRNDINT
+    pub(super) fn lift_fldcw<O: Operands>(&self, instr: &Instruction<O>) -> Result<()> {
+        let src = &instr.operands()[0];
+        let control = self.load_single_int_op(src, src.size)?;
+        self.store_x87_word(ExtendedRegisterEnum::X87CONTROL, control);
+        Ok(())
+    }
+
+    /// FNCLEX. Clears exception flags and the busy bit
+    pub(super) fn lift_fnclex(&self) -> Result<()> {
+        let status = self.load_x87_word(ExtendedRegisterEnum::X87STATUS)?;
+        let status =
+            self.builder
+                .build_and(status, self.context.i16_type().const_int(0x7F00, false), "")?;
+        self.store_x87_word(ExtendedRegisterEnum::X87STATUS, status);
+        Ok(())
+    }
+
+    /// FNINIT. Values of the stack registers stay, but all of them are marked as empty
+    pub(super) fn lift_fninit(&self) -> Result<()> {
+        self.reset_x87_words();
+        Ok(())
+    }
+
+    fn reset_x87_words(&self) {
+        let i16_ty = self.context.i16_type();
+
+        self.store_x87_word(
+            ExtendedRegisterEnum::X87CONTROL,
+            i16_ty.const_int(DEFAULT_CONTROL_WORD, false),
+        );
+        self.store_x87_word(ExtendedRegisterEnum::X87STATUS, i16_ty.const_zero());
+        self.store_x87_word(
+            ExtendedRegisterEnum::X87TAG,
+            i16_ty.const_int(EMPTY_TAG_WORD, false),
+        );
+    }
+
+    fn x87_push(&self, value: FloatValue<'ctx>) -> Result<()> {
+        for index in (1..STACK.len()).rev() {
+            let below = self.load_st(index - 1)?;
+            self.store_st(index, below);
+        }
+        self.store_st(0, value);
+
+        self.move_x87_top(true)
+    }
+
+    /// The popped value wraps around to ST7, like the physical register it's kept in
+    fn x87_pop(&self) -> Result<()> {
+        let top = self.load_st(0)?;
+        for index in 0..STACK.len() - 1 {
+            let above = self.load_st(index + 1)?;
+            self.store_st(index, above);
+        }
+        self.store_st(STACK.len() - 1, top);
+
+        self.move_x87_top(false)
+    }
+
+    /// Updates TOP in the status word and the tag of the physical register which was pushed to
+    /// (marked valid) or popped from (marked empty)
+    fn move_x87_top(&self, push: bool) -> Result<()> {
+        let builder = &self.builder;
+        let i16_ty = self.context.i16_type();
+        let const_i16 = |value: u64| i16_ty.const_int(value, false);
+
+        let status = self.load_x87_word(ExtendedRegisterEnum::X87STATUS)?;
+        let top = builder.build_right_shift(status, const_i16(TOP_SHIFT), false, "")?;
+        let top = builder.build_and(top, const_i16(7), "")?;
+
+        // Decrementing modulo 8 is adding 7
+        let step = if push { 7 } else { 1 };
+        let new_top = builder.build_int_add(top, const_i16(step), "")?;
+        let new_top = builder.build_and(new_top, const_i16(7), "")?;
+
+        let status = builder.build_and(status, const_i16(!TOP_MASK), "")?;
+        let shifted_top = builder.build_left_shift(new_top, const_i16(TOP_SHIFT), "")?;
+        let status = builder.build_or(status, shifted_top, "")?;
+        self.store_x87_word(ExtendedRegisterEnum::X87STATUS, status);
+
+        let slot = if push { new_top } else { top };
+        let tag_shift = builder.build_int_mul(slot, const_i16(2), "")?;
+        let tag_bits = builder.build_left_shift(const_i16(3), tag_shift, "")?;
+
+        let tag = self.load_x87_word(ExtendedRegisterEnum::X87TAG)?;
+        let tag = if push {
+            let valid = builder.build_not(tag_bits, "")?;
+            builder.build_and(tag, valid, "")?
+        } else {
+            builder.build_or(tag, tag_bits, "")?
+        };
+        self.store_x87_word(ExtendedRegisterEnum::X87TAG, tag);
+        Ok(())
+    }
+
+    /// Rounds to an integral value in the mode selected by the rounding control of the control
+    /// word
+    fn build_x87_round(&self, value: FloatValue<'ctx>) -> Result<FloatValue<'ctx>> {
+        let builder = &self.builder;
+        let i16_ty = self.context.i16_type();
+
+        let control = self.load_x87_word(ExtendedRegisterEnum::X87CONTROL)?;
+        let rounding_control = builder.build_right_shift(
+            control,
+            i16_ty.const_int(ROUNDING_CONTROL_SHIFT, false),
+            false,
+            "",
+        )?;
+        let rounding_control =
+            builder.build_and(rounding_control, i16_ty.const_int(3, false), "")?;
+
+        // Mode 0 rounds to nearest even
+        let mut result = self.build_float_intrinsic("llvm.rint", value)?;
+        for (mode, intrinsic_name) in [(1, "llvm.floor"), (2, "llvm.ceil"), (3, "llvm.trunc")] {
+            let rounded = self.build_float_intrinsic(intrinsic_name, value)?;
+            let selected = builder.build_int_compare(
+                IntPredicate::EQ,
+                rounding_control,
+                i16_ty.const_int(mode, false),
+                "",
+            )?;
+            result = builder
+                .build_select(selected, rounded, result, "")?
+                .into_float_value();
+        }
+
+        Ok(result)
+    }
+
+    /// Stack register, or a float (`is_integer` selects signed integer) in memory converted to
+    /// extended precision
+    fn load_x87_operand(
+        &self,
+        operand: &DecodedOperand,
+        is_integer: bool,
+    ) -> Result<FloatValue<'ctx>> {
+        let builder = &self.builder;
+        let f80_ty = self.context.x86_f80_type();
+
+        match operand.kind {
+            DecodedOperandKind::Reg(reg) => self.load_st(stack_index(reg)),
+            DecodedOperandKind::Mem(ref memory_info) => {
+                let bits = self.mergen_load_mem(memory_info, operand.size.into())?;
+                if is_integer {
+                    return Ok(builder.build_signed_int_to_float(bits, f80_ty, "")?);
+                }
+
+                let float_ty = match operand.size {
+                    32 => self.context.f32_type(),
+                    64 => self.context.f64_type(),
+                    _ => f80_ty,
+                };
+                let value = builder
+                    .build_bit_cast(bits, float_ty, "")?
+                    .into_float_value();
+                if float_ty == f80_ty {
+                    return Ok(value);
+                }
+                Ok(builder.build_float_ext(value, f80_ty, "")?)
+            }
+            _ => unreachable!("x87 operands are stack registers or memory"),
+        }
+    }
+
+    /// Stores to a stack register, or rounds to the precision of the memory operand
+    fn store_x87_operand(&self, operand: &DecodedOperand, value: FloatValue<'ctx>) -> Result<()> {
+        let builder = &self.builder;
+
+        match operand.kind {
+            DecodedOperandKind::Reg(reg) => {
+                self.store_st(stack_index(reg), value);
+                Ok(())
+            }
+            DecodedOperandKind::Mem(_) => {
+                let value = match operand.size {
+                    32 => builder.build_float_trunc(value, self.context.f32_type(), "")?,
+                    64 => builder.build_float_trunc(value, self.context.f64_type(), "")?,
+                    _ => value,
+                };
+                self.store_op(operand, value)
+            }
+            _ => unreachable!("x87 operands are stack registers or memory"),
+        }
+    }
+
+    fn load_st(&self, index: usize) -> Result<FloatValue<'ctx>> {
+        self.regs_hashmap()[&STACK[index]].try_into()
+    }
+
+    fn store_st(&self, index: usize, value: FloatValue<'ctx>) {
+        self.regs_hashmap_mut().insert(STACK[index], value.into());
+    }
+
+    /// Control, status or tag word. Block merges may widen them, so they are truncated back
+    fn load_x87_word(&self, reg: ExtendedRegisterEnum) -> Result<IntValue<'ctx>> {
+        let value: IntValue<'_> = self.regs_hashmap()[&reg].try_into()?;
+        self.create_z_ext_or_trunc(value, self.context.i16_type())
+    }
+
+    fn store_x87_word(&self, reg: ExtendedRegisterEnum, value: IntValue<'ctx>) {
+        self.regs_hashmap_mut().insert(reg, value.into());
+    }
+}
+
+fn stack_index(reg: Register) -> usize {
+    reg.id() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::execute;
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+
+    const ONE: u128 = 0x3FFF_8000_0000_0000_0000;
+    const ONE_AND_A_HALF: u128 = 0x3FFF_C000_0000_0000_0000;
+    const TWO_AND_A_HALF: u128 = 0x4000_A000_0000_0000_0000;
+    const FOUR: u128 = 0x4001_8000_0000_0000_0000;
+
+    #[test]
+    fn stack_registers_round_trip() {
+        let mut start: StartContextX86<u64> = StartContextX86 {
+            fpu_control: 0x37F,
+            fpu_tag: 0xFFF0,
+            ..Default::default()
+        };
+        start.st[0] = ONE_AND_A_HALF;
+        start.st[1] = TWO_AND_A_HALF;
+        start.st[7] = FOUR;
+
+        // fadd st0, st1
+        let execution = execute(&[0xd8, 0xc1], start, MemoryImage::default());
+
+        assert_eq!(execution.context.st[0], FOUR);
+        assert_eq!(execution.context.st[1], TWO_AND_A_HALF);
+        assert_eq!(execution.context.st[7], FOUR);
+        assert_eq!(execution.context.fpu_control, 0x37F);
+        assert_eq!(execution.context.fpu_tag, 0xFFF0);
+    }
+
+    #[test]
+    fn push_updates_the_returned_status_and_tag_words() {
+        let mut start: StartContextX86<u64> = StartContextX86 {
+            fpu_control: 0x37F,
+            fpu_tag: 0xFFFF,
+            ..Default::default()
+        };
+        start.st[0] = FOUR;
+
+        // fld1
+        let execution = execute(&[0xd9, 0xe8], start, MemoryImage::default());
+
+        assert_eq!(execution.context.st[0], ONE);
+        assert_eq!(execution.context.st[1], FOUR);
+        // TOP moves from 0 to 7, which is marked valid
+        assert_eq!(execution.context.fpu_status, 0x3800);
+        assert_eq!(execution.context.fpu_tag, 0x3FFF);
+    }
+}
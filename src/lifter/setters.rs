@@ -12,12 +12,18 @@ impl<'ctx> LifterX86<'ctx> {
     where
         PossibleLLVMValueEnum<'ctx>: From<T>,
     {
-        let val = PossibleLLVMValueEnum::from(value);
+        // Floats are stored as their bits. Note that a scalar written to a vector register replaces
+        // the whole register, merging it into a lane is up to the caller
+        let val = match PossibleLLVMValueEnum::from(value) {
+            PossibleLLVMValueEnum::IntValue(int_value) => int_value,
+            PossibleLLVMValueEnum::FloatValue(float_value) => self.float_as_int(float_value)?,
+        };
 
         match &op.kind {
-            // NOTE: When adding float support, must revisit this first
-            DecodedOperandKind::Reg(reg) => self.store_reg(*reg, val.try_into()?)?,
-            DecodedOperandKind::Mem(memory_info) => self.mergen_store_mem(memory_info, val)?,
+            DecodedOperandKind::Reg(reg) => self.store_reg(*reg, val)?,
+            DecodedOperandKind::Mem(memory_info) => {
+                self.mergen_store_mem(memory_info, val.into())?
+            }
             _ => unreachable!("Tried to set value to operand with kind {:?}", op.kind),
         };
 
@@ -115,14 +121,14 @@ impl<'ctx> LifterX86<'ctx> {
         value: IntValue<'ctx>,
     ) -> Result<IntValue<'ctx>> {
         let builder = &self.builder;
-        let ctx = self.context;
 
-        let full_reg_key = reg.largest_enclosing(self.mode);
+        let full_reg_key = self.get_register_largest_enclosing(&reg);
         let mut full_reg_value = self.load_register_value(&full_reg_key)?.try_into()?;
         //full_reg_value = self.create_z_ext_or_trunc(full_reg_value, ctx.i64_type())?;
         full_reg_value = self.create_z_ext_or_trunc(full_reg_value, self.get_max_int_type())?;
+        let full_reg_ty = full_reg_value.get_type();
 
-        let mut extended_value = builder.build_int_z_extend(value, ctx.i64_type(), "")?;
+        let mut extended_value = builder.build_int_z_extend(value, full_reg_ty, "")?;
 
         let is_high_byte_reg =
             [Register::AH, Register::CH, Register::DH, Register::BH].contains(&reg);
@@ -133,7 +139,7 @@ impl<'ctx> LifterX86<'ctx> {
             0xFFFFFFFFFFFFFF00
         };
 
-        let mask_value = ctx.i64_type().const_int(mask, false);
+        let mask_value = full_reg_ty.const_int(mask, false);
         let masked_full_reg = builder.build_and(full_reg_value, mask_value, "maskedreg_")?;
 
         if is_high_byte_reg {
@@ -158,7 +164,7 @@ impl<'ctx> LifterX86<'ctx> {
     ) -> Result<IntValue<'ctx>> {
         let builder = &self.builder;
 
-        let full_reg_key = reg.largest_enclosing(self.mode);
+        let full_reg_key = self.get_register_largest_enclosing(&reg);
         let full_reg_value: IntValue<'_> = self.load_register_value(&full_reg_key)?.try_into()?;
         let last_4_cleared = full_reg_value
             .get_type()
@@ -0,0 +1,313 @@
+//! Symbolic stack depth relative to the stack pointer the lifted function was entered with.
+//!
+//! The stack pointer is never tracked separately: its current LLVM value is walked back through
+//! additions and subtractions of constants and through the phis of guest blocks until the entry
+//! value is reached. Offsets are cached per value, so every value is only walked once until a phi
+//! gets another incoming edge
+use super::{largest_enclosing_register, LifterX86, PossibleLLVMValueEnum};
+
+use std::collections::{HashMap, HashSet};
+
+use inkwell::values::{InstructionOpcode, InstructionValue, IntValue};
+use zydis::Register;
+
+/// Offset of a value from the entry stack pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StackOffset {
+    Known(i64),
+    Unknown,
+    /// Reached a phi which is already being walked, so it doesn't constrain the offset
+    Cycle,
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    /// Remembers the current stack pointer as the one the function was entered with
+    pub(crate) fn record_entry_stack_pointer(&self) {
+        let stack_pointer = largest_enclosing_register(Register::SP, self.mode);
+        let entry = match self.regs_hashmap().get(&stack_pointer.into()) {
+            Some(PossibleLLVMValueEnum::IntValue(value)) => Some(*value),
+            _ => None,
+        };
+        self.entry_stack_pointer.set(entry);
+        self.forget_stack_offsets();
+    }
+
+    /// Drops the cached offsets. They are outdated once a phi they were derived from gets another
+    /// incoming value
+    pub(super) fn forget_stack_offsets(&self) {
+        self.stack_offsets_mut().clear();
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn stack_offsets_mut(&self) -> &mut HashMap<IntValue<'ctx>, StackOffset> {
+        unsafe { &mut (*self.stack_offsets.get()) }
+    }
+
+    /// Offset of the current stack pointer from its entry value, if it can be proven constant.
+    /// Zero means the stack pointer points at the return address pushed by the caller
+    pub(crate) fn stack_depth(&self) -> Option<i64> {
+        let stack_pointer = largest_enclosing_register(Register::SP, self.mode);
+        let value = match self.regs_hashmap().get(&stack_pointer.into()) {
+            Some(PossibleLLVMValueEnum::IntValue(value)) => *value,
+            _ => return None,
+        };
+
+        match self.stack_offset(value, &mut HashSet::new()) {
+            StackOffset::Known(offset) => Some(offset),
+            StackOffset::Unknown | StackOffset::Cycle => None,
+        }
+    }
+
+    fn stack_offset(
+        &self,
+        value: IntValue<'ctx>,
+        visiting: &mut HashSet<InstructionValue<'ctx>>,
+    ) -> StackOffset {
+        if let Some(offset) = self.stack_offsets_mut().get(&value) {
+            return *offset;
+        }
+
+        let offset = self.walk_stack_offset(value, visiting);
+        // Inside of a phi the offset may only be known up to the cycle through it
+        if visiting.is_empty() {
+            self.stack_offsets_mut().insert(value, offset);
+        }
+        offset
+    }
+
+    fn walk_stack_offset(
+        &self,
+        value: IntValue<'ctx>,
+        visiting: &mut HashSet<InstructionValue<'ctx>>,
+    ) -> StackOffset {
+        let Some(entry) = self.entry_stack_pointer.get() else {
+            return StackOffset::Unknown;
+        };
+
+        if value == entry {
+            return StackOffset::Known(0);
+        }
+        if let (Some(value), Some(entry)) = (
+            value.get_sign_extended_constant(),
+            entry.get_sign_extended_constant(),
+        ) {
+            return StackOffset::Known(value.wrapping_sub(entry));
+        }
+
+        let Some(instruction) = value.as_instruction() else {
+            return StackOffset::Unknown;
+        };
+        let operand = |index| int_operand(instruction, index);
+
+        match instruction.get_opcode() {
+            InstructionOpcode::Add => {
+                let (Some(lhs), Some(rhs)) = (operand(0), operand(1)) else {
+                    return StackOffset::Unknown;
+                };
+                match (
+                    lhs.get_sign_extended_constant(),
+                    rhs.get_sign_extended_constant(),
+                ) {
+                    (_, Some(constant)) => self.shifted_offset(lhs, constant, visiting),
+                    (Some(constant), _) => self.shifted_offset(rhs, constant, visiting),
+                    _ => StackOffset::Unknown,
+                }
+            }
+            InstructionOpcode::Sub => {
+                let (Some(lhs), Some(rhs)) = (operand(0), operand(1)) else {
+                    return StackOffset::Unknown;
+                };
+                match rhs.get_sign_extended_constant() {
+                    Some(constant) => self.shifted_offset(lhs, constant.wrapping_neg(), visiting),
+                    None => StackOffset::Unknown,
+                }
+            }
+            // 16 bit stack pointer updates are merged into the lower half of ESP as
+            // `or (and esp, !0xffff), (zext sp)`, see `set_val_to_sub_reg_16b`
+            InstructionOpcode::Trunc | InstructionOpcode::ZExt => match operand(0) {
+                Some(value) => self.stack_offset(value, visiting),
+                None => StackOffset::Unknown,
+            },
+            InstructionOpcode::Or => {
+                let (Some(lhs), Some(rhs)) = (operand(0), operand(1)) else {
+                    return StackOffset::Unknown;
+                };
+                let (kept, low) = if is_zero_extended_16_bits(rhs) {
+                    (lhs, rhs)
+                } else {
+                    (rhs, lhs)
+                };
+                let kept_base_offset = upper_bits_base(kept)
+                    .map(|base| self.stack_offset(base, visiting))
+                    .unwrap_or(StackOffset::Unknown);
+                match (kept_base_offset, is_zero_extended_16_bits(low)) {
+                    (StackOffset::Known(_) | StackOffset::Cycle, true) => {
+                        self.stack_offset(low, visiting)
+                    }
+                    _ => StackOffset::Unknown,
+                }
+            }
+            // Every incoming value has to agree. Phis only see the edges lifted so far, image
+            // functions are lifted in reverse postorder, so only back edges are missing
+            InstructionOpcode::Phi => {
+                if !visiting.insert(instruction) {
+                    return StackOffset::Cycle;
+                }
+
+                let mut result = StackOffset::Cycle;
+                for index in 0..instruction.get_num_operands() {
+                    let offset = match operand(index) {
+                        Some(incoming) => self.stack_offset(incoming, visiting),
+                        None => StackOffset::Unknown,
+                    };
+                    result = match (result, offset) {
+                        (StackOffset::Unknown, _) | (_, StackOffset::Unknown) => {
+                            StackOffset::Unknown
+                        }
+                        (StackOffset::Cycle, offset) | (offset, StackOffset::Cycle) => offset,
+                        (StackOffset::Known(a), StackOffset::Known(b)) if a == b => {
+                            StackOffset::Known(a)
+                        }
+                        (StackOffset::Known(_), StackOffset::Known(_)) => StackOffset::Unknown,
+                    };
+                }
+
+                visiting.remove(&instruction);
+                result
+            }
+            _ => StackOffset::Unknown,
+        }
+    }
+
+    fn shifted_offset(
+        &self,
+        value: IntValue<'ctx>,
+        constant: i64,
+        visiting: &mut HashSet<InstructionValue<'ctx>>,
+    ) -> StackOffset {
+        match self.stack_offset(value, visiting) {
+            StackOffset::Known(offset) => StackOffset::Known(offset.wrapping_add(constant)),
+            other => other,
+        }
+    }
+}
+
+fn int_operand<'ctx>(instruction: InstructionValue<'ctx>, index: u32) -> Option<IntValue<'ctx>> {
+    instruction
+        .get_operand(index)
+        .and_then(|operand| operand.left())
+        .filter(|operand| operand.is_int_value())
+        .map(|operand| operand.into_int_value())
+}
+
+/// `base` of `and base, !0xffff`, which keeps the bits of a register above its lower 16 bits
+fn upper_bits_base(value: IntValue<'_>) -> Option<IntValue<'_>> {
+    let instruction = value.as_instruction()?;
+    if instruction.get_opcode() != InstructionOpcode::And {
+        return None;
+    }
+
+    let mask = int_operand(instruction, 1)?.get_sign_extended_constant()?;
+    (mask == !0xffff).then(|| int_operand(instruction, 0))?
+}
+
+/// Whether `value` is a zero extended 16 bit value
+fn is_zero_extended_16_bits(value: IntValue<'_>) -> bool {
+    value
+        .as_instruction()
+        .filter(|instruction| instruction.get_opcode() == InstructionOpcode::ZExt)
+        .and_then(|instruction| int_operand(instruction, 0))
+        .is_some_and(|operand| operand.get_type().get_bit_width() == 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::{Execution, MemoryImage};
+    use crate::compiler::Compiler;
+    use crate::lifter::memory::CallbackMemory;
+
+    use inkwell::context::Context;
+    use zydis::MachineMode;
+
+    const CODE_ADDRESS: u64 = 0x1000;
+    const RETURN_ADDRESS: u32 = 0x5000;
+
+    /// Lifts the 32 bit function `code` and runs it with the return address on the stack. RETs
+    /// used as jumps set EIP to the popped address, returns leave it unchanged
+    fn run(code: &[u8]) -> Execution<u32> {
+        let context = Context::create();
+        let compiler = Compiler::new_state_function(
+            &context,
+            MachineMode::LEGACY_32,
+            CODE_ADDRESS,
+            Box::new(CallbackMemory),
+        )
+        .unwrap();
+        compiler
+            .lift_function_at(code, CODE_ADDRESS, CODE_ADDRESS, false)
+            .unwrap();
+
+        let start = StartContextX86 {
+            rcx: 3,
+            rsp: 0x8000,
+            rip: CODE_ADDRESS as u32,
+            ..Default::default()
+        };
+        let mut memory = MemoryImage::default();
+        memory.write(0x8000, &RETURN_ADDRESS.to_le_bytes());
+        let execution = compiler.create_jit().unwrap().run(start, memory).unwrap();
+        execution
+    }
+
+    #[test]
+    fn ret_after_push_and_pop_returns() {
+        // push ebx; pop ebx; ret
+        let execution = run(&[0x53, 0x5b, 0xc3]);
+
+        assert_eq!(execution.context.rip, CODE_ADDRESS as u32);
+        assert_eq!(execution.context.rsp, 0x8004);
+    }
+
+    #[test]
+    fn push_immediate_and_ret_jumps() {
+        // push 0x1234; ret
+        let execution = run(&[0x68, 0x34, 0x12, 0x00, 0x00, 0xc3]);
+
+        assert_eq!(execution.context.rip, 0x1234);
+        assert_eq!(execution.context.rsp, 0x8000);
+    }
+
+    #[test]
+    fn ret_after_16_bit_stack_pointer_updates_returns() {
+        // sub sp, 4; add sp, 4; ret
+        let execution = run(&[0x66, 0x83, 0xec, 0x04, 0x66, 0x83, 0xc4, 0x04, 0xc3]);
+
+        assert_eq!(execution.context.rip, CODE_ADDRESS as u32);
+    }
+
+    #[test]
+    fn other_or_into_the_stack_pointer_makes_the_depth_unknown() {
+        // and eax, 0x10; or esp, eax; ret
+        let execution = run(&[0x83, 0xe0, 0x10, 0x09, 0xc4, 0xc3]);
+
+        assert_eq!(execution.context.rip, RETURN_ADDRESS);
+    }
+
+    #[test]
+    fn ret_lifted_after_the_loop_before_it_returns() {
+        // 0x1000: push ebx
+        // 0x1001: jmp 0x1005
+        // 0x1003: pop ebx
+        // 0x1004: ret
+        // 0x1005: dec ecx
+        // 0x1007: jnz 0x1005
+        // 0x1009: jmp 0x1003
+        let execution = run(&[
+            0x53, 0xeb, 0x02, 0x5b, 0xc3, 0xff, 0xc9, 0x75, 0xfc, 0xeb, 0xf8,
+        ]);
+
+        assert_eq!(execution.context.rip, CODE_ADDRESS as u32);
+        assert_eq!(execution.context.rcx, 0);
+    }
+}
@@ -0,0 +1,200 @@
+//! Register state passed between lifted functions.
+//!
+//! Functions lifted with the state ABI have the type `void (ptr %state)`. The state is a struct
+//! with one field per register, flag, vector register part and x87 register, in the same order as
+//! the parameters of the default lifted function
+use super::{largest_enclosing_register, LifterX86, Result};
+use crate::compiler::{
+    cpu_flag_type, ALL_REGS_IN_MIN_SIZE, CPU_FLAGS, MASK_REGS, SEGMENT_BASES, SEGMENT_REGS,
+    VECTOR_HIGH_REGS, VECTOR_REGS, X87_REGS, X87_WORDS, ZMM_HIGH_REGS,
+};
+use crate::miscellaneous::ExtendedRegisterEnum;
+
+use inkwell::{
+    context::Context,
+    types::{BasicTypeEnum, StructType},
+    values::{FunctionValue, PointerValue},
+    AddressSpace,
+};
+use zydis::MachineMode;
+
+/// Hook called for targets which aren't known while lifting:
+///
+/// ```llvm
+/// declare void @__bin_lift_call(i64 %target, ptr %state)
+/// ```
+pub const CALL_HOOK: &str = "__bin_lift_call";
+
+/// Dispatcher for jumps whose target couldn't be resolved. It runs the code at `target` with the
+/// state, the jumping function returns the state afterwards:
+///
+/// ```llvm
+/// declare void @__bin_lift_jump(i64 %target, ptr %state)
+/// ```
+pub const JUMP_HOOK: &str = "__bin_lift_jump";
+
+/// How CALL instructions are lifted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CallPolicy {
+    /// Only push the return address. Lifting continues with the next instruction, which is the
+    /// callee itself in traces
+    #[default]
+    Inline,
+    /// Call the lifted function of a direct target or [CALL_HOOK] for the others, passing the
+    /// register state. The return address is pushed before the call, as the callee pops it
+    Call,
+}
+
+/// Name of the state ABI function lifted from `address`
+pub fn lifted_function_name(address: u64) -> String {
+    format!("sub_{address:x}")
+}
+
+/// Registers in the order of the state fields, with their types
+pub(crate) fn state_layout(
+    context: &Context,
+    mode: MachineMode,
+) -> Vec<(ExtendedRegisterEnum, BasicTypeEnum<'_>)> {
+    let gpr_ty = context.custom_width_int_type(
+        largest_enclosing_register(zydis::Register::AX, mode)
+            .width(mode)
+            .into(),
+    );
+
+    let gprs = ALL_REGS_IN_MIN_SIZE
+        .map(|reg| (largest_enclosing_register(reg, mode).into(), gpr_ty.into()));
+    let flags = CPU_FLAGS.map(|flag| (flag, cpu_flag_type(context, flag).into()));
+    let vectors = VECTOR_REGS
+        .into_iter()
+        .chain(VECTOR_HIGH_REGS)
+        .map(|reg| (reg.into(), context.i128_type().into()));
+    let zmm_highs =
+        ZMM_HIGH_REGS.map(|reg| (reg.into(), context.custom_width_int_type(256).into()));
+    let masks = MASK_REGS.map(|reg| (reg.into(), context.i64_type().into()));
+    let segment_bases = SEGMENT_BASES.map(|base| (base, gpr_ty.into()));
+    let segments = SEGMENT_REGS.map(|reg| (reg.into(), context.i16_type().into()));
+    let x87 = X87_REGS.map(|reg| (reg.into(), context.x86_f80_type().into()));
+    let x87_words = X87_WORDS.map(|word| (word, context.i16_type().into()));
+
+    gprs.into_iter()
+        .chain(flags)
+        .chain(vectors)
+        .chain(zmm_highs)
+        .chain(masks)
+        .chain(segment_bases)
+        .chain(segments)
+        .chain(x87)
+        .chain(x87_words)
+        .collect()
+}
+
+/// State pointer of a function lifted with the state ABI
+pub(crate) fn state_parameter(func_value: FunctionValue<'_>) -> Option<PointerValue<'_>> {
+    if func_value.count_params() != 1 {
+        return None;
+    }
+
+    func_value
+        .get_first_param()
+        .filter(|param| param.is_pointer_value())
+        .map(|param| param.into_pointer_value())
+}
+
+impl<'ctx> LifterX86<'ctx> {
+    pub(crate) fn state_type(&self) -> StructType<'ctx> {
+        let field_types: Vec<_> = state_layout(self.context, self.mode)
+            .into_iter()
+            .map(|(_, ty)| ty)
+            .collect();
+        self.context.struct_type(&field_types, false)
+    }
+
+    /// Writes every register to `state`. Registers without a value on this path are written with
+    /// the value they had at the function entry
+    pub(crate) fn store_state(&self, state: PointerValue<'ctx>) -> Result<()> {
+        let state_ty = self.state_type();
+
+        for (index, (reg, ty)) in state_layout(self.context, self.mode)
+            .into_iter()
+            .enumerate()
+        {
+            let value = self.regs_hashmap().get(&reg).copied();
+            let value = self.phi_incoming_value(reg, value, ty)?;
+
+            let field = self
+                .builder
+                .build_struct_gep(state_ty, state, index as u32, "")?;
+            self.builder.build_store(field, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces every register with its value in `state`
+    pub(crate) fn load_state(&self, state: PointerValue<'ctx>) -> Result<()> {
+        let state_ty = self.state_type();
+
+        for (index, (reg, ty)) in state_layout(self.context, self.mode)
+            .into_iter()
+            .enumerate()
+        {
+            let field = self
+                .builder
+                .build_struct_gep(state_ty, state, index as u32, "")?;
+            let value = self.builder.build_load(ty, field, &format!("{reg:?}_"))?;
+
+            self.regs_hashmap_mut().insert(reg, value.try_into()?);
+        }
+
+        Ok(())
+    }
+
+    /// State passed to called functions, allocated once at the start of the lifted function
+    pub(crate) fn get_or_create_call_state(&self) -> Result<PointerValue<'ctx>> {
+        if let Some(state) = self.call_state.get() {
+            return Ok(*state);
+        }
+
+        let builder = &self.builder;
+        let current_block = builder
+            .get_insert_block()
+            .expect("Builder must be positioned before lifting");
+
+        let entry_block = self
+            .func_value
+            .get_first_basic_block()
+            .expect("Lifted function always has an entry block");
+        match entry_block.get_first_instruction() {
+            Some(first_instruction) => builder.position_before(&first_instruction),
+            None => builder.position_at_end(entry_block),
+        }
+        let state = builder.build_alloca(self.state_type(), "call_state")?;
+        builder.position_at_end(current_block);
+
+        self.call_state
+            .set(state)
+            .expect("Call state is allocated only once");
+        Ok(state)
+    }
+
+    /// Declaration of [CALL_HOOK] or [JUMP_HOOK]
+    pub(crate) fn hook_function(&self, name: &str) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| {
+            let ptr_ty = self.context.ptr_type(AddressSpace::default());
+            let fn_ty = self
+                .context
+                .void_type()
+                .fn_type(&[self.context.i64_type().into(), ptr_ty.into()], false);
+            self.module.add_function(name, fn_ty, None)
+        })
+    }
+
+    /// Declaration of a function taking the register state
+    pub(crate) fn state_function(&self, name: &str) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| {
+            let ptr_ty = self.context.ptr_type(AddressSpace::default());
+            let fn_ty = self.context.void_type().fn_type(&[ptr_ty.into()], false);
+            self.module.add_function(name, fn_ty, None)
+        })
+    }
+}
@@ -0,0 +1,266 @@
+//! Layout of the Windows thread and process environment blocks, so PEB walks read like the
+//! structures they access.
+//!
+//! Loads of known fields are named after them. The TEB is the segment FS in 32 bit code and GS in
+//! 64 bit code, fields of the other blocks are recognised through the named pointers leading to
+//! them:
+//!
+//! ```llvm
+//! %TEB.ProcessEnvironmentBlock = load i64, ptr %0
+//! %PEB.Ldr = load i64, ptr %2
+//! %PEB_LDR_DATA.InMemoryOrderModuleList = load i64, ptr %4
+//! ```
+use super::{LifterX86, PossibleLLVMValueEnum};
+
+use zydis::{ffi::MemoryInfo, MachineMode, Register};
+
+struct Field {
+    offset: u64,
+    name: &'static str,
+    /// Layout of the structure the field points to
+    points_to: Option<&'static str>,
+}
+
+struct Layout {
+    name: &'static str,
+    fields: &'static [Field],
+}
+
+const fn field(offset: u64, name: &'static str) -> Field {
+    Field {
+        offset,
+        name,
+        points_to: None,
+    }
+}
+
+const fn pointer(offset: u64, name: &'static str, points_to: &'static str) -> Field {
+    Field {
+        offset,
+        name,
+        points_to: Some(points_to),
+    }
+}
+
+const LAYOUTS_64: &[Layout] = &[
+    Layout {
+        name: "TEB",
+        fields: &[
+            field(0x00, "ExceptionList"),
+            field(0x08, "StackBase"),
+            field(0x10, "StackLimit"),
+            pointer(0x30, "Self", "TEB"),
+            field(0x40, "UniqueProcess"),
+            field(0x48, "UniqueThread"),
+            field(0x58, "ThreadLocalStoragePointer"),
+            pointer(0x60, "ProcessEnvironmentBlock", "PEB"),
+            field(0x68, "LastErrorValue"),
+        ],
+    },
+    Layout {
+        name: "PEB",
+        fields: &[
+            field(0x02, "BeingDebugged"),
+            field(0x10, "ImageBaseAddress"),
+            pointer(0x18, "Ldr", "PEB_LDR_DATA"),
+            field(0x20, "ProcessParameters"),
+            field(0x30, "ProcessHeap"),
+            field(0xBC, "NtGlobalFlag"),
+        ],
+    },
+    Layout {
+        name: "PEB_LDR_DATA",
+        fields: &[
+            field(0x10, "InLoadOrderModuleList"),
+            field(0x20, "InMemoryOrderModuleList"),
+            field(0x30, "InInitializationOrderModuleList"),
+        ],
+    },
+];
+
+const LAYOUTS_32: &[Layout] = &[
+    Layout {
+        name: "TEB",
+        fields: &[
+            field(0x00, "ExceptionList"),
+            field(0x04, "StackBase"),
+            field(0x08, "StackLimit"),
+            pointer(0x18, "Self", "TEB"),
+            field(0x20, "UniqueProcess"),
+            field(0x24, "UniqueThread"),
+            field(0x2C, "ThreadLocalStoragePointer"),
+            pointer(0x30, "ProcessEnvironmentBlock", "PEB"),
+            field(0x34, "LastErrorValue"),
+        ],
+    },
+    Layout {
+        name: "PEB",
+        fields: &[
+            field(0x02, "BeingDebugged"),
+            field(0x08, "ImageBaseAddress"),
+            pointer(0x0C, "Ldr", "PEB_LDR_DATA"),
+            field(0x10, "ProcessParameters"),
+            field(0x18, "ProcessHeap"),
+            field(0x68, "NtGlobalFlag"),
+        ],
+    },
+    Layout {
+        name: "PEB_LDR_DATA",
+        fields: &[
+            field(0x0C, "InLoadOrderModuleList"),
+            field(0x14, "InMemoryOrderModuleList"),
+            field(0x1C, "InInitializationOrderModuleList"),
+        ],
+    },
+];
+
+impl<'ctx> LifterX86<'ctx> {
+    /// Name of the environment block field accessed through `mem`, if it's a known one
+    pub(super) fn environment_block_field(&self, mem: &MemoryInfo) -> Option<String> {
+        let (layouts, teb_segment) = match self.mode {
+            MachineMode::LONG_64 => (LAYOUTS_64, Register::GS),
+            MachineMode::LONG_COMPAT_32 | MachineMode::LEGACY_32 => (LAYOUTS_32, Register::FS),
+            _ => return None,
+        };
+        if mem.index != Register::NONE {
+            return None;
+        }
+
+        let layout_name = if mem.segment == teb_segment && mem.base == Register::NONE {
+            "TEB"
+        } else if mem.base != Register::NONE {
+            self.pointed_layout(layouts, mem.base)?
+        } else {
+            return None;
+        };
+
+        let layout = layouts.iter().find(|layout| layout.name == layout_name)?;
+        let offset = mem.disp.displacement as u64;
+        let field = layout.fields.iter().find(|field| field.offset == offset)?;
+        Some(format!("{}.{}", layout.name, field.name))
+    }
+
+    /// Layout `register` points to, when it holds a named pointer field
+    fn pointed_layout(&self, layouts: &[Layout], register: Register) -> Option<&'static str> {
+        let PossibleLLVMValueEnum::IntValue(value) = self.get_register(register).ok()? else {
+            return None;
+        };
+        let name = value.get_name().to_str().ok()?;
+        // LLVM makes repeated names unique with a numeric suffix
+        let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        let (layout_name, field_name) = name.split_once('.')?;
+
+        let layout = layouts.iter().find(|layout| layout.name == layout_name)?;
+        let field = layout
+            .fields
+            .iter()
+            .find(|field| field.name == field_name)?;
+        field.points_to
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::contexts::StartContextX86;
+    use crate::compiler::jit::MemoryImage;
+    use crate::compiler::Compiler;
+    use crate::lifter::memory::CallbackMemory;
+
+    use inkwell::context::Context;
+    use zydis::{AllOperands, Decoder, FullInstruction, MachineMode};
+
+    const CODE_ADDRESS: u64 = 0x401000;
+
+    fn decode(mode: MachineMode, code: &[u8]) -> Vec<FullInstruction> {
+        let decoder = Decoder::new(mode, crate::compiler::cfg::stack_width_for_mode(mode)).unwrap();
+        decoder
+            .decode_all::<AllOperands>(code, CODE_ADDRESS)
+            .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    /// Names of the values in the function lifted from `code`
+    fn value_names(mode: MachineMode, code: &[u8]) -> Vec<String> {
+        let context = Context::create();
+        let compiler = Compiler::new_with_x86_lifter(&context, mode, Some(CODE_ADDRESS)).unwrap();
+        compiler.lift_function(&decode(mode, code), false).unwrap();
+
+        let function = compiler.lifter.module.get_function("protected").unwrap();
+        function
+            .get_basic_block_iter()
+            .flat_map(|block| block.get_instructions())
+            .filter_map(|instruction| {
+                let name = instruction.get_name()?;
+                Some(name.to_string_lossy().into_owned())
+            })
+            .collect()
+    }
+
+    fn contains_in_order(names: &[String], expected: &[&str]) -> bool {
+        let mut names = names.iter();
+        expected
+            .iter()
+            .all(|expected| names.any(|name| name == expected))
+    }
+
+    const PEB_WALK: [&str; 3] = [
+        "TEB.ProcessEnvironmentBlock",
+        "PEB.Ldr",
+        "PEB_LDR_DATA.InMemoryOrderModuleList",
+    ];
+
+    #[test]
+    fn peb_walk_is_named_in_64_bit_code() {
+        // mov rax, gs:[0x60]; mov rax, [rax+0x18]; mov rax, [rax+0x20]
+        let code = [
+            0x65, 0x48, 0x8b, 0x04, 0x25, 0x60, 0x00, 0x00, 0x00, 0x48, 0x8b, 0x40, 0x18, 0x48,
+            0x8b, 0x40, 0x20,
+        ];
+
+        let names = value_names(MachineMode::LONG_64, &code);
+
+        assert!(contains_in_order(&names, &PEB_WALK), "{names:?}");
+    }
+
+    #[test]
+    fn peb_walk_is_named_in_32_bit_code() {
+        // mov eax, fs:[0x30]; mov eax, [eax+0xc]; mov eax, [eax+0x14]
+        let code = [
+            0x64, 0xa1, 0x30, 0x00, 0x00, 0x00, 0x8b, 0x40, 0x0c, 0x8b, 0x40, 0x14,
+        ];
+
+        let names = value_names(MachineMode::LEGACY_32, &code);
+
+        assert!(contains_in_order(&names, &PEB_WALK), "{names:?}");
+    }
+
+    #[test]
+    fn teb_is_read_at_gs_base() {
+        let context = Context::create();
+        let start: StartContextX86<u64> = StartContextX86 {
+            gs_base: 0x7ff0_0000,
+            rip: CODE_ADDRESS,
+            ..Default::default()
+        };
+        let mut memory = MemoryImage::default();
+        memory.write_u64(0x7ff0_0060, 0x1234_5678);
+        memory.write_u64(0x60, 0xdead);
+
+        // mov rax, gs:[0x60]
+        let code = [0x65, 0x48, 0x8b, 0x04, 0x25, 0x60, 0x00, 0x00, 0x00];
+        let compiler = Compiler::new_state_function(
+            &context,
+            MachineMode::LONG_64,
+            CODE_ADDRESS,
+            Box::new(CallbackMemory),
+        )
+        .unwrap();
+        compiler
+            .lift_function(&decode(MachineMode::LONG_64, &code), false)
+            .unwrap();
+        let execution = compiler.create_jit().unwrap().run(start, memory).unwrap();
+
+        assert_eq!(execution.context.rax, 0x1234_5678);
+    }
+}
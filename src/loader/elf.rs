@@ -0,0 +1,557 @@
+//! ELF32 and ELF64 loader
+use std::collections::BTreeMap;
+
+use zydis::MachineMode;
+
+use super::{
+    map_section, read_array, read_c_str, read_u16, read_u32, read_u64, table_entry_offset, Error,
+    ImageFormat, Import, LoadedImage, Relocation, Result, Section,
+};
+
+const ELF_MAGIC: [u8; 4] = *b"\x7FELF";
+
+const CLASS_32: u8 = 1;
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+
+const MACHINE_386: u16 = 3;
+const MACHINE_X86_64: u16 = 62;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+const SHT_SYMTAB: u32 = 2;
+const SHT_RELA: u32 = 4;
+const SHT_REL: u32 = 9;
+const SHT_DYNSYM: u32 = 11;
+
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+// Same numbers are used by both i386 and x86-64
+const R_GLOB_DAT: u64 = 6;
+const R_JUMP_SLOT: u64 = 7;
+const R_RELATIVE: u64 = 8;
+
+const PLT_ENTRY_SIZE: usize = 16;
+const PLT_SECTIONS: [&str; 3] = [".plt", ".plt.sec", ".plt.got"];
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Entry of the section header table
+#[derive(Debug, Clone)]
+struct ElfSection {
+    name: String,
+    kind: u32,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+}
+
+#[derive(Debug, Clone)]
+struct ElfSymbol {
+    name: String,
+    value: u64,
+    info: u8,
+    section_index: u16,
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<LoadedImage> {
+    if read_array::<4>(bytes, 0)? != ELF_MAGIC {
+        return Err(Error::UnknownFormat);
+    }
+
+    let [class, data_encoding] = read_array(bytes, 4)?;
+    if data_encoding != DATA_LITTLE_ENDIAN {
+        return Err(Error::Malformed("ELF data encoding"));
+    }
+    let is_64 = match class {
+        CLASS_32 => false,
+        CLASS_64 => true,
+        _ => return Err(Error::Malformed("ELF class")),
+    };
+
+    let machine = read_u16(bytes, 18)?;
+    let mode = match (machine, is_64) {
+        (MACHINE_386, false) => MachineMode::LEGACY_32,
+        (MACHINE_X86_64, true) => MachineMode::LONG_64,
+        _ => return Err(Error::UnsupportedMachine(machine.into())),
+    };
+
+    let (entry_point, program_headers_offset, section_headers_offset) = if is_64 {
+        (
+            read_u64(bytes, 24)?,
+            read_u64(bytes, 32)?,
+            read_u64(bytes, 40)?,
+        )
+    } else {
+        (
+            read_u32(bytes, 24)?.into(),
+            read_u32(bytes, 28)?.into(),
+            read_u32(bytes, 32)?.into(),
+        )
+    };
+
+    let table_info_offset = if is_64 { 54 } else { 42 };
+    let program_header_size = u64::from(read_u16(bytes, table_info_offset)?);
+    let program_header_count = u64::from(read_u16(bytes, table_info_offset + 2)?);
+    let section_header_size = u64::from(read_u16(bytes, table_info_offset + 4)?);
+    let section_header_count = u64::from(read_u16(bytes, table_info_offset + 6)?);
+    let section_names_index = u64::from(read_u16(bytes, table_info_offset + 8)?);
+
+    let mut segments = vec![];
+    let mut mapped_size = 0;
+    for index in 0..program_header_count {
+        let header_offset =
+            table_entry_offset(bytes, program_headers_offset, index, program_header_size)?;
+        if let Some(segment) = read_segment(
+            bytes,
+            header_offset,
+            is_64,
+            segments.len(),
+            &mut mapped_size,
+        )? {
+            segments.push(segment);
+        }
+    }
+
+    let image_base = segments
+        .iter()
+        .map(|segment| segment.virtual_address)
+        .min()
+        .ok_or(Error::Malformed("ELF without loadable segments"))?
+        & !(PAGE_SIZE - 1);
+
+    let mut image = LoadedImage {
+        format: ImageFormat::Elf,
+        mode,
+        image_base,
+        entry_point,
+        sections: segments,
+        imports: vec![],
+        import_stubs: BTreeMap::new(),
+        relocations: vec![],
+        symbols: BTreeMap::new(),
+    };
+
+    // Section headers are optional, without them only the segments are known
+    if section_headers_offset == 0 {
+        return Ok(image);
+    }
+
+    let sections = read_section_headers(
+        bytes,
+        section_headers_offset,
+        section_header_size,
+        section_header_count,
+        section_names_index,
+        is_64,
+    )?;
+
+    for section in &sections {
+        match section.kind {
+            SHT_SYMTAB | SHT_DYNSYM => {
+                for symbol in read_symbols(bytes, section, &sections, is_64)? {
+                    if symbol.info & 0xF == STT_FUNC
+                        && symbol.section_index != SHN_UNDEF
+                        && !symbol.name.is_empty()
+                    {
+                        image.symbols.insert(symbol.name, symbol.value);
+                    }
+                }
+            }
+            SHT_RELA | SHT_REL => read_relocations(bytes, section, &sections, is_64, &mut image)?,
+            _ => {}
+        }
+    }
+
+    image.import_stubs = find_import_stubs(bytes, &sections, is_64, &image);
+
+    Ok(image)
+}
+
+fn read_segment(
+    bytes: &[u8],
+    header_offset: u64,
+    is_64: bool,
+    index: usize,
+    mapped_size: &mut u64,
+) -> Result<Option<Section>> {
+    if read_u32(bytes, header_offset)? != PT_LOAD {
+        return Ok(None);
+    }
+
+    let (flags, file_offset, virtual_address, file_size, memory_size) = if is_64 {
+        (
+            read_u32(bytes, header_offset + 4)?,
+            read_u64(bytes, header_offset + 8)?,
+            read_u64(bytes, header_offset + 16)?,
+            read_u64(bytes, header_offset + 32)?,
+            read_u64(bytes, header_offset + 40)?,
+        )
+    } else {
+        (
+            read_u32(bytes, header_offset + 24)?,
+            read_u32(bytes, header_offset + 4)?.into(),
+            read_u32(bytes, header_offset + 8)?.into(),
+            read_u32(bytes, header_offset + 16)?.into(),
+            read_u32(bytes, header_offset + 20)?.into(),
+        )
+    };
+
+    let data = map_section(bytes, file_offset, file_size, memory_size, mapped_size)?;
+
+    let segment = Section {
+        name: format!("segment_{index}"),
+        virtual_address,
+        data,
+        executable: flags & PF_X != 0,
+        writable: flags & PF_W != 0,
+    };
+    Ok(Some(segment))
+}
+
+fn read_section_headers(
+    bytes: &[u8],
+    table_offset: u64,
+    header_size: u64,
+    count: u64,
+    names_index: u64,
+    is_64: bool,
+) -> Result<Vec<ElfSection>> {
+    let mut name_offsets = vec![];
+    let mut sections = vec![];
+
+    for index in 0..count {
+        let offset = table_entry_offset(bytes, table_offset, index, header_size)?;
+        name_offsets.push(read_u32(bytes, offset)?);
+
+        let section = if is_64 {
+            ElfSection {
+                name: String::new(),
+                kind: read_u32(bytes, offset + 4)?,
+                address: read_u64(bytes, offset + 16)?,
+                offset: read_u64(bytes, offset + 24)?,
+                size: read_u64(bytes, offset + 32)?,
+                link: read_u32(bytes, offset + 40)?,
+            }
+        } else {
+            ElfSection {
+                name: String::new(),
+                kind: read_u32(bytes, offset + 4)?,
+                address: read_u32(bytes, offset + 12)?.into(),
+                offset: read_u32(bytes, offset + 16)?.into(),
+                size: read_u32(bytes, offset + 20)?.into(),
+                link: read_u32(bytes, offset + 24)?,
+            }
+        };
+        sections.push(section);
+    }
+
+    let names_offset = sections
+        .get(names_index as usize)
+        .ok_or(Error::Malformed("section name table index"))?
+        .offset;
+    for (section, name_offset) in sections.iter_mut().zip(name_offsets) {
+        section.name = read_c_str(bytes, string_offset(names_offset, name_offset)?)?;
+    }
+
+    Ok(sections)
+}
+
+/// File offset of the string at `name_offset` in the string table at `table_offset`
+fn string_offset(table_offset: u64, name_offset: u32) -> Result<u64> {
+    table_offset
+        .checked_add(name_offset.into())
+        .ok_or(Error::Malformed("string table offset"))
+}
+
+fn read_symbols(
+    bytes: &[u8],
+    table: &ElfSection,
+    sections: &[ElfSection],
+    is_64: bool,
+) -> Result<Vec<ElfSymbol>> {
+    let names_offset = sections
+        .get(table.link as usize)
+        .ok_or(Error::Malformed("symbol name table index"))?
+        .offset;
+    let entry_size = if is_64 { 24 } else { 16 };
+
+    (0..table.size / entry_size)
+        .map(|index| {
+            let offset = table_entry_offset(bytes, table.offset, index, entry_size)?;
+            let (info, section_index, value) = if is_64 {
+                (
+                    read_array::<1>(bytes, offset + 4)?[0],
+                    read_u16(bytes, offset + 6)?,
+                    read_u64(bytes, offset + 8)?,
+                )
+            } else {
+                (
+                    read_array::<1>(bytes, offset + 12)?[0],
+                    read_u16(bytes, offset + 14)?,
+                    read_u32(bytes, offset + 4)?.into(),
+                )
+            };
+            let name_offset = read_u32(bytes, offset)?;
+
+            Ok(ElfSymbol {
+                name: read_c_str(bytes, string_offset(names_offset, name_offset)?)?,
+                value,
+                info,
+                section_index,
+            })
+        })
+        .collect()
+}
+
+/// Collects imports from jump slots and GOT entries of undefined symbols. Relative relocations
+/// are applied, so the mapped image looks like loaded at `image_base`
+fn read_relocations(
+    bytes: &[u8],
+    table: &ElfSection,
+    sections: &[ElfSection],
+    is_64: bool,
+    image: &mut LoadedImage,
+) -> Result<()> {
+    let has_addend = table.kind == SHT_RELA;
+    // Relocation sections which aren't linked to a symbol table only contain relative entries
+    let symbols = match sections.get(table.link as usize) {
+        Some(symbol_table) if [SHT_SYMTAB, SHT_DYNSYM].contains(&symbol_table.kind) => {
+            read_symbols(bytes, symbol_table, sections, is_64)?
+        }
+        _ => vec![],
+    };
+
+    let word_size = if is_64 { 8 } else { 4 };
+    let entry_size = word_size * if has_addend { 3 } else { 2 };
+
+    for index in 0..table.size / entry_size {
+        let offset = table_entry_offset(bytes, table.offset, index, entry_size)?;
+        let read_word = |offset: u64| -> Result<u64> {
+            if is_64 {
+                read_u64(bytes, offset)
+            } else {
+                read_u32(bytes, offset).map(u64::from)
+            }
+        };
+
+        let address = read_word(offset)?;
+        let info = read_word(offset + word_size)?;
+        let (kind, symbol_index) = if is_64 {
+            (info & 0xFFFF_FFFF, info >> 32)
+        } else {
+            (info & 0xFF, info >> 8)
+        };
+
+        match kind {
+            R_JUMP_SLOT | R_GLOB_DAT => {
+                let Some(symbol) = symbols.get(symbol_index as usize) else {
+                    continue;
+                };
+                if symbol.section_index != SHN_UNDEF || symbol.name.is_empty() {
+                    continue;
+                }
+
+                image.imports.push(Import {
+                    library: None,
+                    name: symbol.name.clone(),
+                    address,
+                });
+            }
+            R_RELATIVE => {
+                if has_addend {
+                    let addend = read_word(offset + word_size * 2)?;
+                    write_word(image, address, addend, word_size as usize);
+                }
+
+                image.relocations.push(Relocation {
+                    address,
+                    size: word_size as u8,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn write_word(image: &mut LoadedImage, address: u64, value: u64, size: usize) {
+    let Some(section) = image
+        .sections
+        .iter_mut()
+        .find(|section| section.contains(address))
+    else {
+        return;
+    };
+
+    let offset = (address - section.virtual_address) as usize;
+    if let Some(slot) = section.data.get_mut(offset..offset + size) {
+        slot.copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+}
+
+/// Maps PLT entries to the import slots they jump through
+fn find_import_stubs(
+    bytes: &[u8],
+    sections: &[ElfSection],
+    is_64: bool,
+    image: &LoadedImage,
+) -> BTreeMap<u64, u64> {
+    // `jmp [ebx + disp]` in position independent 32 bit code is relative to `.got.plt`
+    let got_address = sections
+        .iter()
+        .find(|section| section.name == ".got.plt")
+        .map(|section| section.address);
+
+    let mut stubs = BTreeMap::new();
+    for section in sections
+        .iter()
+        .filter(|section| PLT_SECTIONS.contains(&section.name.as_str()))
+    {
+        let start = section.offset as usize;
+        let Some(plt) = bytes.get(start..start + section.size as usize) else {
+            continue;
+        };
+
+        for (index, entry) in plt.chunks(PLT_ENTRY_SIZE).enumerate() {
+            let entry_address = section.address + (index * PLT_ENTRY_SIZE) as u64;
+            let Some(slot) = plt_entry_slot(entry, entry_address, is_64, got_address) else {
+                continue;
+            };
+
+            if image.import_at(slot).is_some() {
+                stubs.insert(entry_address, slot);
+            }
+        }
+    }
+
+    stubs
+}
+
+/// Finds the indirect `jmp` of a PLT entry (it may be preceded by `endbr` or `bnd`) and returns
+/// address of the slot it reads
+fn plt_entry_slot(
+    entry: &[u8],
+    entry_address: u64,
+    is_64: bool,
+    got_address: Option<u64>,
+) -> Option<u64> {
+    (0..entry.len().saturating_sub(5)).find_map(|position| {
+        if entry[position] != 0xFF {
+            return None;
+        }
+
+        let displacement = i32::from_le_bytes(entry[position + 2..position + 6].try_into().ok()?);
+        let displacement = i64::from(displacement) as u64;
+        match (entry[position + 1], is_64) {
+            // jmp [rip + disp]
+            (0x25, true) => Some(
+                entry_address
+                    .wrapping_add(position as u64 + 6)
+                    .wrapping_add(displacement),
+            ),
+            // jmp [disp]
+            (0x25, false) => Some(displacement & 0xFFFF_FFFF),
+            // jmp [ebx + disp]
+            (0xA3, false) => Some(got_address?.wrapping_add(displacement) & 0xFFFF_FFFF),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zydis::MachineMode;
+
+    use super::super::{Error, ImageFormat, LoadedImage};
+
+    /// Minimal ELF64 with a single executable segment at 0x400000 containing `mov eax, 1; ret`
+    fn minimal_elf64() -> Vec<u8> {
+        let mut bytes = vec![0; 0x80];
+        bytes[..4].copy_from_slice(b"\x7FELF");
+        bytes[4] = 2;
+        bytes[5] = 1;
+        bytes[6] = 1;
+        bytes[0x10..0x12].copy_from_slice(&2u16.to_le_bytes());
+        bytes[0x12..0x14].copy_from_slice(&62u16.to_le_bytes());
+        bytes[0x18..0x20].copy_from_slice(&0x400078u64.to_le_bytes());
+        bytes[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        bytes[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        bytes[0x38..0x3A].copy_from_slice(&1u16.to_le_bytes());
+        // Program header
+        bytes[0x40..0x44].copy_from_slice(&1u32.to_le_bytes());
+        bytes[0x44..0x48].copy_from_slice(&5u32.to_le_bytes());
+        bytes[0x50..0x58].copy_from_slice(&0x400000u64.to_le_bytes());
+        bytes[0x60..0x68].copy_from_slice(&0x80u64.to_le_bytes());
+        bytes[0x68..0x70].copy_from_slice(&0x80u64.to_le_bytes());
+        // Code
+        bytes[0x78..0x7E].copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+
+        bytes
+    }
+
+    #[test]
+    fn load_minimal_elf64() {
+        let image = LoadedImage::load(&minimal_elf64()).unwrap();
+
+        assert_eq!(image.format, ImageFormat::Elf);
+        assert_eq!(image.mode, MachineMode::LONG_64);
+        assert_eq!(image.image_base, 0x400000);
+        assert!(image.sections[0].executable);
+        assert!(!image.sections[0].writable);
+        assert_eq!(
+            image.read_bytes(image.entry_point, 6),
+            Some(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3][..])
+        );
+    }
+
+    #[test]
+    fn huge_segment_is_rejected() {
+        let mut bytes = minimal_elf64();
+        bytes[0x68..0x70].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::SectionTooLarge(u64::MAX))
+        ));
+    }
+
+    #[test]
+    fn many_segments_are_rejected_in_total() {
+        let mut bytes = minimal_elf64();
+        let segment_count = 5;
+        bytes.resize(0x40 + segment_count * 0x38, 0);
+        bytes[0x38..0x3A].copy_from_slice(&(segment_count as u16).to_le_bytes());
+        for index in 0..segment_count {
+            // Each segment is just below the size limit for sections and has no file data
+            let header = 0x40 + index * 0x38;
+            bytes[header..header + 0x38].fill(0);
+            bytes[header..header + 4].copy_from_slice(&1u32.to_le_bytes());
+            bytes[header + 4..header + 8].copy_from_slice(&6u32.to_le_bytes());
+            let virtual_address = 0x400000 + index as u64 * 0x1000_0000;
+            bytes[header + 0x10..header + 0x18].copy_from_slice(&virtual_address.to_le_bytes());
+            bytes[header + 0x28..header + 0x30].copy_from_slice(&0x0FFF_F000u64.to_le_bytes());
+        }
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::ImageTooLarge(0x4FFF_B000))
+        ));
+    }
+
+    #[test]
+    fn header_table_overflow_is_rejected() {
+        let mut bytes = minimal_elf64();
+        bytes[0x20..0x28].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+pub(crate) type Result<T> = core::result::Result<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unknown file format")]
+    UnknownFormat,
+
+    #[error("File is truncated, can't read {size} bytes at offset {offset:#x}")]
+    Truncated { offset: u64, size: u64 },
+
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+
+    #[error("Mapping {0:#x} bytes exceeds the size limit for sections")]
+    SectionTooLarge(u64),
+
+    #[error("Mapping {0:#x} bytes in total exceeds the size limit for images")]
+    ImageTooLarge(u64),
+
+    #[error("Unsupported machine type {0:#x}")]
+    UnsupportedMachine(u32),
+}
@@ -0,0 +1,280 @@
+//! Loaders for executable files. They map sections at their virtual addresses, so the lifter can
+//! decode code and resolve constant memory reads straight from the image
+use std::collections::BTreeMap;
+
+use zydis::MachineMode;
+
+mod elf;
+mod pe;
+
+pub(super) mod error;
+pub use error::Error;
+pub(crate) use error::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Pe,
+    Elf,
+}
+
+/// Section mapped at its virtual address
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub virtual_address: u64,
+    /// Contents as mapped in memory, zero filled past the data stored in the file
+    pub data: Vec<u8>,
+    pub executable: bool,
+    pub writable: bool,
+}
+
+impl Section {
+    pub fn contains(&self, address: u64) -> bool {
+        address
+            .checked_sub(self.virtual_address)
+            .is_some_and(|offset| offset < self.data.len() as u64)
+    }
+}
+
+/// Function imported from another module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub library: Option<String>,
+    /// Imports by ordinal are named `#<ordinal>`
+    pub name: String,
+    /// Address of the slot the loader writes the resolved address to (IAT entry on Windows)
+    pub address: u64,
+}
+
+/// Location which has to be adjusted when the image isn't loaded at its preferred base
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub address: u64,
+    /// Width of the patched value in bytes
+    pub size: u8,
+}
+
+/// Executable file mapped into memory
+#[derive(Debug, Clone)]
+pub struct LoadedImage {
+    pub format: ImageFormat,
+    pub mode: MachineMode,
+    pub image_base: u64,
+    pub entry_point: u64,
+    pub sections: Vec<Section>,
+    pub imports: Vec<Import>,
+    /// Stubs jumping through import slots (PLT entries) mapped to the slot they use
+    pub import_stubs: BTreeMap<u64, u64>,
+    pub relocations: Vec<Relocation>,
+    /// Named function addresses (exports of PE files, ELF function symbols)
+    pub symbols: BTreeMap<String, u64>,
+}
+
+impl LoadedImage {
+    /// Detects the format of `bytes` and loads them
+    pub fn load(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(b"MZ") {
+            pe::load(bytes)
+        } else if bytes.starts_with(b"\x7FELF") {
+            elf::load(bytes)
+        } else {
+            Err(Error::UnknownFormat)
+        }
+    }
+
+    pub fn section_containing(&self, address: u64) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|section| section.contains(address))
+    }
+
+    /// Mapped bytes from `address` to the end of its section
+    pub fn bytes_at(&self, address: u64) -> Option<&[u8]> {
+        let section = self.section_containing(address)?;
+        let offset = (address - section.virtual_address) as usize;
+        Some(&section.data[offset..])
+    }
+
+    /// Reads `size` bytes at `address`. Fails if they aren't mapped or cross a section boundary
+    pub fn read_bytes(&self, address: u64, size: usize) -> Option<&[u8]> {
+        self.bytes_at(address)?.get(..size)
+    }
+
+    /// Bytes at `address` which can't change at runtime, e.g. constants in `.rdata`. Import slots
+    /// are excluded, because they are filled in by the system loader
+    pub fn read_only_bytes(&self, address: u64, size: usize) -> Option<&[u8]> {
+        let section = self.section_containing(address)?;
+        if section.writable {
+            return None;
+        }
+
+        let end = address.checked_add(size as u64)?;
+        let pointer_size = self.pointer_size();
+        if self
+            .imports
+            .iter()
+            .any(|import| import.address < end && address < import.address + pointer_size)
+        {
+            return None;
+        }
+
+        self.read_bytes(address, size)
+    }
+
+    pub fn pointer_size(&self) -> u64 {
+        match self.mode {
+            MachineMode::LONG_64 => 8,
+            MachineMode::LONG_COMPAT_16 | MachineMode::LEGACY_16 | MachineMode::REAL_16 => 2,
+            MachineMode::LONG_COMPAT_32 | MachineMode::LEGACY_32 => 4,
+        }
+    }
+
+    pub fn import_at(&self, address: u64) -> Option<&Import> {
+        self.imports.iter().find(|import| import.address == address)
+    }
+
+    /// Import called through the stub at `address`
+    pub fn import_for_stub(&self, address: u64) -> Option<&Import> {
+        let slot = self.import_stubs.get(&address)?;
+        self.import_at(*slot)
+    }
+
+    /// Moves the image to `new_base`, applying all relocations
+    pub fn rebase(&mut self, new_base: u64) {
+        let delta = new_base.wrapping_sub(self.image_base);
+
+        for relocation in &self.relocations {
+            let Some(section) = self
+                .sections
+                .iter_mut()
+                .find(|section| section.contains(relocation.address))
+            else {
+                continue;
+            };
+
+            let offset = (relocation.address - section.virtual_address) as usize;
+            let size = usize::from(relocation.size);
+            let Some(slot) = section.data.get_mut(offset..offset + size) else {
+                continue;
+            };
+
+            let mut value = [0; 8];
+            value[..size].copy_from_slice(slot);
+            let relocated = u64::from_le_bytes(value).wrapping_add(delta);
+            slot.copy_from_slice(&relocated.to_le_bytes()[..size]);
+        }
+
+        let move_address = |address: u64| address.wrapping_add(delta);
+        for section in &mut self.sections {
+            section.virtual_address = move_address(section.virtual_address);
+        }
+        for import in &mut self.imports {
+            import.address = move_address(import.address);
+        }
+        for relocation in &mut self.relocations {
+            relocation.address = move_address(relocation.address);
+        }
+        self.import_stubs = self
+            .import_stubs
+            .iter()
+            .map(|(stub, slot)| (move_address(*stub), move_address(*slot)))
+            .collect();
+        for address in self.symbols.values_mut() {
+            *address = move_address(*address);
+        }
+        self.entry_point = move_address(self.entry_point);
+        self.image_base = new_base;
+    }
+}
+
+/// Largest section which is mapped. Sizes are read from the file, so they can't be trusted
+const MAX_SECTION_SIZE: u64 = 0x1000_0000;
+
+/// Largest total size of the sections of an image, as there may be many of them
+const MAX_IMAGE_SIZE: u64 = 0x4000_0000;
+
+/// Section of `memory_size` bytes, starting with `file_size` bytes read at `file_offset` and zero
+/// filled after them. `mapped_size` is the size of the sections of the image mapped so far
+fn map_section(
+    bytes: &[u8],
+    file_offset: u64,
+    file_size: u64,
+    memory_size: u64,
+    mapped_size: &mut u64,
+) -> Result<Vec<u8>> {
+    if memory_size > MAX_SECTION_SIZE {
+        return Err(Error::SectionTooLarge(memory_size));
+    }
+    let total_size = *mapped_size + memory_size;
+    if total_size > MAX_IMAGE_SIZE {
+        return Err(Error::ImageTooLarge(total_size));
+    }
+    *mapped_size = total_size;
+
+    let mut data = vec![0; memory_size as usize];
+    let copied_size = file_size.min(memory_size);
+    if copied_size != 0 {
+        let file_data = usize::try_from(file_offset)
+            .ok()
+            .and_then(|start| bytes.get(start..start.checked_add(copied_size as usize)?))
+            .ok_or(Error::Truncated {
+                offset: file_offset,
+                size: copied_size,
+            })?;
+        data[..copied_size as usize].copy_from_slice(file_data);
+    }
+    Ok(data)
+}
+
+/// File offset of entry `index` in a table of `entry_size` byte entries. The offset is inside of
+/// `bytes`, so adding a field offset to it can't overflow
+fn table_entry_offset(bytes: &[u8], table_offset: u64, index: u64, entry_size: u64) -> Result<u64> {
+    let offset = index
+        .checked_mul(entry_size)
+        .and_then(|offset| table_offset.checked_add(offset))
+        .ok_or(Error::Malformed("table offset"))?;
+    if offset >= bytes.len() as u64 {
+        return Err(Error::Truncated {
+            offset,
+            size: entry_size,
+        });
+    }
+    Ok(offset)
+}
+
+fn read_array<const N: usize>(bytes: &[u8], offset: u64) -> Result<[u8; N]> {
+    usize::try_from(offset)
+        .ok()
+        .and_then(|start| bytes.get(start..start.checked_add(N)?))
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(Error::Truncated {
+            offset,
+            size: N as u64,
+        })
+}
+
+fn read_u16(bytes: &[u8], offset: u64) -> Result<u16> {
+    read_array(bytes, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(bytes: &[u8], offset: u64) -> Result<u32> {
+    read_array(bytes, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(bytes: &[u8], offset: u64) -> Result<u64> {
+    read_array(bytes, offset).map(u64::from_le_bytes)
+}
+
+/// Reads NUL terminated string starting at `offset`
+fn read_c_str(bytes: &[u8], offset: u64) -> Result<String> {
+    let tail = usize::try_from(offset)
+        .ok()
+        .and_then(|start| bytes.get(start..))
+        .ok_or(Error::Truncated { offset, size: 1 })?;
+    let end = tail
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or(Error::Malformed("string"))?;
+
+    Ok(String::from_utf8_lossy(&tail[..end]).into_owned())
+}
@@ -0,0 +1,392 @@
+//! PE32 and PE32+ loader
+use std::collections::BTreeMap;
+
+use zydis::MachineMode;
+
+use super::{
+    map_section, read_c_str, read_u16, read_u32, read_u64, table_entry_offset, Error, ImageFormat,
+    Import, LoadedImage, Relocation, Result, Section,
+};
+
+const PE_SIGNATURE: [u8; 4] = *b"PE\0\0";
+
+const MACHINE_I386: u16 = 0x14C;
+const MACHINE_AMD64: u16 = 0x8664;
+
+const OPTIONAL_HEADER_MAGIC_PE32: u16 = 0x10B;
+const OPTIONAL_HEADER_MAGIC_PE32_PLUS: u16 = 0x20B;
+
+const DIRECTORY_EXPORT: u32 = 0;
+const DIRECTORY_IMPORT: u32 = 1;
+const DIRECTORY_BASE_RELOCATION: u32 = 5;
+
+const SECTION_EXECUTE: u32 = 0x2000_0000;
+const SECTION_WRITE: u32 = 0x8000_0000;
+
+const SECTION_HEADER_SIZE: u64 = 40;
+const IMPORT_DESCRIPTOR_SIZE: u64 = 20;
+
+const RELOCATION_ABSOLUTE: u16 = 0;
+const RELOCATION_HIGHLOW: u16 = 3;
+const RELOCATION_DIR64: u16 = 10;
+
+#[derive(Debug, Clone, Copy)]
+struct DataDirectory {
+    rva: u32,
+    size: u32,
+}
+
+pub(super) fn load(bytes: &[u8]) -> Result<LoadedImage> {
+    let pe_offset = u64::from(read_u32(bytes, 0x3C)?);
+    if super::read_array::<4>(bytes, pe_offset)? != PE_SIGNATURE {
+        return Err(Error::Malformed("PE signature"));
+    }
+
+    let coff_offset = pe_offset + 4;
+    let machine = read_u16(bytes, coff_offset)?;
+    let number_of_sections = read_u16(bytes, coff_offset + 2)?;
+    let size_of_optional_header = read_u16(bytes, coff_offset + 16)?;
+
+    let mode = match machine {
+        MACHINE_I386 => MachineMode::LEGACY_32,
+        MACHINE_AMD64 => MachineMode::LONG_64,
+        _ => return Err(Error::UnsupportedMachine(machine.into())),
+    };
+
+    let optional_offset = coff_offset + 20;
+    let magic = read_u16(bytes, optional_offset)?;
+    let entry_point_rva = read_u32(bytes, optional_offset + 16)?;
+
+    let (image_base, directories_offset) = match magic {
+        OPTIONAL_HEADER_MAGIC_PE32 => (
+            u64::from(read_u32(bytes, optional_offset + 28)?),
+            optional_offset + 92,
+        ),
+        OPTIONAL_HEADER_MAGIC_PE32_PLUS => (
+            read_u64(bytes, optional_offset + 24)?,
+            optional_offset + 108,
+        ),
+        _ => return Err(Error::Malformed("optional header magic")),
+    };
+    let is_pe32_plus = magic == OPTIONAL_HEADER_MAGIC_PE32_PLUS;
+
+    let number_of_directories = read_u32(bytes, directories_offset)?;
+    let data_directory = |index: u32| -> Result<Option<DataDirectory>> {
+        if index >= number_of_directories {
+            return Ok(None);
+        }
+
+        let offset = directories_offset + 4 + u64::from(index) * 8;
+        let directory = DataDirectory {
+            rva: read_u32(bytes, offset)?,
+            size: read_u32(bytes, offset + 4)?,
+        };
+        Ok((directory.rva != 0).then_some(directory))
+    };
+
+    let section_headers_offset = optional_offset + u64::from(size_of_optional_header);
+    let mut mapped_size = 0;
+    let sections = (0..u64::from(number_of_sections))
+        .map(|index| {
+            let header_offset =
+                table_entry_offset(bytes, section_headers_offset, index, SECTION_HEADER_SIZE)?;
+            read_section(bytes, header_offset, image_base, &mut mapped_size)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut image = LoadedImage {
+        format: ImageFormat::Pe,
+        mode,
+        image_base,
+        entry_point: rva_address(image_base, entry_point_rva)?,
+        sections,
+        imports: vec![],
+        import_stubs: BTreeMap::new(),
+        relocations: vec![],
+        symbols: BTreeMap::new(),
+    };
+
+    if let Some(directory) = data_directory(DIRECTORY_IMPORT)? {
+        image.imports = read_imports(&image, directory, is_pe32_plus)?;
+    }
+    if let Some(directory) = data_directory(DIRECTORY_BASE_RELOCATION)? {
+        image.relocations = read_relocations(&image, directory)?;
+    }
+    if let Some(directory) = data_directory(DIRECTORY_EXPORT)? {
+        image.symbols = read_exports(&image, directory)?;
+    }
+
+    Ok(image)
+}
+
+fn read_section(
+    bytes: &[u8],
+    header_offset: u64,
+    image_base: u64,
+    mapped_size: &mut u64,
+) -> Result<Section> {
+    let raw_name = super::read_array::<8>(bytes, header_offset)?;
+    let name_length = raw_name.iter().position(|byte| *byte == 0).unwrap_or(8);
+    let name = String::from_utf8_lossy(&raw_name[..name_length]).into_owned();
+
+    let virtual_size = read_u32(bytes, header_offset + 8)?;
+    let virtual_address = read_u32(bytes, header_offset + 12)?;
+    let size_of_raw_data = read_u32(bytes, header_offset + 16)?;
+    let pointer_to_raw_data = read_u32(bytes, header_offset + 20)?;
+    let characteristics = read_u32(bytes, header_offset + 36)?;
+
+    // Some linkers leave the virtual size empty
+    let section_size = if virtual_size == 0 {
+        size_of_raw_data
+    } else {
+        virtual_size
+    };
+    let data = map_section(
+        bytes,
+        pointer_to_raw_data.into(),
+        size_of_raw_data.into(),
+        section_size.into(),
+        mapped_size,
+    )?;
+
+    let section = Section {
+        name,
+        virtual_address: rva_address(image_base, virtual_address)?,
+        data,
+        executable: characteristics & SECTION_EXECUTE != 0,
+        writable: characteristics & SECTION_WRITE != 0,
+    };
+    Ok(section)
+}
+
+/// Virtual address of `rva`, failing when it's beyond the address space
+fn rva_address(image_base: u64, rva: u32) -> Result<u64> {
+    image_base
+        .checked_add(rva.into())
+        .ok_or(Error::Malformed("RVA beyond the address space"))
+}
+
+/// Mapped bytes at `rva`
+fn rva_bytes(image: &LoadedImage, rva: u32) -> Result<&[u8]> {
+    image
+        .bytes_at(rva_address(image.image_base, rva)?)
+        .ok_or(Error::Malformed("RVA outside of the sections"))
+}
+
+fn read_imports(
+    image: &LoadedImage,
+    directory: DataDirectory,
+    is_pe32_plus: bool,
+) -> Result<Vec<Import>> {
+    let descriptors = rva_bytes(image, directory.rva)?;
+    let thunk_size: u32 = if is_pe32_plus { 8 } else { 4 };
+    let ordinal_flag = if is_pe32_plus { 1 << 63 } else { 1 << 31 };
+
+    let mut imports = vec![];
+    for offset in (0..).step_by(IMPORT_DESCRIPTOR_SIZE as usize) {
+        let original_first_thunk = read_u32(descriptors, offset)?;
+        let name_rva = read_u32(descriptors, offset + 12)?;
+        let first_thunk = read_u32(descriptors, offset + 16)?;
+        if name_rva == 0 && first_thunk == 0 {
+            break;
+        }
+
+        let library = read_c_str(rva_bytes(image, name_rva)?, 0)?;
+        // Bound imports only have the IAT
+        let lookup_table_rva = if original_first_thunk != 0 {
+            original_first_thunk
+        } else {
+            first_thunk
+        };
+        let lookup_table = rva_bytes(image, lookup_table_rva)?;
+
+        for index in 0u32.. {
+            let thunk_offset = index
+                .checked_mul(thunk_size)
+                .ok_or(Error::Malformed("import lookup table"))?;
+            let thunk = if is_pe32_plus {
+                read_u64(lookup_table, thunk_offset.into())?
+            } else {
+                u64::from(read_u32(lookup_table, thunk_offset.into())?)
+            };
+            if thunk == 0 {
+                break;
+            }
+
+            let name = if thunk & ordinal_flag != 0 {
+                format!("#{}", thunk & 0xFFFF)
+            } else {
+                // Skip the hint
+                read_c_str(rva_bytes(image, thunk as u32)?, 2)?
+            };
+
+            imports.push(Import {
+                library: Some(library.clone()),
+                name,
+                address: first_thunk
+                    .checked_add(thunk_offset)
+                    .ok_or(Error::Malformed("import address table"))
+                    .and_then(|rva| rva_address(image.image_base, rva))?,
+            });
+        }
+    }
+
+    Ok(imports)
+}
+
+fn read_relocations(image: &LoadedImage, directory: DataDirectory) -> Result<Vec<Relocation>> {
+    let blocks = rva_bytes(image, directory.rva)?;
+    let directory_size = u64::from(directory.size);
+
+    let mut relocations = vec![];
+    let mut block_offset = 0;
+    while block_offset + 8 <= directory_size {
+        let page_rva = read_u32(blocks, block_offset)?;
+        let block_size = u64::from(read_u32(blocks, block_offset + 4)?);
+        if block_size < 8 {
+            return Err(Error::Malformed("base relocation block"));
+        }
+
+        for entry_offset in (block_offset + 8..block_offset + block_size).step_by(2) {
+            let entry = read_u16(blocks, entry_offset)?;
+            let size = match entry >> 12 {
+                RELOCATION_ABSOLUTE => continue,
+                RELOCATION_HIGHLOW => 4,
+                RELOCATION_DIR64 => 8,
+                _ => return Err(Error::Malformed("base relocation type")),
+            };
+
+            relocations.push(Relocation {
+                address: page_rva
+                    .checked_add((entry & 0xFFF).into())
+                    .ok_or(Error::Malformed("base relocation block"))
+                    .and_then(|rva| rva_address(image.image_base, rva))?,
+                size,
+            });
+        }
+
+        block_offset += block_size;
+    }
+
+    Ok(relocations)
+}
+
+fn read_exports(image: &LoadedImage, directory: DataDirectory) -> Result<BTreeMap<String, u64>> {
+    let export_directory = rva_bytes(image, directory.rva)?;
+    let number_of_names = read_u32(export_directory, 24)?;
+    let functions = rva_bytes(image, read_u32(export_directory, 28)?)?;
+    let names = rva_bytes(image, read_u32(export_directory, 32)?)?;
+    let ordinals = rva_bytes(image, read_u32(export_directory, 36)?)?;
+
+    let directory_range = directory.rva..directory.rva.saturating_add(directory.size);
+
+    let mut symbols = BTreeMap::new();
+    for index in 0..u64::from(number_of_names) {
+        let name = read_c_str(rva_bytes(image, read_u32(names, index * 4)?)?, 0)?;
+        let ordinal = read_u16(ordinals, index * 2)?;
+        let function_rva = read_u32(functions, u64::from(ordinal) * 4)?;
+
+        // Forwarders point to a string inside the export directory instead of code
+        if directory_range.contains(&function_rva) {
+            continue;
+        }
+
+        symbols.insert(name, rva_address(image.image_base, function_rva)?);
+    }
+
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use zydis::MachineMode;
+
+    use super::super::{Error, ImageFormat, LoadedImage};
+
+    /// Minimal PE32+ with a single `.text` section at RVA 0x1000 containing `mov eax, 1; ret`
+    fn minimal_pe64() -> Vec<u8> {
+        let mut bytes = vec![0; 0x400];
+        bytes[..2].copy_from_slice(b"MZ");
+        bytes[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+
+        bytes[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // COFF header
+        bytes[0x44..0x46].copy_from_slice(&0x8664u16.to_le_bytes());
+        bytes[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+        bytes[0x54..0x56].copy_from_slice(&0xF0u16.to_le_bytes());
+        // Optional header
+        bytes[0x58..0x5A].copy_from_slice(&0x20Bu16.to_le_bytes());
+        bytes[0x68..0x6C].copy_from_slice(&0x1000u32.to_le_bytes());
+        bytes[0x70..0x78].copy_from_slice(&0x1_4000_0000u64.to_le_bytes());
+        bytes[0xC4..0xC8].copy_from_slice(&16u32.to_le_bytes());
+        // Section header
+        bytes[0x148..0x14D].copy_from_slice(b".text");
+        bytes[0x150..0x154].copy_from_slice(&6u32.to_le_bytes());
+        bytes[0x154..0x158].copy_from_slice(&0x1000u32.to_le_bytes());
+        bytes[0x158..0x15C].copy_from_slice(&0x200u32.to_le_bytes());
+        bytes[0x15C..0x160].copy_from_slice(&0x200u32.to_le_bytes());
+        bytes[0x16C..0x170].copy_from_slice(&0x6000_0020u32.to_le_bytes());
+        // Code
+        bytes[0x200..0x206].copy_from_slice(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3]);
+
+        bytes
+    }
+
+    #[test]
+    fn load_minimal_pe64() {
+        let image = LoadedImage::load(&minimal_pe64()).unwrap();
+
+        assert_eq!(image.format, ImageFormat::Pe);
+        assert_eq!(image.mode, MachineMode::LONG_64);
+        assert_eq!(image.entry_point, 0x1_4000_1000);
+        assert_eq!(image.sections.len(), 1);
+        assert!(image.sections[0].executable);
+        assert_eq!(
+            image.read_bytes(image.entry_point, 6),
+            Some(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3][..])
+        );
+    }
+
+    #[test]
+    fn huge_section_is_rejected() {
+        let mut bytes = minimal_pe64();
+        bytes[0x150..0x154].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::SectionTooLarge(0xFFFF_FFFF))
+        ));
+    }
+
+    #[test]
+    fn many_sections_are_rejected_in_total() {
+        let mut bytes = minimal_pe64();
+        let section_count = 5;
+        bytes[0x46..0x48].copy_from_slice(&(section_count as u16).to_le_bytes());
+        for index in 0..section_count {
+            // Each section is just below the size limit for sections and has no file data
+            let header = 0x148 + index * 40;
+            bytes[header..header + 40].fill(0);
+            bytes[header..header + 5].copy_from_slice(b".data");
+            bytes[header + 8..header + 12].copy_from_slice(&0x0FFF_F000u32.to_le_bytes());
+            let virtual_address = 0x1000 + index as u32 * 0x1000_0000;
+            bytes[header + 12..header + 16].copy_from_slice(&virtual_address.to_le_bytes());
+        }
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::ImageTooLarge(0x4FFF_B000))
+        ));
+    }
+
+    #[test]
+    fn address_overflow_is_rejected() {
+        let mut bytes = minimal_pe64();
+        bytes[0x70..0x78].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        assert!(matches!(
+            LoadedImage::load(&bytes),
+            Err(Error::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+use inkwell::context::Context;
+use std::{error::Error, path::PathBuf, process::ExitCode};
+use zydis::{AllOperands, Decoder, MachineMode};
+use zydis2llvmir::{
+    compiler::{
+        cfg::stack_width_for_mode,
+        report::{LiftReport, UnsupportedPolicy},
+        Compiler, Error as CompilerError,
+    },
+    loader::LoadedImage,
+};
+
+const USAGE: &str = "\
+Usage: bin_lift <input> [options]
+
+Options:
+  -f, --format <raw|trace|exe>  How the input is interpreted [default: exe if the file is a PE
+                                or ELF image, raw otherwise]
+  -m, --mode <64|32|16|real>    Machine mode of raw code and traces [default: 64]
+  -b, --base <address>          Address raw code is mapped at [default: 0]
+  -s, --start <address>         Address lifting starts at. Defaults to the base for raw code and
+                                to the entry point for executables
+      --symbol <name>           Lift the executable's function with this name
+      --no-optimize             Don't run LLVM optimizations on the lifted function
+      --on-unsupported <skip|stop|unreachable|trap>
+                                What to do with unsupported instructions [default: skip]
+  -o, --output <path>           Output file, bitcode is written when it ends with .bc
+                                [default: lifted.ll]
+  -h, --help                    Print this message
+
+Raw code is decoded following its control flow from the start address. A trace is a sequence of
+executed instructions, which are lifted one after another.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    Raw,
+    Trace,
+    Executable,
+}
+
+#[derive(Debug)]
+struct Options {
+    input: PathBuf,
+    format: Option<InputFormat>,
+    mode: MachineMode,
+    /// Only for raw code and traces, which are mapped at 0 by default
+    base_address: Option<u64>,
+    start_address: Option<u64>,
+    symbol: Option<String>,
+    optimize: bool,
+    unsupported_policy: UnsupportedPolicy,
+    output: PathBuf,
+}
+
+impl Options {
+    /// Returns `None` when help was requested
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut input = None;
+        let mut options = Options {
+            input: PathBuf::new(),
+            format: None,
+            mode: MachineMode::LONG_64,
+            base_address: None,
+            start_address: None,
+            symbol: None,
+            optimize: true,
+            unsupported_policy: UnsupportedPolicy::Skip,
+            output: PathBuf::from("lifted.ll"),
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-f" | "--format" => {
+                    options.format = Some(match value()?.as_str() {
+                        "raw" => InputFormat::Raw,
+                        "trace" => InputFormat::Trace,
+                        "exe" => InputFormat::Executable,
+                        other => return Err(format!("Unknown input format {other}").into()),
+                    })
+                }
+                "-m" | "--mode" => {
+                    options.mode = match value()?.as_str() {
+                        "64" => MachineMode::LONG_64,
+                        "32" => MachineMode::LEGACY_32,
+                        "16" => MachineMode::LEGACY_16,
+                        "real" => MachineMode::REAL_16,
+                        other => return Err(format!("Unknown machine mode {other}").into()),
+                    }
+                }
+                "-b" | "--base" => options.base_address = Some(parse_address(&value()?)?),
+                "-s" | "--start" => options.start_address = Some(parse_address(&value()?)?),
+                "--symbol" => options.symbol = Some(value()?),
+                "--no-optimize" => options.optimize = false,
+                "--on-unsupported" => {
+                    options.unsupported_policy = match value()?.as_str() {
+                        "skip" => UnsupportedPolicy::Skip,
+                        "stop" => UnsupportedPolicy::Stop,
+                        "unreachable" => UnsupportedPolicy::Unreachable,
+                        "trap" => UnsupportedPolicy::Trap,
+                        other => return Err(format!("Unknown policy {other}").into()),
+                    }
+                }
+                "-o" | "--output" => options.output = PathBuf::from(value()?),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {arg}").into()),
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => return Err(format!("Unexpected argument {arg}").into()),
+            }
+        }
+
+        options.input = input.ok_or("Input file is required")?;
+        if let Some(format) = options.format {
+            options.check_format(format)?;
+        }
+        Ok(Some(options))
+    }
+
+    /// Rejects options which don't apply to inputs of `format`
+    fn check_format(&self, format: InputFormat) -> Result<(), Box<dyn Error>> {
+        match format {
+            InputFormat::Raw | InputFormat::Trace if self.symbol.is_some() => {
+                Err("--symbol only applies to executables".into())
+            }
+            InputFormat::Executable if self.base_address.is_some() => Err(
+                "--base doesn't apply to executables, they are mapped at their image base".into(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    fn base_address(&self) -> u64 {
+        self.base_address.unwrap_or(0)
+    }
+}
+
+/// Parses hexadecimal address, the `0x` prefix is optional
+fn parse_address(address: &str) -> Result<u64, Box<dyn Error>> {
+    let digits = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))
+        .unwrap_or(address);
+    u64::from_str_radix(digits, 16).map_err(|e| format!("Invalid address {address}: {e}").into())
+}
+
+fn lift(options: &Options) -> Result<LiftReport, Box<dyn Error>> {
+    let bytes = std::fs::read(&options.input)?;
+    let format = options.format.unwrap_or_else(|| {
+        if bytes.starts_with(b"MZ") || bytes.starts_with(b"\x7FELF") {
+            InputFormat::Executable
+        } else {
+            InputFormat::Raw
+        }
+    });
+    options.check_format(format)?;
+
+    let context = Context::create();
+    let (compiler, report) = match format {
+        InputFormat::Raw => {
+            let start_address = options.start_address.unwrap_or(options.base_address());
+            let compiler =
+                Compiler::new_with_x86_lifter(&context, options.mode, Some(start_address))?;
+            compiler.set_unsupported_policy(options.unsupported_policy);
+            let (_cfg, report) = compiler.lift_function_at(
+                &bytes,
+                options.base_address(),
+                start_address,
+                options.optimize,
+            )?;
+            (compiler, report)
+        }
+        InputFormat::Trace => {
+            let start_address = options.start_address.unwrap_or(options.base_address());
+            let decoder = Decoder::new(options.mode, stack_width_for_mode(options.mode))?;
+            let instructions = decoder
+                .decode_all::<AllOperands>(&bytes, start_address)
+                .map(|instruction_info| instruction_info.map(|(_, _, instruction)| instruction))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let compiler =
+                Compiler::new_with_x86_lifter(&context, options.mode, Some(start_address))?;
+            compiler.set_unsupported_policy(options.unsupported_policy);
+            let report = compiler.lift_function(&instructions, options.optimize)?;
+            (compiler, report)
+        }
+        InputFormat::Executable => {
+            let image = LoadedImage::load(&bytes)?;
+            let start_address = options.start_address.unwrap_or(image.entry_point);
+            let compiler = Compiler::new_for_image(&context, image)?;
+            compiler.set_unsupported_policy(options.unsupported_policy);
+            let (_cfg, report) = match &options.symbol {
+                Some(symbol) => compiler.lift_image_symbol(symbol, options.optimize)?,
+                None => compiler.lift_image_function(start_address, options.optimize)?,
+            };
+            (compiler, report)
+        }
+    };
+
+    let module = &compiler.lifter.module;
+    let writes_bitcode = options
+        .output
+        .extension()
+        .is_some_and(|extension| extension == "bc");
+    if writes_bitcode {
+        if !module.write_bitcode_to_path(&options.output) {
+            return Err(format!("Unable to write {}", options.output.display()).into());
+        }
+    } else {
+        module.print_to_file(&options.output)?;
+    }
+
+    Ok(report)
+}
+
+fn print_report(report: &LiftReport) {
+    eprintln!(
+        "Lifted {} instructions, {} unsupported",
+        report.lifted_count(),
+        report.unsupported().count()
+    );
+    for (mnemonic, count) in report.most_frequent_unsupported() {
+        eprintln!("  {:<16} {count}", mnemonic.to_string());
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match lift(&options) {
+        Ok(report) => {
+            print_report(&report);
+            eprintln!("Written to {}", options.output.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            // Instructions lifted before the failure are still reported
+            if let Some(CompilerError::LiftingFailed(report)) = e.downcast_ref() {
+                print_report(report);
+            }
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_address, InputFormat, Options};
+
+    use zydis::MachineMode;
+    use zydis2llvmir::compiler::report::UnsupportedPolicy;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string())).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&["code.bin"]).unwrap().unwrap();
+
+        assert_eq!(options.input.to_str(), Some("code.bin"));
+        assert_eq!(options.format, None);
+        assert_eq!(options.mode, MachineMode::LONG_64);
+        assert_eq!(options.base_address(), 0);
+        assert_eq!(options.start_address, None);
+        assert!(options.optimize);
+        assert_eq!(options.unsupported_policy, UnsupportedPolicy::Skip);
+        assert_eq!(options.output.to_str(), Some("lifted.ll"));
+    }
+
+    #[test]
+    fn every_option() {
+        let options = parse(&[
+            "-f",
+            "raw",
+            "code.bin",
+            "--mode",
+            "32",
+            "-b",
+            "0x401000",
+            "--start",
+            "401010",
+            "--no-optimize",
+            "--on-unsupported",
+            "trap",
+            "-o",
+            "out.bc",
+        ])
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(options.format, Some(InputFormat::Raw));
+        assert_eq!(options.mode, MachineMode::LEGACY_32);
+        assert_eq!(options.base_address(), 0x401000);
+        assert_eq!(options.start_address, Some(0x401010));
+        assert!(!options.optimize);
+        assert_eq!(options.unsupported_policy, UnsupportedPolicy::Trap);
+        assert_eq!(options.output.to_str(), Some("out.bc"));
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        assert!(parse(&["--help", "--unknown"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        for args in [
+            &[][..],
+            &["code.bin", "other.bin"],
+            &["code.bin", "--unknown"],
+            &["code.bin", "--mode"],
+            &["code.bin", "--mode", "8"],
+            &["code.bin", "--format", "elf"],
+            &["code.bin", "--on-unsupported", "ignore"],
+            &["code.bin", "--base", "xyz"],
+        ] {
+            assert!(parse(args).is_err(), "{args:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn symbol_is_rejected_for_raw_code_and_traces() {
+        for format in ["raw", "trace"] {
+            let error = parse(&["code.bin", "-f", format, "--symbol", "main"]).unwrap_err();
+            assert!(error.contains("--symbol"), "{error}");
+        }
+        assert!(parse(&["app.exe", "-f", "exe", "--symbol", "main"]).is_ok());
+    }
+
+    #[test]
+    fn base_is_rejected_for_executables() {
+        let error = parse(&["app.exe", "-f", "exe", "--base", "0x400000"]).unwrap_err();
+        assert!(error.contains("--base"), "{error}");
+
+        let options = parse(&["app.exe", "--base", "0x400000"]).unwrap().unwrap();
+        assert!(options.check_format(InputFormat::Executable).is_err());
+        assert!(options.check_format(InputFormat::Raw).is_ok());
+    }
+
+    #[test]
+    fn addresses_are_hexadecimal() {
+        assert_eq!(parse_address("0x1000").unwrap(), 0x1000);
+        assert_eq!(parse_address("0X1000").unwrap(), 0x1000);
+        assert_eq!(parse_address("ff").unwrap(), 0xff);
+        assert_eq!(parse_address("ffffffffffffffff").unwrap(), u64::MAX);
+        assert!(parse_address("").is_err());
+        assert!(parse_address("0x").is_err());
+        assert!(parse_address("10000000000000000").is_err());
+        assert!(parse_address("-1").is_err());
+    }
+}
@@ -315,6 +315,10 @@ pub enum ExtendedRegisterEnum {
     Reserved1,
     Reserved3,
     Reserved5,
+
+    // Hidden parts of the segment registers
+    FSBASE,
+    GSBASE,
 }
 
 impl From<zydis::Register> for ExtendedRegisterEnum {